@@ -0,0 +1,73 @@
+use chip8::chip8::Chip8;
+use chip8::lockstep::Lockstep;
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+
+// cargo bench --bench cpu
+// Each ROM loops forever so the benches can keep calling step()
+
+// register math only: 6xnn, 7xnn, 8xy4, 8xy5, 8xy2 and a jump
+const DISPATCH: [u8; 14] = [
+    0x60, 0x05, 0x61, 0x03, 0x80, 0x14, 0x81, 0x05, 0x80, 0x12, 0x70, 0x01, 0x12, 0x00,
+];
+
+// 15 row sprite drawn over and over at (V0, V1), with wrapping on the right edge
+const DRAW: [u8; 10] = [0xA0, 0x50, 0x60, 0x3C, 0x61, 0x08, 0xD0, 0x1F, 0x12, 0x06];
+
+// store and load all sixteen registers
+const MEMORY: [u8; 8] = [0xA3, 0x00, 0xFF, 0x55, 0xFF, 0x65, 0x12, 0x02];
+
+// something like a game frame: clear, draw a few digits, some math and a BCD
+const FRAME: [u8; 26] = [
+    0x00, 0xE0, // clear
+    0x62, 0x00, // V2 = 0
+    0xF2, 0x29, // I = font(V2)
+    0xD0, 0x15, // draw V0, V1
+    0x70, 0x05, // V0 += 5
+    0x72, 0x01, // V2 += 1
+    0x32, 0x08, // skip if V2 == 8
+    0x12, 0x04, // loop
+    0xC1, 0x1F, // V1 = rand
+    0xA3, 0x00, // I = 0x300
+    0xF1, 0x33, // BCD V1
+    0xF2, 0x65, // load V0..V2
+    0x12, 0x00, // again
+];
+
+fn run_steps(chip: &mut Chip8, steps: usize) {
+    for _ in 0..steps {
+        chip.step().unwrap();
+    }
+}
+
+fn benches(c: &mut Criterion) {
+    let mut chip = Chip8::new(&DISPATCH).unwrap();
+    c.bench_function("dispatch 1000 steps", |b| {
+        b.iter(|| run_steps(black_box(&mut chip), 1000))
+    });
+
+    let mut chip = Chip8::new(&DISPATCH).unwrap();
+    chip.set_decode_cache(true);
+    c.bench_function("dispatch 1000 steps, decode cache", |b| {
+        b.iter(|| run_steps(black_box(&mut chip), 1000))
+    });
+
+    let mut chip = Chip8::new(&DRAW).unwrap();
+    c.bench_function("dxyn 1000 steps", |b| {
+        b.iter(|| run_steps(black_box(&mut chip), 1000))
+    });
+
+    let mut chip = Chip8::new(&MEMORY).unwrap();
+    c.bench_function("fx55 fx65 1000 steps", |b| {
+        b.iter(|| run_steps(black_box(&mut chip), 1000))
+    });
+
+    let mut chip = Chip8::new(&FRAME).unwrap();
+    chip.set_seed(0);
+    let mut lockstep = Lockstep::new(1000);
+    c.bench_function("full frame 1000 ipf", |b| {
+        b.iter(|| lockstep.run_frame(black_box(&mut chip)).unwrap())
+    });
+}
+
+criterion_group!(cpu, benches);
+criterion_main!(cpu);
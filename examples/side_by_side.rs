@@ -0,0 +1,80 @@
+// Two ROMs side by side, each with its own machine, window and beeper, to compare two
+// versions of a game or check that a change didn't break anything:
+//     cargo run --example side_by_side --features gui,audio -- left.ch8 right.ch8
+// Keys go to the window with the focus. Closing either window ends both.
+use chip8::testrunner::IPF;
+use chip8::{Audio, Chip8, Emulator, Screen};
+use std::time::{Duration, Instant};
+
+const SCALE: usize = 10;
+
+struct Side {
+    name: String,
+    emulator: Emulator,
+    screen: Screen,
+    audio: Audio,
+    crashed: bool,
+}
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.len() != 2 {
+        eprintln!("usage: side_by_side LEFT.ch8 RIGHT.ch8");
+        std::process::exit(1)
+    }
+
+    // one device stream for both beepers
+    let (_stream, stream_handle) =
+        rodio::OutputStream::try_default().expect("Failed to open audio stream");
+    let mut sides: Vec<Side> = paths
+        .iter()
+        .enumerate()
+        .map(|(index, path)| {
+            let rom = std::fs::read(path).expect("Failed to read ROM");
+            let chip = Chip8::new(&rom).unwrap_or_else(|error| {
+                eprintln!("ERROR: {}: {:?}", path, error);
+                std::process::exit(1)
+            });
+            let mut screen = Screen::with_options(path, SCALE).unwrap_or_else(|error| {
+                eprintln!("ERROR: can't open a window: {}", error);
+                std::process::exit(1)
+            });
+            screen.set_position((index * (64 * SCALE + 16)) as isize, 0);
+            Side {
+                name: path.clone(),
+                emulator: Emulator::new(chip, IPF),
+                screen,
+                audio: Audio::with_stream(&stream_handle).unwrap_or_else(|_| Audio::silent()),
+                crashed: false,
+            }
+        })
+        .collect();
+
+    let sixty_hz = Duration::from_micros(16_666);
+    while sides.iter().all(|side| side.screen.is_open()) {
+        let frame_start = Instant::now();
+        for side in &mut sides {
+            side.screen.read_keys(&mut side.emulator.chip.keyboard);
+            // a crashed side keeps its last screen, the other one goes on
+            if !side.crashed
+                && let Err(error) = side.emulator.run_frame()
+            {
+                eprintln!("{} crashed: {:?}", side.name, error);
+                side.crashed = true;
+            }
+
+            let chip = &mut side.emulator.chip;
+            if chip.draw_flag {
+                side.screen.render(&chip.display);
+                chip.draw_flag = false;
+            } else {
+                side.screen.update();
+            }
+            side.audio.update(!side.crashed && chip.is_sound_on());
+        }
+
+        if let Some(rest) = sixty_hz.checked_sub(frame_start.elapsed()) {
+            std::thread::sleep(rest);
+        }
+    }
+}
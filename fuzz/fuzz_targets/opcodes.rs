@@ -0,0 +1,35 @@
+#![no_main]
+
+use chip8::chip8::Chip8;
+use libfuzzer_sys::fuzz_target;
+
+// Random machine state followed by a random opcode stream straight into decode/execute.
+// Layout of the input: 16 bytes for V0..VF, 2 for I, 2 for the pressed keys, then opcodes.
+// Execution goes on after errors, so odd states left behind by a failed instruction are covered too
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 20 {
+        return;
+    }
+    let (header, opcodes) = data.split_at(20);
+
+    let mut chip = Chip8::new(&[]).unwrap();
+    chip.set_seed(0);
+    for (index, &value) in header[..16].iter().enumerate() {
+        chip.set_v(index, value).unwrap();
+    }
+    let _ = chip.set_i(u16::from_be_bytes([header[16], header[17]]) & 0x0FFF);
+    let keys = u16::from_be_bytes([header[18], header[19]]);
+    for key in 0..16 {
+        chip.keyboard.set_key(key, keys & (1 << key) != 0).unwrap();
+    }
+
+    for pair in opcodes.chunks_exact(2) {
+        let opcode = u16::from_be_bytes([pair[0], pair[1]]);
+        let result = chip
+            .decode(opcode)
+            .and_then(|instruction| chip.execute(instruction));
+        if let Err(error) = result {
+            assert!(error.is_rom_error(), "{:#06X}: {:?}", opcode, error);
+        }
+    }
+});
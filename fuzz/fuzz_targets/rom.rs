@@ -0,0 +1,28 @@
+#![no_main]
+
+use chip8::chip8::{Chip8, Chip8Error};
+use libfuzzer_sys::fuzz_target;
+
+// Random bytes as a ROM: loading and running it may fail, but only with errors a ROM can cause
+fuzz_target!(|rom: &[u8]| {
+    let mut chip = match Chip8::new(rom) {
+        Ok(chip) => chip,
+        Err(error) => {
+            assert_eq!(error, Chip8Error::RomTooLarge);
+            return;
+        }
+    };
+    chip.set_seed(0);
+
+    for frame in 0..1000 {
+        for _ in 0..10 {
+            if let Err(error) = chip.step() {
+                assert!(error.is_rom_error(), "{:?}", error);
+                return;
+            }
+        }
+        if frame % 10 == 0 {
+            chip.decrease_timers();
+        }
+    }
+});
@@ -0,0 +1,132 @@
+/* chip8 C API, generated by cbindgen from src/ffi.rs, don't edit by hand */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define CHIP8_WIDTH 64
+
+#define CHIP8_HEIGHT 32
+
+/**
+ * Success, errors are negative
+ */
+#define CHIP8_OK 0
+
+/**
+ * A null machine or buffer was passed in
+ */
+#define CHIP8_NULL_POINTER -100
+
+/**
+ * Opaque to C, a machine plus its frame counter
+ */
+typedef struct Chip8Machine Chip8Machine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * New machine with an empty ROM, 10 instructions per frame
+ */
+struct Chip8Machine *chip8_new(void);
+
+/**
+ * Releases a machine, null is ignored
+ *
+ * # Safety
+ * `machine` must come from chip8_new and not be used afterwards.
+ */
+void chip8_free(struct Chip8Machine *machine);
+
+/**
+ * Resets the machine and loads the ROM at 0x200. The random generator is reseeded
+ * from entropy, call chip8_set_seed afterwards for repeatable runs
+ *
+ * # Safety
+ * `machine` must come from chip8_new and `rom` point to `len` readable bytes.
+ */
+int chip8_load_rom(struct Chip8Machine *machine, const uint8_t *rom, size_t len);
+
+/**
+ * Runs one frame: the instructions of a frame, then one tick of the timers
+ *
+ * # Safety
+ * `machine` must come from chip8_new.
+ */
+int chip8_step_frame(struct Chip8Machine *machine);
+
+/**
+ * CHIP8_WIDTH * CHIP8_HEIGHT bytes, one per pixel (0 or 1), row by row. The pointer is
+ * valid until the machine is freed, null if the machine is null
+ *
+ * # Safety
+ * `machine` must come from chip8_new.
+ */
+const uint8_t *chip8_framebuffer(const struct Chip8Machine *machine);
+
+/**
+ * Presses or releases one of the 16 keys (0x0 to 0xF)
+ *
+ * # Safety
+ * `machine` must come from chip8_new.
+ */
+int chip8_set_key(struct Chip8Machine *machine, uint8_t key, bool pressed);
+
+/**
+ * True while the buzzer should sound
+ *
+ * # Safety
+ * `machine` must come from chip8_new.
+ */
+bool chip8_sound_on(const struct Chip8Machine *machine);
+
+/**
+ * Seeds the random generator of Cxnn
+ *
+ * # Safety
+ * `machine` must come from chip8_new.
+ */
+int chip8_set_seed(struct Chip8Machine *machine, uint64_t seed);
+
+/**
+ * Copies V0 to VF into `out`
+ *
+ * # Safety
+ * `machine` must come from chip8_new and `out` point to 16 writable bytes.
+ */
+int chip8_registers(const struct Chip8Machine *machine, uint8_t *out);
+
+/**
+ * Sets V0 to VF from `registers`
+ *
+ * # Safety
+ * `machine` must come from chip8_new and `registers` point to 16 readable bytes.
+ */
+int chip8_set_registers(struct Chip8Machine *machine, const uint8_t *registers);
+
+/**
+ * Copies the return addresses on the stack into `out`, oldest first, and returns how
+ * many there are (0 to 16). A negative return is an error code
+ *
+ * # Safety
+ * `machine` must come from chip8_new and `out` point to room for 16 addresses.
+ */
+int chip8_stack(const struct Chip8Machine *machine, uint16_t *out);
+
+/**
+ * Static string for a return code, never null
+ */
+const char *chip8_error_name(int code);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHIP8_H */
@@ -0,0 +1,304 @@
+// The programs.json of the chip8Archive (github.com/JohnEarnest/chip8Archive), what its
+// games are and how Octo runs them best. The archive keeps the ROMs in roms/ under it, one
+// entry per file stem:
+//     "snake": {
+//         "title": "Snake", "authors": ["TomRintjema"], "release": "2021-10-30",
+//         "desc": "...", "platform": "xochip",
+//         "options": { "tickrate": 20, "fillColor": "#FFFFFF", "shiftQuirks": false, ... }
+//     }
+// tickrate is our speed, fillColor, fillColor2, blendColor and backgroundColor the palette,
+// and the Octo quirk flags are turned around where they mean the opposite of ours. The
+// platform picks ours when we have it. The other keys are ignored.
+use crate::config::{self, ConfigError, Overrides};
+use crate::cpu::{Platform, Quirks};
+use crate::display::Palette;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &str = "programs.json";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub title: String,
+    pub authors: Vec<String>,
+    // of the release date
+    pub year: Option<u16>,
+    pub description: String,
+    // "chip8", "schip" or "xochip"
+    pub platform: String,
+    // what the options recommend, applied under [game] sections and profiles
+    pub recommended: Overrides,
+}
+
+impl Program {
+    // "Snake by TomRintjema, 2021"
+    pub fn summary(&self) -> String {
+        let mut summary = self.title.clone();
+        if !self.authors.is_empty() {
+            summary += &format!(" by {}", self.authors.join(", "));
+        }
+        if let Some(year) = self.year {
+            summary += &format!(", {}", year);
+        }
+        summary
+    }
+
+    // Whether the emulator has the platform it's made for
+    pub fn is_supported(&self) -> bool {
+        self.platform.is_empty() || Platform::parse(&self.platform).is_some()
+    }
+}
+
+// A programs.json that's there but can't be read, for the app to warn about
+#[derive(Debug)]
+pub struct ArchiveError {
+    pub path: PathBuf,
+    pub error: ConfigError,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can't read {}: {}", self.path.display(), self.error)
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Entry {
+    title: String,
+    authors: Vec<String>,
+    release: String,
+    desc: String,
+    platform: String,
+    options: Options,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct Options {
+    tickrate: Option<u32>,
+    fill_color: Option<String>,
+    fill_color2: Option<String>,
+    blend_color: Option<String>,
+    background_color: Option<String>,
+    shift_quirks: Option<bool>,
+    load_store_quirks: Option<bool>,
+    jump_quirks: Option<bool>,
+    logic_quirks: Option<bool>,
+}
+
+impl Options {
+    fn overrides(&self) -> Overrides {
+        let default = Palette::default();
+        let color = |text: &Option<String>| text.as_deref().and_then(config::parse_color);
+        let (on, off) = (color(&self.fill_color), color(&self.background_color));
+        let (second, both) = (color(&self.fill_color2), color(&self.blend_color));
+        let quirks = [
+            self.shift_quirks,
+            self.load_store_quirks,
+            self.jump_quirks,
+            self.logic_quirks,
+        ];
+        // a missing flag is off in Octo
+        let flag = |flag: Option<bool>| flag.unwrap_or(false);
+        Overrides {
+            speed: self.tickrate.map(|tickrate| tickrate.max(1)),
+            palette: [on, off, second, both]
+                .iter()
+                .any(Option::is_some)
+                .then(|| Palette {
+                    on: on.unwrap_or(default.on),
+                    off: off.unwrap_or(default.off),
+                    second: second.unwrap_or(default.second),
+                    both: both.unwrap_or(default.both),
+                }),
+            quirks: quirks.iter().any(Option::is_some).then(|| Quirks {
+                shift_uses_vy: !flag(self.shift_quirks),
+                load_store_increment_i: !flag(self.load_store_quirks),
+                jump_uses_vx: flag(self.jump_quirks),
+                vf_reset: flag(self.logic_quirks),
+            }),
+            ..Overrides::default()
+        }
+    }
+}
+
+pub struct Archive {
+    programs: BTreeMap<String, Program>,
+}
+
+impl Archive {
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let entries: BTreeMap<String, Entry> =
+            serde_json::from_str(text).map_err(|error| ConfigError::Parse(error.to_string()))?;
+        let programs = entries
+            .into_iter()
+            .map(|(id, entry)| {
+                let platform = Platform::parse(&entry.platform);
+                let program = Program {
+                    title: entry.title,
+                    authors: entry.authors,
+                    year: entry.release.get(..4).and_then(|year| year.parse().ok()),
+                    description: entry.desc.trim().to_string(),
+                    platform: entry.platform,
+                    recommended: Overrides {
+                        platform,
+                        ..entry.options.overrides()
+                    },
+                };
+                (id, program)
+            })
+            .collect();
+        Ok(Archive { programs })
+    }
+
+    // The programs.json of a directory of ROMs, or of the one above like in the archive.
+    // None when there's none
+    pub fn find(dir: &Path) -> Result<Option<Archive>, ArchiveError> {
+        let Some(path) = dir
+            .ancestors()
+            .take(2)
+            .map(|dir| dir.join(FILE_NAME))
+            .find(|path| path.is_file())
+        else {
+            return Ok(None);
+        };
+        Archive::load(&path)
+            .map(Some)
+            .map_err(|error| ArchiveError { path, error })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Archive::parse(&text)
+    }
+
+    // The entry of a ROM, by its file name without .ch8
+    pub fn program(&self, rom_path: &Path) -> Option<&Program> {
+        let stem = rom_path.file_stem()?.to_str()?;
+        self.programs.get(stem)
+    }
+}
+
+// The ROM's entry in the programs.json around it, if it has one
+pub fn program_for(rom_path: &Path) -> Result<Option<Program>, ArchiveError> {
+    let Some(dir) = rom_path.parent() else {
+        return Ok(None);
+    };
+    let archive = Archive::find(dir)?;
+    Ok(archive.and_then(|archive| archive.program(rom_path).cloned()))
+}
+
+// Each .ch8 of a directory with its entry in the archive (Archive::find), in name order
+pub fn list(dir: &Path, archive: Option<&Archive>) -> io::Result<Vec<(PathBuf, Option<Program>)>> {
+    Ok(crate::golden::corpus(dir)?
+        .into_iter()
+        .map(|rom| {
+            let program = archive.and_then(|archive| archive.program(&rom)).cloned();
+            (rom, program)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: &str = r##"{
+        "snake": {
+            "title": "Snake",
+            "authors": ["TomRintjema"],
+            "release": "2021-10-30",
+            "desc": "Eat the apples. ",
+            "platform": "xochip",
+            "images": ["snake.gif"],
+            "options": {
+                "tickrate": 20,
+                "fillColor": "#FFFFFF",
+                "backgroundColor": "#000080",
+                "blendColor": "#808080",
+                "shiftQuirks": true,
+                "logicQuirks": true,
+                "screenRotation": 0
+            }
+        },
+        "plain": { "title": "Plain" },
+        "big": { "title": "Big", "platform": "schip" },
+        "odd": { "title": "Odd", "platform": "megachip" }
+    }"##;
+
+    #[test]
+    fn test_parse() {
+        let archive = Archive::parse(PROGRAMS).unwrap();
+        let snake = archive.program(Path::new("roms/snake.ch8")).unwrap();
+        assert_eq!(snake.summary(), "Snake by TomRintjema, 2021");
+        assert_eq!(snake.description, "Eat the apples.");
+        assert!(snake.is_supported());
+        assert_eq!(snake.recommended.platform, Some(Platform::XoChip));
+        let recommended = &snake.recommended;
+        assert_eq!(recommended.speed, Some(20));
+        assert_eq!(
+            recommended.palette,
+            Some(Palette {
+                on: 0xFFFFFF,
+                off: 0x000080,
+                both: 0x808080,
+                ..Palette::default()
+            })
+        );
+        assert_eq!(
+            recommended.quirks,
+            Some(Quirks {
+                shift_uses_vy: false,
+                load_store_increment_i: true,
+                jump_uses_vx: false,
+                vf_reset: true,
+            })
+        );
+
+        // no options, nothing recommended
+        let plain = archive.program(Path::new("plain.ch8")).unwrap();
+        assert_eq!(plain.summary(), "Plain");
+        assert!(plain.is_supported());
+        assert_eq!(plain.recommended, Overrides::default());
+        let big = archive.program(Path::new("big.ch8")).unwrap();
+        assert!(big.is_supported());
+        assert_eq!(big.recommended.platform, Some(Platform::SuperChip));
+        let odd = archive.program(Path::new("odd.ch8")).unwrap();
+        assert!(!odd.is_supported());
+        assert_eq!(odd.recommended.platform, None);
+        assert!(archive.program(Path::new("missing.ch8")).is_none());
+        assert!(Archive::parse("[1, 2]").is_err());
+    }
+    #[test]
+    fn test_find() {
+        let dir = std::env::temp_dir().join(format!("chip8-archive-{}", std::process::id()));
+        let roms = dir.join("roms");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&roms).unwrap();
+        assert!(Archive::find(&roms).unwrap().is_none());
+
+        // the one above the ROMs, like in the archive
+        fs::write(dir.join(FILE_NAME), PROGRAMS).unwrap();
+        let archive = Archive::find(&roms).unwrap().unwrap();
+        assert!(archive.program(Path::new("snake.ch8")).is_some());
+        let snake = program_for(&roms.join("snake.ch8")).unwrap().unwrap();
+        assert_eq!(snake.title, "Snake");
+        fs::write(roms.join("snake.ch8"), [0x12, 0x00]).unwrap();
+        let listed = list(&roms, Some(&archive)).unwrap();
+        assert_eq!(listed[0].1.as_ref().unwrap().title, "Snake");
+
+        // a broken one is the caller's to report
+        fs::write(dir.join(FILE_NAME), "{").unwrap();
+        let Err(error) = Archive::find(&roms) else {
+            panic!("a broken {} was read", FILE_NAME);
+        };
+        assert_eq!(error.path, dir.join(FILE_NAME));
+        assert!(program_for(&roms.join("snake.ch8")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
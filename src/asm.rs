@@ -0,0 +1,351 @@
+use crate::memory::PROGRAM_START;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+// A small assembler for the mnemonics the disassembler prints (Cowgod's, see the Display of
+// Instruction in cpu.rs), so a listing from it assembles back to the same program:
+//
+//     ; a comment, # works too
+//     start:
+//         LD I, sprite
+//         DRW V0, V1, 5
+//         JP start
+//     sprite:
+//         db %11110000, 0x90, 90, 90, F0
+//
+// Numbers are hex like in the disassembly (`0x` is fine too, 0B0 is hex), `%` is binary. A
+// label is `name:` or Octo's `: name` and goes anywhere a number does. `db` writes bytes and
+// `dw` big endian words. `LD I, LONG addr` is XO-CHIP's 4 byte F000. Mnemonics, registers and
+// directives don't care about case, labels do. The program starts at 0x200.
+
+// What's wrong and on which line, counted from 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand<'a> {
+    V(u16),
+    // Vx-Vy of XO-CHIP's 5xy2/5xy3
+    Range(u16, u16),
+    I,
+    // [I]
+    AtI,
+    Dt,
+    St,
+    K,
+    Hf,
+    R,
+    // LONG, with the address after it or not
+    Long(Option<&'a str>),
+    Audio,
+    Pitch,
+    // a number or a label, worked out in the second pass
+    Value(&'a str),
+}
+
+struct Line<'a> {
+    number: usize,
+    mnemonic: String,
+    operands: Vec<Operand<'a>>,
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    // first pass: where every label is
+    let mut labels = BTreeMap::new();
+    let mut lines = Vec::new();
+    let mut address = PROGRAM_START;
+    for (index, text) in source.lines().enumerate() {
+        let number = index + 1;
+        let error = |message: String| AsmError {
+            line: number,
+            message,
+        };
+        let mut text = text.split([';', '#']).next().unwrap_or_default().trim();
+        if let Some(label) = text.strip_prefix(':') {
+            // Octo's `: name` takes the whole line
+            define(&mut labels, label.trim(), address).map_err(error)?;
+            text = "";
+        } else if let Some((label, rest)) = text.split_once(':') {
+            define(&mut labels, label.trim(), address).map_err(error)?;
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let operands = match rest.trim() {
+            "" => Vec::new(),
+            rest => rest.split(',').map(|o| operand(o.trim())).collect(),
+        };
+        let line = Line {
+            number,
+            mnemonic: mnemonic.to_ascii_uppercase(),
+            operands,
+        };
+        address += size(&line);
+        lines.push(line);
+    }
+
+    // second pass: the bytes
+    let mut rom = Vec::new();
+    for line in &lines {
+        let error = |message: String| AsmError {
+            line: line.number,
+            message,
+        };
+        let value = |text: &str, max: u16| value(&labels, text, max).map_err(error);
+        match line.mnemonic.as_str() {
+            "DB" => {
+                for operand in &line.operands {
+                    let Operand::Value(text) = *operand else {
+                        return Err(error(format!("db takes numbers, not {:?}", operand)));
+                    };
+                    rom.push(value(text, 0xFF)? as u8);
+                }
+            }
+            "DW" => {
+                for operand in &line.operands {
+                    let Operand::Value(text) = *operand else {
+                        return Err(error(format!("dw takes numbers, not {:?}", operand)));
+                    };
+                    rom.extend_from_slice(&value(text, 0xFFFF)?.to_be_bytes());
+                }
+            }
+            _ => {
+                let opcode = encode(line, &value)?;
+                rom.extend_from_slice(&opcode.to_be_bytes());
+                if let [Operand::I, Operand::Long(Some(text))] = line.operands[..] {
+                    rom.extend_from_slice(&value(text, 0xFFFF)?.to_be_bytes());
+                }
+            }
+        }
+    }
+    Ok(rom)
+}
+
+fn define(labels: &mut BTreeMap<String, usize>, label: &str, address: usize) -> Result<(), String> {
+    let mut chars = label.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid || operand(label) != Operand::Value(label) {
+        return Err(format!("`{}` can't be a label", label));
+    }
+    if labels.insert(label.into(), address).is_some() {
+        return Err(format!("`{}` is defined twice", label));
+    }
+    Ok(())
+}
+
+fn operand(text: &str) -> Operand<'_> {
+    let register = |text: &str| {
+        let digit = text.strip_prefix(['V', 'v'])?;
+        (digit.len() == 1)
+            .then(|| u16::from_str_radix(digit, 16).ok())
+            .flatten()
+    };
+    if let Some(x) = register(text) {
+        return Operand::V(x);
+    }
+    if let Some((x, y)) = text.split_once('-')
+        && let (Some(x), Some(y)) = (register(x.trim()), register(y.trim()))
+    {
+        return Operand::Range(x, y);
+    }
+    let upper = text.to_ascii_uppercase();
+    if upper == "LONG" {
+        return Operand::Long(None);
+    }
+    if upper.starts_with("LONG") && text[4..].starts_with(char::is_whitespace) {
+        return Operand::Long(Some(text[4..].trim()));
+    }
+    match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::AtI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "HF" => Operand::Hf,
+        "R" => Operand::R,
+        "AUDIO" => Operand::Audio,
+        "PITCH" => Operand::Pitch,
+        _ => Operand::Value(text),
+    }
+}
+
+// Bytes a line takes
+fn size(line: &Line) -> usize {
+    match (line.mnemonic.as_str(), &line.operands[..]) {
+        ("DB", operands) => operands.len(),
+        ("DW", operands) => operands.len() * 2,
+        (_, [Operand::I, Operand::Long(Some(_))]) => 4,
+        _ => 2,
+    }
+}
+
+// A label, or a number up to `max`. Labels come first, `add:` makes `add` an address
+fn value(labels: &BTreeMap<String, usize>, text: &str, max: u16) -> Result<u16, String> {
+    let parsed = match labels.get(text) {
+        Some(&address) => Some(address as u32),
+        None => match (text.strip_prefix('%'), text.get(..2)) {
+            (Some(binary), _) => u32::from_str_radix(binary, 2).ok(),
+            (None, Some("0x" | "0X")) => u32::from_str_radix(&text[2..], 16).ok(),
+            _ => u32::from_str_radix(text, 16).ok(),
+        },
+    };
+    match parsed {
+        Some(value) if value <= max as u32 => Ok(value as u16),
+        Some(_) => Err(format!("`{}` is more than {:X}", text, max)),
+        None => Err(format!("`{}` isn't a number or a label", text)),
+    }
+}
+
+fn encode(
+    line: &Line,
+    value: &impl Fn(&str, u16) -> Result<u16, AsmError>,
+) -> Result<u16, AsmError> {
+    use Operand::*;
+    let xy = |x: u16, y: u16| x << 8 | y << 4;
+    let opcode = match (line.mnemonic.as_str(), &line.operands[..]) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCD", [Value(n)]) => 0x00C0 | value(n, 0xF)?,
+        ("SCU", [Value(n)]) => 0x00D0 | value(n, 0xF)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("DBG", [AtI]) => 0x00FA,
+        ("DBG", [V(x)]) => 0xF0FA | x << 8,
+        ("JP", [Value(a)]) => 0x1000 | value(a, 0xFFF)?,
+        ("JP", [V(0), Value(a)]) => 0xB000 | value(a, 0xFFF)?,
+        ("CALL", [Value(a)]) => 0x2000 | value(a, 0xFFF)?,
+        ("SE", [V(x), Value(nn)]) => 0x3000 | x << 8 | value(nn, 0xFF)?,
+        ("SNE", [V(x), Value(nn)]) => 0x4000 | x << 8 | value(nn, 0xFF)?,
+        ("SE", [V(x), V(y)]) => 0x5000 | xy(*x, *y),
+        ("SNE", [V(x), V(y)]) => 0x9000 | xy(*x, *y),
+        ("LD", [V(x), Value(nn)]) => 0x6000 | x << 8 | value(nn, 0xFF)?,
+        ("ADD", [V(x), Value(nn)]) => 0x7000 | x << 8 | value(nn, 0xFF)?,
+        ("LD", [V(x), V(y)]) => 0x8000 | xy(*x, *y),
+        ("OR", [V(x), V(y)]) => 0x8001 | xy(*x, *y),
+        ("AND", [V(x), V(y)]) => 0x8002 | xy(*x, *y),
+        ("XOR", [V(x), V(y)]) => 0x8003 | xy(*x, *y),
+        ("ADD", [V(x), V(y)]) => 0x8004 | xy(*x, *y),
+        ("SUB", [V(x), V(y)]) => 0x8005 | xy(*x, *y),
+        ("SHR", [V(x)]) => 0x8006 | xy(*x, *x),
+        ("SHR", [V(x), V(y)]) => 0x8006 | xy(*x, *y),
+        ("SUBN", [V(x), V(y)]) => 0x8007 | xy(*x, *y),
+        ("SHL", [V(x)]) => 0x800E | xy(*x, *x),
+        ("SHL", [V(x), V(y)]) => 0x800E | xy(*x, *y),
+        ("LD", [I, Value(a)]) => 0xA000 | value(a, 0xFFF)?,
+        ("LD", [I, Long(_)]) => 0xF000,
+        ("RND", [V(x), Value(nn)]) => 0xC000 | x << 8 | value(nn, 0xFF)?,
+        ("DRW", [V(x), V(y), Value(n)]) => 0xD000 | xy(*x, *y) | value(n, 0xF)?,
+        ("SKP", [V(x)]) => 0xE09E | x << 8,
+        ("SKNP", [V(x)]) => 0xE0A1 | x << 8,
+        ("LD", [V(x), Dt]) => 0xF007 | x << 8,
+        ("LD", [V(x), K]) => 0xF00A | x << 8,
+        ("LD", [Dt, V(x)]) => 0xF015 | x << 8,
+        ("LD", [St, V(x)]) => 0xF018 | x << 8,
+        ("ADD", [I, V(x)]) => 0xF01E | x << 8,
+        // F and B are hex digits too, they're only read as registers here
+        ("LD", [Value(f), V(x)]) if f.eq_ignore_ascii_case("F") => 0xF029 | x << 8,
+        ("LD", [Hf, V(x)]) => 0xF030 | x << 8,
+        ("LD", [Value(b), V(x)]) if b.eq_ignore_ascii_case("B") => 0xF033 | x << 8,
+        ("LD", [AtI, V(x)]) => 0xF055 | x << 8,
+        ("LD", [V(x), AtI]) => 0xF065 | x << 8,
+        ("LD", [R, V(x)]) => 0xF075 | x << 8,
+        ("LD", [V(x), R]) => 0xF085 | x << 8,
+        ("LD", [AtI, Range(x, y)]) => 0x5002 | xy(*x, *y),
+        ("LD", [Range(x, y), AtI]) => 0x5003 | xy(*x, *y),
+        ("PLANE", [Value(n)]) => 0xF001 | value(n, 0xF)? << 8,
+        ("LD", [Audio, AtI]) => 0xF002,
+        ("LD", [Pitch, V(x)]) => 0xF03A | x << 8,
+        (mnemonic, operands) => {
+            return Err(AsmError {
+                line: line.number,
+                message: format!("no {} takes {:?}", mnemonic, operands),
+            });
+        }
+    };
+    Ok(opcode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::cpu::Platform;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn test_every_instruction_assembles_back() {
+        let mut chip = Chip8::new(&[]).unwrap();
+        chip.set_platform(Platform::XoChip);
+        chip.set_debug_port(true);
+        for opcode in 0..=0xFFFF {
+            let Ok(instruction) = chip.decode(opcode) else {
+                continue;
+            };
+            // the decoder ignores some bits, 5xy1 is 5xy0, so the instruction has to match
+            let text = instruction.to_string();
+            let rom = assemble(&text).unwrap_or_else(|error| panic!("{}: {}", text, error));
+            let assembled = u16::from_be_bytes([rom[0], rom[1]]);
+            assert_eq!(rom.len(), 2);
+            assert_eq!(chip.decode(assembled), Ok(instruction), "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_labels_and_data() {
+        let source = "
+            ; draws a box and stops
+            start:  LD I, box   # forward
+                    ld v0, 0x0A
+                    DRW V0, V0, 4
+            : stop
+                    JP stop
+            box:    db %11110000, 90, 90, F0
+                    dw start, 0xBEEF
+                    LD I, LONG box
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                0xA2, 0x08, 0x60, 0x0A, 0xD0, 0x04, 0x12, 0x06, 0xF0, 0x90, 0x90, 0xF0, 0x02, 0x00,
+                0xBE, 0xEF, 0xF0, 0x00, 0x02, 0x08,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| assemble(source).unwrap_err();
+        assert_eq!(error("CLS\nJP nowhere").line, 2);
+        assert_eq!(error("a:\na:").message, "`a` is defined twice");
+        assert_eq!(error("LD V0, 100").message, "`100` is more than FF");
+        assert_eq!(error("V1: CLS").message, "`V1` can't be a label");
+        assert!(error("MOV V0, V1").message.starts_with("no MOV"));
+        assert!(error("db V0").message.starts_with("db takes"));
+        assert_eq!(
+            error("JP 1000").to_string(),
+            "line 1: `1000` is more than FFF"
+        );
+    }
+}
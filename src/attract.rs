@@ -0,0 +1,131 @@
+use crate::golden;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Attract mode, for booths and museums: the ROMs of a directory take turns playing by
+// themselves for a while each, and touching a key hands the one on screen to the player,
+// from its start. Once nobody has pressed anything for a while the demos go on with the
+// next ROM. A ROM plays its input script (game.txt next to game.ch8, like --input-script)
+// when it has one, or sits on its title screen without.
+
+pub struct Demo {
+    pub rom: PathBuf,
+    pub script: Option<PathBuf>,
+}
+
+// Every .ch8 in the directory in name order, with the .txt of the same name as its script
+pub fn demos(dir: &Path) -> io::Result<Vec<Demo>> {
+    Ok(golden::corpus(dir)?
+        .into_iter()
+        .map(|rom| {
+            let script = rom.with_extension("txt");
+            Demo {
+                script: script.is_file().then_some(script),
+                rom,
+            }
+        })
+        .collect())
+}
+
+// What a frame changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    // load the demo at this index, its turn came or the player walked away
+    Demo(usize),
+    // a key was pressed, the current ROM starts over for the player
+    Play,
+}
+
+// Counts frames and decides when the demos move on. Knows nothing of ROMs, only indexes
+pub struct Attract {
+    count: usize,
+    current: usize,
+    // frames per demo, and without input before play goes back to the demos (0 never)
+    turn: u64,
+    idle_limit: u64,
+    playing: bool,
+    // frames into the demo, or without input while playing
+    frames: u64,
+}
+
+impl Attract {
+    pub fn new(count: usize, turn: u64, idle_limit: u64) -> Self {
+        Attract {
+            count,
+            current: 0,
+            turn: turn.max(1),
+            idle_limit,
+            playing: false,
+            frames: 0,
+        }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    // After every frame, with whether any key was down in it
+    pub fn frame(&mut self, input: bool) -> Option<Change> {
+        if input {
+            self.frames = 0;
+            if !self.playing {
+                self.playing = true;
+                return Some(Change::Play);
+            }
+            return None;
+        }
+        self.frames += 1;
+        let limit = if self.playing {
+            self.idle_limit
+        } else {
+            self.turn
+        };
+        if limit == 0 || self.frames < limit {
+            return None;
+        }
+        Some(self.skip())
+    }
+
+    // Straight to the next demo, for one that crashed
+    pub fn skip(&mut self) -> Change {
+        self.current = (self.current + 1) % self.count.max(1);
+        self.playing = false;
+        self.frames = 0;
+        Change::Demo(self.current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turns_and_takeover() {
+        let mut attract = Attract::new(3, 10, 20);
+        assert!((0..9).all(|_| attract.frame(false).is_none()));
+        assert_eq!(attract.frame(false), Some(Change::Demo(1)));
+
+        // a key takes over, keys held while playing keep it going
+        assert_eq!(attract.frame(true), Some(Change::Play));
+        assert!(attract.is_playing());
+        assert!((0..19).all(|_| attract.frame(false).is_none()));
+        assert_eq!(attract.frame(true), None);
+        assert!((0..19).all(|_| attract.frame(false).is_none()));
+        // walking away goes on with the next demo, and the last wraps around
+        assert_eq!(attract.frame(false), Some(Change::Demo(2)));
+        assert!(!attract.is_playing());
+        assert_eq!(attract.skip(), Change::Demo(0));
+    }
+
+    #[test]
+    fn test_play_without_idle_limit() {
+        let mut attract = Attract::new(2, 5, 0);
+        assert_eq!(attract.frame(true), Some(Change::Play));
+        assert!((0..1000).all(|_| attract.frame(false).is_none()));
+        assert_eq!(attract.current(), 0);
+    }
+}
@@ -1,51 +1,441 @@
+use crate::chip8::{Chip8, Pitch, Timbre};
+use crate::sound::AudioBackend;
 use rodio::source::{SineWave, Source};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
 pub struct Audio {
-    sink: rodio::Sink,
-    _stream: rodio::OutputStream,
+    // None when running without a sound device, beeping is still tracked
+    sink: Option<rodio::Sink>,
+    _stream: Option<rodio::OutputStream>,
     pub beeping: AtomicBool,
+    volume: f32,
+    tone: f32,
+    pitch: Pitch,
+    steps_per_octave: f32,
+    timbre: Timbre,
+    // the 60 Hz hum of the power supply on the VIP buzzer
+    ripple: bool,
+    // what the beep plays, the tone unless the pitch follows the game
+    note: f32,
+    // XO-CHIP's pattern and pitch, played instead of the beep when the game loaded one
+    pattern: Option<([u8; 16], u8)>,
 }
 
+const VOLUME: f32 = 0.2;
+const TONE: f32 = 440.0;
+// a value of 1 is a semitone
+const STEPS_PER_OCTAVE: f32 = 12.0;
+// notes stay where people hear them
+const LOWEST: f32 = 20.0;
+const HIGHEST: f32 = 16_000.0;
+
+// The VIP buzzer is a square wave from an RC oscillator at about 1400 Hz, high a bit less
+// than half of each cycle, and its pitch wanders a little as it warms up
+pub const VIP_TONE: f32 = 1400.0;
+const VIP_DUTY: f32 = 0.45;
+const VIP_DRIFT: f32 = 0.005;
+// a square is much louder than a sine of the same amplitude
+const VIP_LEVEL: f32 = 0.5;
+// with ripple the mains hum takes this much off the loudness and bends the pitch this much
+const HUM: f32 = 60.0;
+const HUM_DEPTH: f32 = 0.15;
+const HUM_BEND: f32 = 0.01;
+const SAMPLE_RATE: u32 = 44_100;
+// XO-CHIP plays its pattern at 4000 bits a second at pitch 64, an octave up every 48
+const PATTERN_RATE: f32 = 4000.0;
+const PATTERN_PITCH: f32 = 64.0;
+const PATTERN_STEPS: f32 = 48.0;
+
 impl Audio {
-    pub fn new() -> Self {
+    // Fails without a sound device, Audio::silent plays the same game without one
+    pub fn new() -> Result<Self, String> {
         let (_stream, stream_handle) =
-            rodio::OutputStream::try_default().expect("Failed to open audio stream");
-        let sink = rodio::Sink::try_new(&stream_handle).expect("Failed to create sink");
+            rodio::OutputStream::try_default().map_err(|e| e.to_string())?;
+        let sink = rodio::Sink::try_new(&stream_handle).map_err(|e| e.to_string())?;
+        sink.pause();
+
+        Ok(Audio {
+            sink: Some(sink),
+            _stream: Some(_stream),
+            beeping: AtomicBool::new(false),
+            volume: VOLUME,
+            tone: TONE,
+            pitch: Pitch::Fixed,
+            steps_per_octave: STEPS_PER_OCTAVE,
+            timbre: Timbre::Sine,
+            ripple: false,
+            note: TONE,
+            pattern: None,
+        })
+    }
+
+    // Beeper on a stream opened by the caller, so several machines share one device
+    // stream instead of each opening its own. The stream must outlive the Audio
+    pub fn with_stream(stream_handle: &rodio::OutputStreamHandle) -> Result<Self, String> {
+        let sink = rodio::Sink::try_new(stream_handle).map_err(|e| e.to_string())?;
         sink.pause();
 
+        Ok(Audio {
+            sink: Some(sink),
+            _stream: None,
+            beeping: AtomicBool::new(false),
+            volume: VOLUME,
+            tone: TONE,
+            pitch: Pitch::Fixed,
+            steps_per_octave: STEPS_PER_OCTAVE,
+            timbre: Timbre::Sine,
+            ripple: false,
+            note: TONE,
+            pattern: None,
+        })
+    }
+
+    // Audio that never touches the sound device
+    pub fn silent() -> Self {
         Audio {
-            sink,
-            _stream,
+            sink: None,
+            _stream: None,
             beeping: AtomicBool::new(false),
+            volume: VOLUME,
+            tone: TONE,
+            pitch: Pitch::Fixed,
+            steps_per_octave: STEPS_PER_OCTAVE,
+            timbre: Timbre::Sine,
+            ripple: false,
+            note: TONE,
+            pattern: None,
+        }
+    }
+
+    // 0.0 to 1.0, the next beep uses it
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    // Frequency of the beep in Hz, the lowest note in musical mode
+    pub fn set_tone(&mut self, tone: f32) {
+        self.tone = tone.max(1.0);
+    }
+
+    // Musical mode: the beep plays frequency(value) of what the pitch follows, so simple
+    // music ROMs play melodies. Pitch::Fixed goes back to the single tone
+    pub fn set_pitch(&mut self, pitch: Pitch, steps_per_octave: f32) {
+        self.pitch = pitch;
+        self.steps_per_octave = steps_per_octave.max(1.0);
+    }
+
+    // The clean sine or the VIP buzzer. Vip plays VIP_TONE instead of the tone when the
+    // pitch is fixed, ripple adds the hum of the VIP's power supply to it
+    pub fn set_timbre(&mut self, timbre: Timbre, ripple: bool) {
+        self.timbre = timbre;
+        self.ripple = ripple;
+        if self.pitch == Pitch::Fixed {
+            self.note = self.base_tone();
+        }
+    }
+
+    fn base_tone(&self) -> f32 {
+        match self.timbre {
+            Timbre::Sine => self.tone,
+            Timbre::Vip => VIP_TONE,
         }
     }
 
+    // The curve of musical mode, tone * 2^(value / steps_per_octave). With 12 steps every
+    // value is a semitone up from the tone
+    pub fn frequency(&self, value: u8) -> f32 {
+        let octaves = value as f32 / self.steps_per_octave;
+        (self.tone * octaves.exp2()).clamp(LOWEST, HIGHEST)
+    }
+
     pub fn start_beep(&self) {
-        if self.sink.empty() {
+        let Some(sink) = &self.sink else {
+            self.beeping.store(true, Ordering::Relaxed);
+            return;
+        };
+
+        if sink.empty() {
             self.beeping.store(false, Ordering::Relaxed);
         }
 
         if !self.beeping.load(Ordering::Relaxed) {
             self.beeping.store(true, Ordering::Relaxed);
-            let source = SineWave::new(440.0)
-                .take_duration(Duration::from_secs(1))
-                .amplify(0.2);
-            self.sink.append(source);
-            self.sink.play();
+            if let Some((bits, pitch)) = self.pattern {
+                sink.append(
+                    Pattern::new(bits, pattern_rate(pitch))
+                        .take_duration(Duration::from_secs(1))
+                        .amplify(self.volume * VIP_LEVEL),
+                );
+                sink.play();
+                return;
+            }
+            match self.timbre {
+                Timbre::Sine => sink.append(
+                    SineWave::new(self.note)
+                        .take_duration(Duration::from_secs(1))
+                        .amplify(self.volume),
+                ),
+                Timbre::Vip => sink.append(
+                    VipBuzzer::new(self.note, self.ripple)
+                        .take_duration(Duration::from_secs(1))
+                        .amplify(self.volume * VIP_LEVEL),
+                ),
+            }
+            sink.play();
         }
     }
 
     pub fn stop_beep(&self) {
-        self.sink.pause();
-        self.sink.clear();
+        if let Some(sink) = &self.sink {
+            sink.pause();
+            sink.clear();
+        }
         self.beeping.store(false, Ordering::Relaxed);
     }
+
+    // update with the pitch of the game, for frontends that support musical mode
+    pub fn update_from(&mut self, chip: &Chip8) {
+        let note = match chip.pitch_value(self.pitch) {
+            Some(value) => self.frequency(value),
+            None => self.base_tone(),
+        };
+        let pattern = chip.audio_pattern().map(|bits| (*bits, chip.audio_pitch()));
+        if note != self.note || pattern != self.pattern {
+            (self.note, self.pattern) = (note, pattern);
+            // a new note right after the last one, without a gap
+            if self.beeping.load(Ordering::Relaxed) {
+                self.stop_beep();
+            }
+        }
+        self.update(chip.is_sound_on());
+    }
+
+    // Follows the sound timer (Chip8::is_sound_on), called once per frame by the frontend
+    pub fn update(&self, sound_on: bool) {
+        profile_scope!("audio");
+        if sound_on {
+            self.start_beep();
+        } else if self.beeping.load(Ordering::Relaxed) {
+            self.stop_beep();
+        }
+    }
+}
+
+impl AudioBackend for Audio {
+    fn start_beep(&mut self) {
+        Audio::start_beep(self);
+    }
+
+    fn stop_beep(&mut self) {
+        Audio::stop_beep(self);
+    }
+
+    fn update_from(&mut self, chip: &Chip8) {
+        Audio::update_from(self, chip);
+    }
+}
+
+// Bits a second XO-CHIP plays its pattern at, for the pitch Fx3A set
+pub fn pattern_rate(pitch: u8) -> f32 {
+    PATTERN_RATE * ((pitch as f32 - PATTERN_PITCH) / PATTERN_STEPS).exp2()
+}
+
+// XO-CHIP's 128 bit pattern over and over, 1.0 for a set bit and -1.0 for a clear one
+struct Pattern {
+    bits: [u8; 16],
+    // bits per sample
+    step: f32,
+    // where in the pattern, 0.0 to 128.0
+    position: f32,
+}
+
+impl Pattern {
+    fn new(bits: [u8; 16], rate: f32) -> Self {
+        Pattern {
+            bits,
+            step: rate / SAMPLE_RATE as f32,
+            position: 0.0,
+        }
+    }
 }
 
+impl Iterator for Pattern {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let bit = self.position as usize;
+        let set = self.bits[bit / 8] & (0x80 >> (bit % 8)) != 0;
+        self.position = (self.position + self.step) % 128.0;
+        Some(if set { 1.0 } else { -1.0 })
+    }
+}
+
+impl Source for Pattern {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// Samples of the VIP buzzer, -1.0 or 1.0 before the hum
+struct VipBuzzer {
+    frequency: f32,
+    ripple: bool,
+    // where in a cycle of the square and of the hum, 0.0 to 1.0
+    phase: f32,
+    hum: f32,
+    // how far the pitch has wandered, -VIP_DRIFT to VIP_DRIFT
+    drift: f32,
+    // xorshift, the drift doesn't need more
+    seed: u32,
+}
+
+impl VipBuzzer {
+    fn new(frequency: f32, ripple: bool) -> Self {
+        VipBuzzer {
+            frequency,
+            ripple,
+            phase: 0.0,
+            hum: 0.0,
+            drift: 0.0,
+            seed: 0x2545_F491,
+        }
+    }
+
+    // -1.0 to 1.0
+    fn noise(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+impl Iterator for VipBuzzer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let mut sample = if self.phase < VIP_DUTY { 1.0 } else { -1.0 };
+        self.drift = (self.drift + self.noise() * VIP_DRIFT / 100.0).clamp(-VIP_DRIFT, VIP_DRIFT);
+        let mut frequency = self.frequency * (1.0 + self.drift);
+        if self.ripple {
+            let hum = (self.hum * core::f32::consts::TAU).sin();
+            sample *= 1.0 - HUM_DEPTH * (0.5 + 0.5 * hum);
+            frequency *= 1.0 + HUM_BEND * hum;
+            self.hum = (self.hum + HUM / SAMPLE_RATE as f32).fract();
+        }
+        self.phase = (self.phase + frequency / SAMPLE_RATE as f32).fract();
+        Some(sample)
+    }
+}
+
+impl Source for VipBuzzer {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// The sound device when there is one, silence otherwise
 impl Default for Audio {
     fn default() -> Self {
-        Self::new()
+        Self::new().unwrap_or_else(|_| Self::silent())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pitch() {
+        let mut audio = Audio::silent();
+        audio.set_tone(220.0);
+        audio.set_pitch(Pitch::SoundTimer, 12.0);
+        assert_eq!(audio.frequency(0), 220.0);
+        assert!((audio.frequency(12) - 440.0).abs() < 0.01);
+        assert!((audio.frequency(7) - 329.63).abs() < 0.01);
+        assert_eq!(audio.frequency(255), HIGHEST);
+
+        let mut chip = Chip8::new(&[]).unwrap();
+        chip.set_st(24);
+        audio.update_from(&chip);
+        assert!((audio.note - 880.0).abs() < 0.01);
+        assert!(audio.beeping.load(Ordering::Relaxed));
+
+        audio.set_pitch(Pitch::Fixed, 12.0);
+        audio.update_from(&chip);
+        assert_eq!(audio.note, 220.0);
+        audio.set_timbre(Timbre::Vip, false);
+        assert_eq!(audio.note, VIP_TONE);
+    }
+
+    #[test]
+    fn test_pattern() {
+        assert_eq!(pattern_rate(64), 4000.0);
+        assert!((pattern_rate(112) - 8000.0).abs() < 0.01);
+
+        // 4 bits on and 4 off at 4000 bits a second is a 500 Hz square
+        let samples: Vec<f32> = Pattern::new([0xF0; 16], 4000.0)
+            .take(SAMPLE_RATE as usize)
+            .collect();
+        let rising = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] > 0.0)
+            .count();
+        assert!((499..=501).contains(&rising), "{}", rising);
+
+        let mut audio = Audio::silent();
+        let mut chip = Chip8::new(&[]).unwrap();
+        chip.set_audio_pattern([0xAA; 16]);
+        chip.set_audio_pitch(80);
+        chip.set_st(5);
+        audio.update_from(&chip);
+        assert_eq!(audio.pattern, Some(([0xAA; 16], 80)));
+        assert!(audio.beeping.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_vip_buzzer() {
+        // a second of it: about 1400 cycles, high 45% of the time
+        for ripple in [false, true] {
+            let samples: Vec<f32> = VipBuzzer::new(VIP_TONE, ripple)
+                .take(SAMPLE_RATE as usize)
+                .collect();
+            let rising = samples
+                .windows(2)
+                .filter(|pair| pair[0] < 0.0 && pair[1] > 0.0)
+                .count() as f32;
+            assert!((rising - VIP_TONE).abs() < VIP_TONE * 0.02, "{}", rising);
+            let high = samples.iter().filter(|&&sample| sample > 0.0).count() as f32;
+            assert!((high / SAMPLE_RATE as f32 - VIP_DUTY).abs() < 0.01);
+            let quietest = samples
+                .iter()
+                .fold(1.0f32, |low, sample| low.min(sample.abs()));
+            assert_eq!(quietest < 1.0, ripple);
+        }
     }
 }
@@ -0,0 +1,118 @@
+// Everything about a crash in one folder, to look at later or attach to a bug report:
+//     screen.png  what was on the screen
+//     state.bin   the machine as save_state has it, PC on the instruction that failed
+//     trace.txt   the error, the registers and the last instructions from Chip8::history
+// `chip8 --capture` writes one to the captures data directory when the game crashes.
+use crate::chip8::{Chip8, Chip8Error};
+use crate::display::Palette;
+use crate::frames::{self, DumpError};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// instructions kept for trace.txt when nothing else is asked for
+pub const DEFAULT_LINES: usize = 64;
+
+// Writes the bundle to a new <name>-<date>-<time> folder in `dir` and returns it
+pub fn capture(
+    dir: &Path,
+    name: &str,
+    chip: &Chip8,
+    error: &Chip8Error,
+    palette: Palette,
+    scale: usize,
+) -> Result<PathBuf, DumpError> {
+    let dir = dir.join(format!("{}-{}", name, timestamp(SystemTime::now())));
+    std::fs::create_dir_all(&dir)?;
+    frames::write_png(&dir.join("screen.png"), &chip.display, palette, scale)?;
+    std::fs::write(dir.join("state.bin"), chip.save_state())?;
+    std::fs::write(dir.join("trace.txt"), trace(chip, error))?;
+    Ok(dir)
+}
+
+fn trace(chip: &Chip8, error: &Chip8Error) -> String {
+    let registers: Vec<String> = chip
+        .registers()
+        .iter()
+        .enumerate()
+        .map(|(index, value)| format!("V{:X}={:02X}", index, value))
+        .collect();
+    let mut text = format!(
+        "{:?} at {:03X}\n\n{}",
+        error,
+        chip.get_pc(),
+        registers.join(" ")
+    );
+    let _ = writeln!(
+        text,
+        "\nI={:03X} DT={:02X} ST={:02X} stack={:03X?}\n",
+        chip.get_i(),
+        chip.get_dt(),
+        chip.get_st(),
+        chip.stack()
+    );
+    for (pc, opcode) in chip.history() {
+        let _ = writeln!(text, "{:03X}  {:04X}", pc, opcode);
+    }
+    text
+}
+
+// UTC as 20261015-142301, sorts by time and is safe in a file name everywhere
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = (seconds / 86400, seconds % 86400);
+    // days since 1970 to a date, from Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101-000000");
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(timestamp(time), "20240229-123456");
+    }
+
+    #[test]
+    fn test_capture() {
+        let dir = std::env::temp_dir().join(format!("chip8-capture-{}", std::process::id()));
+        // 6005 then a return with nothing to return to
+        let mut chip = Chip8::new(&[0x60, 0x05, 0x00, 0xEE]).unwrap();
+        chip.set_history(DEFAULT_LINES);
+        chip.step().unwrap();
+        let error = chip.step().unwrap_err();
+
+        let bundle = capture(&dir, "crash", &chip, &error, Palette::default(), 1).unwrap();
+        assert!(bundle.starts_with(&dir));
+        assert!(bundle.join("screen.png").exists());
+        let state = std::fs::read(bundle.join("state.bin")).unwrap();
+        assert!(Chip8::new(&[]).unwrap().load_state(&state).is_ok());
+        let trace = std::fs::read_to_string(bundle.join("trace.txt")).unwrap();
+        assert!(trace.starts_with("StackUnderflow at 202\n"));
+        assert!(trace.contains("V0=05 V1=00"));
+        assert!(trace.ends_with("200  6005\n202  00EE\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
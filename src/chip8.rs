@@ -1,24 +1,97 @@
-use super::{audio::Audio, display::Display, keyboard::Keyboard};
-use std::sync::atomic::Ordering;
+#[cfg(feature = "alloc")]
+use super::cpu::DecodeCache;
+use super::cpu::{Platform, Quirks};
+use super::display::Display;
+#[cfg(feature = "image")]
+use super::display::Palette;
+use super::keyboard::{KEYBOARD_STATE_SIZE, Keyboard};
+use super::memory::{
+    FONT_SIZE, FONT_START, LARGE_FONT_SIZE, LARGE_FONT_START, MemoryMap, PROGRAM_START, Patch,
+    RAM_SIZE, Region,
+};
+#[cfg(feature = "alloc")]
+use super::rewind::Rewind;
+#[cfg(feature = "alloc")]
+use super::trace::TraceEntry;
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use alloc::collections::VecDeque;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Chip8Error {
-    PCOutOfBounds,
-    IOutOfBounds,
-    InvalidMemoryAccess,
-    StackOverflow,
-    StackUnderflow,
+    // Errors a ROM can cause by itself while running
+    PCOutOfBounds,       // jump/skip/return past the end of ram
+    IOutOfBounds,        // Fx1E pushed I past the end of ram
+    InvalidMemoryAccess, // read/write past the end of ram (fetch, Dxyn, Fx33, Fx55, Fx65)
+    StackOverflow,       // more than 16 nested calls
+    StackUnderflow,      // return without call
+    UnknownInstruction,  // opcode outside the instruction set
+    InvalidKey,          // Ex9E/ExA1 with Vx > 0xF
+
+    // Loading errors
+    RomTooLarge,
+    InvalidState, // save state of another size or version, or with impossible values
+
+    // Only reachable through the public API with bad arguments, a ROM can't cause these.
+    // If one of them shows up while running a ROM, it's a bug in the emulator
     InvalidRegisterAccess,
     InvalidKeyAccess,
-    RomTooLarge,
-    UnknownInstruction,
     InvalidPixelAccess,
     InvalidPixelValue,
-    InvalidKey,
+    InvalidDigit, // font_address of a digit above F
+}
+
+impl Chip8Error {
+    // True for the errors a running ROM is allowed to produce, used by the fuzz targets
+    pub fn is_rom_error(&self) -> bool {
+        !matches!(
+            self,
+            Chip8Error::InvalidRegisterAccess
+                | Chip8Error::InvalidKeyAccess
+                | Chip8Error::InvalidPixelAccess
+                | Chip8Error::InvalidPixelValue
+                | Chip8Error::InvalidDigit
+        )
+    }
 }
 
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            Chip8Error::PCOutOfBounds => "PC went past the end of RAM",
+            Chip8Error::IOutOfBounds => "I went past the end of RAM",
+            Chip8Error::InvalidMemoryAccess => "access past the end of RAM",
+            Chip8Error::StackOverflow => "more than 16 nested calls",
+            Chip8Error::StackUnderflow => "return without a call",
+            Chip8Error::UnknownInstruction => "unknown instruction",
+            Chip8Error::InvalidKey => "key above F",
+            Chip8Error::RomTooLarge => {
+                "the ROM doesn't fit in RAM from 0x200 (3584 bytes at most, 65024 on XO-CHIP)"
+            }
+            Chip8Error::InvalidState => "not a save state of this version",
+            Chip8Error::InvalidRegisterAccess => "register above VF",
+            Chip8Error::InvalidKeyAccess => "key index above F",
+            Chip8Error::InvalidPixelAccess => "pixel outside the screen",
+            Chip8Error::InvalidPixelValue => "pixel value other than 0 or 1",
+            Chip8Error::InvalidDigit => "no font glyph above F",
+        };
+        f.write_str(message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Chip8Error {}
+
 // In the original chip8, fontset was native from hardware
-const FONTSET: [u8; 80] = [
+const FONTSET: [u8; FONT_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -37,6 +110,29 @@ const FONTSET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// SUPER-CHIP's 8x10 digits for Fx30. It only had 0 to 9, A to F are Octo's
+const LARGE_FONTSET: [u8; LARGE_FONT_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// No window or sound device in here, frontends read the display and the sound timer
+// and feed the keyboard, so the same machine runs on a PC, in a browser or on a microcontroller
+#[derive(Clone)]
 pub struct Chip8 {
     // Program Counter, points to the next instruction in ram
     pc: u16,
@@ -50,7 +146,7 @@ pub struct Chip8 {
 
     // ram memory and pointer (I doesn't point to instructions, only for normal memory)
     i: u16,
-    ram: [u8; 4096],
+    ram: Ram,
 
     // display virtual hardware
     pub display: Display,
@@ -58,53 +154,317 @@ pub struct Chip8 {
     // keyboard virtual hardware
     pub keyboard: Keyboard,
 
-    // audio virtual hardware
-    pub audio: Audio,
-
     // delay timer
     dt: u8,
 
     // sound timer
     st: u8,
 
+    // last value written to the sound timer, for the musical mode of audio.rs. Not part of
+    // save states, it only picks a pitch
+    sound_value: u8,
+
     // draw flag to avoid unnecessary rendering on screen
     pub draw_flag: bool,
+
+    // instructions and Dxyn draws run so far, for stats. Not part of save states, a state
+    // loaded keeps the counts the machine had
+    pub(crate) instructions: u64,
+    pub(crate) draws: u64,
+
+    // random generator for Cxnn, owned by the machine so it can be seeded
+    rng: ChaCha8Rng,
+
+    // how the ambiguous instructions behave, a setting rather than state so save states skip it
+    pub quirks: Quirks,
+
+    // the instruction set, a setting like the quirks, see set_platform
+    platform: Platform,
+
+    // what SUPER-CHIP's Fx75 saved, the HP-48 kept them in its RPL user flags
+    flags: [u8; 16],
+
+    // XO-CHIP's sound: the 128 bits F002 loaded, played while ST runs, at the rate of the
+    // pitch Fx3A set. None until a program loads one, the beep plays until then
+    audio_pattern: Option<[u8; 16]>,
+    audio_pitch: u8,
+
+    // off unless set_decode_cache turned it on, see cpu.rs
+    #[cfg(feature = "alloc")]
+    pub(crate) decode_cache: Option<DecodeCache>,
+
+    // see set_timer_callback
+    #[cfg(feature = "alloc")]
+    timer_callback: TimerCallback,
+
+    // see set_history
+    #[cfg(feature = "alloc")]
+    history: Option<History>,
+
+    // see set_trace
+    #[cfg(feature = "alloc")]
+    trace: TraceCallback,
+
+    // what 00FA/FxFA printed since the last take_debug_output, None while the port is off
+    #[cfg(feature = "alloc")]
+    debug_output: Option<Vec<DebugOutput>>,
+
+    // see set_rewind
+    #[cfg(feature = "alloc")]
+    rewind: RewindBuffer,
+}
+
+// 4 KiB, or 64 KiB on XO-CHIP. Without alloc it's always 4 KiB and XO-CHIP programs only
+// get that much
+#[cfg(feature = "alloc")]
+type Ram = Box<[u8]>;
+#[cfg(not(feature = "alloc"))]
+type Ram = [u8; RAM_SIZE];
+
+#[cfg(feature = "alloc")]
+fn new_ram(size: usize) -> Ram {
+    alloc::vec![0; size].into_boxed_slice()
+}
+
+#[cfg(not(feature = "alloc"))]
+fn new_ram(_size: usize) -> Ram {
+    [0; RAM_SIZE]
+}
+
+// where XO-CHIP's pitch plays the pattern at its usual rate, 4000 bits a second
+pub const DEFAULT_AUDIO_PITCH: u8 = 64;
+
+// What a ROM printed through the debug port, see set_debug_port
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugOutput {
+    // 00FA, the string at I
+    Text { pc: u16, text: String },
+    // FxFA
+    Value { pc: u16, register: usize, value: u8 },
+}
+
+#[cfg(feature = "alloc")]
+impl fmt::Display for DebugOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DebugOutput::Text { pc, text } => write!(f, "{:03X}: {}", pc, text),
+            DebugOutput::Value {
+                pc,
+                register,
+                value,
+            } => write!(f, "{:03X}: V{:X} = {:02X} ({})", pc, register, value, value),
+        }
+    }
+}
+
+// Longest 00FA string, and the most outputs kept when nobody takes them
+#[cfg(feature = "alloc")]
+const DEBUG_TEXT_LEN: usize = 64;
+#[cfg(feature = "alloc")]
+const DEBUG_OUTPUT_LEN: usize = 256;
+
+// The last instructions the CPU ran, oldest first, as (PC, opcode)
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+struct History {
+    entries: VecDeque<(u16, u16)>,
+    len: usize,
+}
+
+// A timer crossing zero, what set_timer_callback reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerEvent {
+    DelayExpired,
+    SoundStarted,
+    SoundStopped,
+}
+
+// Copies of the machine (rewind, lockstep checks, load_state) don't take the callback
+// along, a snapshot running on its own shouldn't beep
+#[cfg(feature = "alloc")]
+#[derive(Default)]
+struct TimerCallback(Option<Box<dyn FnMut(TimerEvent) + Send>>);
+
+#[cfg(feature = "alloc")]
+impl Clone for TimerCallback {
+    fn clone(&self) -> Self {
+        TimerCallback(None)
+    }
+}
+
+// Nor the trace, a copy stepping on its own would write into the same log
+#[cfg(feature = "alloc")]
+#[derive(Default)]
+struct TraceCallback(Option<Box<TraceFn>>);
+
+#[cfg(feature = "alloc")]
+type TraceFn = dyn FnMut(&TraceEntry) + Send;
+
+#[cfg(feature = "alloc")]
+impl Clone for TraceCallback {
+    fn clone(&self) -> Self {
+        TraceCallback(None)
+    }
+}
+
+// Nor the rewind buffer, it's the past of the machine being played and can be large
+#[cfg(feature = "alloc")]
+#[derive(Default)]
+struct RewindBuffer(Option<Box<Rewind>>);
+
+#[cfg(feature = "alloc")]
+impl Clone for RewindBuffer {
+    fn clone(&self) -> Self {
+        RewindBuffer(None)
+    }
 }
 
 // the chip8 impl only worry about safe state transition of its attributes, the logic beyond the changes isn't resposability of this impl
 impl Chip8 {
     pub fn new(rom: &[u8]) -> Result<Self, Chip8Error> {
-        if rom.len() > 4096 - 0x200 {
-            return Err(Chip8Error::RomTooLarge);
-        }
-
-        let display = Display::new();
-        let keyboard = Keyboard::new();
-        let audio = Audio::new();
         let mut chip = Chip8 {
-            pc: 0x200,
+            pc: PROGRAM_START as u16,
             v: [0; 16],
             sp: 0,
             stack: [0; 16],
             i: 0,
-            ram: [0; 4096],
-            display,
-            keyboard,
-            audio,
+            ram: new_ram(RAM_SIZE),
+            display: Display::new(),
+            keyboard: Keyboard::new(),
             dt: 0,
             st: 0,
+            sound_value: 0,
             draw_flag: false,
+            instructions: 0,
+            draws: 0,
+            rng: default_rng(),
+            quirks: Quirks::default(),
+            platform: Platform::default(),
+            flags: [0; 16],
+            audio_pattern: None,
+            audio_pitch: DEFAULT_AUDIO_PITCH,
+            #[cfg(feature = "alloc")]
+            decode_cache: None,
+            #[cfg(feature = "alloc")]
+            timer_callback: TimerCallback::default(),
+            #[cfg(feature = "alloc")]
+            trace: TraceCallback::default(),
+            #[cfg(feature = "alloc")]
+            history: None,
+            #[cfg(feature = "alloc")]
+            debug_output: None,
+            #[cfg(feature = "alloc")]
+            rewind: RewindBuffer::default(),
         };
 
         // loading fontset on hardware
-        chip.ram[0x50..0x50 + FONTSET.len()].copy_from_slice(&FONTSET);
+        chip.ram[MemoryMap::CHIP8.range(Region::Font)].copy_from_slice(&FONTSET);
+        chip.ram[MemoryMap::CHIP8.range(Region::LargeFont)].copy_from_slice(&LARGE_FONTSET);
 
         // loading rom on hardware ram
-        chip.ram[0x200..0x200 + rom.len()].copy_from_slice(rom);
+        chip.load_rom(rom)?;
 
         Ok(chip)
     }
 
+    // Puts a program in RAM from 0x200 and PC on it. What was there before is cleared, the
+    // font, registers, screen and the rest of the machine stay as they are. On XO-CHIP it
+    // can go on into the extended memory, set the platform first
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let program = PROGRAM_START..self.ram.len();
+        if rom.len() > program.len() {
+            return Err(Chip8Error::RomTooLarge);
+        }
+        self.ram[program.clone()].fill(0);
+        self.ram[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
+        self.invalidate_decoded(program.start, program.len());
+        self.pc = PROGRAM_START as u16;
+        // an Fx0A of the program before isn't this one's
+        self.keyboard.cancel_wait();
+        Ok(())
+    }
+
+    // load_rom with the bytes of a file. A ROM too large is an InvalidData error that says
+    // how large it is
+    #[cfg(feature = "std")]
+    pub fn load_rom_from_file(&mut self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let rom = std::fs::read(path)?;
+        self.load_rom(&rom).map_err(|error| {
+            let message = format!("a ROM of {} bytes, {}", rom.len(), error);
+            std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+        })
+    }
+
+    // Where the glyph of a hex digit (0 to F) is in the font, what Fx29 points I at
+    pub fn font_address(digit: u8) -> Result<u16, Chip8Error> {
+        if digit > 0xF {
+            return Err(Chip8Error::InvalidDigit);
+        }
+        Ok((FONT_START + digit as usize * FONT_SIZE / 16) as u16)
+    }
+
+    // The same in the large font, what Fx30 points I at
+    pub fn large_font_address(digit: u8) -> Result<u16, Chip8Error> {
+        if digit > 0xF {
+            return Err(Chip8Error::InvalidDigit);
+        }
+        Ok((LARGE_FONT_START + digit as usize * LARGE_FONT_SIZE / 16) as u16)
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    // Which instructions decode, the ones of the other platforms are unknown instructions.
+    // XO-CHIP gets 64 KiB of RAM (with alloc) and the others go back to 4 KiB, what's in the
+    // first 4 KiB stays
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        #[cfg(feature = "alloc")]
+        {
+            let size = platform.memory_map().size;
+            if self.ram.len() != size {
+                let mut ram = new_ram(size);
+                let kept = size.min(self.ram.len());
+                ram[..kept].copy_from_slice(&self.ram[..kept]);
+                self.ram = ram;
+            }
+        }
+        // blocks decoded for the other platform would keep it
+        #[cfg(feature = "alloc")]
+        if self.decode_cache.is_some() {
+            self.set_decode_cache(true);
+        }
+    }
+
+    // The SUPER-CHIP flags of Fx75/Fx85, frontends keep them between runs like the HP-48 did
+    pub fn flags(&self) -> &[u8; 16] {
+        &self.flags
+    }
+
+    pub fn set_flags(&mut self, flags: [u8; 16]) {
+        self.flags = flags;
+    }
+
+    // XO-CHIP's sound, see the fields. Frontends play the pattern instead of the beep when
+    // there is one
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn set_audio_pattern(&mut self, pattern: [u8; 16]) {
+        self.audio_pattern = Some(pattern);
+    }
+
+    pub fn audio_pitch(&self) -> u8 {
+        self.audio_pitch
+    }
+
+    pub fn set_audio_pitch(&mut self, pitch: u8) {
+        self.audio_pitch = pitch;
+    }
+
     // Safe stack operations
     pub fn push_stack(&mut self, value: u16) -> Result<bool, Chip8Error> {
         if self.sp >= 16 {
@@ -125,9 +485,26 @@ impl Chip8 {
         }
     }
 
-    // Safe PC operations
+    // The return addresses on the stack, oldest first
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    // Replaces the whole stack, oldest first, at most 16 addresses
+    pub fn set_stack(&mut self, addresses: &[u16]) -> Result<bool, Chip8Error> {
+        if addresses.len() > 16 {
+            return Err(Chip8Error::StackOverflow);
+        }
+        self.stack[..addresses.len()].copy_from_slice(addresses);
+        self.stack[addresses.len()..].fill(0);
+        self.sp = addresses.len() as u8;
+        Ok(true)
+    }
+
+    // Safe PC operations, PC has to point into RAM: up to 4095, or 65535 on XO-CHIP
+    #[inline]
     pub fn set_pc(&mut self, value: u16) -> Result<bool, Chip8Error> {
-        if value > 4094 {
+        if value as usize >= self.ram.len() {
             Err(Chip8Error::PCOutOfBounds)
         } else {
             self.pc = value;
@@ -135,11 +512,14 @@ impl Chip8 {
         }
     }
 
+    #[inline]
     pub fn increment_pc(&mut self) -> Result<(), Chip8Error> {
-        self.set_pc(*self.get_pc() + 2)?; // PC += 2
+        let pc = self.pc.checked_add(2).ok_or(Chip8Error::PCOutOfBounds)?;
+        self.set_pc(pc)?; // PC += 2
         Ok(())
     }
 
+    #[inline]
     pub fn get_pc(&self) -> &u16 {
         &self.pc
     }
@@ -147,20 +527,21 @@ impl Chip8 {
     // Safe I operations
     pub fn set_i(&mut self, value: u16) -> Result<bool, Chip8Error> {
         self.i = value;
-        if self.i >= 4096 {
+        if self.i as usize >= self.ram.len() {
             Err(Chip8Error::IOutOfBounds)
         } else {
             Ok(true)
         }
     }
 
+    #[inline]
     pub fn get_i(&self) -> &u16 {
         &self.i
     }
 
     // Safe ram usage
     pub fn get_ram(&self, index: u16) -> Result<u8, Chip8Error> {
-        if index >= 4096 {
+        if index as usize >= self.ram.len() {
             Err(Chip8Error::InvalidMemoryAccess)
         } else {
             Ok(self.ram[index as usize])
@@ -168,15 +549,271 @@ impl Chip8 {
     }
 
     pub fn set_ram(&mut self, index: u16, value: u8) -> Result<bool, Chip8Error> {
-        if index >= 4096 {
+        if index as usize >= self.ram.len() {
             Err(Chip8Error::InvalidMemoryAccess)
         } else {
             self.ram[index as usize] = value;
+            self.invalidate_decoded(index as usize, 1);
             Ok(true)
         }
     }
 
+    // All of RAM as it is, the font and the program included, for a file other tools read.
+    // 4 KiB, 64 KiB on XO-CHIP
+    pub fn ram_image(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // Replaces all of RAM with an image of exactly its size. PC, I and everything else
+    // stay, callers point them into the new code themselves
+    pub fn load_ram_image(&mut self, image: &[u8]) -> Result<bool, Chip8Error> {
+        if image.len() != self.ram.len() {
+            return Err(Chip8Error::InvalidState);
+        }
+        self.ram.copy_from_slice(image);
+        self.invalidate_decoded(0, image.len());
+        Ok(true)
+    }
+
+    // Byte patches over the loaded ROM, in order
+    pub fn apply_patches(&mut self, patches: &[Patch]) -> Result<bool, Chip8Error> {
+        for patch in patches {
+            self.set_ram(patch.address, patch.value)?;
+        }
+        Ok(true)
+    }
+
+    // Crate-only fast paths for the CPU hot loop, a whole range is checked once
+    // instead of every byte going through get_ram/set_ram
+    #[inline]
+    pub(crate) fn ram_range(&self, start: u16, len: usize) -> Result<&[u8], Chip8Error> {
+        let start = start as usize;
+        self.ram
+            .get(start..start + len)
+            .ok_or(Chip8Error::InvalidMemoryAccess)
+    }
+
+    #[inline]
+    pub(crate) fn ram_range_mut(
+        &mut self,
+        start: u16,
+        len: usize,
+    ) -> Result<&mut [u8], Chip8Error> {
+        let start = start as usize;
+        self.invalidate_decoded(start, len);
+        self.ram
+            .get_mut(start..start + len)
+            .ok_or(Chip8Error::InvalidMemoryAccess)
+    }
+
+    // The layout of RAM, see memory.rs
+    pub fn memory_map(&self) -> MemoryMap {
+        MemoryMap {
+            size: self.ram.len(),
+        }
+    }
+
+    // A whole region, e.g. the font or the loaded program, for debugger views and tools
+    pub fn region(&self, region: Region) -> &[u8] {
+        &self.ram[self.memory_map().range(region)]
+    }
+
+    // `len` bytes at `offset` into a region, an error if they'd run out of it
+    pub fn region_range(
+        &self,
+        region: Region,
+        offset: usize,
+        len: usize,
+    ) -> Result<&[u8], Chip8Error> {
+        let range = self
+            .memory_map()
+            .checked_range(region, offset, len)
+            .ok_or(Chip8Error::InvalidMemoryAccess)?;
+        Ok(&self.ram[range])
+    }
+
+    pub fn region_range_mut(
+        &mut self,
+        region: Region,
+        offset: usize,
+        len: usize,
+    ) -> Result<&mut [u8], Chip8Error> {
+        let range = self
+            .memory_map()
+            .checked_range(region, offset, len)
+            .ok_or(Chip8Error::InvalidMemoryAccess)?;
+        self.invalidate_decoded(range.start, range.len());
+        Ok(&mut self.ram[range])
+    }
+
+    // Decoding once per address instead of every step, worth it for turbo and benchmark
+    // runs. Costs a decoded instruction per byte of RAM, off by default
+    #[cfg(feature = "alloc")]
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled.then(|| DecodeCache::new(self.ram.len()));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[inline]
+    fn invalidate_decoded(&mut self, start: usize, len: usize) {
+        if let Some(cache) = &mut self.decode_cache {
+            cache.invalidate(start, len);
+        }
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    fn invalidate_decoded(&mut self, _start: usize, _len: usize) {}
+
+    // Remembers the PC and opcode of the last `len` instructions step runs, for crash
+    // reports and debuggers. 0 turns it off, which is the default
+    #[cfg(feature = "alloc")]
+    pub fn set_history(&mut self, len: usize) {
+        self.history = (len > 0).then(|| History {
+            entries: VecDeque::with_capacity(len),
+            len,
+        });
+    }
+
+    // (PC, opcode) of the last instructions, oldest first. The one that failed is the last
+    #[cfg(feature = "alloc")]
+    pub fn history(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.history
+            .iter()
+            .flat_map(|history| history.entries.iter().copied())
+    }
+
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub(crate) fn record_history(&mut self) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        let pc = self.pc as usize;
+        // past the end of RAM fetch fails anyway, the PC alone says enough
+        let opcode = match self.ram.get(pc..pc + 2) {
+            Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+            None => 0,
+        };
+        if history.entries.len() == history.len {
+            history.entries.pop_front();
+        }
+        history.entries.push_back((self.pc, opcode));
+    }
+
+    // Calls `callback` after every instruction step runs, with its address, opcode,
+    // mnemonic and the registers it changed (see trace.rs). Instructions that fail aren't
+    // reported, history has those. Can be turned on and off any time
+    #[cfg(feature = "alloc")]
+    pub fn set_trace(&mut self, callback: impl FnMut(&TraceEntry) + Send + 'static) {
+        self.trace = TraceCallback(Some(Box::new(callback)));
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn clear_trace(&mut self) {
+        self.trace = TraceCallback(None);
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn is_tracing(&self) -> bool {
+        self.trace.0.is_some()
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn report_trace(&mut self, entry: &TraceEntry) {
+        if let Some(callback) = &mut self.trace.0 {
+            callback(entry);
+        }
+    }
+
+    // printf debugging for homebrew: with the port on, 00FA prints the zero terminated
+    // string at I and FxFA prints Vx. Both are unknown instructions anywhere else, so ROMs
+    // should only keep them in debug builds. Off by default
+    #[cfg(feature = "alloc")]
+    pub fn set_debug_port(&mut self, enabled: bool) {
+        self.debug_output = enabled.then(Vec::new);
+        // blocks decoded with the other setting would keep it
+        if self.decode_cache.is_some() {
+            self.set_decode_cache(true);
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn is_debug_port_on(&self) -> bool {
+        self.debug_output.is_some()
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    pub(crate) fn is_debug_port_on(&self) -> bool {
+        false
+    }
+
+    // Everything printed since the last call, oldest first
+    #[cfg(feature = "alloc")]
+    pub fn take_debug_output(&mut self) -> Vec<DebugOutput> {
+        self.debug_output
+            .as_mut()
+            .map(core::mem::take)
+            .unwrap_or_default()
+    }
+
+    // 00FA, up to DEBUG_TEXT_LEN bytes or the end of RAM, bytes that aren't printable
+    // ASCII show as '?'
+    #[cfg(feature = "alloc")]
+    pub(crate) fn debug_text(&mut self) {
+        let start = (self.i as usize).min(self.ram.len());
+        let text = self.ram[start..]
+            .iter()
+            .take(DEBUG_TEXT_LEN)
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| match byte {
+                b' '..=b'~' => byte as char,
+                _ => '?',
+            })
+            .collect();
+        self.debug_print(DebugOutput::Text { pc: self.pc, text });
+    }
+
+    // FxFA
+    #[cfg(feature = "alloc")]
+    pub(crate) fn debug_value(&mut self, register: usize) {
+        let value = self.v[register];
+        self.debug_print(DebugOutput::Value {
+            pc: self.pc,
+            register,
+            value,
+        });
+    }
+
+    #[cfg(feature = "alloc")]
+    fn debug_print(&mut self, output: DebugOutput) {
+        if let Some(outputs) = &mut self.debug_output
+            && outputs.len() < DEBUG_OUTPUT_LEN
+        {
+            outputs.push(output);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn v_registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    #[inline]
+    pub(crate) fn v_registers_mut(&mut self) -> &mut [u8; 16] {
+        &mut self.v
+    }
+
+    // All of V0 to VF at once, for debuggers and tools
+    pub fn registers(&self) -> [u8; 16] {
+        self.v
+    }
+
+    pub fn set_registers(&mut self, registers: &[u8; 16]) {
+        self.v = *registers;
+    }
+
     // Safe V usage
+    #[inline]
     pub fn get_v(&self, index: usize) -> Result<u8, Chip8Error> {
         if index >= 16 {
             Err(Chip8Error::InvalidRegisterAccess)
@@ -185,6 +822,7 @@ impl Chip8 {
         }
     }
 
+    #[inline]
     pub fn set_v(&mut self, index: usize, value: u8) -> Result<bool, Chip8Error> {
         if index >= 16 {
             Err(Chip8Error::InvalidRegisterAccess)
@@ -196,6 +834,9 @@ impl Chip8 {
 
     // Set and decrease timers
     pub fn set_dt(&mut self, value: u8) {
+        if self.dt > 0 && value == 0 {
+            self.timer_event(TimerEvent::DelayExpired);
+        }
         self.dt = value;
     }
 
@@ -204,7 +845,18 @@ impl Chip8 {
     }
 
     pub fn set_st(&mut self, value: u8) {
+        if self.st == 0 && value > 0 {
+            self.timer_event(TimerEvent::SoundStarted);
+        } else if self.st > 0 && value == 0 {
+            self.timer_event(TimerEvent::SoundStopped);
+        }
         self.st = value;
+        self.sound_value = value;
+    }
+
+    // What ST was last set to, it keeps that value while ST counts down
+    pub fn get_sound_value(&self) -> &u8 {
+        &self.sound_value
     }
 
     pub fn get_st(&self) -> &u8 {
@@ -212,23 +864,452 @@ impl Chip8 {
     }
 
     pub fn decrease_timers(&mut self) {
+        if self.dt == 1 {
+            self.timer_event(TimerEvent::DelayExpired);
+        }
+        if self.st == 1 {
+            self.timer_event(TimerEvent::SoundStopped);
+        }
         self.dt = self.dt.saturating_sub(1);
         self.st = self.st.saturating_sub(1);
+        self.keyboard.end_frame();
+        #[cfg(feature = "alloc")]
+        if let Some(mut rewind) = self.rewind.0.take() {
+            rewind.frame(|| self.save_state());
+            self.rewind.0 = Some(rewind);
+        }
+    }
+
+    // Keeps a save state every `every` frames (60 Hz timer ticks) for rewind, the last
+    // `snapshots` of them. 0 snapshots turns it off, which is the default
+    #[cfg(feature = "alloc")]
+    pub fn set_rewind(&mut self, every: u32, snapshots: usize) {
+        self.rewind =
+            RewindBuffer((snapshots > 0).then(|| Box::new(Rewind::new(every, snapshots))));
+    }
+
+    // Goes back at least this many frames, or as far as the snapshots go, and says how far
+    // that was. The snapshots after it are gone, playing on makes new ones
+    #[cfg(feature = "alloc")]
+    pub fn rewind(&mut self, frames: u32) -> u32 {
+        let mut buffer = core::mem::take(&mut self.rewind);
+        let back = buffer.0.as_mut().and_then(|rewind| rewind.back(frames));
+        let frames = match back {
+            Some((frames, state)) if self.load_state(state).is_ok() => frames,
+            _ => 0,
+        };
+        self.rewind = buffer;
+        frames
+    }
+
+    // How many frames back rewind can go
+    #[cfg(feature = "alloc")]
+    pub fn rewind_frames(&self) -> u32 {
+        self.rewind.0.as_ref().map_or(0, |rewind| rewind.frames())
+    }
+
+    // Called when DT reaches zero and when ST starts or stops, through Fx15/Fx18, the 60 Hz
+    // countdown or set_dt/set_st, so a frontend can start and stop the beep on the spot
+    // instead of checking is_sound_on every frame. Loading a state doesn't call it
+    #[cfg(feature = "alloc")]
+    pub fn set_timer_callback(&mut self, callback: impl FnMut(TimerEvent) + Send + 'static) {
+        self.timer_callback = TimerCallback(Some(Box::new(callback)));
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn clear_timer_callback(&mut self) {
+        self.timer_callback = TimerCallback(None);
+    }
 
-        if self.get_st() > &0 && !self.audio.beeping.load(Ordering::Relaxed) {
-            self.audio.start_beep();
+    #[cfg(feature = "alloc")]
+    fn timer_event(&mut self, event: TimerEvent) {
+        if let Some(callback) = &mut self.timer_callback.0 {
+            callback(event);
         }
-        if self.get_st() == &0 && self.audio.beeping.load(Ordering::Relaxed) {
-            self.audio.stop_beep();
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    fn timer_event(&mut self, _event: TimerEvent) {}
+
+    // The whole keypad in one read, bit n is key n
+    pub fn keys_bitmask(&self) -> u16 {
+        self.keyboard.bitmask()
+    }
+
+    // The buzzer sounds while ST is above zero, frontends poll this once per frame
+    pub fn is_sound_on(&self) -> bool {
+        self.st > 0
+    }
+
+    // Instructions the CPU started since the machine was made, one that failed included
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    // Dxyn instructions run, the same way
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    // One row of a sprite at (x, y) like Dxyn draws it, wrapping around the screen. True
+    // if a lit pixel was turned off, for tools drawing without going through the CPU
+    pub fn xor_sprite_row(&mut self, x: usize, y: usize, byte: u8) -> bool {
+        self.draw_flag = true;
+        self.display.xor_sprite_row(x, y, byte)
+    }
+
+    // Every pixel as (x, y, lit), see Display::pixels
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, bool)> + '_ {
+        self.display.pixels()
+    }
+
+    // The screen as an image, one image pixel per Chip-8 pixel
+    #[cfg(feature = "image")]
+    pub fn to_image(&self, palette: Palette) -> image::RgbaImage {
+        self.display.to_image(palette, 1)
+    }
+
+    // The same with each Chip-8 pixel a scale x scale square
+    #[cfg(feature = "image")]
+    pub fn to_image_scaled(&self, palette: Palette, scale: usize) -> image::RgbaImage {
+        self.display.to_image(palette, scale)
+    }
+
+    // Screen helpers for tests and tooling, see assert_display_eq!
+    pub fn display_hash(&self) -> u64 {
+        self.display.hash()
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn display_ascii(&self) -> String {
+        self.display.to_ascii()
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn display_ascii_mismatch(&self, art: &str) -> Option<String> {
+        crate::display::ascii_mismatch(&self.display, art)
+    }
+
+    // Random source for Cxnn, reseeding makes every run of a ROM repeat itself
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+    }
+
+    pub fn random_byte(&mut self) -> u8 {
+        self.rng.r#gen()
+    }
+
+    // Save states, for quicksaves and test fixtures. Little endian: magic and version, pc,
+    // i, sp, dt, st, draw flag, V0-VF, stack, the first 4 KiB of ram, the first 128x64
+    // plane of the screen 8 pixels per byte and whether it's hi-res, keys 8 per byte, seed,
+    // stream and position of the rng, the SUPER-CHIP flags, XO-CHIP's second plane,
+    // selected planes, pitch and pattern (a byte for whether there's one and its 16), the
+    // keys at the end of the last frame and the wait of Fx0A. Then the platform (its place
+    // in Platform::ALL) and the ram past 4 KiB, which only XO-CHIP has
+    #[cfg(feature = "alloc")]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(self.state_size());
+        state.extend_from_slice(&self.save_state_array());
+        let platform = Platform::ALL.iter().position(|&p| p == self.platform);
+        state.push(platform.unwrap_or_default() as u8);
+        state.extend_from_slice(&self.ram[RAM_SIZE..]);
+        state
+    }
+
+    // How long save_state is for this machine, it depends on the platform
+    pub fn state_size(&self) -> usize {
+        STATE_SIZE + 1 + self.ram.len() - RAM_SIZE
+    }
+
+    // A save state without its end in a fixed size array, no allocation needed. It has
+    // neither the platform nor the ram past 4 KiB, the machine it loads in keeps its own
+    pub fn save_state_array(&self) -> [u8; STATE_SIZE] {
+        let mut state = [0u8; STATE_SIZE];
+        let mut writer = StateWriter {
+            data: &mut state,
+            at: 0,
+        };
+        writer.put(&STATE_MAGIC);
+        writer.put(&self.pc.to_le_bytes());
+        writer.put(&self.i.to_le_bytes());
+        writer.put(&[self.sp, self.dt, self.st, self.draw_flag as u8]);
+        writer.put(&self.v);
+        for address in self.stack {
+            writer.put(&address.to_le_bytes());
+        }
+        writer.put(&self.ram[..RAM_SIZE]);
+        let [first, second] = self.display.planes();
+        for row in first {
+            writer.put(&row.to_be_bytes());
+        }
+        writer.put(&[self.display.is_hires() as u8]);
+        for key in [0, 8] {
+            writer.put(&[pack_bits(
+                (key..key + 8).map(|k| self.keyboard.is_pressed(k) == Ok(true)),
+            )]);
+        }
+        writer.put(&self.rng.get_seed());
+        writer.put(&self.rng.get_stream().to_le_bytes());
+        writer.put(&self.rng.get_word_pos().to_le_bytes());
+        writer.put(&self.flags);
+        for row in second {
+            writer.put(&row.to_be_bytes());
+        }
+        writer.put(&[self.display.selected_planes(), self.audio_pitch]);
+        writer.put(&[self.audio_pattern.is_some() as u8]);
+        writer.put(&self.audio_pattern.unwrap_or_default());
+        writer.put(&self.keyboard.frame_state());
+        state
+    }
+
+    // Restores a save_state or a save_state_array, the machine is left untouched if the
+    // state is rejected
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Chip8Error> {
+        if state.len() < STATE_SIZE || state[..4] != STATE_MAGIC {
+            return Err(Chip8Error::InvalidState);
+        }
+        let mut reader = StateReader { data: state, at: 4 };
+        let pc = u16::from_le_bytes(reader.array());
+        let i = u16::from_le_bytes(reader.array());
+        let [sp, dt, st, draw_flag] = reader.array();
+        if sp > 16 || draw_flag > 1 {
+            return Err(Chip8Error::InvalidState);
+        }
+
+        let mut chip = self.clone();
+        if state.len() > STATE_SIZE {
+            let platform = Platform::ALL.get(state[STATE_SIZE] as usize);
+            chip.set_platform(*platform.ok_or(Chip8Error::InvalidState)?);
+            let extended = &state[STATE_SIZE + 1..];
+            if extended.len() != chip.ram.len() - RAM_SIZE {
+                return Err(Chip8Error::InvalidState);
+            }
+            chip.ram[RAM_SIZE..].copy_from_slice(extended);
+            chip.invalidate_decoded(RAM_SIZE, extended.len());
+        }
+        if pc as usize >= chip.ram.len() {
+            return Err(Chip8Error::InvalidState);
+        }
+        chip.pc = pc;
+        chip.i = i;
+        (chip.sp, chip.dt, chip.st, chip.draw_flag) = (sp, dt, st, draw_flag == 1);
+        chip.v = reader.array();
+        for address in chip.stack.iter_mut() {
+            *address = u16::from_le_bytes(reader.array());
+        }
+        chip.ram[..RAM_SIZE].copy_from_slice(&reader.array::<RAM_SIZE>());
+        chip.invalidate_decoded(0, RAM_SIZE);
+        let first = read_plane(&mut reader);
+        let [hires] = reader.array();
+        let [low, high]: [u8; 2] = reader.array();
+        // written from key 0 down in the high bit, the other way round of the bitmask
+        let keys = u16::from_le_bytes([low.reverse_bits(), high.reverse_bits()]);
+        chip.rng = ChaCha8Rng::from_seed(reader.array());
+        chip.rng.set_stream(u64::from_le_bytes(reader.array()));
+        chip.rng.set_word_pos(u128::from_le_bytes(reader.array()));
+        chip.flags = reader.array();
+        let second = read_plane(&mut reader);
+        let [selected, pitch, has_pattern] = reader.array();
+        let pattern = reader.array();
+        if hires > 1 || selected > 3 || has_pattern > 1 {
+            return Err(Chip8Error::InvalidState);
+        }
+        chip.display
+            .set_planes([first, second], hires == 1, selected);
+        chip.audio_pattern = (has_pattern == 1).then_some(pattern);
+        chip.audio_pitch = pitch;
+        chip.keyboard.restore(keys, reader.array())?;
+
+        self.restore(chip);
+        Ok(())
+    }
+
+    // Turns this machine into `other`, settings like quirks included, but keeps its timer
+    // callback, trace and rewind buffer. For going back to a snapshot, which was cloned
+    // without them
+    pub fn restore(&mut self, other: Chip8) {
+        #[cfg(feature = "alloc")]
+        let callback = core::mem::take(&mut self.timer_callback);
+        #[cfg(feature = "alloc")]
+        let trace = core::mem::take(&mut self.trace);
+        #[cfg(feature = "alloc")]
+        let rewind = core::mem::take(&mut self.rewind);
+        *self = other;
+        #[cfg(feature = "alloc")]
+        {
+            self.timer_callback = callback;
+            self.trace = trace;
+            self.rewind = rewind;
+        }
+    }
+}
+
+// The size of save_state_array, a save_state is 1 byte more plus the ram past 4 KiB
+pub const STATE_SIZE: usize = STATE_CHIP8_SIZE + 1024 + 3 + 16 + KEYBOARD_STATE_SIZE;
+// up to the SUPER-CHIP flags, the rest is XO-CHIP's and the keyboard's
+const STATE_CHIP8_SIZE: usize =
+    4 + 2 + 2 + 4 + 16 + 32 + RAM_SIZE + 1024 + 1 + 2 + 32 + 8 + 16 + 16;
+const STATE_MAGIC: [u8; 4] = *b"C8S\x01";
+
+fn read_plane(reader: &mut StateReader) -> [u128; 64] {
+    core::array::from_fn(|_| u128::from_be_bytes(reader.array()))
+}
+
+fn pack_bits(bits: impl Iterator<Item = bool>) -> u8 {
+    bits.fold(0, |byte, bit| (byte << 1) | bit as u8)
+}
+
+struct StateWriter<'a> {
+    data: &'a mut [u8],
+    at: usize,
+}
+
+impl StateWriter<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.data[self.at..self.at + bytes.len()].copy_from_slice(bytes);
+        self.at += bytes.len();
+    }
+}
+
+// The length is checked once up front, so reads can't run past the end
+struct StateReader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl StateReader<'_> {
+    fn array<const N: usize>(&mut self) -> [u8; N] {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.data[self.at..self.at + N]);
+        self.at += N;
+        bytes
+    }
+}
+
+// With the `serde` feature a machine goes into any serde format as its save_state bytes, so
+// there's one versioned format whether it ends up in a .state file, JSON or bincode
+#[cfg(feature = "serde")]
+impl serde::Serialize for Chip8 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.save_state())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Chip8 {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state = deserializer.deserialize_bytes(StateVisitor)?;
+        let mut chip = Chip8::new(&[]).map_err(serde::de::Error::custom)?;
+        chip.load_state(&state).map_err(serde::de::Error::custom)?;
+        Ok(chip)
+    }
+}
+
+// Bytes in formats that have them, a sequence of numbers in the ones that don't (JSON)
+#[cfg(feature = "serde")]
+struct StateVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for StateVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a chip8 save state")
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut state = Vec::with_capacity(seq.size_hint().unwrap_or(STATE_SIZE));
+        while let Some(byte) = seq.next_element()? {
+            state.push(byte);
         }
+        Ok(state)
     }
+}
 
-    // update method for keyboard (needs access to screen)
-    pub fn update_keyboard(&mut self) {
-        self.keyboard.update(self.display.window());
+// Without an OS there's no entropy source, so no_std builds start from a fixed seed.
+// Call set_seed with something random (an ADC reading, a timer) to get different games
+#[cfg(feature = "std")]
+fn default_rng() -> ChaCha8Rng {
+    ChaCha8Rng::from_entropy()
+}
+
+#[cfg(not(feature = "std"))]
+fn default_rng() -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(0)
+}
+
+// Which value of the machine sets the pitch of the beep, for the musical mode of audio.rs.
+// Fixed is the classic single tone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pitch {
+    Fixed,
+    // the value last written to ST
+    SoundTimer,
+    // whatever Vx holds
+    Register(usize),
+}
+
+impl Pitch {
+    // "fixed", "timer" or "v0" to "vf", any case
+    pub fn parse(name: &str) -> Option<Pitch> {
+        if name.eq_ignore_ascii_case("fixed") {
+            return Some(Pitch::Fixed);
+        }
+        if name.eq_ignore_ascii_case("timer") {
+            return Some(Pitch::SoundTimer);
+        }
+        let digit = name.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        let x = u8::from_str_radix(digit, 16).ok()?;
+        Some(Pitch::Register(x as usize))
+    }
+}
+
+// What the beep sounds like, played by audio.rs. Sine is the clean synthesized tone, Vip
+// the harsh square of the COSMAC VIP's buzzer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timbre {
+    Sine,
+    Vip,
+}
+
+impl Timbre {
+    // "sine" or "vip", any case
+    pub fn parse(name: &str) -> Option<Timbre> {
+        if name.eq_ignore_ascii_case("sine") {
+            Some(Timbre::Sine)
+        } else if name.eq_ignore_ascii_case("vip") {
+            Some(Timbre::Vip)
+        } else {
+            None
+        }
+    }
+}
+
+impl Chip8 {
+    // The value a pitch follows right now, None for Fixed
+    pub fn pitch_value(&self, pitch: Pitch) -> Option<u8> {
+        match pitch {
+            Pitch::Fixed => None,
+            Pitch::SoundTimer => Some(self.sound_value),
+            Pitch::Register(x) => self.get_v(x).ok(),
+        }
     }
 }
 
+// FNV-1a of a ROM file like Display::hash, to tell ROMs apart (netplay, per game settings)
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 impl Default for Chip8 {
     fn default() -> Self {
         let rom: [u8; 1] = [0];
@@ -245,8 +1326,7 @@ mod tests {
 
     // chip8 initializer for tests
     fn chip_test() -> Chip8 {
-        let chip = Chip8::new(&ROM).unwrap();
-        chip
+        Chip8::new(&ROM).unwrap()
     }
 
     // testing stack safety
@@ -323,8 +1403,21 @@ mod tests {
         assert_eq!(chip.get_pc(), &0x10);
         chip.set_pc(0).unwrap();
         assert_eq!(chip.get_pc(), &0);
-        assert!(chip.set_pc(4096).is_ok());
-        assert_eq!(chip.get_pc(), &4096);
+    }
+
+    // PC goes up to the last byte of RAM, wherever that is on the platform
+    #[test]
+    fn test_pc_bounds() {
+        let mut chip = chip_test();
+        chip.set_pc(4095).unwrap();
+        assert_eq!(chip.set_pc(4096), Err(Chip8Error::PCOutOfBounds));
+        assert_eq!(chip.get_pc(), &4095);
+        #[cfg(feature = "alloc")]
+        {
+            chip.set_platform(Platform::XoChip);
+            chip.set_pc(0xFFFF).unwrap();
+            assert_eq!(chip.get_pc(), &0xFFFF);
+        }
     }
 
     // testing I safety
@@ -373,6 +1466,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_regions() {
+        let mut chip = chip_test();
+        assert_eq!(&chip.region(Region::Font)[..5], &FONTSET[..5]);
+        assert_eq!(&chip.region(Region::Program)[..ROM.len()], &ROM);
+        assert!(chip.region(Region::Extended).is_empty());
+
+        chip.region_range_mut(Region::WorkRam, 0, 2)
+            .unwrap()
+            .copy_from_slice(&[1, 2]);
+        assert_eq!(chip.get_ram(0x141), Ok(2));
+        assert_eq!(
+            chip.region_range(Region::WorkRam, 0xBF, 2),
+            Err(Chip8Error::InvalidMemoryAccess)
+        );
+
+        let patches = [
+            Patch::parse("0x200=0x00").unwrap(),
+            Patch::parse("0x201=0xEE").unwrap(),
+        ];
+        chip.apply_patches(&patches).unwrap();
+        assert_eq!(chip.fetch(), Ok(0x00EE));
+    }
+
     // Testing V safety
     #[test]
     fn test_v_full_use() {
@@ -389,16 +1506,406 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_register_file_and_stack() {
+        let mut chip = chip_test();
+        let registers: [u8; 16] = core::array::from_fn(|index| index as u8 * 3);
+        chip.set_registers(&registers);
+        assert_eq!(chip.registers(), registers);
+        assert_eq!(chip.get_v(0xF), Ok(45));
+
+        assert_eq!(chip.set_stack(&[0x202, 0x300]), Ok(true));
+        assert_eq!(chip.stack(), &[0x202, 0x300]);
+        assert_eq!(chip.pop_stack(), Ok(0x300));
+        assert_eq!(chip.stack(), &[0x202]);
+        assert_eq!(chip.set_stack(&[0; 17]), Err(Chip8Error::StackOverflow));
+        assert_eq!(chip.stack(), &[0x202]);
+        chip.set_stack(&[]).unwrap();
+        assert_eq!(chip.pop_stack(), Err(Chip8Error::StackUnderflow));
+    }
+
     // testing dt and st
     #[test]
     fn test_timers() {
         let mut chip = chip_test();
         chip.set_dt(250);
         chip.set_st(150);
+        assert!(chip.is_sound_on());
         for _ in 0..256 {
             chip.decrease_timers();
         }
         assert_eq!(chip.get_dt(), &0);
         assert_eq!(chip.get_st(), &0);
+        assert_eq!(chip.get_sound_value(), &150);
+        assert_eq!(chip.pitch_value(Pitch::SoundTimer), Some(150));
+        assert_eq!(chip.pitch_value(Pitch::Fixed), None);
+        assert_eq!(Pitch::parse("vA"), Some(Pitch::Register(10)));
+        assert_eq!(Pitch::parse("TIMER"), Some(Pitch::SoundTimer));
+        assert_eq!(Pitch::parse("v10"), None);
+        assert!(!chip.is_sound_on());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_timer_callback() {
+        use std::sync::{Arc, Mutex};
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut chip = chip_test();
+        let log = events.clone();
+        chip.set_timer_callback(move |event| log.lock().unwrap().push(event));
+
+        chip.set_dt(2);
+        chip.set_st(1);
+        chip.set_st(3);
+        let state = chip.save_state();
+        for _ in 0..3 {
+            chip.decrease_timers();
+        }
+        // a copy runs without the callback, a loaded state keeps it
+        chip.clone().set_st(5);
+        chip.load_state(&state).unwrap();
+        chip.set_st(0);
+        chip.clear_timer_callback();
+        chip.set_st(1);
+
+        use TimerEvent::*;
+        assert_eq!(
+            *events.lock().unwrap(),
+            [SoundStarted, DelayExpired, SoundStopped, SoundStopped]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_history() {
+        // 6001 7001 1202, then 00EE with nothing to return to
+        let mut chip = Chip8::new(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x06, 0x00, 0xEE]).unwrap();
+        chip.step().unwrap();
+        assert_eq!(chip.history().count(), 0);
+
+        chip.set_history(2);
+        for _ in 0..2 {
+            chip.step().unwrap();
+        }
+        assert_eq!(chip.step(), Err(Chip8Error::StackUnderflow));
+        let history: Vec<_> = chip.history().collect();
+        assert_eq!(history, [(0x204, 0x1206), (0x206, 0x00EE)]);
+
+        chip.set_history(0);
+        assert_eq!(chip.history().count(), 0);
+    }
+
+    #[test]
+    fn test_counters() {
+        // D005 draws nothing but counts, then 00EE with nothing to return to
+        let mut chip = Chip8::new(&[0xD0, 0x05, 0xD0, 0x05, 0x00, 0xEE]).unwrap();
+        chip.step().unwrap();
+        chip.step().unwrap();
+        assert_eq!(chip.step(), Err(Chip8Error::StackUnderflow));
+        assert_eq!((chip.instructions(), chip.draws()), (3, 2));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let mut chip = Chip8::new(&[0x60, 0x2A, 0xA3, 0x00]).unwrap();
+        chip.set_platform(Platform::XoChip);
+        chip.step().unwrap();
+        chip.step().unwrap();
+        let json = serde_json::to_string(&chip).unwrap();
+        let other: Chip8 = serde_json::from_str(&json).unwrap();
+        assert_eq!(other.save_state(), chip.save_state());
+        assert_eq!(other.get_v(0), Ok(0x2A));
+        assert!(serde_json::from_str::<Chip8>("[1, 2, 3]").is_err());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_fx0a_wait_in_save_states() {
+        // Fx0A into V1
+        let mut chip = Chip8::new(&[0xF1, 0x0A, 0x12, 0x02]).unwrap();
+        chip.keyboard.set_key(4, true).unwrap();
+        chip.decrease_timers();
+        chip.step().unwrap();
+        let waiting = chip.save_state();
+
+        // the key held at the load is up in the state, that's no release
+        let mut other = Chip8::new(&[0xF1, 0x0A, 0x12, 0x02]).unwrap();
+        other.keyboard.set_key(4, true).unwrap();
+        other.keyboard.set_key(9, true).unwrap();
+        while other.keyboard.next_event().is_some() {}
+        other.load_state(&waiting).unwrap();
+        assert_eq!(other.keyboard.bitmask(), 1 << 4);
+        assert_eq!(other.keyboard.next_event(), None);
+        other.step().unwrap();
+        assert_eq!(*other.get_pc(), 0x200);
+        // the wait goes on from the state, key 4 going up ends it
+        other.keyboard.set_key(4, false).unwrap();
+        other.step().unwrap();
+        assert_eq!((*other.get_pc(), other.get_v(1)), (0x202, Ok(4)));
+        // and the frame's edges are the ones of the state
+        assert_eq!(other.keyboard.is_just_released(4), Ok(true));
+        assert_eq!(other.keyboard.is_just_pressed(9), Ok(false));
+
+        // a new program doesn't finish the wait of the old one
+        chip.keyboard.set_key(4, false).unwrap();
+        chip.load_rom(&[0xF2, 0x0A]).unwrap();
+        chip.step().unwrap();
+        assert_eq!(*chip.get_pc(), 0x200);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_rewind() {
+        // V0 += 1, one instruction a frame
+        let mut chip = Chip8::new(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let frame = |chip: &mut Chip8| {
+            chip.step().unwrap();
+            chip.step().unwrap();
+            chip.decrease_timers();
+        };
+        frame(&mut chip);
+        assert_eq!(chip.rewind(5), 0);
+
+        // a snapshot on the first frame and every 2 after it, the last 5 are kept: the
+        // ones where V0 was 12, 14, 16, 18 and 20
+        chip.set_rewind(2, 5);
+        for _ in 0..20 {
+            frame(&mut chip);
+        }
+        assert_eq!(chip.get_v(0), Ok(21));
+        assert_eq!(chip.rewind_frames(), 9);
+        // copies don't have it
+        assert_eq!(chip.clone().rewind_frames(), 0);
+        assert_eq!(chip.rewind(3), 3);
+        assert_eq!(chip.get_v(0), Ok(18));
+        assert_eq!(chip.rewind(100), 6);
+        assert_eq!(chip.get_v(0), Ok(12));
+        assert_eq!(chip.rewind(1), 0);
+
+        // playing on records from there, loading a state keeps it
+        for _ in 0..4 {
+            frame(&mut chip);
+        }
+        chip.load_state(&chip.save_state()).unwrap();
+        assert_eq!(chip.rewind(2), 2);
+        assert_eq!(chip.get_v(0), Ok(14));
+        chip.set_rewind(2, 0);
+        assert_eq!(chip.rewind_frames(), 0);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_save_load_hires_state() {
+        let mut chip = chip_test();
+        chip.display.set_hires(true);
+        chip.display.xor_sprite_row(120, 60, 0xFF);
+        chip.set_flags([7; 16]);
+        let mut other = chip_test();
+        other.load_state(&chip.save_state()).unwrap();
+        assert!(other.display.is_hires());
+        assert!(other.display.pixel(127, 60) && !other.display.pixel(119, 60));
+        assert_eq!(other.flags(), &[7; 16]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_save_load_xo_chip_state() {
+        let mut chip = chip_test();
+        chip.set_platform(Platform::XoChip);
+        chip.display.select_planes(2);
+        chip.display.xor_sprite_row(0, 0, 0x80);
+        chip.set_audio_pattern([0xF0; 16]);
+        chip.set_audio_pitch(100);
+        let mut other = chip_test();
+        other.set_platform(Platform::XoChip);
+        other.load_state(&chip.save_state()).unwrap();
+        assert_eq!(other.display.pixel_value(0, 0), 2);
+        assert_eq!(other.display.selected_planes(), 2);
+        assert_eq!(other.audio_pattern(), Some(&[0xF0; 16]));
+        assert_eq!(other.audio_pitch(), 100);
+
+        // the platform and the ram past 4 KiB come along
+        chip.set_ram(0xFFFF, 0x42).unwrap();
+        chip.set_i(0x8000).unwrap();
+        let state = chip.save_state();
+        assert_eq!(state.len(), STATE_SIZE + 1 + 0xF000);
+        assert_eq!(chip.state_size(), state.len());
+        let mut other = chip_test();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.platform(), Platform::XoChip);
+        assert_eq!((other.get_ram(0xFFFF), *other.get_i()), (Ok(0x42), 0x8000));
+        assert_eq!(other.save_state(), state);
+        let mut bad = state.clone();
+        bad[STATE_SIZE] = Platform::ALL.len() as u8;
+        assert_eq!(other.load_state(&bad), Err(Chip8Error::InvalidState));
+        assert_eq!(
+            other.load_state(&state[..state.len() - 1]),
+            Err(Chip8Error::InvalidState)
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_xo_chip_memory() {
+        let mut chip = chip_test();
+        let rom = [0xAA; 0x1000];
+        assert_eq!(chip.load_rom(&rom), Err(Chip8Error::RomTooLarge));
+        chip.set_platform(Platform::XoChip);
+        assert_eq!(chip.memory_map(), MemoryMap::XO_CHIP);
+        assert_eq!(chip.region(Region::Font)[..5], FONTSET[..5]);
+        chip.load_rom(&rom).unwrap();
+        assert_eq!(chip.get_ram(0x11FF), Ok(0xAA));
+        assert_eq!(chip.get_ram(0x1200), Ok(0));
+        chip.set_i(0xFFFF).unwrap();
+        assert_eq!(chip.ram_image().len(), 0x10000);
+
+        // back to 4 KiB, I stays where it was and fails on use
+        chip.set_platform(Platform::Chip8);
+        assert_eq!(chip.ram_image().len(), RAM_SIZE);
+        assert_eq!(chip.get_ram(0x1000), Err(Chip8Error::InvalidMemoryAccess));
+    }
+
+    #[test]
+    fn test_ram_image() {
+        let source = Chip8::new(&[0x60, 0x05]).unwrap();
+        let image = source.ram_image();
+        assert_eq!(image[0x200..0x202], [0x60, 0x05]);
+
+        // 6109 already ran once, the image replaces it with 6005
+        let mut chip = Chip8::new(&[0x61, 0x09]).unwrap();
+        chip.step().unwrap();
+        chip.set_pc(0x200).unwrap();
+        chip.load_ram_image(image).unwrap();
+        chip.step().unwrap();
+        assert_eq!(chip.get_v(0), Ok(5));
+        assert_eq!(chip.get_pc(), &0x202);
+        assert_eq!(
+            chip.load_ram_image(&image[1..]),
+            Err(Chip8Error::InvalidState)
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_font() {
+        let chip = chip_test();
+        assert_eq!(Chip8::font_address(0), Ok(0x50));
+        assert_eq!(Chip8::font_address(0xF), Ok(0x9B));
+        assert_eq!(Chip8::font_address(0x10), Err(Chip8Error::InvalidDigit));
+        // the 1 and the F, all 16 glyphs are in from new
+        let glyph = |digit| {
+            let address = Chip8::font_address(digit).unwrap();
+            (address..address + 5)
+                .map(|at| chip.get_ram(at).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(glyph(1), [0x20, 0x60, 0x20, 0x20, 0x70]);
+        assert_eq!(glyph(0xF), [0xF0, 0x80, 0xF0, 0x80, 0x80]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_load_rom() {
+        // a second ROM over a first one that ran, with the decode cache on
+        let mut chip = Chip8::new(&[0x60, 0x05, 0x61, 0x07]).unwrap();
+        chip.set_decode_cache(true);
+        chip.step().unwrap();
+        chip.load_rom(&[0x62, 0x09]).unwrap();
+        assert_eq!(chip.get_pc(), &0x200);
+        assert_eq!(chip.get_ram(0x202), Ok(0));
+        chip.step().unwrap();
+        assert_eq!(chip.get_v(2), Ok(9));
+        // the font and the registers stay
+        assert_eq!(chip.get_ram(Chip8::font_address(0).unwrap()), Ok(0xF0));
+        assert_eq!(chip.get_v(0), Ok(5));
+
+        let largest = [0xAA; RAM_SIZE - PROGRAM_START];
+        chip.load_rom(&largest).unwrap();
+        assert_eq!(chip.get_ram(RAM_SIZE as u16 - 1), Ok(0xAA));
+        let too_large = [0; RAM_SIZE - PROGRAM_START + 1];
+        assert_eq!(chip.load_rom(&too_large), Err(Chip8Error::RomTooLarge));
+        assert!(Chip8::new(&too_large).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_load_rom_from_file() {
+        let path = std::env::temp_dir().join(format!("chip8-rom-{}.ch8", std::process::id()));
+        let mut chip = chip_test();
+        std::fs::write(&path, [0x60, 0x05]).unwrap();
+        chip.load_rom_from_file(&path).unwrap();
+        assert_eq!(chip.get_ram(0x201), Ok(0x05));
+
+        std::fs::write(&path, [0; 4000]).unwrap();
+        let error = chip.load_rom_from_file(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("4000 bytes"));
+        assert!(chip.load_rom_from_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_xor_sprite_row() {
+        let mut chip = chip_test();
+        // wraps off the right edge onto the left one
+        assert!(!chip.xor_sprite_row(60, 33, 0xFF));
+        assert!(chip.draw_flag);
+        assert_eq!(chip.display.get_pixel(64 + 63), Ok(1));
+        assert_eq!(chip.display.get_pixel(64 + 3), Ok(1));
+        assert_eq!(chip.display.get_pixel(64 + 4), Ok(0));
+        assert!(chip.xor_sprite_row(0, 1, 0x80));
+        assert_eq!(chip.display.get_pixel(64), Ok(0));
+    }
+
+    // testing seeded random source
+    #[test]
+    fn test_seeded_random() {
+        let mut a = chip_test();
+        let mut b = chip_test();
+        a.set_seed(42);
+        b.set_seed(42);
+        for _ in 0..64 {
+            assert_eq!(a.random_byte(), b.random_byte());
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_save_load_state() {
+        let mut chip = chip_test();
+        chip.set_seed(9);
+        chip.set_ram(0x300, 0xAB).unwrap();
+        chip.set_v(3, 7).unwrap();
+        chip.push_stack(0x222).unwrap();
+        chip.set_pc(0x204).unwrap();
+        chip.set_dt(12);
+        chip.display.set_pixel(65, 1).unwrap();
+        chip.keyboard.set_key(0xA, true).unwrap();
+        chip.keyboard.end_frame();
+        chip.random_byte();
+        let state = chip.save_state();
+        let array = chip.save_state_array();
+
+        let mut other = Chip8::new(&[0x12, 0x00]).unwrap();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.save_state(), state);
+        assert_eq!(other.get_ram(0x300), Ok(0xAB));
+        assert_eq!(other.pop_stack(), Ok(0x222));
+        assert_eq!(other.display.get_pixel(65), Ok(1));
+        assert_eq!(other.keyboard.is_pressed(0xA), Ok(true));
+        // the rng continues where it was
+        assert_eq!(other.random_byte(), chip.random_byte());
+
+        assert_eq!(other.load_state(&state[1..]), Err(Chip8Error::InvalidState));
+        let mut bad = state.clone();
+        bad[4..6].copy_from_slice(&4096u16.to_le_bytes());
+        assert!(other.load_state(&bad).is_err());
+        assert_eq!(other.get_ram(0x300), Ok(0xAB));
+
+        // the array is the same without the platform and the ram past 4 KiB
+        assert_eq!(array[..], state[..STATE_SIZE]);
+        other.load_state(&array).unwrap();
+        assert_eq!(other.save_state(), state);
     }
 }
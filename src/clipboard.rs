@@ -0,0 +1,31 @@
+// The screen on the system clipboard as an image, quicker than saving a PNG when pasting a
+// frame into a bug report or a chat. The copy_frame hotkey uses it.
+use crate::display::{Display, Palette};
+use std::borrow::Cow;
+
+// big enough to see what's on it in a chat window
+const SCALE: usize = 8;
+
+// On X11 what was copied goes away with the arboard::Clipboard, so keep this one open for as
+// long as the pasting should work
+pub struct Clipboard {
+    clipboard: arboard::Clipboard,
+}
+
+impl Clipboard {
+    // Fails without a desktop session to hold the clipboard
+    pub fn new() -> Result<Self, String> {
+        let clipboard = arboard::Clipboard::new().map_err(|e| e.to_string())?;
+        Ok(Clipboard { clipboard })
+    }
+
+    pub fn copy_frame(&mut self, display: &Display, palette: Palette) -> Result<(), String> {
+        let image = display.to_image(palette, SCALE);
+        let image = arboard::ImageData {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            bytes: Cow::Owned(image.into_raw()),
+        };
+        self.clipboard.set_image(image).map_err(|e| e.to_string())
+    }
+}
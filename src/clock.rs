@@ -0,0 +1,169 @@
+use core::time::Duration;
+
+// Where the run loops get the time from. RealClock is the wall clock, VirtualClock only
+// moves when told to, so tests of timers and pacing run instantly and the same way every
+// time. Without std, boards implement Clock with a hardware timer.
+pub trait Clock {
+    // Time since the clock started
+    fn now(&self) -> Duration;
+
+    // Until now() is `duration` later
+    fn sleep(&mut self, duration: Duration);
+}
+
+// 60 Hz, the rate of the timers and of a frame
+pub const FRAME: Duration = Duration::from_micros(16_667);
+
+#[cfg(feature = "std")]
+pub struct RealClock {
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl RealClock {
+    pub fn new() -> Self {
+        RealClock {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for RealClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+// Starts at zero, sleeping moves it forward right away
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Duration,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        VirtualClock {
+            now: Duration::ZERO,
+        }
+    }
+
+    // Time going by outside the loop, like the emulator taking long for a frame
+    pub fn advance(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+}
+
+// Turns the time of a clock into frames. After a stall at most max_catch_up frames are
+// due at once and the rest are dropped, so the game slows down instead of racing
+pub struct Scheduler<C: Clock> {
+    clock: C,
+    period: Duration,
+    max_catch_up: u32,
+    // when the next frame is due
+    next: Duration,
+}
+
+impl<C: Clock> Scheduler<C> {
+    // The first frame is due one period from now
+    pub fn new(clock: C, period: Duration, max_catch_up: u32) -> Self {
+        let next = clock.now() + period;
+        Scheduler {
+            clock,
+            period,
+            max_catch_up: max_catch_up.max(1),
+            next,
+        }
+    }
+
+    // Frames due since the last call, 0 if it's too early for the next one
+    pub fn due(&mut self) -> u32 {
+        let now = self.clock.now();
+        if now < self.next {
+            return 0;
+        }
+        let late = ((now - self.next).as_nanos() / self.period.as_nanos().max(1)) as u32;
+        let frames = late.saturating_add(1);
+        self.next += self.period * frames;
+        frames.min(self.max_catch_up)
+    }
+
+    // Sleeps until the next frame is due, then returns like due
+    pub fn wait(&mut self) -> u32 {
+        let now = self.clock.now();
+        if now < self.next {
+            self.clock.sleep(self.next - now);
+        }
+        self.due()
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    pub fn clock_mut(&mut self) -> &mut C {
+        &mut self.clock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_from_virtual_time() {
+        let mut scheduler = Scheduler::new(VirtualClock::new(), FRAME, 4);
+        assert_eq!(scheduler.due(), 0);
+        scheduler.clock_mut().advance(FRAME);
+        assert_eq!(scheduler.due(), 1);
+        assert_eq!(scheduler.due(), 0);
+
+        // two and a half frames late: two due now, the next one half a frame from now
+        scheduler.clock_mut().advance(FRAME * 5 / 2);
+        assert_eq!(scheduler.due(), 2);
+        scheduler.clock_mut().advance(FRAME / 2);
+        assert_eq!(scheduler.due(), 1);
+
+        // a stall of a second only catches up 4 frames
+        scheduler.clock_mut().advance(Duration::from_secs(1));
+        assert_eq!(scheduler.due(), 4);
+        assert_eq!(scheduler.due(), 0);
+    }
+
+    #[test]
+    fn test_wait_sleeps_to_the_next_frame() {
+        let mut scheduler = Scheduler::new(VirtualClock::new(), FRAME, 1);
+        scheduler.clock_mut().advance(FRAME / 4);
+        assert_eq!(scheduler.wait(), 1);
+        assert_eq!(scheduler.clock().now(), FRAME);
+        for _ in 0..59 {
+            scheduler.wait();
+        }
+        assert_eq!(scheduler.clock().now(), FRAME * 60);
+        // already late, no sleep
+        scheduler.clock_mut().advance(FRAME * 3);
+        assert_eq!(scheduler.wait(), 1);
+        assert_eq!(scheduler.clock().now(), FRAME * 63);
+    }
+}
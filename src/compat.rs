@@ -0,0 +1,236 @@
+use crate::chip8::{Chip8, Chip8Error};
+use crate::display::Display;
+use crate::golden;
+use crate::lockstep::Lockstep;
+use crate::testrunner::IPF;
+use std::fmt::Write as _;
+use std::path::Path;
+
+// Compatibility report: every ROM of a directory runs headless for a few seconds and
+// gets a status depending on what it did. Nobody presses keys, so games waiting on
+// a menu show up as WaitingForKey, that's expected and not a failure.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    // drew something on screen and didn't crash
+    Works,
+    // kept running but the screen stayed blank
+    NoOutput,
+    // stopped on a jump to itself before drawing anything
+    BootLoop { pc: u16 },
+    // blank screen and stuck on Fx0A
+    WaitingForKey,
+    Crashed { error: Chip8Error, frame: u64 },
+}
+
+impl Status {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Status::Works => "works",
+            Status::NoOutput => "no output",
+            Status::BootLoop { .. } => "loops at boot",
+            Status::WaitingForKey => "waits for key",
+            Status::Crashed { .. } => "crashed",
+        }
+    }
+
+    pub fn detail(&self) -> String {
+        match self {
+            Status::BootLoop { pc } => format!("jump to self at {:#05X}", pc),
+            Status::Crashed { error, frame } => format!("{:?} at frame {}", error, frame),
+            _ => String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub rom: String,
+    pub status: Status,
+    pub frames: u64,
+    // frames that ended with something on screen
+    pub frames_drawn: u64,
+}
+
+pub fn run_rom(name: &str, rom: &[u8], frames: u64) -> Report {
+    let mut report = Report {
+        rom: name.to_string(),
+        status: Status::NoOutput,
+        frames: 0,
+        frames_drawn: 0,
+    };
+
+    let mut chip = match Chip8::new(rom) {
+        Ok(chip) => chip,
+        Err(error) => {
+            report.status = Status::Crashed { error, frame: 0 };
+            return report;
+        }
+    };
+    chip.set_seed(0);
+    // nothing watches these runs, they may as well go fast
+    chip.set_decode_cache(true);
+
+    let blank = Display::new().hash();
+    let mut lockstep = Lockstep::new(IPF);
+    while lockstep.frame() < frames {
+        if let Err(error) = lockstep.run_frame(&mut chip) {
+            report.status = Status::Crashed {
+                error,
+                frame: lockstep.frame(),
+            };
+            return report;
+        }
+        report.frames = lockstep.frame();
+        if chip.display_hash() != blank {
+            report.frames_drawn += 1;
+        }
+
+        // a jump to itself never leaves, no need to keep running
+        let pc = *chip.get_pc();
+        if chip.fetch() == Ok(0x1000 | pc) {
+            if report.frames_drawn == 0 {
+                report.status = Status::BootLoop { pc };
+                return report;
+            }
+            break;
+        }
+    }
+
+    report.status = if report.frames_drawn > 0 {
+        Status::Works
+    } else if chip.fetch().is_ok_and(|opcode| opcode & 0xF0FF == 0xF00A) {
+        Status::WaitingForKey
+    } else {
+        Status::NoOutput
+    };
+    report
+}
+
+// Runs every .ch8 of the directory for the given number of frames
+pub fn run_dir(dir: &Path, frames: u64) -> std::io::Result<Vec<Report>> {
+    let mut reports = Vec::new();
+    for path in golden::corpus(dir)? {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let rom = std::fs::read(&path)?;
+        reports.push(run_rom(&name, &rom, frames));
+    }
+    Ok(reports)
+}
+
+fn summary(reports: &[Report]) -> String {
+    let working = reports
+        .iter()
+        .filter(|report| report.status == Status::Works)
+        .count();
+    format!("{} of {} ROMs draw on screen", working, reports.len())
+}
+
+pub fn to_markdown(reports: &[Report]) -> String {
+    let mut text = String::from("# Compatibility report\n\n");
+    let _ = writeln!(text, "{}\n", summary(reports));
+    text.push_str("| ROM | Status | Details | Frames run | Frames drawn |\n");
+    text.push_str("|-----|--------|---------|-----------:|-------------:|\n");
+    for report in reports {
+        let _ = writeln!(
+            text,
+            "| {} | {} | {} | {} | {} |",
+            report.rom.replace('|', "\\|"),
+            report.status.label(),
+            report.status.detail(),
+            report.frames,
+            report.frames_drawn
+        );
+    }
+    text
+}
+
+pub fn to_html(reports: &[Report]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Chip-8 compatibility report</title>\n\
+         <style>\nbody { font-family: sans-serif; }\ntable { border-collapse: collapse; }\n\
+         td, th { border: 1px solid #999; padding: 4px 8px; }\n\
+         .works { background: #c8f7c5; }\n.crashed { background: #f7c5c5; }\n\
+         .other { background: #f7efc5; }\n</style>\n</head>\n<body>\n<h1>Compatibility report</h1>\n",
+    );
+    let _ = writeln!(html, "<p>{}</p>", summary(reports));
+    html.push_str(
+        "<table>\n<tr><th>ROM</th><th>Status</th><th>Details</th><th>Frames run</th><th>Frames drawn</th></tr>\n",
+    );
+    for report in reports {
+        let class = match report.status {
+            Status::Works => "works",
+            Status::Crashed { .. } => "crashed",
+            _ => "other",
+        };
+        let _ = writeln!(
+            html,
+            "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            class,
+            escape_html(&report.rom),
+            report.status.label(),
+            report.status.detail(),
+            report.frames,
+            report.frames_drawn
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_detection() {
+        // draws the font 0 and stops
+        let draws = [0xA0, 0x50, 0xD0, 0x05, 0x12, 0x04];
+        assert_eq!(run_rom("a", &draws, 60).status, Status::Works);
+        // jump to self right away
+        let spin = [0x12, 0x00];
+        assert_eq!(
+            run_rom("b", &spin, 60).status,
+            Status::BootLoop { pc: 0x200 }
+        );
+        // blank and waiting for a key
+        let wait = [0xF0, 0x0A, 0x12, 0x00];
+        assert_eq!(run_rom("c", &wait, 60).status, Status::WaitingForKey);
+        // busy loop that never draws
+        let busy = [0x70, 0x01, 0x12, 0x00];
+        assert_eq!(run_rom("d", &busy, 60).status, Status::NoOutput);
+        // return without call
+        let crash = [0x00, 0xEE];
+        assert_eq!(
+            run_rom("e", &crash, 60).status,
+            Status::Crashed {
+                error: Chip8Error::StackUnderflow,
+                frame: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_reports_render() {
+        let reports = vec![
+            run_rom("good.ch8", &[0xA0, 0x50, 0xD0, 0x05, 0x12, 0x04], 10),
+            run_rom("<bad>.ch8", &[0x00, 0xEE], 10),
+        ];
+        let markdown = to_markdown(&reports);
+        assert!(markdown.contains("1 of 2 ROMs draw on screen"));
+        assert!(markdown.contains("| good.ch8 | works |"));
+        let html = to_html(&reports);
+        assert!(html.contains("&lt;bad&gt;.ch8"));
+        assert!(html.contains("class=\"crashed\""));
+    }
+}
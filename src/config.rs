@@ -0,0 +1,1172 @@
+// Settings that persist between runs, read from chip8.toml in the config directory:
+//     Linux    $XDG_CONFIG_HOME/chip8/chip8.toml, usually ~/.config/chip8/chip8.toml
+//     macOS    ~/Library/Application Support/chip8/chip8.toml
+//     Windows  %APPDATA%\chip8\config\chip8.toml
+// or $CHIP8_CONFIG_DIR/chip8.toml when that's set.
+// Every key is optional and a missing file means the defaults, the README has a full file.
+// Command line flags win over what the file says, that merge is up to the binary, which
+// hands them to with_overrides. machine and emulator make what the settings describe, for
+// the binary and for programs using the library with the same file.
+//
+// Scale, palette, volume and keymap can change while playing, save_tweaks writes them back,
+// and [window] where the window was when chip8 closed.
+// Watcher notices edits of the file so they apply without a restart.
+//
+// Files the emulator writes (save states, RPL flags, screenshots, recordings, crash captures)
+// go to the standard places of the platform too, see data_dir. $CHIP8_DATA_DIR or [paths] move them.
+//
+// [game."<name or hash>"] sections change the speed, keymap, palette, platform or quirks of
+// one ROM, found by its file name (with or without .ch8) or the hex rom_hash of its contents.
+// [profile.<name>] sections are the same kind of fragment under a name, for games and the
+// command line to pick. Profiles can pick other profiles, vip and schip-modern are built in.
+use crate::chip8::{Chip8, Chip8Error, Pitch, Timbre, rom_hash};
+use crate::cpu::{Platform, Quirks};
+use crate::display::{Aspect, Palette};
+use crate::emulator::Emulator;
+use crate::icon::{DEFAULT_TITLE, WindowGeometry};
+use crate::keyboard::{HostKey, Keymap, LayoutPreset};
+use crate::memory::Patch;
+use directories::{ProjectDirs, UserDirs};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, fs, io};
+use toml_edit::{DocumentMut, InlineTable, Item, table, value};
+
+pub const FILE_NAME: &str = "chip8.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    // bad TOML or a bad value, the message says which line
+    Parse(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "{}", error),
+            ConfigError::Parse(message) => write!(f, "{}", message.trim_end()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // instructions per frame, 60 frames a second
+    pub speed: u32,
+    // host keys of 0x0 to 0xF in order, see Keymap
+    pub keymap: Keymap,
+    pub display: DisplayConfig,
+    pub audio: AudioConfig,
+    // "chip8", or "schip" for SUPER-CHIP games
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub hotkeys: Hotkeys,
+    pub paths: Paths,
+    pub window: WindowConfig,
+    pub game: BTreeMap<String, Overrides>,
+    pub profile: BTreeMap<String, Overrides>,
+    // patches of the ROM from its section and the profiles, not a key of its own since
+    // patches only make sense for one ROM
+    #[serde(skip)]
+    pub patch: Vec<Patch>,
+}
+
+// What a [game."..."] or [profile.name] section can change, anything left out comes from the
+// settings it applies over. A quirks table there replaces the whole quirks table
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Overrides {
+    // profiles applied first, in order, then the values below
+    pub profile: Vec<String>,
+    pub speed: Option<u32>,
+    pub keymap: Option<Keymap>,
+    pub palette: Option<Palette>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    // "0x2A4=0xEE" bytes to change after loading, added to the ones of the profiles
+    pub patch: Vec<Patch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    // window pixels per chip8 pixel, the terminal ignores it
+    pub scale: usize,
+    // "2:1" makes pixels twice as wide as tall, see display::Aspect
+    pub aspect: Aspect,
+    // "#RRGGBB" colors of lit and unlit pixels
+    pub palette: Palette,
+    // window title, {rom} is the file name without .ch8 and {version} the one of chip8
+    pub title: String,
+    // PNG for the window icon, the font's 8 in the palette colors without it
+    pub icon: Option<PathBuf>,
+    // lowers the contrast while a game strobes, see flash.rs
+    pub flash_limit: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    pub enabled: bool,
+    // 0.0 to 1.0
+    pub volume: f32,
+    // beep frequency in Hz, the lowest note when the pitch follows the game
+    pub tone: f32,
+    // "fixed", or "timer" / "v0" to "vf" for the musical mode of audio.rs
+    pub pitch: Pitch,
+    // how many values up make an octave in musical mode, 12 is a semitone each
+    pub steps_per_octave: f32,
+    // "sine", or "vip" for the square buzzer of the COSMAC VIP at its own 1400 Hz
+    pub timbre: Timbre,
+    // the 60 Hz hum of the VIP's power supply on the buzzer
+    pub ripple: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hotkeys {
+    pub quit: HostKey,
+    pub pause: HostKey,
+    // back to the machine as it was loaded
+    pub reset: HostKey,
+    // to the next profile, or none after the last one
+    pub profile: HostKey,
+    // shows or hides the keypad with the keys that are down
+    pub keypad: HostKey,
+    // these three are saved in the file right away
+    pub swap_colors: HostKey,
+    pub volume_down: HostKey,
+    pub volume_up: HostKey,
+    // the screen to the clipboard as an image
+    pub copy_frame: HostKey,
+    // shows or hides the graph of the last frame times
+    pub frame_times: HostKey,
+    // in and out of fullscreen, only the sdl2 frontend can
+    pub fullscreen: HostKey,
+    // opens or closes a second window with the registers, memory and code
+    pub debug_window: HostKey,
+    // the machine to the ROM's file in the states folder and back
+    pub quicksave: HostKey,
+    pub quickload: HostKey,
+    // goes back in time while it's held
+    pub rewind: HostKey,
+}
+
+// Folders to use instead of the standard ones, see data_dir
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Paths {
+    // where the games are, the file dialog of `chip8` without a ROM opens there
+    pub roms: Option<PathBuf>,
+    pub states: Option<PathBuf>,
+    pub rpl: Option<PathBuf>,
+    pub screenshots: Option<PathBuf>,
+    pub recordings: Option<PathBuf>,
+    pub captures: Option<PathBuf>,
+}
+
+// Where the window was when chip8 last closed, written on the way out. Missing keys open
+// the window in the default spot and at display.scale
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    // false opens the window the default way every time and stops the writing
+    pub remember: bool,
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fullscreen: bool,
+    // the screens it was on, see WindowGeometry
+    pub monitors: Option<String>,
+}
+
+impl WindowConfig {
+    // What to open the window with, nothing when it shouldn't be remembered
+    pub fn geometry(&self) -> WindowGeometry {
+        if !self.remember {
+            return WindowGeometry::default();
+        }
+        WindowGeometry {
+            position: self.x.zip(self.y),
+            size: self.width.zip(self.height),
+            fullscreen: self.fullscreen,
+            monitors: self.monitors.clone(),
+        }
+    }
+
+    // Where the window is now, for the next save_tweaks
+    pub fn set_geometry(&mut self, geometry: &WindowGeometry) {
+        (self.x, self.y) = geometry.position.unzip();
+        (self.width, self.height) = geometry.size.unzip();
+        self.fullscreen = geometry.fullscreen;
+        self.monitors = geometry.monitors.clone();
+    }
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            remember: true,
+            x: None,
+            y: None,
+            width: None,
+            height: None,
+            fullscreen: false,
+            monitors: None,
+        }
+    }
+}
+
+// The kinds of files the emulator writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataKind {
+    States,
+    // the SCHIP user flags that survive between runs
+    Rpl,
+    Screenshots,
+    Recordings,
+    // what --capture saves when a game crashes
+    Captures,
+}
+
+impl DataKind {
+    pub const ALL: [DataKind; 5] = [
+        DataKind::States,
+        DataKind::Rpl,
+        DataKind::Screenshots,
+        DataKind::Recordings,
+        DataKind::Captures,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DataKind::States => "states",
+            DataKind::Rpl => "rpl",
+            DataKind::Screenshots => "screenshots",
+            DataKind::Recordings => "recordings",
+            DataKind::Captures => "captures",
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            speed: 10,
+            keymap: Keymap::new(),
+            display: DisplayConfig::default(),
+            audio: AudioConfig::default(),
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            hotkeys: Hotkeys::default(),
+            paths: Paths::default(),
+            window: WindowConfig::default(),
+            game: BTreeMap::new(),
+            profile: BTreeMap::new(),
+            patch: Vec::new(),
+        }
+    }
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig {
+            scale: 20,
+            aspect: Aspect::SQUARE,
+            palette: Palette::default(),
+            title: DEFAULT_TITLE.into(),
+            icon: None,
+            flash_limit: true,
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            enabled: true,
+            volume: 0.2,
+            tone: 440.0,
+            pitch: Pitch::Fixed,
+            steps_per_octave: 12.0,
+            timbre: Timbre::Sine,
+            ripple: false,
+        }
+    }
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Hotkeys {
+            quit: HostKey::Escape,
+            pause: HostKey::Char('p'),
+            reset: HostKey::F(5),
+            profile: HostKey::F(4),
+            keypad: HostKey::F(3),
+            swap_colors: HostKey::F(6),
+            volume_down: HostKey::F(7),
+            volume_up: HostKey::F(8),
+            copy_frame: HostKey::F(9),
+            frame_times: HostKey::F(2),
+            fullscreen: HostKey::F(11),
+            debug_window: HostKey::F(10),
+            quicksave: HostKey::F(1),
+            quickload: HostKey::F(12),
+            rewind: HostKey::Backspace,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let config: Config =
+            toml::from_str(text).map_err(|error| ConfigError::Parse(error.to_string()))?;
+        let mut sections = config.game.values().chain(config.profile.values());
+        if config.speed == 0 || sections.any(|section| section.speed == Some(0)) {
+            return Err(ConfigError::Parse("speed must be at least 1".into()));
+        }
+        // unknown profiles and loops are found now rather than when a game starts
+        for section in config.game.values().chain(config.profile.values()) {
+            config.clone().apply(section, 0)?;
+        }
+        if config.display.scale == 0 {
+            return Err(ConfigError::Parse(
+                "display.scale must be at least 1".into(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&config.audio.volume) {
+            return Err(ConfigError::Parse("audio.volume must be 0.0 to 1.0".into()));
+        }
+        if config.audio.tone <= 0.0 {
+            return Err(ConfigError::Parse("audio.tone must be above 0 Hz".into()));
+        }
+        if config.audio.steps_per_octave < 1.0 {
+            return Err(ConfigError::Parse(
+                "audio.steps_per_octave must be at least 1".into(),
+            ));
+        }
+        Ok(config)
+    }
+
+    // The [game."..."] section of a ROM and its key, the hash wins over the file name
+    pub fn find_game(&self, path: &Path, rom: &[u8]) -> Option<(&str, &Overrides)> {
+        let hash = format!("{:016x}", rom_hash(rom));
+        let file_name = path.file_name().and_then(|name| name.to_str());
+        let stem = path.file_stem().and_then(|stem| stem.to_str());
+        [Some(hash.as_str()), file_name, stem]
+            .into_iter()
+            .flatten()
+            .find_map(|name| {
+                self.game
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            })
+            .map(|(key, game)| (key.as_str(), game))
+    }
+
+    // Where files of this kind go, first of:
+    //     [paths] in the file
+    //     $CHIP8_DATA_DIR/<kind>
+    //     screenshots and recordings: chip8 in the pictures and videos folders of the user
+    //     the data directory of the platform /<kind>, like ~/.local/share/chip8/states
+    // Nothing is created here, whoever writes the first file does that
+    pub fn data_dir(&self, kind: DataKind) -> Option<PathBuf> {
+        let chosen = match kind {
+            DataKind::States => &self.paths.states,
+            DataKind::Rpl => &self.paths.rpl,
+            DataKind::Screenshots => &self.paths.screenshots,
+            DataKind::Recordings => &self.paths.recordings,
+            DataKind::Captures => &self.paths.captures,
+        };
+        if let Some(dir) = chosen {
+            return Some(dir.clone());
+        }
+        if let Some(dir) = env_dir("CHIP8_DATA_DIR") {
+            return Some(dir.join(kind.name()));
+        }
+        let user = UserDirs::new();
+        let media = match kind {
+            DataKind::Screenshots => user.as_ref().and_then(UserDirs::picture_dir),
+            DataKind::Recordings => user.as_ref().and_then(UserDirs::video_dir),
+            _ => None,
+        };
+        match media {
+            Some(dir) => Some(dir.join("chip8")),
+            None => Some(project_dirs()?.data_dir().join(kind.name())),
+        }
+    }
+
+    // These settings with the section of the ROM applied, if it has one
+    pub fn for_rom(&self, path: &Path, rom: &[u8]) -> Config {
+        let mut config = self.clone();
+        if let Some((_, game)) = self.find_game(path, rom) {
+            // parse already checked its profiles
+            let _ = config.apply(game, 0);
+        }
+        config
+    }
+
+    // These settings with profiles applied over them in order, like --profile vip,green
+    pub fn with_profiles(&self, names: &[String]) -> Result<Config, ConfigError> {
+        let mut config = self.clone();
+        let overrides = Overrides {
+            profile: names.to_vec(),
+            ..Overrides::default()
+        };
+        config.apply(&overrides, 0)?;
+        Ok(config)
+    }
+
+    // These settings with overrides from elsewhere over them, like the recommended options
+    // of a ROM in the chip8Archive
+    pub fn with_overrides(&self, overrides: &Overrides) -> Result<Config, ConfigError> {
+        let mut config = self.clone();
+        config.apply(overrides, 0)?;
+        Ok(config)
+    }
+
+    // Built in profiles first, then the ones of the file
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = BUILT_IN_PROFILES
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        for name in self.profile.keys() {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    // A profile of the file wins over a built in one of the same name
+    pub fn find_profile(&self, name: &str) -> Option<Overrides> {
+        self.profile
+            .get(name)
+            .cloned()
+            .or_else(|| built_in_profile(name))
+    }
+
+    fn apply(&mut self, overrides: &Overrides, depth: usize) -> Result<(), ConfigError> {
+        if depth > MAX_PROFILE_DEPTH {
+            return Err(ConfigError::Parse(
+                "profiles pick each other in a loop".into(),
+            ));
+        }
+        for name in &overrides.profile {
+            let profile = self
+                .find_profile(name)
+                .ok_or_else(|| ConfigError::Parse(format!("there's no profile `{}`", name)))?;
+            self.apply(&profile, depth + 1)?;
+        }
+        self.speed = overrides.speed.unwrap_or(self.speed);
+        self.keymap = overrides.keymap.unwrap_or(self.keymap);
+        self.display.palette = overrides.palette.unwrap_or(self.display.palette);
+        self.platform = overrides.platform.unwrap_or(self.platform);
+        self.quirks = overrides.quirks.unwrap_or(self.quirks);
+        self.patch.extend_from_slice(&overrides.patch);
+        Ok(())
+    }
+
+    // The ROM in a new machine of this platform, with these quirks and patches. Use for_rom
+    // first for the ROM's own section. RomTooLarge when it doesn't fit the platform
+    pub fn machine(&self, rom: &[u8]) -> Result<Chip8, Chip8Error> {
+        let mut chip = Chip8::new(&[])?;
+        chip.set_platform(self.platform);
+        chip.load_rom(rom)?;
+        chip.quirks = self.quirks;
+        chip.apply_patches(&self.patch)?;
+        Ok(chip)
+    }
+
+    // The same machine in an Emulator running `speed` instructions per frame, silent and
+    // without a display until those are set on it
+    pub fn emulator(&self, rom: &[u8]) -> Result<Emulator, Chip8Error> {
+        Ok(Emulator::new(self.machine(rom)?, self.speed))
+    }
+
+    // Writes scale, palette, volume and keymap into the file at path where they differ from
+    // what it says, the rest of the file and its comments stay as they are. The new file
+    // replaces the old one with a rename, so a crash never leaves half a file behind
+    pub fn save_tweaks(&self, path: &Path) -> Result<(), ConfigError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(ConfigError::Io(error)),
+        };
+        let saved = Config::parse(&text)?;
+        let mut document: DocumentMut = text
+            .parse()
+            .map_err(|error: toml_edit::TomlError| ConfigError::Parse(error.to_string()))?;
+
+        if self == &saved {
+            return Ok(());
+        }
+        if self.display.scale != saved.display.scale {
+            section(&mut document, "display")["scale"] = value(self.display.scale as i64);
+        }
+        if self.display.palette != saved.display.palette {
+            let mut palette = InlineTable::new();
+            palette.insert("on", color_text(self.display.palette.on).into());
+            palette.insert("off", color_text(self.display.palette.off).into());
+            // the XO-CHIP colors only when they aren't the usual ones
+            let default = Palette::default();
+            let (second, both) = (self.display.palette.second, self.display.palette.both);
+            if second != default.second {
+                palette.insert("second", color_text(second).into());
+            }
+            if both != default.both {
+                palette.insert("both", color_text(both).into());
+            }
+            section(&mut document, "display")["palette"] = value(palette);
+        }
+        if self.audio.volume != saved.audio.volume {
+            // 0.3 and not 0.30000001192092896
+            let volume = (self.audio.volume as f64 * 100.0).round() / 100.0;
+            section(&mut document, "audio")["volume"] = value(volume);
+        }
+        if self.keymap != saved.keymap {
+            let keymap: String = self.keymap.iter().map(|(host_key, _)| host_key).collect();
+            document["keymap"] = value(keymap);
+        }
+        if self.window != saved.window {
+            let (window, now) = (section(&mut document, "window"), &self.window);
+            let numbers = [
+                ("x", now.x.map(i64::from)),
+                ("y", now.y.map(i64::from)),
+                ("width", now.width.map(i64::from)),
+                ("height", now.height.map(i64::from)),
+            ];
+            for (key, number) in numbers {
+                set_or_remove(window, key, number.map(value));
+            }
+            window["fullscreen"] = value(now.fullscreen);
+            set_or_remove(window, "monitors", now.monitors.as_deref().map(value));
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(ConfigError::Io)?;
+        }
+        let temporary = path.with_extension("toml.tmp");
+        let write = || -> io::Result<()> {
+            let mut file = fs::File::create(&temporary)?;
+            io::Write::write_all(&mut file, document.to_string().as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temporary, path)
+        };
+        write().map_err(|error| {
+            let _ = fs::remove_file(&temporary);
+            ConfigError::Io(error)
+        })
+    }
+
+    // No file is fine, that's the defaults
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(error) => Err(ConfigError::Io(error)),
+        }
+    }
+}
+
+const MAX_PROFILE_DEPTH: usize = 8;
+
+const BUILT_IN_PROFILES: [&str; 2] = ["vip", "schip-modern"];
+
+fn built_in_profile(name: &str) -> Option<Overrides> {
+    let (speed, platform, quirks) = match name {
+        // the original interpreter on the COSMAC VIP, what most old games expect
+        "vip" => (10, Platform::Chip8, Quirks::VIP),
+        // SUPER-CHIP as later emulators run it, what most games from the 90s on expect
+        "schip-modern" => (30, Platform::SuperChip, Quirks::MODERN),
+        _ => return None,
+    };
+    Some(Overrides {
+        speed: Some(speed),
+        platform: Some(platform),
+        quirks: Some(quirks),
+        ..Overrides::default()
+    })
+}
+
+// Notices when the file changes. Only looks at its modification time, at most twice a
+// second, so it's cheap enough to call every loop
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    next_check: Instant,
+}
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+impl Watcher {
+    pub fn new(path: &Path) -> Self {
+        Watcher {
+            path: path.to_path_buf(),
+            modified: modified(path),
+            next_check: Instant::now() + WATCH_INTERVAL,
+        }
+    }
+
+    // The file read again when it changed since the last call, errors included so they can
+    // be shown. A file that went away is the defaults, like at startup
+    pub fn poll(&mut self) -> Option<Result<Config, ConfigError>> {
+        let now = Instant::now();
+        if now < self.next_check {
+            return None;
+        }
+        self.next_check = now + WATCH_INTERVAL;
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(Config::load(&self.path))
+    }
+
+    // After writing the file ourselves, so that doesn't count as a change
+    pub fn mark_seen(&mut self) {
+        self.modified = modified(&self.path);
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Where chip8.toml lives on this machine, None if there's no home directory to speak of
+pub fn default_path() -> Option<PathBuf> {
+    let dir = match env_dir("CHIP8_CONFIG_DIR") {
+        Some(dir) => dir,
+        None => project_dirs()?.config_dir().to_path_buf(),
+    };
+    Some(dir.join(FILE_NAME))
+}
+
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("", "", "chip8")
+}
+
+// An empty variable counts as not set, like XDG does
+fn env_dir(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+// The core types stay free of serde, their TOML forms live here
+
+impl<'de> Deserialize<'de> for Keymap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        parse_keymap(&text).ok_or_else(|| {
+            D::Error::custom(
+                "keymap wants qwerty, azerty, colemak, mirror or 16 different letters or \
+                 digits, the keys of 0 to F",
+            )
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for HostKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        HostKey::parse(&name).ok_or_else(|| D::Error::custom(format!("unknown key `{}`", name)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Pitch {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Pitch::parse(&name).ok_or_else(|| {
+            D::Error::custom(format!(
+                "unknown pitch `{}`, it's fixed, timer or v0 to vf",
+                name
+            ))
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Timbre {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Timbre::parse(&name)
+            .ok_or_else(|| D::Error::custom(format!("unknown timbre `{}`, it's sine or vip", name)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Patch {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Patch::parse(&text).ok_or_else(|| {
+            D::Error::custom(format!(
+                "bad patch `{}`, it's \"address=value\" like \"0x2A4=0xEE\"",
+                text
+            ))
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Aspect {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Aspect::parse(&text)
+            .ok_or_else(|| D::Error::custom(format!("`{}` is not an aspect like 1:1 or 2:1", text)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Palette {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Colors {
+            // a Palette::preset the colors below change
+            preset: Option<String>,
+            on: Option<String>,
+            off: Option<String>,
+            second: Option<String>,
+            both: Option<String>,
+        }
+
+        let colors = Colors::deserialize(deserializer)?;
+        let color = |text: Option<String>, default: u32| match text {
+            None => Ok(default),
+            Some(text) => parse_color(&text)
+                .ok_or_else(|| D::Error::custom(format!("`{}` is not a #RRGGBB color", text))),
+        };
+        let default = match colors.preset {
+            None => Palette::default(),
+            Some(name) => Palette::preset(&name).ok_or_else(|| {
+                D::Error::custom(format!(
+                    "there's no palette `{}`, only {}",
+                    name,
+                    Palette::PRESETS.join(", ")
+                ))
+            })?,
+        };
+        Ok(Palette {
+            on: color(colors.on, default.on)?,
+            off: color(colors.off, default.off)?,
+            second: color(colors.second, default.second)?,
+            both: color(colors.both, default.both)?,
+        })
+    }
+}
+
+// A [section] of the file, added at the end when it isn't there yet
+fn section<'a>(document: &'a mut DocumentMut, name: &str) -> &'a mut Item {
+    document.entry(name).or_insert(table())
+}
+
+fn set_or_remove(table: &mut Item, key: &str, item: Option<Item>) {
+    match item {
+        Some(item) => table[key] = item,
+        None => {
+            if let Some(table) = table.as_table_like_mut() {
+                table.remove(key);
+            }
+        }
+    }
+}
+
+fn color_text(color: u32) -> String {
+    format!("#{:06X}", color)
+}
+
+// "#33FF66,#000000" for lit and unlit pixels, then XO-CHIP's second plane and both planes
+// if given
+// A LayoutPreset by name, or the 16 host keys of Keymap::parse
+pub fn parse_keymap(text: &str) -> Option<Keymap> {
+    LayoutPreset::parse(text)
+        .map(Keymap::preset)
+        .or_else(|| Keymap::parse(text))
+}
+
+// A Palette::preset by name, or 2 to 4 colors for on, off, second and both
+pub fn parse_palette(text: &str) -> Option<Palette> {
+    if let Some(palette) = Palette::preset(text.trim()) {
+        return Some(palette);
+    }
+    let colors: Vec<u32> = text
+        .split(',')
+        .map(|c| parse_color(c.trim()))
+        .collect::<Option<_>>()?;
+    let default = Palette::default();
+    match colors[..] {
+        [on, off] => Some(Palette { on, off, ..default }),
+        [on, off, second] => Some(Palette {
+            on,
+            off,
+            second,
+            ..default
+        }),
+        [on, off, second, both] => Some(Palette {
+            on,
+            off,
+            second,
+            both,
+        }),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_color(text: &str) -> Option<u32> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r##"
+            speed = 15
+            keymap = "0123456789abcdef"
+            platform = "schip"
+
+            [display]
+            scale = 8
+            aspect = "2:1"
+            palette = { on = "#33FF66" }
+            title = "{rom} - chip8"
+
+            [audio]
+            volume = 0.5
+            pitch = "timer"
+            timbre = "VIP"
+
+            [quirks]
+            shift_uses_vy = false
+
+            [hotkeys]
+            pause = "Space"
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(config.speed, 15);
+        assert_eq!(config.keymap.key_for('a'), Some(0xA));
+        assert_eq!(config.display.scale, 8);
+        assert_eq!(config.display.aspect.to_string(), "2:1");
+        assert_eq!(config.display.palette.on, 0x33FF66);
+        // whatever isn't in the file keeps its default
+        assert_eq!(config.display.palette.off, 0x000000);
+        assert_eq!(config.display.title, "{rom} - chip8");
+        assert_eq!(config.display.icon, None);
+        assert_eq!(config.audio.volume, 0.5);
+        assert_eq!(config.audio.pitch, Pitch::SoundTimer);
+        assert_eq!(config.audio.timbre, Timbre::Vip);
+        assert!(!config.audio.ripple);
+        assert!(config.audio.enabled);
+        assert_eq!(config.platform, Platform::SuperChip);
+        assert!(!config.quirks.shift_uses_vy);
+        assert!(config.quirks.vf_reset);
+        assert_eq!(config.hotkeys.pause, HostKey::Space);
+        assert_eq!(config.hotkeys.quit, HostKey::Escape);
+
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_game_sections() {
+        let rom = [0x12, 0x00];
+        let config = Config::parse(&format!(
+            r##"
+            speed = 10
+
+            [game.pong]
+            speed = 4
+
+            [game."{:016x}"]
+            palette = {{ on = "#FFFFFF" }}
+            quirks = {{ vf_reset = false }}
+
+            [game."brix.ch8"]
+            keymap = "0123456789abcdef"
+            "##,
+            rom_hash(&rom)
+        ))
+        .unwrap();
+
+        let pong = config.for_rom(Path::new("roms/PONG.ch8"), &[0x00, 0xE0]);
+        assert_eq!(pong.speed, 4);
+        assert_eq!(pong.display.palette, Palette::default());
+
+        // the hash finds it whatever the file is called, and beats the name
+        let (key, _) = config.find_game(Path::new("pong.ch8"), &rom).unwrap();
+        assert_eq!(key, format!("{:016x}", rom_hash(&rom)));
+        let hashed = config.for_rom(Path::new("pong.ch8"), &rom);
+        assert_eq!(hashed.speed, 10);
+        assert_eq!(hashed.display.palette.on, 0xFFFFFF);
+        assert!(!hashed.quirks.vf_reset);
+
+        let brix = config.for_rom(Path::new("brix.ch8"), &[0x00, 0xE0]);
+        assert_eq!(brix.keymap.key_for('f'), Some(0xF));
+        let other = config.for_rom(Path::new("tetris.ch8"), &[0x00, 0xE0]);
+        assert_eq!(other, config);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config = Config::parse(
+            r##"
+            [quirks]
+            jump_uses_vx = true
+
+            [profile.green]
+            palette = { on = "#33FF66" }
+
+            [profile.vip-green]
+            profile = ["vip", "green"]
+            speed = 12
+
+            # a file profile with a built in name wins
+            [profile.schip-modern]
+            speed = 40
+
+            [profile.skip-intro]
+            patch = ["0x200=0x12", "0x201=0x40"]
+
+            [game.pong]
+            profile = ["vip-green", "skip-intro"]
+            speed = 4
+            patch = ["0x201=0x60"]
+            "##,
+        )
+        .unwrap();
+
+        let pong = config.for_rom(Path::new("pong.ch8"), &[0x00, 0xE0]);
+        assert_eq!(pong.speed, 4);
+        assert_eq!(pong.quirks, Quirks::VIP);
+        assert_eq!(pong.display.palette.on, 0x33FF66);
+        let patched: Vec<_> = pong.patch.iter().map(|patch| patch.value).collect();
+        assert_eq!(patched, [0x12, 0x40, 0x60]);
+
+        let names = ["vip-green".to_string(), "schip-modern".to_string()];
+        let picked = config.with_profiles(&names).unwrap();
+        assert_eq!(picked.speed, 40);
+        assert_eq!(picked.quirks, Quirks::VIP);
+        assert_eq!(picked.display.palette.on, 0x33FF66);
+        assert!(config.with_profiles(&["nope".to_string()]).is_err());
+
+        assert_eq!(
+            config.profile_names(),
+            ["vip", "schip-modern", "green", "skip-intro", "vip-green"]
+        );
+        let modern = Config::default()
+            .with_profiles(&["schip-modern".to_string()])
+            .unwrap();
+        assert_eq!(modern.quirks, Quirks::MODERN);
+        assert_eq!(modern.platform, Platform::SuperChip);
+    }
+
+    #[test]
+    fn test_bad_values() {
+        for text in [
+            "speed = 0",
+            "sped = 10",
+            "keymap = \"123\"",
+            "[display]\npalette = { on = \"orange\" }",
+            "[game.pong]\npatch = [\"0x1000=0\"]",
+            "patch = [\"0x200=0\"]",
+            "[audio]\nvolume = 2.0",
+            "[audio]\npitch = \"v16\"",
+            "[hotkeys]\nquit = \"Shift\"",
+            "[quirks]\nwrap_sprites = true",
+            "[game.pong]\nspeed = 0",
+            "[game.pong]\nscale = 2",
+            "[game.pong]\nprofile = [\"nope\"]",
+            "[profile.a]\nprofile = [\"b\"]\n[profile.b]\nprofile = [\"a\"]",
+        ] {
+            assert!(
+                matches!(Config::parse(text), Err(ConfigError::Parse(_))),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_machine() {
+        let config = Config::parse(
+            r#"
+            speed = 15
+            platform = "xochip"
+            [quirks]
+            vf_reset = false
+            [game.big]
+            patch = ["0x300=0xAB"]
+            "#,
+        )
+        .unwrap()
+        .for_rom(Path::new("big.ch8"), &[]);
+        // larger than 4 KiB, which only fits XO-CHIP
+        let rom = vec![0x12; 0x1100];
+        let chip = config.machine(&rom).unwrap();
+        assert_eq!(chip.platform(), Platform::XoChip);
+        assert!(!chip.quirks.vf_reset);
+        assert_eq!(chip.get_ram(0x300), Ok(0xAB));
+        assert_eq!(config.emulator(&rom).unwrap().lockstep().ipf(), 15);
+
+        let chip8 = Config::default();
+        assert_eq!(chip8.machine(&rom).err(), Some(Chip8Error::RomTooLarge));
+    }
+
+    #[test]
+    fn test_keymap_presets() {
+        let config = Config::parse("keymap = \"azerty\"").unwrap();
+        assert_eq!(config.keymap, Keymap::preset(LayoutPreset::Azerty));
+        let config = Config::parse("[profile.left]\nkeymap = \"Mirror\"").unwrap();
+        let mirror = config.with_profiles(&["left".to_string()]).unwrap();
+        assert_eq!(mirror.keymap, Keymap::preset(LayoutPreset::Mirror));
+        assert_eq!(
+            parse_keymap("0123456789abcdef"),
+            Keymap::parse("0123456789abcdef")
+        );
+        assert!(Config::parse("keymap = \"dvorak\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_palette() {
+        let palette = parse_palette("#33FF66, 000000").unwrap();
+        assert_eq!((palette.on, palette.off), (0x33FF66, 0));
+        assert_eq!(palette.both, Palette::default().both);
+        let palette = parse_palette("#111111,#222222,#333333,#444444").unwrap();
+        assert_eq!(palette.both, 0x444444);
+        assert_eq!(parse_palette("gameboy"), Palette::preset("gameboy"));
+        let config =
+            Config::parse("[display]\npalette = { preset = \"green\", off = \"#000000\" }");
+        let palette = config.unwrap().display.palette;
+        assert_eq!((palette.on, palette.off), (0x33FF66, 0));
+        assert!(Config::parse("[display]\npalette = { preset = \"sepia\" }").is_err());
+        for text in ["#33FF66", "#33FF66,black", "1,2,3,4,5", ""] {
+            assert_eq!(parse_palette(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_save_tweaks_keeps_the_rest() {
+        let dir = std::env::temp_dir().join(format!("chip8-config-{}", std::process::id()));
+        let path = dir.join(FILE_NAME);
+        let _ = fs::remove_dir_all(&dir);
+
+        // no file yet, nothing changed, nothing written
+        Config::default().save_tweaks(&path).unwrap();
+        assert!(!path.exists());
+
+        fs::create_dir_all(&dir).unwrap();
+        let text = "# my settings\nspeed = 15 # fast\n\n[audio]\nvolume = 0.5\n";
+        fs::write(&path, text).unwrap();
+        let mut config = Config::load(&path).unwrap();
+        config.audio.volume = 0.3;
+        config.display.palette.on = 0x33FF66;
+        config.keymap = Keymap::parse("0123456789abcdef").unwrap();
+        config.save_tweaks(&path).unwrap();
+
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.starts_with("# my settings\nspeed = 15 # fast\n"));
+        assert!(saved.contains("volume = 0.3\n"));
+        assert_eq!(Config::load(&path).unwrap(), config);
+
+        // the window moved and left fullscreen, a monitor went away
+        config.window.set_geometry(&WindowGeometry {
+            position: Some((40, -20)),
+            size: Some((1280, 640)),
+            fullscreen: true,
+            monitors: Some("1920x1080+0+0".into()),
+        });
+        config.save_tweaks(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+        config.window.set_geometry(&WindowGeometry {
+            size: Some((640, 320)),
+            ..WindowGeometry::default()
+        });
+        config.save_tweaks(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("monitors") && !saved.contains("x ="));
+        assert_eq!(
+            Config::load(&path).unwrap().window.geometry().size,
+            Some((640, 320))
+        );
+        assert!(!path.with_extension("toml.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_watcher() {
+        let dir = std::env::temp_dir().join(format!("chip8-watch-{}", std::process::id()));
+        let path = dir.join(FILE_NAME);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "speed = 10\n").unwrap();
+        let mut watcher = Watcher::new(&path);
+        // file times can be coarse, so every write below moves the time on by hand
+        let touch = |text: &str, seconds: u64| {
+            fs::write(&path, text).unwrap();
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::now() + Duration::from_secs(seconds))
+                .unwrap();
+        };
+
+        watcher.next_check = Instant::now();
+        assert!(watcher.poll().is_none());
+
+        touch("speed = 20\n", 10);
+        // not before the interval
+        assert!(watcher.poll().is_none());
+        watcher.next_check = Instant::now();
+        assert_eq!(watcher.poll().unwrap().unwrap().speed, 20);
+
+        touch("speed = \n", 20);
+        watcher.next_check = Instant::now();
+        assert!(matches!(watcher.poll(), Some(Err(ConfigError::Parse(_)))));
+
+        touch("speed = 30\n", 30);
+        watcher.mark_seen();
+        watcher.next_check = Instant::now();
+        assert!(watcher.poll().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_data_dirs() {
+        let config = Config::parse(
+            r#"
+            [paths]
+            roms = "/games"
+            states = "/games/states"
+            "#,
+        )
+        .unwrap();
+        let states = config.data_dir(DataKind::States).unwrap();
+        assert_eq!(states, Path::new("/games/states"));
+        assert_eq!(config.paths.roms.as_deref(), Some(Path::new("/games")));
+        // the others stay where the platform wants them, if there's a home at all
+        if let Some(rpl) = config.data_dir(DataKind::Rpl) {
+            assert!(rpl.ends_with("rpl"));
+        }
+        assert!(Config::parse("[paths]\nsaves = \"/tmp\"").is_err());
+    }
+
+    #[test]
+    fn test_missing_file_is_defaults() {
+        let path = std::env::temp_dir()
+            .join("chip8-no-such-dir")
+            .join(FILE_NAME);
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+    }
+}
@@ -1,9 +1,16 @@
 use crate::chip8::{Chip8, Chip8Error};
+use crate::memory::MemoryMap;
+#[cfg(feature = "alloc")]
+use crate::trace::{Registers, TraceEntry};
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+use core::fmt;
 
 /*  HEX     DESCRIPTION                                   ENUM NAME
 
     00E0    Clear the display                              ClearDisplay
     00EE    Return from subroutine                         Return
+    0nnn    Machine code routine at nnn, not emulated      (UnknownInstruction)
 
     1nnn    Jump to address nnn                            Jump
     2nnn    Call subroutine at nnn                         Call
@@ -38,7 +45,7 @@ use crate::chip8::{Chip8, Chip8Error};
     ExA1    Skip next instruction if key[Vx] not pressed   SkipIfKeyNotPressed
 
     Fx07    Set Vx = delay timer value                     LoadDelayTimer
-    Fx0A    Wait for key press, store key in Vx            WaitKey
+    Fx0A    Wait for key press and release, key in Vx      WaitKey
     Fx15    Set delay timer = Vx                           SetDelayTimer
     Fx18    Set sound timer = Vx                           SetSoundTimer
     Fx1E    Set I = I + Vx                                 AddVxI
@@ -46,10 +53,132 @@ use crate::chip8::{Chip8, Chip8Error};
     Fx33    Store BCD of Vx in memory at I                 BCD
     Fx55    Store V0..Vx in memory starting at I           StoreMemV
     Fx65    Load V0..Vx from memory starting at I          LoadMemV
+
+    SUPER-CHIP only (Platform::SuperChip):
+    00Cn    Scroll the screen down n lines                 ScrollDown
+    00FB    Scroll the screen right 4 pixels               ScrollRight
+    00FC    Scroll the screen left 4 pixels                ScrollLeft
+    00FD    Exit the interpreter, stays on this instruction Exit
+    00FE    Low-res, 64x32                                 LowRes
+    00FF    Hi-res, 128x64                                 HighRes
+    Dxy0    Draw a 16x16 sprite at (Vx, Vy)                DrawLarge
+    Fx30    Set I to the large font digit Vx               LoadLargeFont
+    Fx75    Save V0..Vx in the flags                       StoreFlags
+    Fx85    Load V0..Vx from the flags                     LoadFlags
+
+    XO-CHIP only (Platform::XoChip), on top of SUPER-CHIP:
+    00Dn    Scroll the screen up n lines                   ScrollUp
+    5xy2    Store Vx..Vy in memory at I, I stays           SaveRange
+    5xy3    Load Vx..Vy from memory at I, I stays          LoadRange
+    F000    Set I = the 16 bits after it, 4 bytes long     LoadLongI
+    Fn01    Draw on the planes of bitmask n                SelectPlanes
+    F002    Load the 16 bytes at I into the audio pattern  LoadPattern
+    Fx3A    Set the pitch of the audio pattern to Vx       SetPitch
+
+    Only with Chip8::set_debug_port:
+    00FA    Print the string at I to the host              DebugText
+    FxFA    Print Vx to the host                           DebugValue
 */
 
+// Instructions that Chip-8 interpreters never agreed on. The default is how the original
+// interpreter on the COSMAC VIP ran them, what most classic games expect. Games written
+// for SUPER-CHIP or later interpreters want MODERN or some toggles of it
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Quirks {
+    // 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place (COSMAC VIP)
+    pub shift_uses_vy: bool,
+    // Fx55/Fx65 leave I pointing past the last register (COSMAC VIP)
+    pub load_store_increment_i: bool,
+    // Bnnn jumps to xnn + Vx instead of nnn + V0 (SUPER-CHIP)
+    pub jump_uses_vx: bool,
+    // 8xy1/8xy2/8xy3 reset VF to 0 (COSMAC VIP)
+    pub vf_reset: bool,
+}
+
+impl Quirks {
+    pub const VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increment_i: true,
+        jump_uses_vx: false,
+        vf_reset: true,
+    };
+
+    // SUPER-CHIP as most later emulators run it, every toggle the other way
+    pub const MODERN: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increment_i: false,
+        jump_uses_vx: true,
+        vf_reset: false,
+    };
+
+    // vip, or modern (schip too) for the other one
+    pub fn preset(name: &str) -> Option<Quirks> {
+        let is = |preset: &str| name.eq_ignore_ascii_case(preset);
+        if is("vip") {
+            Some(Quirks::VIP)
+        } else if is("modern") || is("schip") {
+            Some(Quirks::MODERN)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::VIP
+    }
+}
+
+// Which interpreter's instructions the CPU knows. SUPER-CHIP adds the 128x64 hi-res screen,
+// scrolling, 16x16 sprites, the large font and the flags on top of Chip-8. XO-CHIP (Octo's)
+// adds a second plane for 4 colors, 64 KiB of RAM and a sound of its own on top of that
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize))]
+pub enum Platform {
+    #[default]
+    #[cfg_attr(feature = "config", serde(rename = "chip8"))]
+    Chip8,
+    #[cfg_attr(feature = "config", serde(rename = "schip"))]
+    SuperChip,
+    #[cfg_attr(feature = "config", serde(rename = "xochip"))]
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 3] = [Platform::Chip8, Platform::SuperChip, Platform::XoChip];
+
+    // "chip8", "schip" or "xochip", like programs.json of the chip8Archive says it
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    // How much RAM its machines have
+    pub fn memory_map(self) -> MemoryMap {
+        match self {
+            Platform::XoChip => MemoryMap::XO_CHIP,
+            _ => MemoryMap::CHIP8,
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Platform> {
+        Platform::ALL
+            .into_iter()
+            .find(|platform| platform.name().eq_ignore_ascii_case(name))
+    }
+}
+
 // Already implemented instructions
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
     ClearDisplay,               // 00E0
     LoadVxByte(usize, u8),      // 6xnn
@@ -70,8 +199,8 @@ pub enum Instruction {
     JimCarrey(usize, usize),    // 8xy4
     BorrowSub(usize, usize),    // 8xy5
     VyBorrowSub(usize, usize),  // 8xy7
-    VxRShift(usize),            // 8xy6
-    VxLShift(usize),            // 8xyE
+    VxRShift(usize, usize),     // 8xy6
+    VxLShift(usize, usize),     // 8xyE
     AddVxI(usize),              // Fx1E
     LoadMemV(usize),            // Fx65
     StoreMemV(usize),           // Fx55
@@ -85,11 +214,176 @@ pub enum Instruction {
     SetDelayTimer(usize),       // Fx15
     SetSoundTimer(usize),       // Fx18
     LoadFont(usize),            // Fx29
+    ScrollDown(u8),             // 00Cn
+    ScrollRight,                // 00FB
+    ScrollLeft,                 // 00FC
+    Exit,                       // 00FD
+    LowRes,                     // 00FE
+    HighRes,                    // 00FF
+    DrawLarge(usize, usize),    // Dxy0
+    LoadLargeFont(usize),       // Fx30
+    StoreFlags(usize),          // Fx75
+    LoadFlags(usize),           // Fx85
+    ScrollUp(u8),               // 00Dn
+    SaveRange(usize, usize),    // 5xy2
+    LoadRange(usize, usize),    // 5xy3
+    LoadLongI,                  // F000 nnnn
+    SelectPlanes(u8),           // Fn01
+    LoadPattern,                // F002
+    SetPitch(usize),            // Fx3A
+    DebugText,                  // 00FA
+    DebugValue(usize),          // FxFA
+}
+
+// Cowgod's mnemonics, "LD V0, 0C" or "DRW V1, V2, 5", for disassembly
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+        match *self {
+            ClearDisplay => write!(f, "CLS"),
+            Return => write!(f, "RET"),
+            Jump(nnn) => write!(f, "JP {:03X}", nnn),
+            Call(nnn) => write!(f, "CALL {:03X}", nnn),
+            JumpIfEq(x, nn) => write!(f, "SE V{:X}, {:02X}", x, nn),
+            JumpIfDiff(x, nn) => write!(f, "SNE V{:X}, {:02X}", x, nn),
+            JumpIfVEq(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            LoadVxByte(x, nn) => write!(f, "LD V{:X}, {:02X}", x, nn),
+            AddVxByte(x, nn) => write!(f, "ADD V{:X}, {:02X}", x, nn),
+            SetVxToVy(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            VxEqVxORvy(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            VxEqVxANDvy(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            VxEqVxXORvy(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            JimCarrey(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            BorrowSub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            VxRShift(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            VyBorrowSub(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            VxLShift(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            JumpIfVDiff(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            SetI(nnn) => write!(f, "LD I, {:03X}", nnn),
+            JumpV0(nnn) => write!(f, "JP V0, {:03X}", nnn),
+            Random(x, nn) => write!(f, "RND V{:X}, {:02X}", x, nn),
+            Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {:X}", x, y, n),
+            SkipIfKeyPressed(x) => write!(f, "SKP V{:X}", x),
+            SkipIfKeyNotPressed(x) => write!(f, "SKNP V{:X}", x),
+            LoadDelayTimer(x) => write!(f, "LD V{:X}, DT", x),
+            WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            SetDelayTimer(x) => write!(f, "LD DT, V{:X}", x),
+            SetSoundTimer(x) => write!(f, "LD ST, V{:X}", x),
+            AddVxI(x) => write!(f, "ADD I, V{:X}", x),
+            LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            BCD(x) => write!(f, "LD B, V{:X}", x),
+            StoreMemV(x) => write!(f, "LD [I], V{:X}", x),
+            LoadMemV(x) => write!(f, "LD V{:X}, [I]", x),
+            ScrollDown(n) => write!(f, "SCD {:X}", n),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            LowRes => write!(f, "LOW"),
+            HighRes => write!(f, "HIGH"),
+            DrawLarge(x, y) => write!(f, "DRW V{:X}, V{:X}, 0", x, y),
+            LoadLargeFont(x) => write!(f, "LD HF, V{:X}", x),
+            StoreFlags(x) => write!(f, "LD R, V{:X}", x),
+            LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+            ScrollUp(n) => write!(f, "SCU {:X}", n),
+            SaveRange(x, y) => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            LoadRange(x, y) => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            LoadLongI => write!(f, "LD I, LONG"),
+            SelectPlanes(n) => write!(f, "PLANE {:X}", n),
+            LoadPattern => write!(f, "LD AUDIO, [I]"),
+            SetPitch(x) => write!(f, "LD PITCH, V{:X}", x),
+            DebugText => write!(f, "DBG [I]"),
+            DebugValue(x) => write!(f, "DBG V{:X}", x),
+        }
+    }
+}
+
+// Decoded instructions by address, opt in with Chip8::set_decode_cache for turbo and
+// benchmark runs. A miss decodes the whole basic block from there up to the next jump, call
+// or return, so a hot loop is decoded once. Writes to RAM throw away what read those bytes
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+pub(crate) struct DecodeCache {
+    // the instruction starting at every address, None when not decoded (yet)
+    entries: Vec<Option<Instruction>>,
+}
+
+#[cfg(feature = "alloc")]
+impl DecodeCache {
+    // an entry per byte of RAM
+    pub(crate) fn new(size: usize) -> Self {
+        DecodeCache {
+            entries: vec![None; size],
+        }
+    }
+
+    pub(crate) fn invalidate(&mut self, start: usize, len: usize) {
+        // the instruction starting a byte earlier reads the first written byte too
+        let end = (start + len).min(self.entries.len());
+        self.entries[start.saturating_sub(1)..end].fill(None);
+    }
+}
+
+#[cfg(feature = "alloc")]
+fn ends_block(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jump(_)
+            | Instruction::JumpV0(_)
+            | Instruction::Call(_)
+            | Instruction::Return
+            | Instruction::Exit
+    )
+}
+
+// The instruction set on its own, for running an opcode that isn't in RAM:
+// Cpu::execute(&mut chip, 0x6A0C) runs 6A0C as if it sat at PC. Chip8::step fetches the
+// opcode from PC and keeps the counters and the history, this doesn't
+pub struct Cpu;
+
+impl Cpu {
+    pub fn execute(chip: &mut Chip8, opcode: u16) -> Result<(), Chip8Error> {
+        let instruction = chip.decode(opcode)?;
+        chip.execute(instruction)
+    }
 }
 
 // I decided to implement fetch, decode, execute and step here to avoid chip8.rs with 1000+ LOC
 impl Chip8 {
     pub fn step(&mut self) -> Result<(), Chip8Error> {
+        #[cfg(feature = "alloc")]
+        if self.is_tracing() {
+            return self.traced_step();
+        }
+        self.untraced_step()
+    }
+
+    // step with the entry for set_trace's callback
+    #[cfg(feature = "alloc")]
+    fn traced_step(&mut self) -> Result<(), Chip8Error> {
+        let pc = *self.get_pc();
+        let before = Registers::of(self);
+        let opcode = self.fetch();
+        self.untraced_step()?;
+        // it ran, so it fetched and decoded fine
+        let opcode = opcode?;
+        let entry = TraceEntry {
+            pc,
+            opcode,
+            instruction: self.decode(opcode)?,
+            changes: before.changes(&Registers::of(self)),
+        };
+        self.report_trace(&entry);
+        Ok(())
+    }
+
+    fn untraced_step(&mut self) -> Result<(), Chip8Error> {
+        self.instructions += 1;
+        #[cfg(feature = "alloc")]
+        self.record_history();
+        #[cfg(feature = "alloc")]
+        if let Some(instruction) = self.cached_instruction() {
+            return self.execute(instruction);
+        }
         let opcode = self.fetch()?;
         // println!("PC: {:#X} | Opcode: {:#X}", self.get_pc(), opcode); // uncomment to see pc position and actual opcode
         let instruction = self.decode(opcode)?;
@@ -98,24 +392,93 @@ impl Chip8 {
         Ok(())
     }
 
+    // One 60 Hz frame: `ipf` instructions, then one timer tick. What Lockstep runs, without
+    // its input script and frame count
+    pub fn run_frame(&mut self, ipf: u32) -> Result<(), Chip8Error> {
+        for _ in 0..ipf {
+            self.step()?;
+        }
+        self.decrease_timers();
+        Ok(())
+    }
+
+    // Steps until `done` holds, checked before the first instruction and after each one.
+    // Some with how many instructions that took, None when it didn't hold within `limit`.
+    // The timers don't tick here, waits on DT need run_frame in a loop
+    pub fn run_until(
+        &mut self,
+        limit: u64,
+        mut done: impl FnMut(&Chip8) -> bool,
+    ) -> Result<Option<u64>, Chip8Error> {
+        for executed in 0..=limit {
+            if done(self) {
+                return Ok(Some(executed));
+            }
+            if executed < limit {
+                self.step()?;
+            }
+        }
+        Ok(None)
+    }
+
+    // The instruction at PC from the decode cache, decoding its block on a miss. None when
+    // the cache is off or PC points at something that doesn't decode, step reports that
+    #[cfg(feature = "alloc")]
+    fn cached_instruction(&mut self) -> Option<Instruction> {
+        profile_scope!("decode cache");
+        let pc = *self.get_pc() as usize;
+        let cache = self.decode_cache.as_ref()?;
+        if let Some(instruction) = cache.entries[pc] {
+            return Some(instruction);
+        }
+
+        let mut cache = self.decode_cache.take()?;
+        let mut at = pc;
+        while let Ok(bytes) = self.ram_range(at as u16, 2) {
+            let Ok(instruction) = self.decode(u16::from_be_bytes([bytes[0], bytes[1]])) else {
+                break;
+            };
+            cache.entries[at] = Some(instruction);
+            at += 2;
+            if ends_block(instruction) {
+                break;
+            }
+        }
+        let instruction = cache.entries[pc];
+        self.decode_cache = Some(cache);
+        instruction
+    }
+
     pub fn fetch(&mut self) -> Result<u16, Chip8Error> {
-        let high = self.get_ram(*self.get_pc())? as u16;
-        let low = self.get_ram(*self.get_pc() + 1)? as u16;
-        let opcode = (high << 8) | low;
+        profile_scope!("fetch");
+        let bytes = self.ram_range(*self.get_pc(), 2)?;
+        let opcode = ((bytes[0] as u16) << 8) | bytes[1] as u16;
         Ok(opcode)
     }
 
     pub fn decode(&self, opcode: u16) -> Result<Instruction, Chip8Error> {
+        profile_scope!("decode");
         let x = ((opcode & 0x0F00) >> 8) as usize;
         let y = ((opcode & 0x00F0) >> 4) as usize;
         let n = (opcode & 0x000F) as u8;
         let nn = (opcode & 0x00FF) as u8;
         let nnn = opcode & 0x0FFF;
 
+        // XO-CHIP has all of SUPER-CHIP
+        let schip = self.platform() != Platform::Chip8;
+        let xo = self.platform() == Platform::XoChip;
         match opcode & 0xF000 {
             0x0000 => match opcode {
                 0x00E0 => Ok(Instruction::ClearDisplay),
                 0x00EE => Ok(Instruction::Return),
+                0x00FA if self.is_debug_port_on() => Ok(Instruction::DebugText),
+                0x00C0..=0x00CF if schip => Ok(Instruction::ScrollDown(n)),
+                0x00D0..=0x00DF if xo => Ok(Instruction::ScrollUp(n)),
+                0x00FB if schip => Ok(Instruction::ScrollRight),
+                0x00FC if schip => Ok(Instruction::ScrollLeft),
+                0x00FD if schip => Ok(Instruction::Exit),
+                0x00FE if schip => Ok(Instruction::LowRes),
+                0x00FF if schip => Ok(Instruction::HighRes),
                 _ => Err(Chip8Error::UnknownInstruction),
             },
 
@@ -123,10 +486,15 @@ impl Chip8 {
             0x6000 => Ok(Instruction::LoadVxByte(x, nn)),
             0x7000 => Ok(Instruction::AddVxByte(x, nn)),
             0xA000 => Ok(Instruction::SetI(nnn)),
+            0xD000 if n == 0 && schip => Ok(Instruction::DrawLarge(x, y)),
             0xD000 => Ok(Instruction::Draw(x, y, n)),
             0x3000 => Ok(Instruction::JumpIfEq(x, nn)),
             0x4000 => Ok(Instruction::JumpIfDiff(x, nn)),
-            0x5000 => Ok(Instruction::JumpIfVEq(x, y)),
+            0x5000 => match n {
+                0x2 if xo => Ok(Instruction::SaveRange(x, y)),
+                0x3 if xo => Ok(Instruction::LoadRange(x, y)),
+                _ => Ok(Instruction::JumpIfVEq(x, y)),
+            },
             0x9000 => Ok(Instruction::JumpIfVDiff(x, y)),
             0x2000 => Ok(Instruction::Call(nnn)),
 
@@ -138,12 +506,16 @@ impl Chip8 {
                 0x4 => Ok(Instruction::JimCarrey(x, y)),
                 0x5 => Ok(Instruction::BorrowSub(x, y)),
                 0x7 => Ok(Instruction::VyBorrowSub(x, y)),
-                0x6 => Ok(Instruction::VxRShift(x)),
-                0xE => Ok(Instruction::VxLShift(x)),
+                0x6 => Ok(Instruction::VxRShift(x, y)),
+                0xE => Ok(Instruction::VxLShift(x, y)),
                 _ => Err(Chip8Error::UnknownInstruction),
             },
 
             0xF000 => match nn {
+                0x00 if xo && x == 0 => Ok(Instruction::LoadLongI),
+                0x01 if xo => Ok(Instruction::SelectPlanes(x as u8)),
+                0x02 if xo && x == 0 => Ok(Instruction::LoadPattern),
+                0x3A if xo => Ok(Instruction::SetPitch(x)),
                 0x1E => Ok(Instruction::AddVxI(x)),
                 0x65 => Ok(Instruction::LoadMemV(x)),
                 0x55 => Ok(Instruction::StoreMemV(x)),
@@ -153,6 +525,10 @@ impl Chip8 {
                 0x15 => Ok(Instruction::SetDelayTimer(x)),
                 0x18 => Ok(Instruction::SetSoundTimer(x)),
                 0x29 => Ok(Instruction::LoadFont(x)),
+                0x30 if schip => Ok(Instruction::LoadLargeFont(x)),
+                0x75 if schip => Ok(Instruction::StoreFlags(x)),
+                0x85 if schip => Ok(Instruction::LoadFlags(x)),
+                0xFA if self.is_debug_port_on() => Ok(Instruction::DebugValue(x)),
                 _ => Err(Chip8Error::UnknownInstruction),
             },
 
@@ -170,11 +546,10 @@ impl Chip8 {
     }
 
     pub fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
+        profile_scope!("execute");
         match instruction {
             Instruction::ClearDisplay => {
-                for i in 0..2048 {
-                    self.display.set_pixel(i, 0)?;
-                }
+                self.display.clear_selected();
                 self.increment_pc()?;
                 self.draw_flag = true;
             }
@@ -194,35 +569,22 @@ impl Chip8 {
                 self.increment_pc()?;
             }
 
-            // Draw instruction made by ChatGPT because IO isn't my focus
+            // Draw instruction made by ChatGPT because IO isn't my focus.
+            // The sprite range is checked once and Display::xor_sprite does the pixels,
+            // going through get_pixel/set_pixel for every pixel was the slowest part of the CPU.
+            // With both XO-CHIP planes selected the sprite of the second follows the first
             Instruction::Draw(x_reg, y_reg, n) => {
                 let vx = self.get_v(x_reg)? as usize;
                 let vy = self.get_v(y_reg)? as usize;
 
-                self.set_v(0xF, 0)?;
-
-                for row in 0..n as usize {
-                    let i = *self.get_i();
-                    let sprite_byte = self.get_ram(i + row as u16)?;
-                    for col in 0..8 {
-                        let sprite_pixel = (sprite_byte >> (7 - col)) & 1;
-                        if sprite_pixel == 0 {
-                            continue;
-                        }
-
-                        let x = (vx + col) % 64;
-                        let y = (vy + row) % 32;
-                        let index = y * 64 + x;
-
-                        let pixel = self.display.get_pixel(index)?;
-                        if pixel == 1 {
-                            self.set_v(0xF, 1)?;
-                        }
-                        self.display.set_pixel(index, pixel ^ sprite_pixel)?;
-                    }
-                }
+                let rows = n as usize * self.display.plane_count();
+                let mut sprite = [0u8; 30];
+                sprite[..rows].copy_from_slice(self.ram_range(*self.get_i(), rows)?);
 
+                let collision = self.display.xor_sprite(vx, vy, &sprite[..rows]);
+                self.set_v(0xF, collision)?;
                 self.draw_flag = true;
+                self.draws += 1;
                 self.increment_pc()?;
             }
 
@@ -232,8 +594,7 @@ impl Chip8 {
 
             Instruction::JumpIfEq(x, nn) => {
                 if self.get_v(x)? == nn {
-                    self.increment_pc()?;
-                    self.increment_pc()?;
+                    self.skip_next()?;
                     // If vx == nn, skip next instruction
                 } else {
                     self.increment_pc()?;
@@ -243,8 +604,7 @@ impl Chip8 {
 
             Instruction::JumpIfDiff(x, nn) => {
                 if self.get_v(x)? != nn {
-                    self.increment_pc()?;
-                    self.increment_pc()?;
+                    self.skip_next()?;
                     // If vx != nn, skip next instruction
                 } else {
                     self.increment_pc()?;
@@ -254,8 +614,7 @@ impl Chip8 {
 
             Instruction::JumpIfVEq(x, y) => {
                 if self.get_v(x)? == self.get_v(y)? {
-                    self.increment_pc()?;
-                    self.increment_pc()?;
+                    self.skip_next()?;
                     // If vx == vy, skip next instruction
                 } else {
                     self.increment_pc()?;
@@ -265,8 +624,7 @@ impl Chip8 {
 
             Instruction::JumpIfVDiff(x, y) => {
                 if self.get_v(x)? != self.get_v(y)? {
-                    self.increment_pc()?;
-                    self.increment_pc()?;
+                    self.skip_next()?;
                     // If vx != vy, skip next instruction
                 } else {
                     self.increment_pc()?;
@@ -294,18 +652,27 @@ impl Chip8 {
             Instruction::VxEqVxORvy(x, y) => {
                 let value = self.get_v(x)? | self.get_v(y)?;
                 self.set_v(x, value)?;
+                if self.quirks.vf_reset {
+                    self.set_v(0xF, 0)?;
+                }
                 self.increment_pc()?;
             }
 
             Instruction::VxEqVxANDvy(x, y) => {
                 let value = self.get_v(x)? & self.get_v(y)?;
                 self.set_v(x, value)?;
+                if self.quirks.vf_reset {
+                    self.set_v(0xF, 0)?;
+                }
                 self.increment_pc()?;
             }
 
             Instruction::VxEqVxXORvy(x, y) => {
                 let value = self.get_v(x)? ^ self.get_v(y)?;
                 self.set_v(x, value)?;
+                if self.quirks.vf_reset {
+                    self.set_v(0xF, 0)?;
+                }
                 self.increment_pc()?;
             }
 
@@ -332,15 +699,15 @@ impl Chip8 {
                 self.increment_pc()?;
             }
 
-            Instruction::VxRShift(x) => {
-                let vx = self.get_v(x)?;
+            Instruction::VxRShift(x, y) => {
+                let vx = self.get_v(if self.quirks.shift_uses_vy { y } else { x })?;
                 self.set_v(x, vx >> 1)?;
                 self.set_v(0xF, vx & 0x1)?;
                 self.increment_pc()?;
             }
 
-            Instruction::VxLShift(x) => {
-                let vx = self.get_v(x)?;
+            Instruction::VxLShift(x, y) => {
+                let vx = self.get_v(if self.quirks.shift_uses_vy { y } else { x })?;
                 self.set_v(x, vx << 1)?;
                 self.set_v(0xF, (vx & 0x80) >> 7)?;
                 self.increment_pc()?;
@@ -348,57 +715,71 @@ impl Chip8 {
 
             Instruction::AddVxI(x) => {
                 let vx = self.get_v(x)? as u16;
-                self.set_i(*self.get_i() + vx)?;
+                self.set_i(
+                    self.get_i()
+                        .checked_add(vx)
+                        .ok_or(Chip8Error::IOutOfBounds)?,
+                )?;
                 self.increment_pc()?;
             }
 
             Instruction::LoadMemV(x) => {
-                let i = *self.get_i();
-                for index in 0..=x {
-                    self.set_v(index, self.get_ram(i + index as u16)?)?;
+                let mut values = [0u8; 16];
+                values[..=x].copy_from_slice(self.ram_range(*self.get_i(), x + 1)?);
+                self.v_registers_mut()[..=x].copy_from_slice(&values[..=x]);
+                if self.quirks.load_store_increment_i {
+                    self.advance_i(x as u16 + 1)?;
                 }
                 self.increment_pc()?;
             }
 
             Instruction::StoreMemV(x) => {
-                let i = *self.get_i();
-                for index in 0..=x {
-                    self.set_ram(index as u16 + i, self.get_v(index)?)?;
+                let values = *self.v_registers();
+                self.ram_range_mut(*self.get_i(), x + 1)?
+                    .copy_from_slice(&values[..=x]);
+                if self.quirks.load_store_increment_i {
+                    self.advance_i(x as u16 + 1)?;
                 }
                 self.increment_pc()?;
             }
 
             Instruction::BCD(x) => {
-                let i = *self.get_i();
                 let v = self.get_v(x)?;
-                self.set_ram(i, v / 100)?;
-                self.set_ram(i + 1, (v / 10) % 10)?;
-                self.set_ram(i + 2, v % 10)?;
+                self.ram_range_mut(*self.get_i(), 3)?.copy_from_slice(&[
+                    v / 100,
+                    (v / 10) % 10,
+                    v % 10,
+                ]);
                 self.increment_pc()?;
             }
 
             Instruction::SkipIfKeyPressed(x) => {
-                self.set_pc(if self.keyboard.is_pressed(self.get_v(x)? as usize)? {
-                    *self.get_pc() + 4
+                if self.keyboard.is_pressed(self.get_v(x)? as usize)? {
+                    self.skip_next()?;
                 } else {
-                    *self.get_pc() + 2
-                })?;
+                    self.increment_pc()?;
+                }
             }
 
             Instruction::SkipIfKeyNotPressed(x) => {
-                self.set_pc(if !self.keyboard.is_pressed(self.get_v(x)? as usize)? {
-                    *self.get_pc() + 4
+                if !self.keyboard.is_pressed(self.get_v(x)? as usize)? {
+                    self.skip_next()?;
                 } else {
-                    *self.get_pc() + 2
-                })?;
+                    self.increment_pc()?;
+                }
             }
 
             Instruction::JumpV0(nnn) => {
-                self.set_pc(nnn + self.get_v(0)? as u16)?;
+                let register = if self.quirks.jump_uses_vx {
+                    (nnn >> 8) as usize
+                } else {
+                    0
+                };
+                self.set_pc(nnn + self.get_v(register)? as u16)?;
             }
 
             Instruction::Random(x, nn) => {
-                let random: u8 = rand::random();
+                let random = self.random_byte();
                 self.set_v(x, random & nn)?;
                 self.increment_pc()?;
             }
@@ -410,8 +791,9 @@ impl Chip8 {
             }
 
             Instruction::WaitKey(x) => {
-                if let Some(key) = self.keyboard.get_pressed_key() {
-                    self.set_v(x, key as u8)?;
+                // PC stays here until then, so the host loop goes on and the timers run
+                if let Some(key) = self.keyboard.wait_for_release() {
+                    self.set_v(x, key)?;
                     self.increment_pc()?;
                 }
             }
@@ -426,13 +808,687 @@ impl Chip8 {
                 self.increment_pc()?;
             }
 
+            // only the low nibble picks the glyph, like the VIP's routine
             Instruction::LoadFont(x) => {
-                let digit = self.get_v(x)? as u16;
-                self.set_i(0x50 + digit * 5)?;
+                let digit = self.get_v(x)? & 0xF;
+                self.set_i(Chip8::font_address(digit)?)?;
+                self.increment_pc()?;
+            }
+
+            Instruction::ScrollDown(n) => {
+                self.display.scroll_down(n as usize);
+                self.draw_flag = true;
+                self.increment_pc()?;
+            }
+
+            Instruction::ScrollRight => {
+                self.display.scroll_right(4);
+                self.draw_flag = true;
+                self.increment_pc()?;
+            }
+
+            Instruction::ScrollLeft => {
+                self.display.scroll_left(4);
+                self.draw_flag = true;
                 self.increment_pc()?;
             }
+
+            // there's no interpreter to go back to, the program stays here like on `JP self`
+            Instruction::Exit => {}
+
+            Instruction::LowRes => {
+                self.display.set_hires(false);
+                self.draw_flag = true;
+                self.increment_pc()?;
+            }
+
+            Instruction::HighRes => {
+                self.display.set_hires(true);
+                self.draw_flag = true;
+                self.increment_pc()?;
+            }
+
+            Instruction::DrawLarge(x_reg, y_reg) => {
+                let vx = self.get_v(x_reg)? as usize;
+                let vy = self.get_v(y_reg)? as usize;
+                let len = 32 * self.display.plane_count();
+                let mut sprite = [0u8; 64];
+                sprite[..len].copy_from_slice(self.ram_range(*self.get_i(), len)?);
+                let collision = self.display.xor_large_sprite(vx, vy, &sprite[..len]);
+                self.set_v(0xF, collision)?;
+                self.draw_flag = true;
+                self.draws += 1;
+                self.increment_pc()?;
+            }
+
+            Instruction::LoadLargeFont(x) => {
+                let digit = self.get_v(x)? & 0xF;
+                self.set_i(Chip8::large_font_address(digit)?)?;
+                self.increment_pc()?;
+            }
+
+            Instruction::StoreFlags(x) => {
+                let mut flags = *self.flags();
+                flags[..=x].copy_from_slice(&self.registers()[..=x]);
+                self.set_flags(flags);
+                self.increment_pc()?;
+            }
+
+            Instruction::LoadFlags(x) => {
+                for register in 0..=x {
+                    self.set_v(register, self.flags()[register])?;
+                }
+                self.increment_pc()?;
+            }
+
+            Instruction::ScrollUp(n) => {
+                self.display.scroll_up(n as usize);
+                self.draw_flag = true;
+                self.increment_pc()?;
+            }
+
+            // from Vx to Vy, backwards when y is below x
+            Instruction::SaveRange(x, y) => {
+                let values = self.registers();
+                let memory = self.ram_range_mut(*self.get_i(), x.abs_diff(y) + 1)?;
+                for (at, register) in register_range(x, y).enumerate() {
+                    memory[at] = values[register];
+                }
+                self.increment_pc()?;
+            }
+
+            Instruction::LoadRange(x, y) => {
+                let mut values = [0u8; 16];
+                let len = x.abs_diff(y) + 1;
+                values[..len].copy_from_slice(self.ram_range(*self.get_i(), len)?);
+                for (at, register) in register_range(x, y).enumerate() {
+                    self.set_v(register, values[at])?;
+                }
+                self.increment_pc()?;
+            }
+
+            Instruction::LoadLongI => {
+                let pc = *self.get_pc();
+                let at = pc.checked_add(2).ok_or(Chip8Error::InvalidMemoryAccess)?;
+                let bytes = self.ram_range(at, 2)?;
+                self.set_i(u16::from_be_bytes([bytes[0], bytes[1]]))?;
+                self.increment_pc()?;
+                self.increment_pc()?;
+            }
+
+            Instruction::SelectPlanes(planes) => {
+                self.display.select_planes(planes);
+                self.increment_pc()?;
+            }
+
+            Instruction::LoadPattern => {
+                let pattern = self.ram_range(*self.get_i(), 16)?.try_into().unwrap();
+                self.set_audio_pattern(pattern);
+                self.increment_pc()?;
+            }
+
+            Instruction::SetPitch(x) => {
+                self.set_audio_pitch(self.get_v(x)?);
+                self.increment_pc()?;
+            }
+
+            // decode only gives these with the port on, which needs alloc
+            #[cfg(feature = "alloc")]
+            Instruction::DebugText => {
+                self.debug_text();
+                self.increment_pc()?;
+            }
+
+            #[cfg(feature = "alloc")]
+            Instruction::DebugValue(x) => {
+                self.debug_value(x);
+                self.increment_pc()?;
+            }
+
+            #[cfg(not(feature = "alloc"))]
+            Instruction::DebugText | Instruction::DebugValue(_) => {
+                return Err(Chip8Error::UnknownInstruction);
+            }
         }
 
         Ok(())
     }
+
+    // Past the instruction after this one, which is 4 bytes when it's XO-CHIP's F000 nnnn
+    fn skip_next(&mut self) -> Result<(), Chip8Error> {
+        self.increment_pc()?;
+        let long = self.platform() == Platform::XoChip
+            && self.ram_range(*self.get_pc(), 2) == Ok(&[0xF0, 0x00]);
+        if long {
+            self.increment_pc()?;
+        }
+        self.increment_pc()
+    }
+
+    // Fx55/Fx65 moving I, which can't go past 0xFFFF on XO-CHIP
+    fn advance_i(&mut self, by: u16) -> Result<(), Chip8Error> {
+        let i = self
+            .get_i()
+            .checked_add(by)
+            .ok_or(Chip8Error::IOutOfBounds)?;
+        self.set_i(i)?;
+        Ok(())
+    }
+}
+
+fn register_range(x: usize, y: usize) -> impl Iterator<Item = usize> {
+    let backwards = y < x;
+    let (low, high) = if backwards { (y, x) } else { (x, y) };
+    (low..=high).map(move |at| if backwards { high + low - at } else { at })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "alloc")]
+    use alloc::string::ToString;
+    #[cfg(feature = "alloc")]
+    use rand::{Rng, SeedableRng};
+    #[cfg(feature = "alloc")]
+    use rand_chacha::ChaCha8Rng;
+
+    // Same checks as the fuzz targets (fuzz/), seeded so they run on every cargo test
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_random_roms_only_fail_with_rom_errors() {
+        let mut rng = ChaCha8Rng::seed_from_u64(678);
+        for _ in 0..200 {
+            let mut rom = vec![0u8; rng.gen_range(0..512)];
+            rng.fill(&mut rom[..]);
+            let mut chip = Chip8::new(&rom).unwrap();
+            for _ in 0..2000 {
+                if let Err(error) = chip.step() {
+                    assert!(error.is_rom_error(), "{:?}", error);
+                    break;
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_random_opcodes_only_fail_with_rom_errors() {
+        let mut rng = ChaCha8Rng::seed_from_u64(678);
+        let mut chip = Chip8::new(&[]).unwrap();
+        for _ in 0..100_000 {
+            let opcode: u16 = rng.r#gen();
+            if let Err(error) = chip.decode(opcode).and_then(|i| chip.execute(i)) {
+                assert!(error.is_rom_error(), "{:#06X}: {:?}", opcode, error);
+            }
+        }
+    }
+
+    // A machine with V0 = 0x12, V1 = 0x34, VF = 0xFF and I = 0x300, and PC at 0x200
+    fn machine() -> Chip8 {
+        let mut chip = Chip8::new(&[]).unwrap();
+        chip.set_v(0, 0x12).unwrap();
+        chip.set_v(1, 0x34).unwrap();
+        chip.set_v(0xF, 0xFF).unwrap();
+        chip.set_i(0x300).unwrap();
+        chip
+    }
+
+    // The machine after one opcode
+    fn run(opcode: u16) -> Chip8 {
+        let mut chip = machine();
+        Cpu::execute(&mut chip, opcode).unwrap();
+        chip
+    }
+
+    fn v(chip: &Chip8, x: usize) -> u8 {
+        chip.get_v(x).unwrap()
+    }
+
+    #[test]
+    fn test_run_frame_and_run_until() {
+        // V0 += 1 forever, with DT set to 3 first
+        let rom = [0x61, 0x03, 0xF1, 0x15, 0x70, 0x01, 0x12, 0x04];
+        let mut chip = Chip8::new(&rom).unwrap();
+        chip.run_frame(10).unwrap();
+        assert_eq!(chip.get_v(0), Ok(4));
+        assert_eq!(*chip.get_dt(), 2);
+
+        assert_eq!(
+            chip.run_until(100, |chip| chip.get_v(0) == Ok(4)),
+            Ok(Some(0))
+        );
+        assert_eq!(
+            chip.run_until(100, |chip| chip.get_v(0) == Ok(6)),
+            Ok(Some(3))
+        );
+        assert_eq!(chip.run_until(3, |chip| chip.get_v(0) == Ok(10)), Ok(None));
+        assert_eq!(*chip.get_pc(), 0x204);
+        assert_eq!(*chip.get_dt(), 2);
+
+        // errors stop both where they happened
+        let mut chip = Chip8::new(&[0x00, 0xEE]).unwrap();
+        assert_eq!(chip.run_frame(10), Err(Chip8Error::StackUnderflow));
+        assert_eq!(
+            chip.run_until(10, |_| false),
+            Err(Chip8Error::StackUnderflow)
+        );
+    }
+
+    #[test]
+    fn test_flow() {
+        // 00E0 clears and moves on
+        let mut chip = machine();
+        chip.display.set_pixel(0, 1).unwrap();
+        Cpu::execute(&mut chip, 0x00E0).unwrap();
+        assert_eq!(chip.display.get_pixel(0), Ok(0));
+        assert!(chip.draw_flag);
+        assert_eq!(*chip.get_pc(), 0x202);
+
+        // 2nnn pushes the next instruction, 00EE goes back to it
+        let mut chip = machine();
+        Cpu::execute(&mut chip, 0x2400).unwrap();
+        assert_eq!((*chip.get_pc(), chip.stack()), (0x400, &[0x202][..]));
+        Cpu::execute(&mut chip, 0x00EE).unwrap();
+        assert_eq!((*chip.get_pc(), chip.stack().len()), (0x202, 0));
+        assert_eq!(
+            Cpu::execute(&mut chip, 0x00EE),
+            Err(Chip8Error::StackUnderflow)
+        );
+
+        assert_eq!(*run(0x1345).get_pc(), 0x345);
+        // Bnnn adds V0
+        assert_eq!(*run(0xB300).get_pc(), 0x312);
+        // 0nnn is code for the CPU of the VIP
+        assert_eq!(
+            Cpu::execute(&mut machine(), 0x0123),
+            Err(Chip8Error::UnknownInstruction)
+        );
+    }
+
+    #[test]
+    fn test_skips() {
+        let skipped = |opcode| *run(opcode).get_pc() == 0x204;
+        assert!(skipped(0x3012) && !skipped(0x3013));
+        assert!(skipped(0x4013) && !skipped(0x4012));
+        assert!(skipped(0x5000) && !skipped(0x5010));
+        assert!(skipped(0x9010) && !skipped(0x9000));
+
+        // Ex9E and ExA1 with key 3 in V2
+        let keys = |opcode, down: bool| {
+            let mut chip = machine();
+            chip.set_v(2, 3).unwrap();
+            chip.keyboard.set_key(3, down).unwrap();
+            Cpu::execute(&mut chip, opcode).unwrap();
+            *chip.get_pc() == 0x204
+        };
+        assert!(keys(0xE29E, true) && !keys(0xE29E, false));
+        assert!(keys(0xE2A1, false) && !keys(0xE2A1, true));
+    }
+
+    #[test]
+    fn test_registers() {
+        assert_eq!(v(&run(0x6A0C), 0xA), 0x0C);
+        assert_eq!(v(&run(0x70FF), 0), 0x11);
+        assert_eq!(v(&run(0x8010), 0), 0x34);
+        assert_eq!(v(&run(0x8011), 0), 0x36);
+        assert_eq!(v(&run(0x8012), 0), 0x10);
+        assert_eq!(v(&run(0x8013), 0), 0x26);
+
+        // 8xy4: VF is the carry
+        let chip = run(0x8014);
+        assert_eq!((v(&chip, 0), v(&chip, 0xF)), (0x46, 0));
+        let mut chip = machine();
+        chip.set_v(1, 0xF0).unwrap();
+        Cpu::execute(&mut chip, 0x8014).unwrap();
+        assert_eq!((v(&chip, 0), v(&chip, 0xF)), (0x02, 1));
+
+        // 8xy5 and 8xy7: VF is 1 without a borrow
+        let chip = run(0x8015);
+        assert_eq!((v(&chip, 0), v(&chip, 0xF)), (0xDE, 0));
+        let chip = run(0x8017);
+        assert_eq!((v(&chip, 0), v(&chip, 0xF)), (0x22, 1));
+
+        // the logic ops clear VF (vf_reset)
+        assert_eq!(v(&run(0x8011), 0xF), 0);
+
+        // 8xy6 and 8xyE shift Vy into Vx (shift_uses_vy), VF is the bit shifted out
+        let chip = run(0x8106);
+        assert_eq!((v(&chip, 1), v(&chip, 0xF)), (0x09, 0));
+        let mut chip = machine();
+        chip.set_v(0, 0x81).unwrap();
+        Cpu::execute(&mut chip, 0x810E).unwrap();
+        assert_eq!((v(&chip, 1), v(&chip, 0xF)), (0x02, 1));
+
+        // Cxnn masks the random byte
+        assert_eq!(v(&run(0xC00F), 0) & 0xF0, 0);
+        assert_eq!(*run(0x8014).get_pc(), 0x202);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_memory() {
+        assert_eq!(*run(0xA123).get_i(), 0x123);
+        assert_eq!(*run(0xF01E).get_i(), 0x312);
+        // V0 is 0x12, the glyph of 2
+        assert_eq!(*run(0xF029).get_i(), 0x5A);
+
+        // 0x34 = 52
+        let chip = run(0xF133);
+        let digits: Vec<u8> = (0..3).map(|at| chip.get_ram(0x300 + at).unwrap()).collect();
+        assert_eq!(digits, [0, 5, 2]);
+
+        let chip = run(0xF155);
+        assert_eq!(chip.get_ram(0x300), Ok(0x12));
+        assert_eq!(chip.get_ram(0x301), Ok(0x34));
+        assert_eq!(chip.get_ram(0x302), Ok(0));
+        // I moved past V1 (load_store_increment_i)
+        assert_eq!(*chip.get_i(), 0x302);
+        let mut chip = chip;
+        chip.set_i(0x300).unwrap();
+        chip.set_v(0, 0).unwrap();
+        chip.set_v(1, 0).unwrap();
+        Cpu::execute(&mut chip, 0xF165).unwrap();
+        assert_eq!((v(&chip, 0), v(&chip, 1)), (0x12, 0x34));
+    }
+
+    #[test]
+    fn test_timers_and_input() {
+        let chip = run(0xF015);
+        assert_eq!(*chip.get_dt(), 0x12);
+        let mut chip = run(0xF118);
+        assert_eq!(*chip.get_st(), 0x34);
+        chip.set_dt(9);
+        Cpu::execute(&mut chip, 0xF207).unwrap();
+        assert_eq!(v(&chip, 2), 9);
+
+        // Fx0A waits on the same instruction until a key goes down and up
+        let mut chip = machine();
+        Cpu::execute(&mut chip, 0xF30A).unwrap();
+        assert_eq!(*chip.get_pc(), 0x200);
+        chip.keyboard.set_key(7, true).unwrap();
+        chip.decrease_timers();
+        Cpu::execute(&mut chip, 0xF30A).unwrap();
+        assert_eq!(*chip.get_pc(), 0x200);
+        chip.keyboard.set_key(7, false).unwrap();
+        Cpu::execute(&mut chip, 0xF30A).unwrap();
+        assert_eq!((*chip.get_pc(), v(&chip, 3)), (0x202, 7));
+    }
+
+    #[test]
+    fn test_draw() {
+        // the 0 of the font (V2) at (V0, V1) = (18, 52 wraps to 20), then again to erase it
+        let mut chip = machine();
+        Cpu::execute(&mut chip, 0xF229).unwrap();
+        chip.set_v(0, 18).unwrap();
+        Cpu::execute(&mut chip, 0xD015).unwrap();
+        assert_eq!(chip.display.get_pixel(20 * 64 + 18), Ok(1));
+        assert_eq!(v(&chip, 0xF), 0);
+        assert_eq!(chip.draws(), 1);
+        Cpu::execute(&mut chip, 0xD015).unwrap();
+        assert_eq!(chip.display.get_pixel(20 * 64 + 18), Ok(0));
+        assert_eq!(v(&chip, 0xF), 1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_super_chip() {
+        // nothing of it on Chip-8
+        for opcode in [
+            0x00C1, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF, 0xF030, 0xF075, 0xF085,
+        ] {
+            assert_eq!(
+                machine().decode(opcode),
+                Err(Chip8Error::UnknownInstruction),
+                "{:04X}",
+                opcode
+            );
+        }
+        assert_eq!(machine().decode(0xD010), Ok(Instruction::Draw(0, 1, 0)));
+
+        let mut chip = machine();
+        chip.set_platform(Platform::SuperChip);
+        Cpu::execute(&mut chip, 0x00FF).unwrap();
+        assert!(chip.display.is_hires());
+        assert_eq!((chip.display.width(), chip.display.height()), (128, 64));
+
+        // a lit 16x16 square at (V0, V1) = (0x12, 0x34), two bytes a row from I
+        for at in 0..32 {
+            chip.set_ram(0x300 + at, 0xFF).unwrap();
+        }
+        Cpu::execute(&mut chip, 0xD010).unwrap();
+        // the bottom rows wrap to the top
+        assert!(chip.display.pixel(0x12, 0x34) && chip.display.pixel(0x12 + 15, 0x3F));
+        assert!(chip.display.pixel(0x12, 0x03) && !chip.display.pixel(0x12, 0x04));
+        assert!(!chip.display.pixel(0x12 + 16, 0x34));
+        assert_eq!(v(&chip, 0xF), 0);
+        Cpu::execute(&mut chip, 0xD010).unwrap();
+        assert_eq!(v(&chip, 0xF), 1);
+
+        // scrolling by hi-res pixels
+        Cpu::execute(&mut chip, 0xD010).unwrap();
+        Cpu::execute(&mut chip, 0x00C3).unwrap();
+        assert!(chip.display.pixel(0x12, 0x37) && !chip.display.pixel(0x12, 0x36));
+        Cpu::execute(&mut chip, 0x00FB).unwrap();
+        assert!(chip.display.pixel(0x16, 0x37) && !chip.display.pixel(0x15, 0x37));
+        Cpu::execute(&mut chip, 0x00FC).unwrap();
+        Cpu::execute(&mut chip, 0x00FC).unwrap();
+        assert!(chip.display.pixel(0x0E, 0x37) && !chip.display.pixel(0x0D, 0x37));
+
+        Cpu::execute(&mut chip, 0xF130).unwrap();
+        assert_eq!(*chip.get_i(), Chip8::large_font_address(4).unwrap());
+        assert_eq!(chip.get_ram(*chip.get_i()), Ok(0xC3));
+
+        // V0 and V1 to the flags and back
+        Cpu::execute(&mut chip, 0xF175).unwrap();
+        chip.set_v(0, 0).unwrap();
+        chip.set_v(1, 0).unwrap();
+        Cpu::execute(&mut chip, 0xF185).unwrap();
+        assert_eq!((v(&chip, 0), v(&chip, 1)), (0x12, 0x34));
+        assert_eq!(chip.flags()[..3], [0x12, 0x34, 0]);
+
+        Cpu::execute(&mut chip, 0x00FE).unwrap();
+        assert!(!chip.display.is_hires());
+        assert_eq!(chip.display.count_differences(""), 0);
+        let pc = *chip.get_pc();
+        Cpu::execute(&mut chip, 0x00FD).unwrap();
+        assert_eq!(*chip.get_pc(), pc);
+        assert_eq!(Instruction::DrawLarge(1, 2).to_string(), "DRW V1, V2, 0");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_xo_chip() {
+        // nothing of it on SUPER-CHIP
+        let mut chip = machine();
+        chip.set_platform(Platform::SuperChip);
+        assert_eq!(chip.decode(0x5012), Ok(Instruction::JumpIfVEq(0, 1)));
+        for opcode in [0x00D1, 0xF000, 0xF101, 0xF002, 0xF03A] {
+            assert_eq!(
+                chip.decode(opcode),
+                Err(Chip8Error::UnknownInstruction),
+                "{:04X}",
+                opcode
+            );
+        }
+
+        let mut chip = machine();
+        chip.set_platform(Platform::XoChip);
+        assert_eq!(chip.memory_map().size, 0x10000);
+
+        // V0..V1 out and back in, either way round, leaving I be
+        Cpu::execute(&mut chip, 0x5012).unwrap();
+        assert_eq!(chip.ram_range(0x300, 2), Ok(&[0x12, 0x34][..]));
+        Cpu::execute(&mut chip, 0x5103).unwrap();
+        assert_eq!((v(&chip, 0), v(&chip, 1)), (0x34, 0x12));
+        assert_eq!(*chip.get_i(), 0x300);
+
+        // F000 nnnn takes 4 bytes, and skips step over all of them
+        chip.set_pc(0x200).unwrap();
+        chip.set_ram(0x202, 0xFF).unwrap();
+        chip.set_ram(0x203, 0x00).unwrap();
+        Cpu::execute(&mut chip, 0xF000).unwrap();
+        assert_eq!((*chip.get_i(), *chip.get_pc()), (0xFF00, 0x204));
+        chip.set_pc(0x200).unwrap();
+        chip.set_ram(0x202, 0xF0).unwrap();
+        Cpu::execute(&mut chip, 0x3034).unwrap();
+        assert_eq!(*chip.get_pc(), 0x206);
+
+        // both planes take a byte each, one after the other
+        chip.set_i(0x300).unwrap();
+        chip.set_ram(0x300, 0x80).unwrap();
+        chip.set_ram(0x301, 0xC0).unwrap();
+        chip.set_v(0, 0).unwrap();
+        Cpu::execute(&mut chip, 0xF301).unwrap();
+        Cpu::execute(&mut chip, 0xD001).unwrap();
+        assert_eq!(chip.display.pixel_value(0, 0), 3);
+        assert_eq!(chip.display.pixel_value(1, 0), 2);
+        assert_eq!(v(&chip, 0xF), 0);
+        Cpu::execute(&mut chip, 0xF201).unwrap();
+        Cpu::execute(&mut chip, 0xD001).unwrap();
+        assert_eq!(v(&chip, 0xF), 1);
+        assert_eq!(chip.display.pixel_value(0, 0), 1);
+
+        // 00E0 and 00Dn only touch the selected planes
+        Cpu::execute(&mut chip, 0xF101).unwrap();
+        Cpu::execute(&mut chip, 0x00D1).unwrap();
+        assert_eq!(chip.display.pixel_value(0, 0), 0);
+        assert_eq!(chip.display.pixel_value(1, 0), 2);
+        Cpu::execute(&mut chip, 0xF201).unwrap();
+        Cpu::execute(&mut chip, 0xD001).unwrap();
+        Cpu::execute(&mut chip, 0xF101).unwrap();
+        Cpu::execute(&mut chip, 0x00E0).unwrap();
+        assert_eq!(chip.display.pixel_value(0, 0), 2);
+
+        // the audio pattern from I and the pitch from Vx
+        for at in 0..16 {
+            chip.set_ram(0x400 + at, at as u8).unwrap();
+        }
+        chip.set_i(0x400).unwrap();
+        chip.set_v(2, 0x70).unwrap();
+        Cpu::execute(&mut chip, 0xF002).unwrap();
+        Cpu::execute(&mut chip, 0xF23A).unwrap();
+        assert_eq!(chip.audio_pattern().unwrap()[15], 15);
+        assert_eq!(chip.audio_pitch(), 0x70);
+
+        assert_eq!(Instruction::SaveRange(1, 4).to_string(), "LD [I], V1-V4");
+        assert_eq!(Instruction::LoadLongI.to_string(), "LD I, LONG");
+        assert_eq!(Instruction::SelectPlanes(3).to_string(), "PLANE 3");
+    }
+
+    #[test]
+    fn test_quirks() {
+        // V1 = 0x81, V2 = 0x06, shift right (8126), V3 = 0x0F, VF = 7, V3 |= V2 (8321),
+        // I = 0x300, store V0..V1 (F155), jump to 0x310 plus V0 or V3 (B310)
+        let rom = [
+            0x61, 0x81, 0x62, 0x06, 0x81, 0x26, 0x63, 0x0F, 0x6F, 0x07, 0x83, 0x21, 0xA3, 0x00,
+            0xF1, 0x55, 0xB3, 0x10,
+        ];
+        let run = |quirks: Quirks| {
+            let mut chip = Chip8::new(&rom).unwrap();
+            chip.quirks = quirks;
+            for _ in 0..9 {
+                chip.step().unwrap();
+            }
+            chip
+        };
+
+        assert_eq!(Quirks::default(), Quirks::VIP);
+        let chip = run(Quirks {
+            shift_uses_vy: false,
+            load_store_increment_i: false,
+            jump_uses_vx: false,
+            vf_reset: false,
+        });
+        assert_eq!(chip.get_v(1).unwrap(), 0x40);
+        assert_eq!(chip.get_v(0xF).unwrap(), 0x07);
+        assert_eq!(*chip.get_i(), 0x300);
+        assert_eq!(*chip.get_pc(), 0x310);
+
+        let chip = run(Quirks {
+            shift_uses_vy: true,
+            load_store_increment_i: true,
+            jump_uses_vx: true,
+            vf_reset: true,
+        });
+        assert_eq!(chip.get_v(1).unwrap(), 0x03);
+        assert_eq!(chip.get_v(0xF).unwrap(), 0x00);
+        assert_eq!(*chip.get_i(), 0x302);
+        assert_eq!(*chip.get_pc(), 0x310 + 0x0F);
+        assert_eq!(Quirks::preset("VIP"), Some(Quirks::VIP));
+        assert_eq!(Quirks::preset("schip"), Some(Quirks::MODERN));
+        assert_eq!(Quirks::preset("xochip"), None);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_decode_cache() {
+        // V0 += 1 (7001) runs once, then gets overwritten with V0 += 5 (7005) by Fx55 from
+        // V0 = 0x70, V1 = 0x05, and runs again
+        let rom = [
+            0x70, 0x01, 0x12, 0x06, 0x00, 0x00, 0xA2, 0x00, 0x60, 0x70, 0x61, 0x05, 0xF1, 0x55,
+            0x60, 0x00, 0x12, 0x00,
+        ];
+        let mut chip = Chip8::new(&rom).unwrap();
+        chip.set_decode_cache(true);
+        for _ in 0..9 {
+            chip.step().unwrap();
+        }
+        assert_eq!(chip.get_v(0).unwrap(), 5);
+
+        // the same random ROMs end the same with and without the cache
+        let mut rng = ChaCha8Rng::seed_from_u64(704);
+        for _ in 0..100 {
+            let mut rom = vec![0u8; rng.gen_range(0..512)];
+            rng.fill(&mut rom[..]);
+            let mut plain = Chip8::new(&rom).unwrap();
+            plain.set_seed(1);
+            let mut cached = plain.clone();
+            cached.set_decode_cache(true);
+            for _ in 0..2000 {
+                let result = plain.step();
+                assert_eq!(cached.step(), result);
+                if result.is_err() {
+                    break;
+                }
+            }
+            assert_eq!(cached.save_state(), plain.save_state());
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_debug_port() {
+        use crate::chip8::DebugOutput;
+
+        // A208 6321 F3FA 00FA, then "HI" at 0x208
+        let rom = [
+            0xA2, 0x08, 0x63, 0x21, 0xF3, 0xFA, 0x00, 0xFA, b'H', b'I', 0x00,
+        ];
+        let mut chip = Chip8::new(&rom).unwrap();
+        chip.step().unwrap();
+        chip.step().unwrap();
+        assert_eq!(chip.clone().step(), Err(Chip8Error::UnknownInstruction));
+
+        chip.set_debug_port(true);
+        chip.step().unwrap();
+        chip.step().unwrap();
+        let printed = chip.take_debug_output();
+        assert_eq!(
+            printed,
+            [
+                DebugOutput::Value {
+                    pc: 0x204,
+                    register: 3,
+                    value: 0x21
+                },
+                DebugOutput::Text {
+                    pc: 0x206,
+                    text: "HI".into()
+                }
+            ]
+        );
+        assert_eq!(printed[0].to_string(), "204: V3 = 21 (33)");
+        assert!(chip.take_debug_output().is_empty());
+    }
 }
@@ -0,0 +1,898 @@
+// Instruction by instruction execution, for finding out what a ROM is doing. Frames work
+// like in lockstep.rs, the timers tick after every `ipf` instructions, so a session in the
+// debugger runs the same as the game in lockstep mode.
+//
+// Going backwards: every SNAPSHOT_EVERY instructions a copy of the machine goes in a ring of
+// the last SNAPSHOTS ones. step_back restores the newest copy before the instruction to go
+// back to and runs forward from there, the machine is deterministic so that ends in the
+// exact state it had. Changes made by hand (keys, registers, RAM) need a checkpoint, or
+// running forward again would use the old values.
+//
+// Running data: the debugger remembers every byte the program wrote (Fx33, Fx55) and which
+// bytes of the ROM are data, the ones no path from 0x200 runs through. PC landing on either
+// is worth a look, self-modifying code on purpose or a jump that went wrong. take_warnings
+// has the first time at each address, break_on(Break::Written / Break::Data) stops there.
+//
+// Breakpoints: addresses PC stops on, before the instruction there runs. They're the plain
+// kind next to the breaks above, run and run_to report them as Stop::Break(Break::At(pc)).
+//
+// Watchpoints: ranges of RAM to stop on when an instruction reads or writes them, Dxyn
+// reading a sprite, Fx55 and Fx33 writing, Fx65 reading and XO-CHIP's ranges and audio
+// pattern. The instruction runs first, so a write has its new value, then run and run_to
+// report Stop::Watch with the instruction's PC, the first watched address and its value.
+//
+// Time travel: the same snapshots and replays give the trace of the last instructions, with
+// their numbers, go_to any of them, and when, the last instruction after which something
+// about the machine started being true. None of it keeps a log, it's all worked out again
+// from the snapshots when asked.
+use crate::chip8::{Chip8, Chip8Error};
+use crate::cpu::Instruction;
+use crate::memory::{PROGRAM_START, RAM_SIZE};
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::vec::Vec;
+
+pub const SNAPSHOT_EVERY: u64 = 1000;
+// how far run and run_to go before giving up, 10 minutes at 10 instructions per frame
+pub const RUN_LIMIT: u64 = 360_000;
+// 600k instructions back, 1000 seconds at 10 instructions per frame
+pub const SNAPSHOTS: usize = 600;
+
+// Where run_to stops. Busy loops make stepping useless in most ROMs, these are the points
+// worth landing on instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    // PC on the next Dxyn
+    Draw,
+    // PC on the next 00E0
+    Clear,
+    // right after the buzzer started or stopped
+    Sound,
+}
+
+// Kinds of instructions that stop run and run_to with PC on them, for landing where the game
+// does something without knowing its address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Break {
+    // Ex9E, ExA1 or Fx0A, the game looking at the keypad
+    Input,
+    // the same for one key: Ex9E or ExA1 with it in Vx, or Fx0A which takes any key
+    Key(u8),
+    // Fx18 with a value above 0, the buzzer starting
+    Sound,
+    // an instruction the program wrote itself
+    Written,
+    // an instruction in what looked like data when the debugger started
+    Data,
+    // PC on a breakpoint, see set_breakpoint
+    At(u16),
+}
+
+// Where PC shouldn't normally be, see the top of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suspicious {
+    Written,
+    Data,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// From start to end, both included, for reads, writes or both
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    fn catches(&self, access: Access, address: usize) -> bool {
+        let wanted = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        wanted && (self.start as usize..=self.end as usize).contains(&address)
+    }
+}
+
+// An instruction touching a watchpoint: its PC, the first watched address it read or wrote
+// and the value there after it ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub pc: u16,
+    pub address: u16,
+    pub access: Access,
+    pub value: u8,
+}
+
+// Why run or run_to gave the machine back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // the target of run_to
+    Reached,
+    // PC on an instruction one of the breaks matches
+    Break(Break),
+    // the instruction before PC touched a watchpoint
+    Watch(WatchHit),
+    // `limit` instructions went by without either
+    Limit,
+}
+
+// An instruction in the trace, number is how many ran before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Traced {
+    pub number: u64,
+    pub pc: u16,
+    pub opcode: u16,
+}
+
+struct Snapshot {
+    // instructions run when it was taken
+    executed: u64,
+    chip: Chip8,
+    written: AddressSet,
+}
+
+// One bit per address of RAM
+#[derive(Clone)]
+struct AddressSet([u64; RAM_SIZE / 64]);
+
+impl AddressSet {
+    fn new() -> Self {
+        AddressSet([0; RAM_SIZE / 64])
+    }
+
+    fn insert(&mut self, address: usize) {
+        if address < RAM_SIZE {
+            self.0[address / 64] |= 1 << (address % 64);
+        }
+    }
+
+    fn contains(&self, address: usize) -> bool {
+        address < RAM_SIZE && self.0[address / 64] & (1 << (address % 64)) != 0
+    }
+}
+
+pub struct Debugger {
+    ipf: u32,
+    // instructions run since the debugger started, the only notion of time here
+    executed: u64,
+    snapshots: VecDeque<Snapshot>,
+    breaks: Vec<Break>,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    // what the last step touched, see watch_hit
+    hit: Option<WatchHit>,
+    // bytes the program wrote
+    written: AddressSet,
+    // bytes of the ROM no instruction seemed to reach
+    data: AddressSet,
+    // addresses already in warnings, each one is reported once
+    warned: AddressSet,
+    warnings: Vec<(u16, Suspicious)>,
+}
+
+impl Debugger {
+    // Starts at `chip` as it is now, step_back can't go further back than that
+    pub fn new(ipf: u32, chip: &Chip8) -> Self {
+        let mut debugger = Debugger {
+            ipf: ipf.max(1),
+            executed: 0,
+            snapshots: VecDeque::with_capacity(SNAPSHOTS),
+            breaks: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            hit: None,
+            written: AddressSet::new(),
+            data: data_bytes(chip),
+            warned: AddressSet::new(),
+            warnings: Vec::new(),
+        };
+        debugger.checkpoint(chip);
+        debugger
+    }
+
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    // Frames completed, the timers ticked this many times
+    pub fn frame(&self) -> u64 {
+        self.executed / self.ipf as u64
+    }
+
+    // Runs one instruction, and the timer tick when it ends a frame. An instruction that
+    // fails doesn't count, PC stays on it
+    pub fn step(&mut self, chip: &mut Chip8) -> Result<(), Chip8Error> {
+        let pc = *chip.get_pc();
+        let access = memory_access(chip);
+        self.hit = None;
+        self.run_one(chip)?;
+        if let Some((access, start, len)) = access {
+            self.hit = (start..start + len)
+                .find(|&address| self.watchpoints.iter().any(|w| w.catches(access, address)))
+                .map(|address| WatchHit {
+                    pc,
+                    address: address as u16,
+                    access,
+                    value: chip.get_ram(address as u16).unwrap_or(0),
+                });
+        }
+        if self.executed.is_multiple_of(SNAPSHOT_EVERY) {
+            self.checkpoint(chip);
+        }
+        let pc = *chip.get_pc();
+        if let Some(kind) = self.suspicious(chip)
+            && !self.warned.contains(pc as usize)
+        {
+            self.warned.insert(pc as usize);
+            self.warnings.push((pc, kind));
+        }
+        Ok(())
+    }
+
+    fn run_one(&mut self, chip: &mut Chip8) -> Result<(), Chip8Error> {
+        let access = memory_access(chip);
+        advance(chip, &mut self.executed, self.ipf)?;
+        if let Some((Access::Write, start, len)) = access {
+            for address in start..start + len {
+                self.written.insert(address);
+            }
+        }
+        Ok(())
+    }
+
+    // The watchpoint the last step touched, None when it didn't touch any
+    pub fn watch_hit(&self) -> Option<WatchHit> {
+        self.hit
+    }
+
+    // Stops run and run_to after an instruction reads or writes RAM in it, until unwatch
+    pub fn watch(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    // Drops the watchpoints starting at `start`, false when there were none
+    pub fn unwatch(&mut self, start: u16) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.start != start);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    // Stops run and run_to from now on, until clear_breaks
+    pub fn break_on(&mut self, condition: Break) {
+        if !self.breaks.contains(&condition) {
+            self.breaks.push(condition);
+        }
+    }
+
+    pub fn breaks(&self) -> &[Break] {
+        &self.breaks
+    }
+
+    pub fn clear_breaks(&mut self) {
+        self.breaks.clear();
+    }
+
+    // Stops run and run_to with PC on `pc`, until clear_breakpoint. False when it was set
+    pub fn set_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.insert(pc)
+    }
+
+    // False when there was none
+    pub fn clear_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    // In order of address
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    // A breakpoint on PC, or else the first break the instruction at PC matches
+    pub fn break_at(&self, chip: &Chip8) -> Option<Break> {
+        let pc = *chip.get_pc();
+        if self.breakpoints.contains(&pc) {
+            return Some(Break::At(pc));
+        }
+        self.breaks.iter().copied().find(|&b| self.matches(b, chip))
+    }
+
+    fn matches(&self, condition: Break, chip: &Chip8) -> bool {
+        let Some(opcode) = next_opcode(chip) else {
+            return false;
+        };
+        let vx = chip.get_v((opcode as usize >> 8) & 0xF).unwrap_or(0);
+        let key_check = matches!(opcode & 0xF0FF, 0xE09E | 0xE0A1);
+        let key_wait = opcode & 0xF0FF == 0xF00A;
+        match condition {
+            Break::Input => key_check || key_wait,
+            Break::Key(key) => (key_check && vx == key) || key_wait,
+            Break::Sound => opcode & 0xF0FF == 0xF018 && vx > 0,
+            Break::Written => self.suspicious(chip) == Some(Suspicious::Written),
+            Break::Data => self.suspicious(chip) == Some(Suspicious::Data),
+            Break::At(pc) => *chip.get_pc() == pc,
+        }
+    }
+
+    // Whether the instruction at PC was written by the program or sits in data
+    pub fn suspicious(&self, chip: &Chip8) -> Option<Suspicious> {
+        let pc = *chip.get_pc() as usize;
+        if self.written.contains(pc) || self.written.contains(pc + 1) {
+            Some(Suspicious::Written)
+        } else if self.data.contains(pc) {
+            Some(Suspicious::Data)
+        } else {
+            None
+        }
+    }
+
+    // PC entering written bytes or data since the last call, the first time for each address
+    pub fn take_warnings(&mut self) -> Vec<(u16, Suspicious)> {
+        core::mem::take(&mut self.warnings)
+    }
+
+    // Runs until a break matches, at least one instruction. Errors stop it like step
+    pub fn run(&mut self, chip: &mut Chip8, limit: u64) -> Result<Stop, Chip8Error> {
+        self.run_until(chip, None, limit)
+    }
+
+    // Runs until the target is reached or a break matches, at least one instruction
+    pub fn run_to(
+        &mut self,
+        chip: &mut Chip8,
+        target: Target,
+        limit: u64,
+    ) -> Result<Stop, Chip8Error> {
+        self.run_until(chip, Some(target), limit)
+    }
+
+    fn run_until(
+        &mut self,
+        chip: &mut Chip8,
+        target: Option<Target>,
+        limit: u64,
+    ) -> Result<Stop, Chip8Error> {
+        for _ in 0..limit {
+            let sound = chip.is_sound_on();
+            self.step(chip)?;
+            if let Some(hit) = self.hit {
+                return Ok(Stop::Watch(hit));
+            }
+            let reached = match target {
+                Some(Target::Draw) => next_opcode(chip).is_some_and(|op| op & 0xF000 == 0xD000),
+                Some(Target::Clear) => next_opcode(chip) == Some(0x00E0),
+                Some(Target::Sound) => chip.is_sound_on() != sound,
+                None => false,
+            };
+            if reached {
+                return Ok(Stop::Reached);
+            }
+            if let Some(condition) = self.break_at(chip) {
+                return Ok(Stop::Break(condition));
+            }
+        }
+        Ok(Stop::Limit)
+    }
+
+    // Back to the machine as it was before the last instruction. False when that's older
+    // than the oldest snapshot, the machine is left alone then
+    pub fn step_back(&mut self, chip: &mut Chip8) -> bool {
+        self.executed
+            .checked_sub(1)
+            .is_some_and(|target| self.go_to(chip, target))
+    }
+
+    // Back to the machine as it was after `target` instructions, with PC on instruction
+    // number `target` of the trace. False for the future and for what's older than the oldest
+    // snapshot, the machine is left alone then
+    pub fn go_to(&mut self, chip: &mut Chip8, target: u64) -> bool {
+        if target > self.executed || self.snapshots.front().is_none_or(|s| s.executed > target) {
+            return false;
+        }
+        // whatever came after the target is a future that may not happen again
+        while self.snapshots.back().is_some_and(|s| s.executed > target) {
+            self.snapshots.pop_back();
+        }
+        let Some(snapshot) = self.snapshots.back() else {
+            return false;
+        };
+
+        let mut replay = snapshot.chip.clone();
+        let executed = self.executed;
+        self.executed = snapshot.executed;
+        let written = core::mem::replace(&mut self.written, snapshot.written.clone());
+        while self.executed < target {
+            // it ran fine the first time, and it's the same machine
+            if self.run_one(&mut replay).is_err() {
+                self.executed = executed;
+                self.written = written;
+                return false;
+            }
+        }
+        chip.restore(replay);
+        true
+    }
+
+    // The last `count` instructions, oldest first, as far back as the snapshots go
+    pub fn trace(&self, count: u64) -> Vec<Traced> {
+        let start = self.executed.saturating_sub(count);
+        let mut trace = Vec::new();
+        self.replay(start, |number, chip| {
+            if number >= start
+                && number < self.executed
+                && let Some(opcode) = next_opcode(chip)
+            {
+                trace.push(Traced {
+                    number,
+                    pc: *chip.get_pc(),
+                    opcode,
+                });
+            }
+        });
+        trace
+    }
+
+    // The last time `condition` went from false to true: the number of instructions run by
+    // then, go_to it to see the machine. The oldest snapshot when it held from there on, None
+    // when it never did
+    pub fn when(&self, mut condition: impl FnMut(&Chip8) -> bool) -> Option<u64> {
+        let mut held = false;
+        let mut found = None;
+        self.replay(0, |number, chip| {
+            let holds = condition(chip);
+            if holds && !held {
+                found = Some(number);
+            }
+            held = holds;
+        });
+        found
+    }
+
+    // Shows `visit` the machine after every number of instructions from the newest snapshot
+    // at or before `from` to now. Each snapshot takes over from the replay before it, changes
+    // made by hand are in them
+    fn replay(&self, from: u64, mut visit: impl FnMut(u64, &Chip8)) {
+        let first = self
+            .snapshots
+            .iter()
+            .rposition(|s| s.executed <= from)
+            .unwrap_or(0);
+        for (index, snapshot) in self.snapshots.iter().enumerate().skip(first) {
+            let end = self
+                .snapshots
+                .get(index + 1)
+                .map_or(self.executed + 1, |next| next.executed);
+            let mut chip = snapshot.chip.clone();
+            let mut executed = snapshot.executed;
+            visit(executed, &chip);
+            while executed + 1 < end {
+                if advance(&mut chip, &mut executed, self.ipf).is_err() {
+                    return;
+                }
+                visit(executed, &chip);
+            }
+        }
+    }
+
+    // Takes a snapshot of the machine as it is, for changes made to it from outside
+    pub fn checkpoint(&mut self, chip: &Chip8) {
+        if self
+            .snapshots
+            .back()
+            .is_some_and(|s| s.executed == self.executed)
+        {
+            self.snapshots.pop_back();
+        }
+        if self.snapshots.len() == SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(Snapshot {
+            executed: self.executed,
+            chip: chip.clone(),
+            written: self.written.clone(),
+        });
+    }
+}
+
+// One instruction, and the timer tick when it ends a frame
+fn advance(chip: &mut Chip8, executed: &mut u64, ipf: u32) -> Result<(), Chip8Error> {
+    chip.step()?;
+    *executed += 1;
+    if executed.is_multiple_of(ipf as u64) {
+        chip.decrease_timers();
+    }
+    Ok(())
+}
+
+// The instruction at PC, None when PC is on the last byte of RAM
+pub fn next_opcode(chip: &Chip8) -> Option<u16> {
+    let pc = *chip.get_pc();
+    let high = chip.get_ram(pc).ok()?;
+    let low = chip.get_ram(pc + 1).ok()?;
+    Some(u16::from_be_bytes([high, low]))
+}
+
+// The RAM the instruction at PC reads or writes, as where from I and how many bytes. Only
+// the instructions that go through I, the fetch of F000's address isn't one
+fn memory_access(chip: &Chip8) -> Option<(Access, usize, usize)> {
+    let instruction = chip.decode(next_opcode(chip)?).ok()?;
+    let planes = chip.display.plane_count();
+    let range = |x: usize, y: usize| x.abs_diff(y) + 1;
+    let (access, len) = match instruction {
+        Instruction::Draw(_, _, n) => (Access::Read, n as usize * planes),
+        Instruction::DrawLarge(..) => (Access::Read, 32 * planes),
+        Instruction::LoadMemV(x) => (Access::Read, x + 1),
+        Instruction::LoadRange(x, y) => (Access::Read, range(x, y)),
+        Instruction::LoadPattern => (Access::Read, 16),
+        Instruction::BCD(_) => (Access::Write, 3),
+        Instruction::StoreMemV(x) => (Access::Write, x + 1),
+        Instruction::SaveRange(x, y) => (Access::Write, range(x, y)),
+        _ => return None,
+    };
+    let start = *chip.get_i() as usize;
+    let end = (start + len).min(chip.memory_map().size);
+    Some((access, start, end.saturating_sub(start)))
+}
+
+// The bytes of the program no instruction reachable from 0x200 covers, up to the last byte
+// that isn't 0. Bnnn jumps can't be followed, so code only reached through one shows up too
+fn data_bytes(chip: &Chip8) -> AddressSet {
+    let opcode_at = |pc: usize| {
+        let high = chip.get_ram(pc as u16).ok()?;
+        let low = chip.get_ram(pc as u16 + 1).ok()?;
+        Some(u16::from_be_bytes([high, low]))
+    };
+    let mut code = AddressSet::new();
+    let mut pending = alloc::vec![PROGRAM_START];
+    while let Some(pc) = pending.pop() {
+        if code.contains(pc) {
+            continue;
+        }
+        let Some(opcode) = opcode_at(pc) else {
+            continue;
+        };
+        if chip.decode(opcode).is_err() {
+            continue;
+        }
+        code.insert(pc);
+        code.insert(pc + 1);
+        let nnn = (opcode & 0x0FFF) as usize;
+        match opcode & 0xF000 {
+            _ if opcode == 0x00EE => {}
+            0x1000 => pending.push(nnn),
+            0x2000 => pending.extend([nnn, pc + 2]),
+            0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xE000 => pending.extend([pc + 2, pc + 4]),
+            0xB000 => {}
+            _ => pending.push(pc + 2),
+        }
+    }
+
+    let mut data = AddressSet::new();
+    let end = (PROGRAM_START..RAM_SIZE)
+        .rev()
+        .find(|&address| chip.get_ram(address as u16) != Ok(0))
+        .map_or(PROGRAM_START, |last| last + 1);
+    for address in PROGRAM_START..end {
+        if !code.contains(address) {
+            data.insert(address);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    // counts V0 up and waits on the delay timer in a loop, with a random V1 for the rng
+    const ROM: [u8; 12] = [
+        0x70, 0x01, 0xC1, 0xFF, 0x62, 0x03, 0xF2, 0x15, 0xF3, 0x07, 0x12, 0x00,
+    ];
+
+    #[test]
+    fn test_step_back() {
+        let mut chip = Chip8::new(&ROM).unwrap();
+        let mut debugger = Debugger::new(4, &chip);
+        let mut states = vec![chip.save_state()];
+        for _ in 0..2500 {
+            debugger.step(&mut chip).unwrap();
+            states.push(chip.save_state());
+        }
+        assert_eq!(debugger.frame(), 625);
+
+        // across snapshots, down to where it started
+        for executed in (0..2500).rev() {
+            assert!(debugger.step_back(&mut chip));
+            assert_eq!(debugger.executed(), executed);
+            assert!(
+                chip.save_state() == states[executed as usize],
+                "{}",
+                executed
+            );
+        }
+        assert!(!debugger.step_back(&mut chip));
+    }
+
+    #[test]
+    fn test_trace_and_when() {
+        let mut chip = Chip8::new(&ROM).unwrap();
+        let mut debugger = Debugger::new(4, &chip);
+        for _ in 0..2500 {
+            debugger.step(&mut chip).unwrap();
+        }
+
+        let trace = debugger.trace(3);
+        let expected = [
+            (2497, 0x202, 0xC1FF),
+            (2498, 0x204, 0x6203),
+            (2499, 0x206, 0xF215),
+        ];
+        for (traced, (number, pc, opcode)) in trace.iter().zip(expected) {
+            assert_eq!(*traced, Traced { number, pc, opcode });
+        }
+        // across the snapshot at 2000
+        let trace = debugger.trace(600);
+        assert!(trace.iter().map(|t| t.number).eq(1900..2500));
+
+        // V0 counts up every 6 instructions and went round once, the last time it became
+        // 100 is the one that counts
+        let when = debugger.when(|chip| chip.get_v(0) == Ok(100));
+        assert_eq!(when, Some(2131));
+        assert!(debugger.go_to(&mut chip, 2131));
+        assert_eq!(chip.get_v(0), Ok(100));
+        assert_eq!(debugger.trace(1)[0].opcode, 0x7001);
+        assert!(
+            debugger
+                .when(|chip| chip.get_v(0) == Ok(200))
+                .is_some_and(|n| n < 2131)
+        );
+        // 0 from the start, and again after the wrap
+        assert_eq!(debugger.when(|chip| chip.get_v(0) == Ok(0)), Some(1531));
+        assert_eq!(debugger.when(|chip| chip.get_i() == &0x300), None);
+        assert_eq!(debugger.when(|_| true), Some(0));
+        assert!(!debugger.go_to(&mut chip, 2132));
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let mut chip = Chip8::new(&ROM).unwrap();
+        let mut debugger = Debugger::new(10, &chip);
+        debugger.step(&mut chip).unwrap();
+        chip.set_v(5, 0xAA).unwrap();
+        debugger.checkpoint(&chip);
+        debugger.step(&mut chip).unwrap();
+
+        // back to the hand made change, not before it
+        assert!(debugger.step_back(&mut chip));
+        assert_eq!(chip.get_v(5), Ok(0xAA));
+        assert!(debugger.step_back(&mut chip));
+        assert_eq!(chip.get_v(5), Ok(0));
+    }
+
+    #[test]
+    fn test_run_to() {
+        // a busy loop on DT, then beep, clear and draw
+        let rom = [
+            0x60, 0x08, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0xF0, 0x18, 0x00, 0xE0,
+            0xD0, 0x05, 0x12, 0x10,
+        ];
+        let mut chip = Chip8::new(&rom).unwrap();
+        let mut debugger = Debugger::new(10, &chip);
+        assert_eq!(
+            debugger.run_to(&mut chip, Target::Draw, RUN_LIMIT),
+            Ok(Stop::Reached)
+        );
+        assert_eq!(*chip.get_pc(), 0x20E);
+        assert_eq!(
+            debugger.run_to(&mut chip, Target::Clear, 100),
+            Ok(Stop::Limit)
+        );
+
+        let mut chip = Chip8::new(&rom).unwrap();
+        let mut debugger = Debugger::new(10, &chip);
+        assert_eq!(
+            debugger.run_to(&mut chip, Target::Sound, RUN_LIMIT),
+            Ok(Stop::Reached)
+        );
+        assert_eq!(*chip.get_pc(), 0x20C);
+        assert!(chip.is_sound_on());
+        // the 00E0 PC is on doesn't count, the next one would
+        assert_eq!(
+            debugger.run_to(&mut chip, Target::Clear, 1000),
+            Ok(Stop::Limit)
+        );
+    }
+
+    #[test]
+    fn test_breaks() {
+        // V1 = 5, skip on key V1 then beep for V0, waits for a key and starts over
+        let rom = [
+            0x61, 0x05, 0xE1, 0x9E, 0x60, 0x00, 0xF0, 0x18, 0x60, 0x02, 0xF0, 0x18, 0xF2, 0x0A,
+            0x12, 0x00,
+        ];
+        let mut chip = Chip8::new(&rom).unwrap();
+        let mut debugger = Debugger::new(10, &chip);
+        assert_eq!(debugger.run(&mut chip, 100), Ok(Stop::Limit));
+
+        let mut chip = Chip8::new(&rom).unwrap();
+        debugger.break_on(Break::Key(5));
+        debugger.break_on(Break::Key(5));
+        assert_eq!(debugger.breaks(), [Break::Key(5)]);
+        assert_eq!(debugger.run(&mut chip, 100), Ok(Stop::Break(Break::Key(5))));
+        assert_eq!(*chip.get_pc(), 0x202);
+
+        debugger.clear_breaks();
+        debugger.break_on(Break::Key(6));
+        debugger.break_on(Break::Sound);
+        // Fx18 with 0 doesn't start anything, Fx0A is any key
+        assert_eq!(debugger.run(&mut chip, 100), Ok(Stop::Break(Break::Sound)));
+        assert_eq!(*chip.get_pc(), 0x20A);
+        assert_eq!(debugger.run(&mut chip, 100), Ok(Stop::Break(Break::Key(6))));
+        assert_eq!(*chip.get_pc(), 0x20C);
+
+        debugger.clear_breaks();
+        debugger.break_on(Break::Input);
+        // still waiting on Fx0A, breaks stop run_to too
+        let stop = debugger.run_to(&mut chip, Target::Draw, 100);
+        assert_eq!(stop, Ok(Stop::Break(Break::Input)));
+        assert_eq!(*chip.get_pc(), 0x20C);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut chip = Chip8::new(&ROM).unwrap();
+        let mut debugger = Debugger::new(4, &chip);
+        assert!(debugger.set_breakpoint(0x206));
+        assert!(debugger.set_breakpoint(0x204));
+        assert!(!debugger.set_breakpoint(0x206));
+        assert!(debugger.breakpoints().eq([0x204, 0x206]));
+
+        // before the instruction there, and going on runs it
+        assert_eq!(
+            debugger.run(&mut chip, 100),
+            Ok(Stop::Break(Break::At(0x204)))
+        );
+        assert_eq!(*chip.get_pc(), 0x204);
+        assert_eq!(chip.get_v(2), Ok(0));
+        assert_eq!(
+            debugger.run(&mut chip, 100),
+            Ok(Stop::Break(Break::At(0x206)))
+        );
+        assert_eq!(chip.get_v(2), Ok(3));
+        // round the loop, ahead of the breaks
+        debugger.break_on(Break::Input);
+        let stop = debugger.run_to(&mut chip, Target::Draw, 100);
+        assert_eq!(stop, Ok(Stop::Break(Break::At(0x204))));
+
+        assert!(debugger.clear_breakpoint(0x204));
+        assert!(!debugger.clear_breakpoint(0x204));
+        assert_eq!(
+            debugger.run(&mut chip, 100),
+            Ok(Stop::Break(Break::At(0x206)))
+        );
+        debugger.clear_breakpoints();
+        assert_eq!(debugger.run(&mut chip, 100), Ok(Stop::Limit));
+        assert_eq!(debugger.executed(), 109);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        // BCD of 123 at 0x300, draws it as a sprite, loads it back in V0 to V2
+        let rom = [
+            0xA3, 0x00, 0x60, 0x7B, 0xF0, 0x33, 0xD0, 0x05, 0xF2, 0x65, 0x12, 0x0A,
+        ];
+        let mut chip = Chip8::new(&rom).unwrap();
+        let mut debugger = Debugger::new(10, &chip);
+        let watch = |start, end, read, write| Watchpoint {
+            start,
+            end,
+            read,
+            write,
+        };
+        debugger.watch(watch(0x300, 0x300, false, true));
+        debugger.watch(watch(0x302, 0x304, true, false));
+        debugger.watch(watch(0x302, 0x304, true, false));
+        assert_eq!(debugger.watchpoints().len(), 2);
+
+        // after the write, with its value
+        let hit = |pc, address, access, value| {
+            Ok(Stop::Watch(WatchHit {
+                pc,
+                address,
+                access,
+                value,
+            }))
+        };
+        assert_eq!(
+            debugger.run(&mut chip, 100),
+            hit(0x204, 0x300, Access::Write, 1)
+        );
+        assert_eq!(*chip.get_pc(), 0x206);
+        // the sprite reads 300 to 304, only the read watchpoint counts
+        assert_eq!(
+            debugger.run(&mut chip, 100),
+            hit(0x206, 0x302, Access::Read, 3)
+        );
+        debugger.step(&mut chip).unwrap();
+        assert_eq!(
+            debugger.watch_hit(),
+            Some(WatchHit {
+                pc: 0x208,
+                address: 0x302,
+                access: Access::Read,
+                value: 3
+            })
+        );
+        debugger.step(&mut chip).unwrap();
+        assert_eq!(debugger.watch_hit(), None);
+
+        assert!(debugger.unwatch(0x302));
+        assert!(!debugger.unwatch(0x302));
+        debugger.clear_watchpoints();
+        assert_eq!(debugger.run(&mut chip, 100), Ok(Stop::Limit));
+    }
+
+    #[test]
+    fn test_data_and_written_code() {
+        // jumps over a sprite into code that writes 6100 (V1 = 0) over the 0000 at 0x20C
+        // and runs it
+        let rom = [
+            0x12, 0x04, 0xFF, 0x81, 0x60, 0x61, 0xA2, 0x0C, 0xF0, 0x55, 0x12, 0x0C, 0x00, 0x00,
+            0x12, 0x02,
+        ];
+        let mut chip = Chip8::new(&rom).unwrap();
+        let mut debugger = Debugger::new(10, &chip);
+        debugger.break_on(Break::Written);
+        assert_eq!(
+            debugger.run(&mut chip, 100),
+            Ok(Stop::Break(Break::Written))
+        );
+        assert_eq!(*chip.get_pc(), 0x20C);
+        assert_eq!(debugger.suspicious(&chip), Some(Suspicious::Written));
+
+        debugger.clear_breaks();
+        debugger.break_on(Break::Data);
+        // the 1202 after it was never reached either, then it jumps into the sprite
+        assert_eq!(debugger.run(&mut chip, 100), Ok(Stop::Break(Break::Data)));
+        assert_eq!(*chip.get_pc(), 0x20E);
+        assert_eq!(debugger.run(&mut chip, 100), Ok(Stop::Break(Break::Data)));
+        assert_eq!(*chip.get_pc(), 0x202);
+        assert_eq!(
+            debugger.take_warnings(),
+            [
+                (0x20C, Suspicious::Written),
+                (0x20E, Suspicious::Data),
+                (0x202, Suspicious::Data)
+            ]
+        );
+        assert!(debugger.take_warnings().is_empty());
+
+        // going back past the write forgets it, 0x20C is only unreached data again
+        for _ in 0..4 {
+            assert!(debugger.step_back(&mut chip));
+        }
+        assert_eq!(*chip.get_pc(), 0x208);
+        chip.set_pc(0x20C).unwrap();
+        assert_eq!(debugger.suspicious(&chip), Some(Suspicious::Data));
+    }
+}
@@ -0,0 +1,585 @@
+// The egui frontend: the game as a texture under a menu bar, with dialogs for the settings
+// the other frontends only take from chip8.toml and the command line. Unlike them it owns
+// the loop, eframe calls ui once per repaint and frames run for the time that went by, so
+// the speed doesn't follow the refresh rate of the monitor. Changes made in the dialogs
+// last until the window closes, chip8.toml stays as it is. Only where the window was goes
+// back to the binary, for [window].
+use crate::audio::Audio;
+use crate::chip8::{Chip8, Chip8Error, Timbre};
+use crate::clock::{FRAME, RealClock, Scheduler};
+use crate::display::{Aspect, Palette};
+use crate::flash::{self, FlashLimiter};
+use crate::icon::{Icon, WindowGeometry};
+use crate::keyboard::Keymap;
+use crate::lockstep::Lockstep;
+use crate::panels::{self, Panels};
+use crate::plugin::{Event, PluginError, Plugins};
+use eframe::egui;
+use std::cell::RefCell;
+use std::rc::Rc;
+// frames run in one repaint at most, after a stall the game slows down instead of racing
+const MAX_CATCH_UP: u32 = 4;
+// height of the menu bar, for the size of the window
+const MENU_HEIGHT: f32 = 24.0;
+// the keypad as it's printed on the COSMAC VIP
+const KEYPAD: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// What the menus and dialogs change
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub title: String,
+    pub scale: usize,
+    pub aspect: Aspect,
+    pub ipf: u32,
+    pub palette: Palette,
+    pub flash_limit: bool,
+    pub keymap: Keymap,
+    pub volume: f32,
+    pub tone: f32,
+    pub timbre: Timbre,
+    pub ripple: bool,
+    pub muted: bool,
+    // where the window opens
+    pub window: WindowGeometry,
+}
+
+#[derive(Default)]
+struct Dialogs {
+    emulation: bool,
+    video: bool,
+    audio: bool,
+    input: bool,
+    registers: bool,
+    // not a dialog, a viewport of its own
+    debug_window: bool,
+}
+
+pub struct DesktopApp {
+    chip: Chip8,
+    // the machine as it was loaded, for reset
+    initial: Chip8,
+    // File > Save state
+    saved: Option<Chip8>,
+    settings: Settings,
+    lockstep: Lockstep,
+    audio: Audio,
+    plugins: Plugins,
+    flash: FlashLimiter,
+    contrast: f32,
+    texture: Option<egui::TextureHandle>,
+    paused: bool,
+    crash: Option<Chip8Error>,
+    dialogs: Dialogs,
+    // the keypad key waiting for a host key in the input dialog
+    rebinding: Option<usize>,
+    scheduler: Scheduler<RealClock>,
+    // where the window is, shared with run since eframe keeps the app
+    geometry: Rc<RefCell<WindowGeometry>>,
+    // the plugins that stopped, shared the same way, and how many of them were dismissed
+    plugin_errors: Rc<RefCell<Vec<PluginError>>>,
+    seen_errors: usize,
+}
+
+impl DesktopApp {
+    // The plugins are already loaded, the app runs their frames and shuts them down
+    pub fn new(chip: Chip8, settings: Settings, mut audio: Audio, plugins: Plugins) -> Self {
+        audio.set_volume(settings.volume);
+        audio.set_tone(settings.tone);
+        let geometry = Rc::new(RefCell::new(settings.window.clone()));
+        DesktopApp {
+            initial: chip.clone(),
+            chip,
+            saved: None,
+            lockstep: Lockstep::new(settings.ipf),
+            settings,
+            audio,
+            plugins,
+            flash: FlashLimiter::new(),
+            contrast: 1.0,
+            texture: None,
+            paused: false,
+            crash: None,
+            dialogs: Dialogs::default(),
+            rebinding: None,
+            scheduler: Scheduler::new(RealClock::new(), FRAME, MAX_CATCH_UP),
+            geometry,
+            plugin_errors: Rc::default(),
+            seen_errors: 0,
+        }
+    }
+
+    // Opens the window and plays until it's closed, then says where the window was. Fails
+    // without a display
+    // Where the window was when it closed and the plugins that failed, for the app to tell
+    pub fn run(self, icon: &Icon) -> Result<(WindowGeometry, Vec<PluginError>), String> {
+        let (width, height) = self.settings.aspect.pixel_size(self.settings.scale);
+        let window = &self.settings.window;
+        let size = window.size.map_or(
+            [64.0 * width as f32, 32.0 * height as f32 + MENU_HEIGHT],
+            |(width, height)| [width as f32, height as f32],
+        );
+        let rgba = icon
+            .pixels
+            .iter()
+            .flat_map(|&argb| {
+                let [a, r, g, b] = argb.to_be_bytes();
+                [r, g, b, a]
+            })
+            .collect();
+        let mut viewport = egui::ViewportBuilder::default()
+            .with_title(self.settings.title.clone())
+            .with_inner_size(size)
+            .with_min_inner_size([320.0, 160.0 + MENU_HEIGHT])
+            .with_fullscreen(window.fullscreen)
+            .with_icon(egui::IconData {
+                rgba,
+                width: icon.width,
+                height: icon.height,
+            });
+        // egui only knows the size of the monitor the window is on, see track_window
+        if let Some((x, y)) = window.position_on(None) {
+            viewport = viewport.with_position([x as f32, y as f32]);
+        }
+        let options = eframe::NativeOptions {
+            viewport,
+            ..Default::default()
+        };
+        let title = self.settings.title.clone();
+        let geometry = Rc::clone(&self.geometry);
+        let plugin_errors = Rc::clone(&self.plugin_errors);
+        eframe::run_native(&title, options, Box::new(|_| Ok(Box::new(self))))
+            .map_err(|error| error.to_string())?;
+        Ok((geometry.take(), plugin_errors.take()))
+    }
+
+    // Where the window is, kept from before going fullscreen or getting minimized
+    fn track_window(&self, ctx: &egui::Context) {
+        let viewport = ctx.input(|input| input.viewport().clone());
+        let mut geometry = self.geometry.borrow_mut();
+        geometry.fullscreen = viewport.fullscreen.unwrap_or(false);
+        if geometry.fullscreen || viewport.minimized == Some(true) {
+            return;
+        }
+        if let Some(outer) = viewport.outer_rect {
+            geometry.position = Some((outer.min.x as i32, outer.min.y as i32));
+        }
+        if let Some(inner) = viewport.inner_rect {
+            geometry.size = Some((inner.width() as u32, inner.height() as u32));
+        }
+        geometry.monitors = viewport
+            .monitor_size
+            .map(|size| format!("{}x{}", size.x, size.y));
+    }
+
+    fn run_frame(&mut self) {
+        if self.paused || self.crash.is_some() {
+            self.audio.update(false);
+            return;
+        }
+        self.lockstep.set_ipf(self.settings.ipf);
+        match self.lockstep.run_frame(&mut self.chip) {
+            Ok(()) => {
+                let errors = self.plugins.frame(&mut self.chip);
+                self.plugin_errors.borrow_mut().extend(errors);
+            }
+            Err(error) => {
+                self.plugins
+                    .event(&mut self.chip, &Event::Crash(error.clone()));
+                self.crash = Some(error);
+            }
+        }
+        self.contrast = if self.settings.flash_limit {
+            self.flash.frame(&self.chip.display)
+        } else {
+            1.0
+        };
+        if self.settings.muted {
+            self.audio.update(false);
+        } else {
+            self.audio.update_from(&self.chip);
+        }
+    }
+
+    fn read_keys(&mut self, ctx: &egui::Context) {
+        // typing in a dialog, or picking a key for the keymap, isn't playing
+        let playing = !ctx.egui_wants_keyboard_input() && self.rebinding.is_none();
+        let keymap = self.settings.keymap;
+        let keyboard = &mut self.chip.keyboard;
+        ctx.input(|input| {
+            for (host_key, key) in keymap.iter() {
+                let down = egui::Key::from_name(&host_key.to_string())
+                    .is_some_and(|host_key| input.key_down(host_key));
+                let _ = keyboard.set_key(key, playing && down);
+            }
+        });
+    }
+
+    fn reset(&mut self) {
+        self.chip.restore(self.initial.clone());
+        self.lockstep = Lockstep::new(self.settings.ipf);
+        self.crash = None;
+    }
+
+    fn menu_bar(&mut self, ui: &mut egui::Ui) {
+        egui::MenuBar::new().ui(ui, |ui| {
+            ui.menu_button("File", |ui| {
+                if ui.button("Save state").clicked() {
+                    self.saved = Some(self.chip.clone());
+                }
+                let load = ui.add_enabled(self.saved.is_some(), egui::Button::new("Load state"));
+                if load.clicked()
+                    && let Some(saved) = &self.saved
+                {
+                    self.chip.restore(saved.clone());
+                    self.crash = None;
+                }
+                ui.separator();
+                if ui.button("Quit").clicked() {
+                    ui.ctx().send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            });
+            ui.menu_button("Emulation", |ui| {
+                let pause = if self.paused { "Resume" } else { "Pause" };
+                if ui.button(pause).clicked() {
+                    self.paused = !self.paused;
+                }
+                if ui.button("Reset").clicked() {
+                    self.reset();
+                }
+                ui.separator();
+                ui.checkbox(&mut self.dialogs.emulation, "Speed and quirks...");
+            });
+            ui.menu_button("Video", |ui| {
+                ui.checkbox(&mut self.dialogs.video, "Colors...");
+                ui.menu_button("Pixel aspect", |ui| {
+                    for text in ["1:1", "2:1", "1:2"] {
+                        let aspect = Aspect::parse(text).unwrap_or_default();
+                        ui.radio_value(&mut self.settings.aspect, aspect, text);
+                    }
+                });
+                ui.checkbox(&mut self.settings.flash_limit, "Limit flashing");
+                let mut fullscreen = self.geometry.borrow().fullscreen;
+                if ui.checkbox(&mut fullscreen, "Fullscreen").changed() {
+                    ui.ctx()
+                        .send_viewport_cmd(egui::ViewportCommand::Fullscreen(fullscreen));
+                }
+            });
+            ui.menu_button("Audio", |ui| {
+                ui.checkbox(&mut self.settings.muted, "Mute");
+                ui.checkbox(&mut self.dialogs.audio, "Sound...");
+            });
+            ui.menu_button("Input", |ui| {
+                ui.checkbox(&mut self.dialogs.input, "Keymap...");
+            });
+            ui.menu_button("Debug", |ui| {
+                ui.checkbox(&mut self.dialogs.registers, "Registers");
+                ui.checkbox(&mut self.dialogs.debug_window, "Debug window");
+                let step = ui.add_enabled(
+                    self.paused && self.crash.is_none(),
+                    egui::Button::new("Step instruction"),
+                );
+                if step.clicked()
+                    && let Err(error) = self.chip.step()
+                {
+                    self.crash = Some(error);
+                }
+            });
+        });
+    }
+
+    // The debugger panels in a second OS window, repainted with the game one. false once
+    // it's closed
+    fn debug_window(&self, ctx: &egui::Context) -> bool {
+        let panels = Panels::new(&self.chip);
+        let (width, height) = panels::window_size();
+        let viewport = egui::ViewportBuilder::default()
+            .with_title("chip8 debugger")
+            .with_inner_size([width as f32, height as f32]);
+        ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("debugger"),
+            viewport,
+            |ui, _| {
+                egui::CentralPanel::default().show(ui, |ui| {
+                    for (title, lines) in [
+                        ("Registers", &panels.registers),
+                        ("Memory at I", &panels.memory),
+                        ("Code", &panels.code),
+                    ] {
+                        ui.strong(title);
+                        ui.monospace(lines.join("\n"));
+                        ui.add_space(8.0);
+                    }
+                });
+                !ui.ctx().input(|input| input.viewport().close_requested())
+            },
+        )
+    }
+
+    fn dialogs(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Emulation")
+            .open(&mut self.dialogs.emulation)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.add(
+                    egui::Slider::new(&mut self.settings.ipf, 1..=1000)
+                        .logarithmic(true)
+                        .text("instructions per frame"),
+                );
+                ui.separator();
+                let quirks = &mut self.chip.quirks;
+                ui.checkbox(&mut quirks.shift_uses_vy, "8xy6/8xyE shift Vy");
+                ui.checkbox(&mut quirks.load_store_increment_i, "Fx55/Fx65 move I");
+                ui.checkbox(&mut quirks.jump_uses_vx, "Bnnn jumps to xnn + Vx");
+                ui.checkbox(&mut quirks.vf_reset, "8xy1/8xy2/8xy3 reset VF");
+            });
+
+        egui::Window::new("Video")
+            .open(&mut self.dialogs.video)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let palette = &mut self.settings.palette;
+                egui::Grid::new("colors").show(ui, |ui| {
+                    for (name, color) in [("On", &mut palette.on), ("Off", &mut palette.off)] {
+                        ui.label(name);
+                        let mut rgb = [(*color >> 16) as u8, (*color >> 8) as u8, *color as u8];
+                        if ui.color_edit_button_srgb(&mut rgb).changed() {
+                            *color = u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]);
+                        }
+                        ui.end_row();
+                    }
+                });
+                if ui.button("Swap").clicked() {
+                    (palette.on, palette.off) = (palette.off, palette.on);
+                }
+            });
+
+        egui::Window::new("Audio")
+            .open(&mut self.dialogs.audio)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let volume =
+                    ui.add(egui::Slider::new(&mut self.settings.volume, 0.0..=1.0).text("volume"));
+                if volume.changed() {
+                    self.audio.set_volume(self.settings.volume);
+                }
+                let tone = ui.add(
+                    egui::Slider::new(&mut self.settings.tone, 20.0..=2000.0)
+                        .logarithmic(true)
+                        .suffix(" Hz")
+                        .text("tone"),
+                );
+                if tone.changed() {
+                    self.audio.set_tone(self.settings.tone);
+                }
+                let timbre = ui.horizontal(|ui| {
+                    let sine = ui.radio_value(&mut self.settings.timbre, Timbre::Sine, "Sine");
+                    let vip = ui.radio_value(&mut self.settings.timbre, Timbre::Vip, "VIP buzzer");
+                    sine.changed() || vip.changed()
+                });
+                let ripple = ui.add_enabled(
+                    self.settings.timbre == Timbre::Vip,
+                    egui::Checkbox::new(&mut self.settings.ripple, "Power supply hum"),
+                );
+                if timbre.inner || ripple.changed() {
+                    self.audio
+                        .set_timbre(self.settings.timbre, self.settings.ripple);
+                }
+            });
+
+        egui::Window::new("Input")
+            .open(&mut self.dialogs.input)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Click a key, then press the host key for it");
+                egui::Grid::new("keypad").show(ui, |ui| {
+                    for row in KEYPAD {
+                        for key in row {
+                            let host_key = self.settings.keymap.host_key(key).unwrap_or('?');
+                            let label = if self.rebinding == Some(key) {
+                                format!("{:X}: ...", key)
+                            } else {
+                                format!("{:X}: {}", key, host_key.to_ascii_uppercase())
+                            };
+                            if ui.button(label).clicked() {
+                                self.rebinding = Some(key);
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+                if ui.button("Default").clicked() {
+                    self.settings.keymap = Keymap::new();
+                }
+            });
+        if let Some(key) = self.rebinding {
+            let typed = ctx.input(|input| {
+                input.events.iter().find_map(|event| match event {
+                    egui::Event::Text(text) => text.chars().next(),
+                    _ => None,
+                })
+            });
+            if let Some(host_key) = typed.filter(char::is_ascii_alphanumeric) {
+                self.settings.keymap = rebind(&self.settings.keymap, key, host_key);
+                self.rebinding = None;
+            }
+            if !self.dialogs.input {
+                self.rebinding = None;
+            }
+        }
+
+        egui::Window::new("Registers")
+            .open(&mut self.dialogs.registers)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let chip = &self.chip;
+                ui.monospace(format!(
+                    "PC {:03X}  I {:03X}  DT {:02X}  ST {:02X}",
+                    chip.get_pc(),
+                    chip.get_i(),
+                    chip.get_dt(),
+                    chip.get_st()
+                ));
+                for (start, values) in chip.registers().chunks(8).enumerate() {
+                    let line: Vec<String> = values
+                        .iter()
+                        .enumerate()
+                        .map(|(index, value)| format!("V{:X} {:02X}", start * 8 + index, value))
+                        .collect();
+                    ui.monospace(line.join("  "));
+                }
+                let stack: Vec<String> = chip
+                    .stack()
+                    .iter()
+                    .map(|address| format!("{:03X}", address))
+                    .collect();
+                ui.monospace(format!("stack [{}]", stack.join(" ")));
+                ui.monospace(format!("frame {}", self.lockstep.frame()));
+            });
+
+        if self.dialogs.debug_window {
+            self.dialogs.debug_window = self.debug_window(ctx);
+        }
+
+        if let Some(error) = self.crash.clone() {
+            egui::Window::new("Crashed")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label(format!("{:?} at {:03X}", error, self.chip.get_pc()));
+                    if ui.button("Reset").clicked() {
+                        self.reset();
+                    }
+                });
+        }
+
+        let errors = self.plugin_errors.borrow().len();
+        if errors > self.seen_errors {
+            egui::Window::new("Plugin stopped")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    for error in &self.plugin_errors.borrow()[self.seen_errors..] {
+                        ui.label(error.to_string());
+                    }
+                    if ui.button("OK").clicked() {
+                        self.seen_errors = errors;
+                    }
+                });
+        }
+    }
+
+    fn game_view(&mut self, ui: &mut egui::Ui) {
+        let palette = flash::dim(self.settings.palette, self.contrast);
+        let display = &self.chip.display;
+        let (width, height) = (display.width(), display.height());
+        let pixels = (0..width * height)
+            .map(|index| {
+                let pixel = display.pixel_value(index % width, index / width);
+                let [_, r, g, b] = palette.color(pixel).to_be_bytes();
+                egui::Color32::from_rgb(r, g, b)
+            })
+            .collect();
+        let image = egui::ColorImage::new([width, height], pixels);
+        let options = egui::TextureOptions::NEAREST;
+        let texture = match &mut self.texture {
+            Some(texture) => {
+                texture.set(image, options);
+                texture
+            }
+            None => self
+                .texture
+                .insert(ui.ctx().load_texture("screen", image, options)),
+        };
+
+        // as big as fits with the pixel aspect, the rest of the window in the off color
+        let area = ui.max_rect();
+        let ratio = self.settings.aspect.ratio();
+        let height = area.height().min(area.width() / ratio);
+        let rect = egui::Rect::from_center_size(area.center(), egui::vec2(height * ratio, height));
+        let [_, r, g, b] = palette.off.to_be_bytes();
+        ui.painter()
+            .rect_filled(area, 0.0, egui::Color32::from_rgb(r, g, b));
+        let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+        ui.painter()
+            .image(texture.id(), rect, uv, egui::Color32::WHITE);
+    }
+}
+
+impl eframe::App for DesktopApp {
+    fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+        let ctx = ui.ctx().clone();
+        self.track_window(&ctx);
+        self.read_keys(&ctx);
+        for _ in 0..self.scheduler.due() {
+            self.run_frame();
+        }
+
+        egui::Panel::top("menu").show(ui, |ui| self.menu_bar(ui));
+        egui::CentralPanel::default()
+            .frame(egui::Frame::NONE)
+            .show(ui, |ui| self.game_view(ui));
+        self.dialogs(&ctx);
+        ctx.request_repaint();
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.audio.update(false);
+        let errors = self.plugins.shutdown(&mut self.chip);
+        self.plugin_errors.borrow_mut().extend(errors);
+    }
+}
+
+// The keymap with `key` on `host_key`. A host key can only play one key, the one that had it
+// gets the old host key of `key` instead
+fn rebind(keymap: &Keymap, key: usize, host_key: char) -> Keymap {
+    let mut keys: Vec<char> = keymap.iter().map(|(host_key, _)| host_key).collect();
+    let host_key = host_key.to_ascii_lowercase();
+    if let Some(other) = keymap.key_for(host_key) {
+        keys[other] = keys[key];
+    }
+    keys[key] = host_key;
+    Keymap::parse(&keys.into_iter().collect::<String>()).unwrap_or(*keymap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebind() {
+        let keymap = Keymap::new();
+        // 5 is on w, 0 on x
+        let keymap = rebind(&keymap, 5, 'K');
+        assert_eq!(keymap.host_key(5), Some('k'));
+        let keymap = rebind(&keymap, 0, 'k');
+        assert_eq!(keymap.host_key(0), Some('k'));
+        assert_eq!(keymap.host_key(5), Some('x'));
+        assert_eq!(rebind(&keymap, 3, 'e'), rebind(&keymap, 3, 'E'));
+    }
+}
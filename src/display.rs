@@ -1,68 +1,254 @@
 use crate::chip8::Chip8Error;
-use minifb::{Key, Window, WindowOptions};
+#[cfg(feature = "alloc")]
+use alloc::{format, string::String};
 
-const SCALE: usize = 20;
-const WIDTH: usize = 64 * SCALE;
-const HEIGHT: usize = 32 * SCALE;
+// Colors of lit and unlit pixels as 0xRRGGBB, frontends paint with it. The default is the
+// amber on black this emulator always had. XO-CHIP pixels lit only on the second plane are
+// `second` and the ones lit on both `both`, Octo's colors for them by default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub on: u32,
+    pub off: u32,
+    pub second: u32,
+    pub both: u32,
+}
+
+impl Palette {
+    pub const PRESETS: [&str; 4] = ["amber", "green", "paper", "gameboy"];
+
+    // Amber on black (the default), green phosphor, black ink on paper white, or the four
+    // greens of the Game Boy
+    pub fn preset(name: &str) -> Option<Palette> {
+        let is = |preset: &str| name.eq_ignore_ascii_case(preset);
+        if is("amber") {
+            Some(Palette::default())
+        } else if is("green") {
+            Some(Palette {
+                on: 0x33FF66,
+                off: 0x001A00,
+                second: 0x1A8033,
+                both: 0xB3FFC6,
+            })
+        } else if is("paper") {
+            Some(Palette {
+                on: 0x202020,
+                off: 0xF4F1E8,
+                second: 0x9A968C,
+                both: 0x5C5A54,
+            })
+        } else if is("gameboy") {
+            Some(Palette {
+                on: 0x0F380F,
+                off: 0x9BBC0F,
+                second: 0x8BAC0F,
+                both: 0x306230,
+            })
+        } else {
+            None
+        }
+    }
+
+    // For a pixel value of Display::pixel_value, 0 to 3
+    pub fn color(&self, pixel: u8) -> u32 {
+        match pixel {
+            1 => self.on,
+            2 => self.second,
+            3 => self.both,
+            _ => self.off,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            on: 0xFFB000,
+            off: 0x000000,
+            second: 0xFF6600,
+            both: 0x662200,
+        }
+    }
+}
+
+// Shape of a Chip-8 pixel on screen, width:height. Square by default, some displays of the
+// time drew them wider than tall and 2:1 stretches the picture the same way. Frontends size
+// pixels with it when they scale up, whatever the size of the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Aspect {
+    pub width: u8,
+    pub height: u8,
+}
+
+impl Aspect {
+    pub const SQUARE: Aspect = Aspect {
+        width: 1,
+        height: 1,
+    };
+
+    // "2:1", both sides from 1 to 8
+    pub fn parse(text: &str) -> Option<Self> {
+        let (width, height) = text.split_once(':')?;
+        let side = |text: &str| {
+            text.trim()
+                .parse()
+                .ok()
+                .filter(|side| (1..=8).contains(side))
+        };
+        Some(Aspect {
+            width: side(width)?,
+            height: side(height)?,
+        })
+    }
+
+    // Screen pixels of one Chip-8 pixel at `scale`: `scale` tall and as wide as the aspect
+    // says, never 0
+    pub fn pixel_size(&self, scale: usize) -> (usize, usize) {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let scale = scale.max(1);
+        (((scale * width + height / 2) / height).max(1), scale)
+    }
+
+    // How many times wider than tall the whole screen is
+    pub fn ratio(&self) -> f32 {
+        2.0 * self.width as f32 / self.height as f32
+    }
+}
 
+impl Default for Aspect {
+    fn default() -> Self {
+        Self::SQUARE
+    }
+}
+
+impl core::fmt::Display for Aspect {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}:{}", self.width, self.height)
+    }
+}
+
+// Just the framebuffer, drawing it somewhere is the frontend's job (see screen.rs). 64x32,
+// or 128x64 while a SUPER-CHIP program has hi-res on. A plane is always 128x64, each row
+// one u128 with the leftmost pixel in the top bit, and low-res only uses its top left
+// quarter, so Dxyn is a rotate, an AND for the collision and an XOR per sprite row.
+// XO-CHIP draws on two planes, picked with Fn01, and a pixel's color is which of them
+// have it lit. Only the first one is ever selected on the other platforms.
+//
+// Everything that takes or gives 64x32 pixels (rows, pixels, get_pixel, blit, the ascii
+// art...) keeps doing so in hi-res, where a pixel of it is lit when any of the 2x2 under it
+// is: frontends with a fixed 64x32 screen still show the game. They see a pixel lit on
+// either plane as lit. width, height, pixel and pixel_value are the screen as it really is
+#[derive(Clone)]
 pub struct Display {
-    // display buffer
-    display: [u8; 64 * 32],
-    buffer: Vec<u32>,
-    window: Window,
+    planes: [[u128; 64]; 2],
+    hires: bool,
+    // bit 0 the first plane, bit 1 the second
+    selected: u8,
 }
 
 impl Display {
     pub fn new() -> Self {
-        let window = Window::new(
-            "Chip-8 by Hernani Samuel Diniz",
-            WIDTH,
-            HEIGHT,
-            WindowOptions::default(),
-        )
-        .unwrap();
-
         Display {
-            display: [0; 64 * 32],
-            buffer: vec![0u32; WIDTH * HEIGHT],
-            window,
+            planes: [[0; 64]; 2],
+            hires: false,
+            selected: 1,
         }
     }
 
-    // Shows the window in use for other modules in a safe way
-    pub fn window(&self) -> &Window {
-        &self.window
+    // Fn01, the planes drawing, clearing and scrolling work on. 0 leaves the screen alone
+    pub fn select_planes(&mut self, planes: u8) {
+        self.selected = planes & 0b11;
     }
 
-    // Render converts display to scaled version buffer and updates screen
-    pub fn render(&mut self) {
-        for y in 0..32 {
-            for x in 0..64 {
-                let color = if self.display[y * 64 + x] == 1 {
-                    0xFFB000
-                } else {
-                    0x000000
-                };
-                for dy in 0..SCALE {
-                    for dx in 0..SCALE {
-                        self.buffer[(y * SCALE + dy) * WIDTH + (x * SCALE + dx)] = color;
-                    }
-                }
-            }
+    pub fn selected_planes(&self) -> u8 {
+        self.selected
+    }
+
+    // How many planes a sprite is drawn on, its bytes for each come one after the other
+    pub fn plane_count(&self) -> usize {
+        self.selected.count_ones() as usize
+    }
+
+    // The selected ones, first plane first
+    fn selected_mut(&mut self) -> impl Iterator<Item = &mut [u128; 64]> + '_ {
+        let selected = self.selected;
+        self.planes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(index, _)| selected & (1 << index) != 0)
+            .map(|(_, plane)| plane)
+    }
+
+    // A row of the screen with a bit lit where either plane is
+    fn row(&self, y: usize) -> u128 {
+        self.planes[0][y] | self.planes[1][y]
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    // 00FE and 00FF, switching clears the screen like modern interpreters do
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.clear();
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires { 128 } else { 64 }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { 64 } else { 32 }
+    }
+
+    // The pixel at (x, y) of the screen in its current resolution, false outside of it
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixel_value(x, y) != 0
+    }
+
+    // Which planes have the pixel lit, bit 0 the first and bit 1 the second, for
+    // Palette::color. 0 outside the screen
+    pub fn pixel_value(&self, x: usize, y: usize) -> u8 {
+        if x >= self.width() || y >= self.height() {
+            return 0;
         }
-        self.window
-            .update_with_buffer(&self.buffer, WIDTH, HEIGHT)
-            .unwrap();
+        let [first, second] = self.planes.map(|plane| ((plane[y] >> (127 - x)) & 1) as u8);
+        first | (second << 1)
     }
 
-    // Function to update screen
-    pub fn window_update(&mut self) {
-        self.window.update();
+    // The 64x32 rows, for frontends that can use bits directly
+    pub fn rows(&self) -> [u64; 32] {
+        core::array::from_fn(|y| {
+            if self.hires {
+                squeeze(self.row(2 * y) | self.row(2 * y + 1))
+            } else {
+                (self.row(y) >> 64) as u64
+            }
+        })
     }
 
-    // Function to say the screen state (open or not)
-    pub fn is_open(&self) -> bool {
-        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    // Every 64x32 pixel as (x, y, lit), row by row from the top left
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, bool)> + '_ {
+        self.rows()
+            .into_iter()
+            .enumerate()
+            .flat_map(|(y, row)| (0..64).map(move |x| (x, y, bit(row, x) == 1)))
+    }
+
+    // One byte per pixel (0 or 1) for each of the 32 rows, from the top
+    pub fn pixel_rows(&self) -> impl Iterator<Item = [u8; 64]> + '_ {
+        self.rows()
+            .into_iter()
+            .map(|row| core::array::from_fn(|x| bit(row, x)))
+    }
+
+    // The whole screen with one byte per pixel (0 or 1), row by row, like blit takes it
+    pub fn pixel_bytes(&self) -> [u8; 64 * 32] {
+        let mut pixels = [0; 64 * 32];
+        for (line, row) in pixels.chunks_mut(64).zip(self.pixel_rows()) {
+            line.copy_from_slice(&row);
+        }
+        pixels
     }
 
     // Safe screen usage
@@ -70,20 +256,322 @@ impl Display {
         if index >= 64 * 32 {
             Err(Chip8Error::InvalidPixelAccess)
         } else {
-            Ok(self.display[index])
+            let (x, y) = (index % 64, index / 64);
+            let lit = if self.hires {
+                (0..4).any(|at| self.pixel(2 * x + at % 2, 2 * y + at / 2))
+            } else {
+                self.pixel(x, y)
+            };
+            Ok(lit as u8)
         }
     }
 
+    // In hi-res the 2x2 pixels under it. On the first plane, the second is cleared under it
     pub fn set_pixel(&mut self, index: usize, value: u8) -> Result<bool, Chip8Error> {
         if index >= 64 * 32 {
             Err(Chip8Error::InvalidPixelAccess)
         } else if value == 1 || value == 0 {
-            self.display[index] = value;
+            let (x, y) = (index % 64, index / 64);
+            let (mask, lines) = if self.hires {
+                (0b11 << (126 - 2 * x), 2 * y..2 * y + 2)
+            } else {
+                (1 << (127 - x), y..y + 1)
+            };
+            for y in lines {
+                let [first, second] = &mut self.planes;
+                first[y] = (first[y] & !mask) | (mask * value as u128);
+                second[y] &= !mask;
+            }
             Ok(true)
         } else {
             Err(Chip8Error::InvalidPixelValue)
         }
     }
+
+    // Both planes, whatever is selected
+    pub fn clear(&mut self) {
+        self.planes = [[0; 64]; 2];
+    }
+
+    // 00E0, which on XO-CHIP only clears the selected planes
+    pub(crate) fn clear_selected(&mut self) {
+        self.selected_mut().for_each(|plane| plane.fill(0));
+    }
+
+    // The whole screen at once, one byte per pixel like pixel_bytes(). Checked once up front,
+    // a bad value leaves the screen as it was. Goes back to low-res, on the first plane
+    pub fn blit(&mut self, pixels: &[u8; 64 * 32]) -> Result<bool, Chip8Error> {
+        if pixels.iter().any(|&pixel| pixel > 1) {
+            return Err(Chip8Error::InvalidPixelValue);
+        }
+        self.set_hires(false);
+        for (row, line) in self.planes[0].iter_mut().zip(pixels.chunks(64)) {
+            *row = (line.iter().fold(0, |row, &pixel| (row << 1) | pixel as u64) as u128) << 64;
+        }
+        Ok(true)
+    }
+
+    // The rows of both 128x64 planes as they are stored, for save states. In low-res only
+    // the top left 64x32 of them is the screen
+    pub fn planes(&self) -> &[[u128; 64]; 2] {
+        &self.planes
+    }
+
+    pub fn set_planes(&mut self, planes: [[u128; 64]; 2], hires: bool, selected: u8) {
+        self.planes = planes;
+        self.hires = hires;
+        self.select_planes(selected);
+    }
+
+    // The 8 pixels of one sprite row XORed in at (x, y) of the selected planes, both
+    // wrapping around the screen. True if a lit pixel was turned off
+    pub fn xor_sprite_row(&mut self, x: usize, y: usize, byte: u8) -> bool {
+        let (bits, line) = self.place(x, y, (byte as u128) << 120);
+        let mut collision = false;
+        for plane in self.selected_mut() {
+            collision |= xor_line(&mut plane[line], bits);
+        }
+        collision
+    }
+
+    // A row of pixels left aligned in `bits` moved to x of the current resolution, and the
+    // plane row it goes on
+    fn place(&self, x: usize, y: usize, bits: u128) -> (u128, usize) {
+        // rotating wraps whatever goes past the right edge
+        if self.hires {
+            (bits.rotate_right((x % 128) as u32), y % 64)
+        } else {
+            let bits = ((bits >> 64) as u64).rotate_right((x % 64) as u32);
+            ((bits as u128) << 64, y % 32)
+        }
+    }
+
+    // Crate-only fast path for Dxyn: XORs the sprite rows at (x, y) with wrapping,
+    // returns 1 if any lit pixel was turned off. The rows of each selected plane one after
+    // the other
+    pub(crate) fn xor_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> u8 {
+        self.xor_sprites(x, y, sprite, 1)
+    }
+
+    // Dxy0 of SUPER-CHIP, a 16x16 sprite of two bytes per row, 32 bytes per plane
+    pub(crate) fn xor_large_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> u8 {
+        self.xor_sprites(x, y, sprite, 2)
+    }
+
+    fn xor_sprites(&mut self, x: usize, y: usize, sprite: &[u8], row_bytes: usize) -> u8 {
+        let count = self.plane_count();
+        if count == 0 || sprite.is_empty() {
+            return 0;
+        }
+        let mut sprites = sprite.chunks(sprite.len() / count);
+        let mut collision = false;
+        for index in 0..2 {
+            if self.selected & (1 << index) == 0 {
+                continue;
+            }
+            let Some(sprite) = sprites.next() else {
+                break;
+            };
+            for (row, bytes) in sprite.chunks(row_bytes).enumerate() {
+                let bits = bytes
+                    .iter()
+                    .fold(0, |bits, &byte| (bits << 8) | byte as u128);
+                let (bits, line) = self.place(x, y + row, bits << (128 - 8 * row_bytes));
+                collision |= xor_line(&mut self.planes[index][line], bits);
+            }
+        }
+        collision as u8
+    }
+
+    // 00Cn, 00DN, 00FB and 00FC move the selected planes by pixels of the current
+    // resolution, what goes past the edge is gone and the other side comes in unlit
+    pub fn scroll_down(&mut self, lines: usize) {
+        let height = self.height();
+        let lines = lines.min(height);
+        for plane in self.selected_mut() {
+            plane.copy_within(0..height - lines, lines);
+            plane[..lines].fill(0);
+        }
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        let height = self.height();
+        let lines = lines.min(height);
+        for plane in self.selected_mut() {
+            plane.copy_within(lines..height, 0);
+            plane[height - lines..height].fill(0);
+        }
+    }
+
+    pub fn scroll_right(&mut self, pixels: usize) {
+        let (mask, height) = (self.mask(), self.height());
+        for plane in self.selected_mut() {
+            for row in &mut plane[..height] {
+                *row = row.checked_shr(pixels as u32).unwrap_or(0) & mask;
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, pixels: usize) {
+        let (mask, height) = (self.mask(), self.height());
+        for plane in self.selected_mut() {
+            for row in &mut plane[..height] {
+                *row = row.checked_shl(pixels as u32).unwrap_or(0) & mask;
+            }
+        }
+    }
+
+    // The bits of a plane row that are on screen
+    fn mask(&self) -> u128 {
+        if self.hires {
+            u128::MAX
+        } else {
+            (u64::MAX as u128) << 64
+        }
+    }
+
+    // Screen as text, 32 lines of 64 chars where '#' is a lit pixel and '.' an unlit one
+    #[cfg(feature = "alloc")]
+    pub fn to_ascii(&self) -> String {
+        let mut text = String::with_capacity(65 * 32);
+        for row in self.rows() {
+            for x in 0..64 {
+                text.push(if bit(row, x) == 1 { '#' } else { '.' });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // Counts pixels that differ from an ascii art screen (see parse_ascii)
+    pub fn count_differences(&self, art: &str) -> usize {
+        let expected = parse_ascii(art);
+        self.pixel_bytes()
+            .iter()
+            .zip(expected.iter())
+            .filter(|(pixel, want)| pixel != want)
+            .count()
+    }
+
+    // The screen in the palette colors, each Chip-8 pixel a scale x scale square, so hi-res
+    // makes an image twice as large. Screenshots, the PNG frames and the clipboard all
+    // start from it
+    #[cfg(feature = "image")]
+    pub fn to_image(&self, palette: Palette, scale: usize) -> image::RgbaImage {
+        let scale = scale.max(1);
+        let (width, height) = (self.width() * scale, self.height() * scale);
+        image::RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+            let pixel = self.pixel_value(x as usize / scale, y as usize / scale);
+            let [_, r, g, b] = palette.color(pixel).to_be_bytes();
+            image::Rgba([r, g, b, 0xFF])
+        })
+    }
+
+    // FNV-1a over the 2048 pixels, stable between versions and platforms. Over the 8192 of
+    // hi-res while it's on. Each pixel is its pixel_value, so XO-CHIP's colors count too
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                hash ^= self.pixel_value(x, y) as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+}
+
+// Where frames go to be seen: a window, a terminal, a panel. The core never needs one,
+// Emulator hands frames to one when it has it (set_display_backend) and runs headless
+// without. Screen (minifb), SdlScreen and Terminal are the ones that come with chip8
+pub trait DisplayBackend {
+    // After a frame that drew
+    fn render(&mut self, display: &Display);
+    // After a frame that didn't, for backends that pump their events or keep their pace
+    fn update(&mut self) {}
+    // False once the user closed it
+    fn is_open(&self) -> bool {
+        true
+    }
+}
+
+fn xor_line(line: &mut u128, bits: u128) -> bool {
+    let collision = *line & bits != 0;
+    *line ^= bits;
+    collision
+}
+
+// A 128 pixel row as 64, each pixel lit when either of the two under it is
+fn squeeze(row: u128) -> u64 {
+    (0..64).fold(0, |squeezed, x| {
+        (squeezed << 1) | ((row >> (126 - 2 * x)) & 0b11 != 0) as u64
+    })
+}
+
+fn bit(row: u64, x: usize) -> u8 {
+    ((row >> (63 - x)) & 1) as u8
+}
+
+// Ascii art to pixels. Each line is trimmed so art can be indented in code, blank lines
+// around it are ignored, '#' is lit and anything else unlit. Missing rows or columns are unlit.
+pub fn parse_ascii(art: &str) -> [u8; 64 * 32] {
+    // blank lines at the end are unlit rows anyway, only the leading ones need skipping
+    let lines = art
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty());
+
+    let mut pixels = [0; 64 * 32];
+    for (y, line) in lines.take(32).enumerate() {
+        for (x, c) in line.chars().take(64).enumerate() {
+            pixels[y * 64 + x] = (c == '#') as u8;
+        }
+    }
+    pixels
+}
+
+// Builds the panic message of assert_display_eq!, None when the screens match.
+// Rows are printed side by side (actual | expected) and differing rows get a '<' mark
+#[cfg(feature = "alloc")]
+pub fn ascii_mismatch(display: &Display, art: &str) -> Option<String> {
+    let differences = display.count_differences(art);
+    if differences == 0 {
+        return None;
+    }
+
+    let actual = display.pixel_bytes();
+    let expected = parse_ascii(art);
+    let mut message = format!(
+        "display mismatch, {} pixels differ (actual | expected)\n",
+        differences
+    );
+    for y in 0..32 {
+        let actual_row = &actual[y * 64..(y + 1) * 64];
+        let expected_row = &expected[y * 64..(y + 1) * 64];
+        for row in [actual_row, expected_row] {
+            message.extend(row.iter().map(|&p| if p == 1 { '#' } else { '.' }));
+            message.push(' ');
+        }
+        if actual_row != expected_row {
+            message.push('<');
+        }
+        message.push('\n');
+    }
+    Some(message)
+}
+
+// Asserts the screen of a Chip8 (or anything with display_ascii_mismatch) matches ascii art:
+//     assert_display_eq!(chip, "
+//         ########
+//         #......#
+//     ");
+#[macro_export]
+macro_rules! assert_display_eq {
+    ($chip:expr, $art:expr $(,)?) => {
+        if let Some(message) = $chip.display_ascii_mismatch($art) {
+            panic!("{}", message);
+        }
+    };
 }
 
 impl Default for Display {
@@ -95,6 +583,8 @@ impl Default for Display {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "alloc")]
+    use alloc::vec::Vec;
     // Testing screen safety
     #[test]
     fn test_screen_full_use() {
@@ -119,4 +609,214 @@ mod tests {
             assert_eq!(display.get_pixel(i).unwrap(), 0);
         }
     }
+
+    // Testing ascii art helpers
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_ascii_round_trip() {
+        let mut display = Display::new();
+        display.set_pixel(0, 1).unwrap();
+        display.set_pixel(64 + 3, 1).unwrap();
+        display.set_pixel(2047, 1).unwrap();
+        let text = display.to_ascii();
+        assert_eq!(text.lines().count(), 32);
+        assert!(text.starts_with("#...............") && text.ends_with("..#\n"));
+        assert_eq!(display.count_differences(&text), 0);
+        assert_eq!(parse_ascii(&text), display.pixel_bytes());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_ascii_art_partial_and_indented() {
+        let mut display = Display::new();
+        display.set_pixel(1, 1).unwrap();
+        display.set_pixel(64, 1).unwrap();
+        let art = "
+            .#
+            #.
+        ";
+        assert_eq!(display.count_differences(art), 0);
+        assert!(ascii_mismatch(&display, art).is_none());
+        assert_eq!(display.count_differences("##"), 2);
+        assert!(
+            ascii_mismatch(&display, "##")
+                .unwrap()
+                .contains("2 pixels differ")
+        );
+    }
+
+    #[test]
+    fn test_xor_sprite_wraps_and_collides() {
+        let mut display = Display::new();
+        // bottom right corner, so the sprite wraps on both axes
+        assert_eq!(display.xor_sprite(62, 31, &[0xC0, 0x80]), 0);
+        assert_eq!(display.get_pixel(31 * 64 + 62).unwrap(), 1);
+        assert_eq!(display.get_pixel(31 * 64 + 63).unwrap(), 1);
+        assert_eq!(display.get_pixel(62).unwrap(), 1);
+        assert_eq!(display.get_pixel(63).unwrap(), 0);
+        // drawing it again erases it and reports the collision
+        assert_eq!(display.xor_sprite(62, 31, &[0xC0, 0x80]), 1);
+        assert_eq!(display.count_differences(""), 0);
+
+        // a whole row of sprite bytes lands in the row's bits, leftmost pixel on top
+        display.xor_sprite(4, 2, &[0xFF]);
+        assert_eq!(display.rows()[2], 0x0FF0_0000_0000_0000);
+        assert_eq!(display.pixel_bytes()[2 * 64 + 4..2 * 64 + 12], [1; 8]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_pixel_iterators() {
+        let mut display = Display::new();
+        display.xor_sprite(62, 1, &[0x80]);
+        let lit: Vec<_> = display.pixels().filter(|&(_, _, lit)| lit).collect();
+        assert_eq!(lit, [(62, 1, true)]);
+        assert_eq!(display.pixels().count(), 64 * 32);
+        let rows: Vec<[u8; 64]> = display.pixel_rows().collect();
+        assert_eq!(rows.len(), 32);
+        assert_eq!(rows[1][62], 1);
+        assert_eq!(rows[1].iter().sum::<u8>(), 1);
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_to_image() {
+        // the font 0 at (0, 0)
+        let mut display = Display::new();
+        display.xor_sprite(0, 0, &[0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        let palette = Palette {
+            on: 0x102030,
+            off: 0x000000,
+            ..Palette::default()
+        };
+        let image = display.to_image(palette, 2);
+        assert_eq!(image.dimensions(), (128, 64));
+        assert_eq!(image.get_pixel(0, 0).0, [0x10, 0x20, 0x30, 0xFF]);
+        assert_eq!(image.get_pixel(1, 1).0, [0x10, 0x20, 0x30, 0xFF]);
+        assert_eq!(image.get_pixel(8, 0).0, [0, 0, 0, 0xFF]);
+        assert_eq!(display.to_image(palette, 1).dimensions(), (64, 32));
+    }
+
+    #[test]
+    fn test_blit() {
+        let mut pixels = [0; 64 * 32];
+        pixels[64 + 2] = 1;
+        pixels[2047] = 1;
+        let mut display = Display::new();
+        assert_eq!(display.blit(&pixels), Ok(true));
+        assert_eq!(display.pixel_bytes(), pixels);
+        assert_eq!(display.rows()[1], 1 << 61);
+
+        // one bad pixel and nothing is written
+        pixels[0] = 2;
+        assert_eq!(display.blit(&pixels), Err(Chip8Error::InvalidPixelValue));
+        assert_eq!(display.get_pixel(0), Ok(0));
+        assert_eq!(display.get_pixel(2047), Ok(1));
+    }
+
+    #[test]
+    fn test_hash() {
+        let mut display = Display::new();
+        let blank = display.hash();
+        assert_eq!(blank, Display::new().hash());
+        display.set_pixel(100, 1).unwrap();
+        assert_ne!(display.hash(), blank);
+    }
+
+    #[test]
+    fn test_hires() {
+        let mut display = Display::new();
+        display.xor_sprite_row(0, 0, 0x80);
+        display.set_hires(true);
+        assert_eq!(display.count_differences(""), 0);
+        assert_eq!((display.width(), display.height()), (128, 64));
+
+        // wraps at 128 and 64, the 64x32 view lights a pixel for any of its 2x2
+        assert!(!display.xor_sprite_row(126, 63, 0xF0));
+        assert!(display.pixel(127, 63) && display.pixel(1, 63) && !display.pixel(2, 63));
+        assert_eq!(display.get_pixel(31 * 64 + 63), Ok(1));
+        assert_eq!(display.get_pixel(31 * 64), Ok(1));
+        assert_eq!(display.get_pixel(31 * 64 + 1), Ok(0));
+        assert_eq!(display.rows()[31], 0x8000_0000_0000_0001);
+        assert_ne!(display.hash(), Display::new().hash());
+
+        display.set_pixel(0, 1).unwrap();
+        assert!(display.pixel(0, 0) && display.pixel(1, 1));
+        assert!(!display.pixel(128, 0));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut display = Display::new();
+        display.xor_sprite(0, 0, &[0xFF, 0x81]);
+        display.scroll_down(2);
+        assert_eq!(display.rows()[2..4], [0xFF << 56, 0x81 << 56]);
+        assert_eq!(display.rows()[0], 0);
+        // low-res scrolls by its own pixels and nothing comes back around
+        display.scroll_right(4);
+        assert_eq!(display.rows()[2], 0xFF << 52);
+        display.scroll_left(8);
+        assert_eq!(display.rows()[2], 0xF << 60);
+        display.scroll_down(40);
+        assert_eq!(display.count_differences(""), 0);
+
+        // 16x16 sprites
+        assert_eq!(display.xor_large_sprite(60, 0, &[0xFF; 32]), 0);
+        assert_eq!(display.rows()[15], 0xFFF0_0000_0000_000F);
+        assert_eq!(display.xor_large_sprite(60, 0, &[0x01; 32]), 1);
+    }
+
+    #[test]
+    fn test_planes() {
+        let mut display = Display::new();
+        assert_eq!((display.selected_planes(), display.plane_count()), (1, 1));
+        display.select_planes(0b11);
+        assert_eq!(display.plane_count(), 2);
+        // a row for the first plane, then one for the second
+        display.xor_sprite(0, 0, &[0xC0, 0xA0]);
+        assert_eq!(display.pixel_value(0, 0), 3);
+        assert_eq!(display.pixel_value(1, 0), 1);
+        assert_eq!(display.pixel_value(2, 0), 2);
+        assert!(display.pixel(2, 0) && !display.pixel(3, 0));
+        assert_eq!(display.rows()[0], 0xE0 << 56);
+
+        // only the selected plane scrolls and clears
+        display.select_planes(0b10);
+        display.scroll_down(1);
+        assert_eq!(display.pixel_value(0, 0), 1);
+        assert_eq!(display.pixel_value(0, 1), 2);
+        display.clear_selected();
+        assert_eq!(display.pixel_value(0, 1), 0);
+        assert_eq!(display.pixel_value(1, 0), 1);
+
+        let palette = Palette::default();
+        assert_eq!(palette.color(2), palette.second);
+        assert_eq!(palette.color(3), palette.both);
+    }
+
+    #[test]
+    fn test_palette_presets() {
+        assert_eq!(Palette::preset("amber"), Some(Palette::default()));
+        assert_eq!(
+            Palette::preset("GameBoy").map(|palette| palette.off),
+            Some(0x9BBC0F)
+        );
+        assert_eq!(Palette::preset("sepia"), None);
+        for name in Palette::PRESETS {
+            let palette = Palette::preset(name).unwrap();
+            assert_ne!(palette.on, palette.off, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_aspect() {
+        let wide = Aspect::parse("2:1").unwrap();
+        assert_eq!(wide.pixel_size(20), (40, 20));
+        assert_eq!(Aspect::parse(" 1 : 2 ").unwrap().pixel_size(5), (3, 5));
+        assert_eq!(Aspect::SQUARE.pixel_size(0), (1, 1));
+        assert_eq!(wide.ratio(), 4.0);
+        for bad in ["2", "0:1", "9:1", "a:b"] {
+            assert_eq!(Aspect::parse(bad), None);
+        }
+    }
 }
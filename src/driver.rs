@@ -0,0 +1,119 @@
+use crate::chip8::{Chip8, Chip8Error};
+use crate::lockstep::Lockstep;
+
+// Plays a ROM from code, for integration tests and bots. Everything runs in lockstep
+// frames, so a test that presses the same keys on the same frames always sees the same
+// screen, and nothing needs a window, a sound device or a clock.
+//
+//     let mut driver = Driver::new(&rom)?;
+//     driver.wait_until(|chip| chip.display.get_pixel(0) == Ok(1))?;
+//     driver.press_key(5, 3)?;
+//     assert!(driver.pixel(10, 4));
+
+// instructions per frame, like speed in chip8.toml
+pub const DEFAULT_IPF: u32 = 10;
+// how long wait_until waits, a minute of play
+pub const DEFAULT_TIMEOUT: u64 = 60 * 60;
+
+pub struct Driver {
+    pub chip: Chip8,
+    lockstep: Lockstep,
+    timeout: u64,
+}
+
+impl Driver {
+    pub fn new(rom: &[u8]) -> Result<Self, Chip8Error> {
+        Ok(Self::with_chip(Chip8::new(rom)?, DEFAULT_IPF))
+    }
+
+    // A machine set up by the caller, with its quirks, seed or patches
+    pub fn with_chip(chip: Chip8, ipf: u32) -> Self {
+        Driver {
+            chip,
+            lockstep: Lockstep::new(ipf),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    // Frames wait_until runs before giving up
+    pub fn set_timeout(&mut self, frames: u64) {
+        self.timeout = frames;
+    }
+
+    // Frames run so far
+    pub fn frame(&self) -> u64 {
+        self.lockstep.frame()
+    }
+
+    pub fn run_frames(&mut self, frames: u64) -> Result<(), Chip8Error> {
+        self.lockstep.run_frames(&mut self.chip, frames)
+    }
+
+    // Holds the key down for this many frames, then lets go of it
+    pub fn press_key(&mut self, key: usize, frames: u64) -> Result<(), Chip8Error> {
+        self.hold_key(key)?;
+        self.run_frames(frames)?;
+        self.release_key(key)
+    }
+
+    // For keys held across other calls, until release_key
+    pub fn hold_key(&mut self, key: usize) -> Result<(), Chip8Error> {
+        self.chip.keyboard.set_key(key, true)?;
+        Ok(())
+    }
+
+    pub fn release_key(&mut self, key: usize) -> Result<(), Chip8Error> {
+        self.chip.keyboard.set_key(key, false)?;
+        Ok(())
+    }
+
+    // Whether the pixel is lit, false off the screen
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < 64 && y < 32 && self.chip.display.get_pixel(y * 64 + x) == Ok(1)
+    }
+
+    // Runs frames until the condition holds after one, checked before the first too.
+    // Some with the frames it took, None if it didn't hold within the timeout
+    pub fn wait_until(
+        &mut self,
+        mut condition: impl FnMut(&Chip8) -> bool,
+    ) -> Result<Option<u64>, Chip8Error> {
+        for frames in 0..=self.timeout {
+            if condition(&self.chip) {
+                return Ok(Some(frames));
+            }
+            if frames < self.timeout {
+                self.lockstep.run_frame(&mut self.chip)?;
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drive_a_rom() {
+        // F00A waits for a key to go down and up, then the font's digit of it is drawn at (8, 4):
+        // F00A F029 6108 6204 D125 120A
+        let rom = [
+            0xF0, 0x0A, 0xF0, 0x29, 0x61, 0x08, 0x62, 0x04, 0xD1, 0x25, 0x12, 0x0A,
+        ];
+        let mut driver = Driver::new(&rom).unwrap();
+        driver.set_timeout(30);
+        assert_eq!(driver.wait_until(|chip| chip.draw_flag).unwrap(), None);
+        assert_eq!(driver.frame(), 30);
+
+        // let go after 2 frames, drawn in the next one
+        driver.press_key(7, 2).unwrap();
+        assert!(!driver.chip.draw_flag);
+        assert_eq!(driver.wait_until(|chip| chip.draw_flag).unwrap(), Some(1));
+        // the top of a 7 is a full row of 4
+        assert!((8..12).all(|x| driver.pixel(x, 4)));
+        assert!(!driver.pixel(12, 4));
+        assert!(!driver.pixel(64, 4));
+        assert_eq!(driver.press_key(16, 1), Err(Chip8Error::InvalidKey));
+    }
+}
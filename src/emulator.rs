@@ -0,0 +1,432 @@
+use crate::chip8::{Chip8, Chip8Error};
+use crate::display::DisplayBackend;
+use crate::keyboard::InputBackend;
+use crate::lockstep::Lockstep;
+#[cfg(feature = "std")]
+use crate::metrics::Metrics;
+use crate::plugin::{Event, Plugin, PluginError, Plugins};
+use crate::sound::{AudioBackend, NullAudio};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::future::Future;
+
+// A machine plus its frame counter, for apps that drive the emulator themselves instead of
+// going through the chip8 binary. run_async never sleeps or blocks: the pace comes from a
+// FrameTimer, so it fits in a tokio task (or any other executor) next to the rest of the app.
+// Without a DisplayBackend it's headless, the app reads chip.display itself, without an
+// InputBackend the app sets chip.keyboard, and the beep goes to the AudioBackend it was
+// made with, NullAudio for Emulator::new.
+
+// Resolves once per frame. With the `tokio` feature a tokio::time::Interval is one, any
+// executor's timer works by implementing this
+pub trait FrameTimer {
+    fn tick(&mut self) -> impl Future<Output = ()> + Send;
+}
+
+#[cfg(feature = "tokio")]
+impl FrameTimer for tokio::time::Interval {
+    async fn tick(&mut self) {
+        tokio::time::Interval::tick(self).await;
+    }
+}
+
+// 60 Hz interval that skips frames instead of bursting after the task was held up
+#[cfg(feature = "tokio")]
+pub fn frame_interval() -> tokio::time::Interval {
+    let mut interval = tokio::time::interval(core::time::Duration::from_micros(16_667));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    interval
+}
+
+pub struct Emulator {
+    pub chip: Chip8,
+    lockstep: Lockstep,
+    plugins: Plugins,
+    // the plugins that stopped since take_plugin_errors
+    plugin_errors: Vec<PluginError>,
+    display: Option<Box<dyn DisplayBackend>>,
+    input: Option<Box<dyn InputBackend>>,
+    audio: Box<dyn AudioBackend>,
+    // off until enable_metrics, measuring costs a clock read per frame
+    #[cfg(feature = "std")]
+    metrics: Option<Metrics>,
+}
+
+impl Emulator {
+    pub fn new(chip: Chip8, ipf: u32) -> Self {
+        Self::with_audio(chip, ipf, Box::new(NullAudio::new()))
+    }
+
+    // Audio::new() for the sound device, with Audio::silent() or NullAudio when it fails
+    pub fn with_audio(chip: Chip8, ipf: u32, audio: Box<dyn AudioBackend>) -> Self {
+        Emulator {
+            chip,
+            lockstep: Lockstep::new(ipf),
+            plugins: Plugins::new(),
+            plugin_errors: Vec::new(),
+            display: None,
+            input: None,
+            audio,
+            #[cfg(feature = "std")]
+            metrics: None,
+        }
+    }
+
+    pub fn lockstep(&self) -> &Lockstep {
+        &self.lockstep
+    }
+
+    // The ROM is already loaded, so the plugin gets on_load right away
+    pub fn add_plugin(&mut self, mut plugin: Box<dyn Plugin>) {
+        plugin.on_load(&mut self.chip);
+        self.plugins.register(plugin);
+    }
+
+    pub fn plugins(&self) -> &Plugins {
+        &self.plugins
+    }
+
+    // Gets every frame from now on, the ones that drew rendered and draw_flag cleared
+    pub fn set_display_backend(&mut self, backend: Box<dyn DisplayBackend>) {
+        self.display = Some(backend);
+    }
+
+    // Back to headless, handing the backend back
+    pub fn take_display_backend(&mut self) -> Option<Box<dyn DisplayBackend>> {
+        self.display.take()
+    }
+
+    // Sets the keypad before every frame from now on
+    pub fn set_input_backend(&mut self, backend: Box<dyn InputBackend>) {
+        self.input = Some(backend);
+    }
+
+    pub fn take_input_backend(&mut self) -> Option<Box<dyn InputBackend>> {
+        self.input.take()
+    }
+
+    // False once the backend was closed, always true headless
+    pub fn is_open(&self) -> bool {
+        self.display
+            .as_ref()
+            .is_none_or(|display| display.is_open())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn enable_metrics(&mut self) {
+        self.metrics.get_or_insert_with(Metrics::new);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_ref()
+    }
+
+    // mutable for the app's audio code to report underruns
+    #[cfg(feature = "std")]
+    pub fn metrics_mut(&mut self) -> Option<&mut Metrics> {
+        self.metrics.as_mut()
+    }
+
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        if let Some(input) = &mut self.input {
+            self.chip.keyboard.set_keys(input.keys());
+        }
+        match self.lockstep.run_frame(&mut self.chip) {
+            Ok(()) => {
+                #[cfg(feature = "std")]
+                if let Some(metrics) = &mut self.metrics {
+                    metrics.record_frame(self.lockstep.ipf() as u64);
+                }
+                let errors = self.plugins.frame(&mut self.chip);
+                self.plugin_errors.extend(errors);
+                self.present();
+                self.audio.update_from(&self.chip);
+                Ok(())
+            }
+            Err(error) => {
+                self.audio.stop_beep();
+                self.plugins
+                    .event(&mut self.chip, &Event::Crash(error.clone()));
+                Err(error)
+            }
+        }
+    }
+
+    fn present(&mut self) {
+        let Some(display) = &mut self.display else {
+            return;
+        };
+        if self.chip.draw_flag {
+            display.render(&self.chip.display);
+            self.chip.draw_flag = false;
+        } else {
+            display.update();
+        }
+    }
+
+    // The plugins that failed and were dropped, for the app to show. A failing plugin doesn't
+    // stop run_frame, the game goes on without it
+    pub fn take_plugin_errors(&mut self) -> Vec<PluginError> {
+        core::mem::take(&mut self.plugin_errors)
+    }
+
+    // Runs the on_shutdown hooks, for when the app stops driving this machine. Gives the
+    // plugin errors take_plugin_errors didn't, the ones of the shutdown last
+    #[must_use]
+    pub fn shutdown(&mut self) -> Vec<PluginError> {
+        self.audio.stop_beep();
+        let errors = self.plugins.shutdown(&mut self.chip);
+        self.plugin_errors.extend(errors);
+        self.take_plugin_errors()
+    }
+
+    // Waits for the timer, runs a frame and hands the machine to on_frame, until on_frame
+    // returns false, the backend is closed or the ROM crashes. on_frame is where the app
+    // beeps and feeds keys, and draws when it has no backend
+    pub async fn run_async<T, F>(
+        &mut self,
+        timer: &mut T,
+        mut on_frame: F,
+    ) -> Result<(), Chip8Error>
+    where
+        T: FrameTimer,
+        F: FnMut(&mut Chip8) -> bool,
+    {
+        loop {
+            timer.tick().await;
+            self.run_frame()?;
+            if !on_frame(&mut self.chip) || !self.is_open() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::Display;
+    use alloc::rc::Rc;
+    use alloc::string::String;
+    use core::cell::Cell;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    struct CountingTimer {
+        ticks: u32,
+    }
+
+    impl FrameTimer for CountingTimer {
+        async fn tick(&mut self) {
+            self.ticks += 1;
+        }
+    }
+
+    // The timer never pends, so a single poll runs the whole loop
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut context = Context::from_waker(Waker::noop());
+        match pin!(future).poll(&mut context) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future pending"),
+        }
+    }
+
+    #[test]
+    fn test_run_async_until_callback_stops() {
+        // draws the font 0, then counts V1 up once per frame from the second one
+        let rom = [0xA0, 0x50, 0xD0, 0x05, 0x71, 0x01, 0x12, 0x04];
+        let mut emulator = Emulator::new(Chip8::new(&rom).unwrap(), 2);
+        let mut timer = CountingTimer { ticks: 0 };
+        let mut frames = 0;
+        let result = block_on(emulator.run_async(&mut timer, |chip| {
+            frames += 1;
+            assert_eq!(chip.display.pixel_bytes()[0], 1);
+            frames < 5
+        }));
+        assert_eq!(result, Ok(()));
+        assert_eq!((timer.ticks, frames), (5, 5));
+        assert_eq!(emulator.lockstep().frame(), 5);
+        assert_eq!(emulator.chip.get_v(1), Ok(4));
+    }
+
+    // Nothing in the core is global, two machines in one process never see each other
+    #[test]
+    fn test_instances_are_independent() {
+        // waits for a key into V0, then V1 = random
+        let rom = [0xF0, 0x0A, 0xC1, 0xFF, 0x12, 0x04];
+        let mut left = Emulator::new(Chip8::new(&rom).unwrap(), 10);
+        let mut right = Emulator::new(Chip8::new(&rom).unwrap(), 10);
+        left.chip.set_seed(1);
+        right.chip.set_seed(1);
+        left.chip.keyboard.set_key(7, true).unwrap();
+        left.run_frame().unwrap();
+        left.chip.keyboard.set_key(7, false).unwrap();
+        left.run_frame().unwrap();
+        right.run_frame().unwrap();
+        assert_eq!(left.chip.get_v(0), Ok(7));
+        assert_eq!(*right.chip.get_pc(), 0x200);
+
+        // later, right draws the same number from its own generator
+        right.chip.keyboard.set_key(7, true).unwrap();
+        right.run_frame().unwrap();
+        right.chip.keyboard.set_key(7, false).unwrap();
+        right.run_frame().unwrap();
+        assert_eq!(right.chip.get_v(1), left.chip.get_v(1));
+    }
+
+    // Counts what it was given, closed after 3 renders
+    struct CountingBackend {
+        frames: Rc<Cell<(u32, u32)>>,
+    }
+
+    impl DisplayBackend for CountingBackend {
+        fn render(&mut self, _display: &Display) {
+            let (renders, updates) = self.frames.get();
+            self.frames.set((renders + 1, updates));
+        }
+
+        fn update(&mut self) {
+            let (renders, updates) = self.frames.get();
+            self.frames.set((renders, updates + 1));
+        }
+
+        fn is_open(&self) -> bool {
+            self.frames.get().0 < 3
+        }
+    }
+
+    #[test]
+    fn test_display_backend() {
+        // draws the font 0 on one frame and nothing on the next, over and over
+        let rom = [0xA0, 0x50, 0xD0, 0x05, 0x61, 0x00, 0x61, 0x00, 0x12, 0x02];
+        let mut emulator = Emulator::new(Chip8::new(&rom).unwrap(), 2);
+        emulator.run_frame().unwrap();
+        assert!(emulator.is_open() && emulator.chip.draw_flag);
+
+        let frames = Rc::new(Cell::new((0, 0)));
+        let backend = CountingBackend {
+            frames: frames.clone(),
+        };
+        emulator.set_display_backend(Box::new(backend));
+        let mut timer = CountingTimer { ticks: 0 };
+        let result = block_on(emulator.run_async(&mut timer, |_| true));
+        assert_eq!(result, Ok(()));
+        assert!(!emulator.is_open() && !emulator.chip.draw_flag);
+        // the first frame's draw is still waiting, then draw, none, draw
+        assert_eq!(frames.get(), (3, 1));
+
+        assert!(emulator.take_display_backend().is_some());
+        assert!(emulator.is_open());
+    }
+
+    // Shares whether it's beeping with the test
+    struct SharedAudio(Rc<Cell<bool>>);
+
+    impl AudioBackend for SharedAudio {
+        fn start_beep(&mut self) {
+            self.0.set(true);
+        }
+
+        fn stop_beep(&mut self) {
+            self.0.set(false);
+        }
+    }
+
+    #[test]
+    fn test_audio_backend() {
+        // ST = 2 on the first frame, then nothing
+        let rom = [0x60, 0x02, 0xF0, 0x18, 0x12, 0x04];
+        let beeping = Rc::new(Cell::new(false));
+        let audio = SharedAudio(beeping.clone());
+        let mut emulator = Emulator::with_audio(Chip8::new(&rom).unwrap(), 3, Box::new(audio));
+        emulator.run_frame().unwrap();
+        assert!(beeping.get());
+        for _ in 0..2 {
+            emulator.run_frame().unwrap();
+        }
+        assert!(!beeping.get());
+
+        emulator.chip.set_st(10);
+        emulator.run_frame().unwrap();
+        assert!(beeping.get());
+        assert!(emulator.shutdown().is_empty());
+        assert!(!beeping.get());
+    }
+
+    // Key 7 down on the first frame, up on the others
+    struct TapSeven {
+        frames: u32,
+    }
+
+    impl InputBackend for TapSeven {
+        fn keys(&mut self) -> [bool; 16] {
+            self.frames += 1;
+            core::array::from_fn(|key| key == 7 && self.frames == 1)
+        }
+    }
+
+    #[test]
+    fn test_input_backend() {
+        let mut emulator = Emulator::new(Chip8::new(&[0x12, 0x00]).unwrap(), 1);
+        emulator.set_input_backend(Box::new(TapSeven { frames: 0 }));
+        emulator.run_frame().unwrap();
+        assert!(emulator.chip.keyboard.is_pressed(7).unwrap());
+        emulator.run_frame().unwrap();
+        assert!(!emulator.chip.keyboard.is_pressed(7).unwrap());
+
+        // headless again, the keys stay as the app leaves them
+        assert!(emulator.take_input_backend().is_some());
+        emulator.chip.keyboard.set_key(2, true).unwrap();
+        emulator.run_frame().unwrap();
+        assert!(emulator.chip.keyboard.is_pressed(2).unwrap());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_metrics_are_opt_in() {
+        let mut emulator = Emulator::new(Chip8::new(&[0x12, 0x00]).unwrap(), 7);
+        emulator.run_frame().unwrap();
+        assert!(emulator.metrics().is_none());
+        emulator.enable_metrics();
+        for _ in 0..3 {
+            emulator.run_frame().unwrap();
+        }
+        let snapshot = emulator.metrics().unwrap().snapshot();
+        assert_eq!((snapshot.frames, snapshot.instructions), (2, 21));
+    }
+
+    #[test]
+    fn test_run_async_stops_on_crash() {
+        let mut emulator = Emulator::new(Chip8::new(&[0x00, 0xEE]).unwrap(), 10);
+        let mut timer = CountingTimer { ticks: 0 };
+        let result = block_on(emulator.run_async(&mut timer, |_| true));
+        assert_eq!(result, Err(Chip8Error::StackUnderflow));
+        assert_eq!(timer.ticks, 1);
+    }
+    // gives up on its first frame
+    struct Broken;
+
+    impl Plugin for Broken {
+        fn name(&self) -> &str {
+            "broken"
+        }
+
+        fn on_frame(&mut self, _chip: &mut Chip8) -> Result<(), String> {
+            Err("no device".into())
+        }
+    }
+
+    #[test]
+    fn test_plugin_errors() {
+        let mut emulator = Emulator::new(Chip8::new(&[0x12, 0x00]).unwrap(), 7);
+        emulator.add_plugin(Box::new(Broken));
+        emulator.run_frame().unwrap();
+        emulator.run_frame().unwrap();
+        let errors = emulator.take_plugin_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "no device");
+        assert!(emulator.plugins().is_empty());
+        assert!(emulator.shutdown().is_empty());
+    }
+}
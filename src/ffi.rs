@@ -0,0 +1,322 @@
+// C API over the lockstep core, for C/C++ frontends and anything else with a C FFI.
+// The header is include/chip8.h, regenerate it after changing this file with
+// `rustup run nightly cbindgen --output include/chip8.h`. The exported functions use ///
+// comments because cbindgen copies them into the header.
+//
+// Every function returning int uses 0 for success and a negative code on error,
+// chip8_error_name turns the code into text. Machines come from chip8_new and must be
+// released with chip8_free.
+use crate::chip8::{Chip8, Chip8Error};
+use crate::lockstep::Lockstep;
+use crate::testrunner::IPF;
+use core::ffi::{CStr, c_char, c_int};
+
+pub const CHIP8_WIDTH: usize = 64;
+pub const CHIP8_HEIGHT: usize = 32;
+
+/// Success, errors are negative
+pub const CHIP8_OK: c_int = 0;
+/// A null machine or buffer was passed in
+pub const CHIP8_NULL_POINTER: c_int = -100;
+
+// Same order as Chip8Error, code -1 is the first variant
+const ERROR_NAMES: [&CStr; 14] = [
+    c"PCOutOfBounds",
+    c"IOutOfBounds",
+    c"InvalidMemoryAccess",
+    c"StackOverflow",
+    c"StackUnderflow",
+    c"UnknownInstruction",
+    c"InvalidKey",
+    c"RomTooLarge",
+    c"InvalidState",
+    c"InvalidRegisterAccess",
+    c"InvalidKeyAccess",
+    c"InvalidPixelAccess",
+    c"InvalidPixelValue",
+    c"InvalidDigit",
+];
+
+/// Opaque to C, a machine plus its frame counter
+pub struct Chip8Machine {
+    chip: Chip8,
+    lockstep: Lockstep,
+    // the display keeps bits, C gets bytes copied out after every call that can draw
+    frame: [u8; 64 * 32],
+}
+
+fn error_code(error: Chip8Error) -> c_int {
+    -1 - error as c_int
+}
+
+fn result_code<T>(result: Result<T, Chip8Error>) -> c_int {
+    match result {
+        Ok(_) => CHIP8_OK,
+        Err(error) => error_code(error),
+    }
+}
+
+/// New machine with an empty ROM, 10 instructions per frame
+#[unsafe(no_mangle)]
+pub extern "C" fn chip8_new() -> *mut Chip8Machine {
+    let machine = Chip8Machine {
+        chip: Chip8::default(),
+        lockstep: Lockstep::new(IPF),
+        frame: [0; 64 * 32],
+    };
+    Box::into_raw(Box::new(machine))
+}
+
+/// Releases a machine, null is ignored
+///
+/// # Safety
+/// `machine` must come from chip8_new and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_free(machine: *mut Chip8Machine) {
+    if !machine.is_null() {
+        drop(unsafe { Box::from_raw(machine) });
+    }
+}
+
+/// Resets the machine and loads the ROM at 0x200. The random generator is reseeded
+/// from entropy, call chip8_set_seed afterwards for repeatable runs
+///
+/// # Safety
+/// `machine` must come from chip8_new and `rom` point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_load_rom(
+    machine: *mut Chip8Machine,
+    rom: *const u8,
+    len: usize,
+) -> c_int {
+    let Some(machine) = (unsafe { machine.as_mut() }) else {
+        return CHIP8_NULL_POINTER;
+    };
+    if rom.is_null() {
+        return CHIP8_NULL_POINTER;
+    }
+    let rom = unsafe { core::slice::from_raw_parts(rom, len) };
+    match Chip8::new(rom) {
+        Ok(chip) => {
+            machine.chip = chip;
+            machine.lockstep = Lockstep::new(machine.lockstep.ipf());
+            machine.frame = machine.chip.display.pixel_bytes();
+            CHIP8_OK
+        }
+        Err(error) => error_code(error),
+    }
+}
+
+/// Runs one frame: the instructions of a frame, then one tick of the timers
+///
+/// # Safety
+/// `machine` must come from chip8_new.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_step_frame(machine: *mut Chip8Machine) -> c_int {
+    let Some(machine) = (unsafe { machine.as_mut() }) else {
+        return CHIP8_NULL_POINTER;
+    };
+    let result = machine.lockstep.run_frame(&mut machine.chip);
+    machine.frame = machine.chip.display.pixel_bytes();
+    result_code(result)
+}
+
+/// CHIP8_WIDTH * CHIP8_HEIGHT bytes, one per pixel (0 or 1), row by row. The pointer is
+/// valid until the machine is freed, null if the machine is null
+///
+/// # Safety
+/// `machine` must come from chip8_new.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_framebuffer(machine: *const Chip8Machine) -> *const u8 {
+    match unsafe { machine.as_ref() } {
+        Some(machine) => machine.frame.as_ptr(),
+        None => core::ptr::null(),
+    }
+}
+
+/// Presses or releases one of the 16 keys (0x0 to 0xF)
+///
+/// # Safety
+/// `machine` must come from chip8_new.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_set_key(
+    machine: *mut Chip8Machine,
+    key: u8,
+    pressed: bool,
+) -> c_int {
+    let Some(machine) = (unsafe { machine.as_mut() }) else {
+        return CHIP8_NULL_POINTER;
+    };
+    result_code(machine.chip.keyboard.set_key(key as usize, pressed))
+}
+
+/// True while the buzzer should sound
+///
+/// # Safety
+/// `machine` must come from chip8_new.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_sound_on(machine: *const Chip8Machine) -> bool {
+    unsafe { machine.as_ref() }.is_some_and(|machine| machine.chip.is_sound_on())
+}
+
+/// Seeds the random generator of Cxnn
+///
+/// # Safety
+/// `machine` must come from chip8_new.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_set_seed(machine: *mut Chip8Machine, seed: u64) -> c_int {
+    let Some(machine) = (unsafe { machine.as_mut() }) else {
+        return CHIP8_NULL_POINTER;
+    };
+    machine.chip.set_seed(seed);
+    CHIP8_OK
+}
+
+/// Copies V0 to VF into `out`
+///
+/// # Safety
+/// `machine` must come from chip8_new and `out` point to 16 writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_registers(machine: *const Chip8Machine, out: *mut u8) -> c_int {
+    let Some(machine) = (unsafe { machine.as_ref() }) else {
+        return CHIP8_NULL_POINTER;
+    };
+    if out.is_null() {
+        return CHIP8_NULL_POINTER;
+    }
+    unsafe { core::ptr::copy_nonoverlapping(machine.chip.registers().as_ptr(), out, 16) };
+    CHIP8_OK
+}
+
+/// Sets V0 to VF from `registers`
+///
+/// # Safety
+/// `machine` must come from chip8_new and `registers` point to 16 readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_set_registers(
+    machine: *mut Chip8Machine,
+    registers: *const u8,
+) -> c_int {
+    let Some(machine) = (unsafe { machine.as_mut() }) else {
+        return CHIP8_NULL_POINTER;
+    };
+    let Some(registers) = (unsafe { registers.cast::<[u8; 16]>().as_ref() }) else {
+        return CHIP8_NULL_POINTER;
+    };
+    machine.chip.set_registers(registers);
+    CHIP8_OK
+}
+
+/// Copies the return addresses on the stack into `out`, oldest first, and returns how
+/// many there are (0 to 16). A negative return is an error code
+///
+/// # Safety
+/// `machine` must come from chip8_new and `out` point to room for 16 addresses.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_stack(machine: *const Chip8Machine, out: *mut u16) -> c_int {
+    let Some(machine) = (unsafe { machine.as_ref() }) else {
+        return CHIP8_NULL_POINTER;
+    };
+    if out.is_null() {
+        return CHIP8_NULL_POINTER;
+    }
+    let stack = machine.chip.stack();
+    unsafe { core::ptr::copy_nonoverlapping(stack.as_ptr(), out, stack.len()) };
+    stack.len() as c_int
+}
+
+/// Static string for a return code, never null
+#[unsafe(no_mangle)]
+pub extern "C" fn chip8_error_name(code: c_int) -> *const c_char {
+    let name = match code {
+        CHIP8_OK => c"Ok",
+        CHIP8_NULL_POINTER => c"NullPointer",
+        _ => usize::try_from(-1 - code)
+            .ok()
+            .and_then(|index| ERROR_NAMES.get(index).copied())
+            .unwrap_or(c"Unknown"),
+    };
+    name.as_ptr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(code: c_int) -> String {
+        unsafe { CStr::from_ptr(chip8_error_name(code)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_error_names_follow_enum() {
+        for error in [
+            Chip8Error::PCOutOfBounds,
+            Chip8Error::IOutOfBounds,
+            Chip8Error::InvalidMemoryAccess,
+            Chip8Error::StackOverflow,
+            Chip8Error::StackUnderflow,
+            Chip8Error::UnknownInstruction,
+            Chip8Error::InvalidKey,
+            Chip8Error::RomTooLarge,
+            Chip8Error::InvalidState,
+            Chip8Error::InvalidRegisterAccess,
+            Chip8Error::InvalidKeyAccess,
+            Chip8Error::InvalidPixelAccess,
+            Chip8Error::InvalidPixelValue,
+            Chip8Error::InvalidDigit,
+        ] {
+            assert_eq!(name(error_code(error.clone())), format!("{:?}", error));
+        }
+        assert_eq!(name(CHIP8_OK), "Ok");
+        assert_eq!(name(7), "Unknown");
+        assert_eq!(name(-15), "Unknown");
+    }
+
+    #[test]
+    fn test_machine_round_trip() {
+        // draws the font 0 at (0, 0), then waits for key 5 to go down and up
+        let rom = [0xA0, 0x50, 0xD0, 0x05, 0xF1, 0x0A, 0x12, 0x06];
+        unsafe {
+            let machine = chip8_new();
+            assert_eq!(chip8_load_rom(machine, rom.as_ptr(), rom.len()), CHIP8_OK);
+            assert_eq!(chip8_step_frame(machine), CHIP8_OK);
+
+            let pixels =
+                core::slice::from_raw_parts(chip8_framebuffer(machine), CHIP8_WIDTH * CHIP8_HEIGHT);
+            assert_eq!(&pixels[..4], &[1, 1, 1, 1]);
+            assert_eq!(pixels[64], 1);
+
+            assert_eq!(chip8_set_key(machine, 5, true), CHIP8_OK);
+            assert_eq!(
+                chip8_set_key(machine, 16, true),
+                error_code(Chip8Error::InvalidKey)
+            );
+            assert_eq!(chip8_step_frame(machine), CHIP8_OK);
+            assert_eq!(chip8_set_key(machine, 5, false), CHIP8_OK);
+            assert_eq!(chip8_step_frame(machine), CHIP8_OK);
+            assert_eq!((*machine).chip.get_v(1), Ok(5));
+            assert!(!chip8_sound_on(machine));
+
+            let mut registers = [0u8; 16];
+            assert_eq!(chip8_registers(machine, registers.as_mut_ptr()), CHIP8_OK);
+            assert_eq!(registers[1], 5);
+            registers[0xF] = 1;
+            assert_eq!(chip8_set_registers(machine, registers.as_ptr()), CHIP8_OK);
+            assert_eq!((*machine).chip.get_v(0xF), Ok(1));
+            let mut stack = [0u16; 16];
+            assert_eq!(chip8_stack(machine, stack.as_mut_ptr()), 0);
+
+            let big = [0u8; 4096];
+            assert_eq!(
+                chip8_load_rom(machine, big.as_ptr(), big.len()),
+                error_code(Chip8Error::RomTooLarge)
+            );
+            chip8_free(machine);
+
+            assert_eq!(chip8_step_frame(core::ptr::null_mut()), CHIP8_NULL_POINTER);
+            assert!(chip8_framebuffer(core::ptr::null()).is_null());
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::display::{Display, Palette};
+
+// Photosensitivity safety. Some ROMs flip most of the screen every few frames, which is
+// far above the 3 flashes per second guidelines like WCAG's treat as a seizure risk. While
+// that goes on the two palette colors get blended towards each other, so the flips still
+// show but with a fraction of the contrast, and they come back apart once it stops.
+// Both changes are gradual, a sudden jump in contrast would be a flash too.
+
+// flashes per second allowed at full contrast, each one is a flip and a flip back
+pub const MAX_FLASHES: u32 = 3;
+// a flip is a quarter of the screen or more turning on or off from one frame to the next
+const FLIP_AREA: u32 = 64 * 32 / 4;
+// contrast while the screen strobes, 1.0 is the palette as it is
+const DIMMED: f32 = 0.3;
+// contrast change per frame, from full to dimmed in a quarter of a second
+const FADE: f32 = 0.05;
+
+#[derive(Debug, Clone)]
+pub struct FlashLimiter {
+    // lit pixels of the last frame
+    lit: u32,
+    // which of the last 60 frames were flips, the newest in bit 0
+    flips: u64,
+    contrast: f32,
+}
+
+impl Default for FlashLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlashLimiter {
+    pub fn new() -> Self {
+        FlashLimiter {
+            lit: 0,
+            flips: 0,
+            contrast: 1.0,
+        }
+    }
+
+    // Called once per 60 Hz frame with what's on screen, returns the contrast to show it with
+    pub fn frame(&mut self, display: &Display) -> f32 {
+        let lit = display.rows().iter().map(|row| row.count_ones()).sum();
+        let flip = self.lit.abs_diff(lit) >= FLIP_AREA;
+        self.lit = lit;
+        self.flips = (self.flips << 1 | flip as u64) & ((1 << 60) - 1);
+
+        let target = if self.flips.count_ones() > MAX_FLASHES * 2 {
+            DIMMED
+        } else {
+            1.0
+        };
+        self.contrast = if self.contrast < target {
+            (self.contrast + FADE).min(target)
+        } else {
+            (self.contrast - FADE).max(target)
+        };
+        self.contrast
+    }
+
+    pub fn contrast(&self) -> f32 {
+        self.contrast
+    }
+}
+
+// Both colors moved towards their mix, 1.0 keeps the palette and 0.0 makes them the same.
+// The XO-CHIP colors move towards unlit the same way
+pub fn dim(palette: Palette, contrast: f32) -> Palette {
+    if contrast >= 1.0 {
+        return palette;
+    }
+    let channel = |color: u32, shift: u32| ((color >> shift) & 0xFF) as f32;
+    let blend = |toward: u32, color: u32| {
+        [16, 8, 0].into_iter().fold(0, |blended, shift| {
+            let mix = (channel(toward, shift) + channel(color, shift)) / 2.0;
+            let value = mix + (channel(color, shift) - mix) * contrast;
+            // rounded, never negative
+            blended | ((value + 0.5) as u32) << shift
+        })
+    };
+    Palette {
+        on: blend(palette.off, palette.on),
+        off: blend(palette.on, palette.off),
+        second: blend(palette.off, palette.second),
+        both: blend(palette.off, palette.both),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strobe_dims_then_recovers() {
+        let dark = Display::new();
+        let mut bright = Display::new();
+        for y in 0..32 {
+            bright.xor_sprite(0, y, &[0xFF; 1]);
+            bright.xor_sprite(8, y, &[0xFF; 1]);
+            bright.xor_sprite(16, y, &[0xFF; 1]);
+            bright.xor_sprite(24, y, &[0xFF; 1]);
+        }
+        let mut limiter = FlashLimiter::new();
+        // a flip every 4 frames is 7.5 flashes per second
+        let mut last = 1.0;
+        for frame in 0..120 {
+            let display = if frame / 4 % 2 == 0 { &dark } else { &bright };
+            let contrast = limiter.frame(display);
+            assert!(contrast <= last && last - contrast <= FADE + 0.001);
+            last = contrast;
+        }
+        assert_eq!(limiter.contrast(), DIMMED);
+
+        // one flash a second is fine
+        for frame in 0..180 {
+            let display = if frame % 60 < 4 { &bright } else { &dark };
+            limiter.frame(display);
+        }
+        assert_eq!(limiter.contrast(), 1.0);
+    }
+
+    #[test]
+    fn test_dim() {
+        let palette = Palette {
+            on: 0xFFFFFF,
+            off: 0x000000,
+            ..Palette::default()
+        };
+        assert_eq!(dim(palette, 1.0), palette);
+        let dimmed = dim(palette, 0.5);
+        assert_eq!(dimmed.on, 0xBFBFBF);
+        assert_eq!(dimmed.off, 0x404040);
+        assert_eq!(dim(palette, 0.0).on, dim(palette, 0.0).off);
+    }
+}
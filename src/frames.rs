@@ -0,0 +1,124 @@
+// The screen as PNG files, for documentation screenshots, regression artifacts and looking
+// at what a ROM draws on a server without a display. `chip8 --dump-frames N` uses it.
+use crate::chip8::{Chip8, Chip8Error};
+use crate::display::{Display, Palette};
+use crate::lockstep::Lockstep;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum DumpError {
+    Io(io::Error),
+    Chip8(Chip8Error),
+}
+
+impl From<io::Error> for DumpError {
+    fn from(error: io::Error) -> Self {
+        DumpError::Io(error)
+    }
+}
+
+impl From<png::EncodingError> for DumpError {
+    fn from(error: png::EncodingError) -> Self {
+        DumpError::Io(error.into())
+    }
+}
+
+impl From<Chip8Error> for DumpError {
+    fn from(error: Chip8Error) -> Self {
+        DumpError::Chip8(error)
+    }
+}
+
+// PNG of the screen with each Chip-8 pixel as a scale x scale square
+pub fn encode_png(display: &Display, palette: Palette, scale: usize) -> Result<Vec<u8>, DumpError> {
+    let image = display.to_image(palette, scale);
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&image)?;
+    Ok(png)
+}
+
+pub fn write_png(
+    path: &Path,
+    display: &Display,
+    palette: Palette,
+    scale: usize,
+) -> Result<(), DumpError> {
+    std::fs::write(path, encode_png(display, palette, scale)?)?;
+    Ok(())
+}
+
+// Runs `frames` lockstep frames and writes frame_0001.png, frame_0002.png... to `dir`, or
+// only every `every`-th frame (still numbered by frame). Returns the files written
+pub fn dump_frames(
+    chip: &mut Chip8,
+    ipf: u32,
+    frames: u64,
+    every: u64,
+    dir: &Path,
+    palette: Palette,
+    scale: usize,
+) -> Result<Vec<PathBuf>, DumpError> {
+    std::fs::create_dir_all(dir)?;
+    let every = every.max(1);
+    let digits = frames.to_string().len().max(4);
+    let mut lockstep = Lockstep::new(ipf);
+    let mut written = Vec::new();
+    for frame in 1..=frames {
+        lockstep.run_frame(chip)?;
+        if frame % every == 0 {
+            let path = dir.join(format!("frame_{:0digits$}.png", frame));
+            write_png(&path, &chip.display, palette, scale)?;
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_png() {
+        // the font 0 at (0, 0)
+        let mut chip = Chip8::new(&[0xA0, 0x50, 0xD0, 0x05]).unwrap();
+        chip.step().unwrap();
+        chip.step().unwrap();
+        let palette = Palette {
+            on: 0x102030,
+            off: 0x000000,
+            ..Palette::default()
+        };
+        let png = encode_png(&chip.display, palette, 2).unwrap();
+
+        let mut reader = png::Decoder::new(io::Cursor::new(png)).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (128, 64));
+        // top left pixel is lit and 2x2, the fifth one isn't
+        let stride = 128 * 4;
+        assert_eq!(
+            &data[..8],
+            &[0x10, 0x20, 0x30, 0xFF, 0x10, 0x20, 0x30, 0xFF]
+        );
+        assert_eq!(&data[stride..stride + 4], &[0x10, 0x20, 0x30, 0xFF]);
+        assert_eq!(&data[4 * 2 * 4..4 * 2 * 4 + 4], &[0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn test_dump_frames() {
+        let dir = std::env::temp_dir().join(format!("chip8-frames-{}", std::process::id()));
+        let mut chip = Chip8::new(&[0x12, 0x00]).unwrap();
+        let written = dump_frames(&mut chip, 10, 6, 3, &dir, Palette::default(), 1).unwrap();
+        assert_eq!(
+            written,
+            [dir.join("frame_0003.png"), dir.join("frame_0006.png")]
+        );
+        assert!(written.iter().all(|path| path.exists()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+use crate::chip8::{Chip8, Chip8Error};
+use crate::lockstep::Lockstep;
+use crate::testrunner::IPF;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+// Golden frames: hashes of the screen at chosen frames for every ROM of a corpus directory.
+// `record` writes them to a text file and `verify` runs everything again and compares,
+// so a change in the emulator can't silently change what a game draws.
+// Every run is headless, in lockstep mode and with seed 0, so hashes are reproducible.
+
+pub const GOLDEN_FILE: &str = "golden.txt";
+pub const DEFAULT_FRAMES: [u64; 3] = [60, 300, 600];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub rom: String,
+    pub frame: u64,
+    pub hash: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Match,
+    Differ { expected: u64, actual: u64 },
+    Missing,
+    Crashed(Chip8Error),
+}
+
+// Runs one ROM and hashes the screen at each frame (frames must be ascending)
+pub fn hashes_at(rom: &[u8], frames: &[u64]) -> Result<Vec<u64>, Chip8Error> {
+    let mut chip = Chip8::new(rom)?;
+    chip.set_seed(0);
+    chip.set_decode_cache(true);
+    let mut lockstep = Lockstep::new(IPF);
+    let mut hashes = Vec::with_capacity(frames.len());
+    for &frame in frames {
+        lockstep.run_frames(&mut chip, frame.saturating_sub(lockstep.frame()))?;
+        hashes.push(chip.display_hash());
+    }
+    Ok(hashes)
+}
+
+// Every .ch8 in the directory, sorted so the golden file is stable
+pub fn corpus(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut roms: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ch8"))
+        .collect();
+    roms.sort();
+    Ok(roms)
+}
+
+pub struct Recording {
+    pub snapshots: Vec<Snapshot>,
+    // ROMs that failed before reaching the last frame, nothing is recorded for them
+    pub crashed: Vec<(String, Chip8Error)>,
+}
+
+// Records snapshots for the whole corpus
+pub fn record(dir: &Path, frames: &[u64]) -> std::io::Result<Recording> {
+    let mut frames = frames.to_vec();
+    frames.sort_unstable();
+    frames.dedup();
+
+    let mut snapshots = Vec::new();
+    let mut crashed = Vec::new();
+    for path in corpus(dir)? {
+        let name = file_name(&path);
+        let rom = std::fs::read(&path)?;
+        match hashes_at(&rom, &frames) {
+            Ok(hashes) => {
+                for (&frame, hash) in frames.iter().zip(hashes) {
+                    snapshots.push(Snapshot {
+                        rom: name.clone(),
+                        frame,
+                        hash,
+                    });
+                }
+            }
+            Err(error) => crashed.push((name, error)),
+        }
+    }
+    Ok(Recording { snapshots, crashed })
+}
+
+pub fn verify(dir: &Path, snapshots: &[Snapshot]) -> Vec<(Snapshot, Verdict)> {
+    let mut results = Vec::with_capacity(snapshots.len());
+    // snapshots of the same ROM are verified with a single run
+    let mut start = 0;
+    while start < snapshots.len() {
+        let rom_name = &snapshots[start].rom;
+        let end = start
+            + snapshots[start..]
+                .iter()
+                .take_while(|snapshot| &snapshot.rom == rom_name)
+                .count();
+        let group = &snapshots[start..end];
+
+        let mut frames: Vec<u64> = group.iter().map(|snapshot| snapshot.frame).collect();
+        frames.sort_unstable();
+        frames.dedup();
+
+        let outcome = std::fs::read(dir.join(rom_name))
+            .map_err(|_| Verdict::Missing)
+            .and_then(|rom| hashes_at(&rom, &frames).map_err(Verdict::Crashed));
+        for snapshot in group {
+            let verdict = match &outcome {
+                Err(verdict) => verdict.clone(),
+                Ok(hashes) => {
+                    let index = frames.binary_search(&snapshot.frame).unwrap_or(0);
+                    if hashes[index] == snapshot.hash {
+                        Verdict::Match
+                    } else {
+                        Verdict::Differ {
+                            expected: snapshot.hash,
+                            actual: hashes[index],
+                        }
+                    }
+                }
+            };
+            results.push((snapshot.clone(), verdict));
+        }
+        start = end;
+    }
+    results
+}
+
+// Golden file format, one snapshot per line: "<rom file> <frame> <hash in hex>"
+pub fn to_text(snapshots: &[Snapshot]) -> String {
+    let mut text = String::from("# chip8 golden frames: rom frame hash\n");
+    for snapshot in snapshots {
+        let _ = writeln!(
+            text,
+            "{} {} {:016x}",
+            snapshot.rom, snapshot.frame, snapshot.hash
+        );
+    }
+    text
+}
+
+// Returns the number of the first bad line on error
+pub fn parse(text: &str) -> Result<Vec<Snapshot>, usize> {
+    let mut snapshots = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // the ROM name may contain spaces, frame and hash are the last two fields
+        let mut fields = line.rsplitn(3, ' ');
+        let hash = fields.next().and_then(|h| u64::from_str_radix(h, 16).ok());
+        let frame = fields.next().and_then(|f| f.parse().ok());
+        let rom = fields.next().map(str::trim_end);
+        match (rom, frame, hash) {
+            (Some(rom), Some(frame), Some(hash)) if !rom.is_empty() => snapshots.push(Snapshot {
+                rom: rom.to_string(),
+                frame,
+                hash,
+            }),
+            _ => return Err(number + 1),
+        }
+    }
+    Ok(snapshots)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Draws the font digit held in V0 and increments it every frame-ish
+    const COUNTER: [u8; 12] = [
+        0x00, 0xE0, // 200: clear
+        0xF0, 0x29, // 202: I = font(V0)
+        0xD1, 0x15, // 204: draw V1, V1, 5
+        0x70, 0x01, // 206: V0 += 1
+        0x60, 0x03, // 208: V0 = 3
+        0x12, 0x08, // 20A: jump 208
+    ];
+
+    fn temp_corpus(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("chip8-golden-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("counter.ch8"), COUNTER).unwrap();
+        std::fs::write(dir.join("spin.ch8"), [0x12, 0x00]).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_record_then_verify() {
+        let dir = temp_corpus("verify");
+        let Recording { snapshots, crashed } = record(&dir, &[10, 2]).unwrap();
+        assert!(crashed.is_empty());
+        assert_eq!(snapshots.len(), 4);
+        assert_eq!(snapshots[0].frame, 2);
+
+        let parsed = parse(&to_text(&snapshots)).unwrap();
+        assert_eq!(parsed, snapshots);
+        for (_, verdict) in verify(&dir, &parsed) {
+            assert_eq!(verdict, Verdict::Match);
+        }
+
+        // the counter ROM now draws a shorter sprite, so its hashes must change
+        let mut changed = COUNTER;
+        changed[5] = 0x13;
+        std::fs::write(dir.join("counter.ch8"), changed).unwrap();
+        let results = verify(&dir, &parsed);
+        assert!(matches!(results[0].1, Verdict::Differ { .. }));
+        assert_eq!(results[2].1, Verdict::Match);
+
+        std::fs::remove_file(dir.join("spin.ch8")).unwrap();
+        assert_eq!(verify(&dir, &parsed)[3].1, Verdict::Missing);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("# only a comment\n\n").unwrap(), vec![]);
+        assert_eq!(
+            parse("my game.ch8 60 00ff\n").unwrap()[0].rom,
+            "my game.ch8"
+        );
+        assert_eq!(parse("ok.ch8 60 ff\nbroken.ch8 sixty ff\n"), Err(2));
+        assert_eq!(parse("60 ff\n"), Err(1));
+    }
+}
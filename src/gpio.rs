@@ -0,0 +1,93 @@
+use crate::keyboard::Keyboard;
+use rppal::gpio::{Gpio, InputPin, OutputPin};
+use std::thread;
+use std::time::Duration;
+
+// A 4x4 matrix keypad on the GPIO pins of a Raspberry Pi, for handhelds and kiosks without
+// a keyboard. The keys are where they are on the COSMAC VIP, so a keypad labeled 1 2 3 A
+// on top has 1 2 3 C there. Rows are driven low one at a time and the columns read with
+// pull-ups, a column that reads low has its key in that row pressed. Pins are BCM numbers.
+
+const KEYPAD: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+// for the columns to follow a row going low
+const SETTLE: Duration = Duration::from_micros(10);
+
+pub struct GpioKeypad {
+    rows: Vec<OutputPin>,
+    columns: Vec<InputPin>,
+    // what the last scan found, releases are only sent for keys the keypad pressed
+    held: [bool; 16],
+}
+
+impl GpioKeypad {
+    pub fn open(rows: [u8; 4], columns: [u8; 4]) -> Result<Self, String> {
+        let gpio = Gpio::new().map_err(|error| error.to_string())?;
+        let pin = |number: u8| {
+            gpio.get(number)
+                .map_err(|error| format!("GPIO {}: {}", number, error))
+        };
+        Ok(GpioKeypad {
+            rows: rows
+                .into_iter()
+                .map(|number| Ok(pin(number)?.into_output_high()))
+                .collect::<Result<_, String>>()?,
+            columns: columns
+                .into_iter()
+                .map(|number| Ok(pin(number)?.into_input_pullup()))
+                .collect::<Result<_, String>>()?,
+            held: [false; 16],
+        })
+    }
+
+    // Called after the frontend read its keys: keys held on the keypad stay down, keys let
+    // go since the last poll go up
+    pub fn poll(&mut self, keyboard: &mut Keyboard) {
+        let mut closed = [[false; 4]; 4];
+        for (row, pin) in self.rows.iter_mut().enumerate() {
+            pin.set_low();
+            thread::sleep(SETTLE);
+            for (column, input) in self.columns.iter().enumerate() {
+                closed[row][column] = input.is_low();
+            }
+            pin.set_high();
+        }
+        let held = keys(closed);
+        for (key, (&now, &before)) in held.iter().zip(&self.held).enumerate() {
+            if now || before {
+                let _ = keyboard.set_key(key, now);
+            }
+        }
+        self.held = held;
+    }
+}
+
+// The keys down for the switches closed at [row][column]
+fn keys(closed: [[bool; 4]; 4]) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (row, columns) in closed.iter().enumerate() {
+        for (column, &closed) in columns.iter().enumerate() {
+            keys[KEYPAD[row][column]] |= closed;
+        }
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let mut closed = [[false; 4]; 4];
+        closed[0][3] = true;
+        closed[3][1] = true;
+        let down: Vec<usize> = (0..16).filter(|&key| keys(closed)[key]).collect();
+        // top right is C, bottom second is 0
+        assert_eq!(down, [0x0, 0xC]);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::chip8::Chip8;
+use crate::cpu::Instruction;
+use core::fmt;
+
+// Notices when a program is done. Most ROMs end on `JP self` (1nnn jumping to its own
+// address), which never leaves, SUPER-CHIP ones can say so with 00FD; others spin in a loop of their own that draws nothing,
+// makes no sound and doesn't look at the keys. The second kind can't be told apart from a
+// long pause for sure, so it only counts after a number of frames the caller picks.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Halt {
+    // 1nnn jumping to its own address
+    JumpToSelf { pc: u16 },
+    // SUPER-CHIP's 00FD
+    Exit { pc: u16 },
+    // the screen, the keys and the buzzer didn't change for this many frames
+    Idle { frames: u64 },
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Halt::JumpToSelf { pc } => write!(f, "jump to itself at {:03X}", pc),
+            Halt::Exit { pc } => write!(f, "exit at {:03X}", pc),
+            Halt::Idle { frames } => write!(f, "nothing changed for {} frames", frames),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HaltDetector {
+    // frames without anything happening before Idle, 0 leaves it out
+    idle_limit: u64,
+    idle: u64,
+    // what the last frame ended with
+    screen: u64,
+    keys: u16,
+    // a halt is reported once, until the program moves again
+    halted: bool,
+}
+
+impl HaltDetector {
+    pub fn new(idle_limit: u64) -> Self {
+        HaltDetector {
+            idle_limit,
+            idle: 0,
+            screen: 0,
+            keys: 0,
+            halted: false,
+        }
+    }
+
+    // Called after every frame, Some on the frame the program halts
+    pub fn frame(&mut self, chip: &Chip8) -> Option<Halt> {
+        let pc = *chip.get_pc();
+        let jump_to_self = opcode_at(chip, pc) == Some(0x1000 | pc);
+        // 00FD only decodes to Exit on SUPER-CHIP
+        let exit =
+            opcode_at(chip, pc).map(|opcode| chip.decode(opcode)) == Some(Ok(Instruction::Exit));
+
+        let screen = chip.display_hash();
+        let keys = chip.keys_bitmask();
+        // Fx0A waits for a key on purpose, that's not a program that's done
+        let waiting = opcode_at(chip, pc).is_some_and(|opcode| opcode & 0xF0FF == 0xF00A);
+        let quiet = screen == self.screen && keys == self.keys && !chip.is_sound_on();
+        self.idle = if quiet && !waiting { self.idle + 1 } else { 0 };
+        (self.screen, self.keys) = (screen, keys);
+
+        let halt = if jump_to_self {
+            Some(Halt::JumpToSelf { pc })
+        } else if exit {
+            Some(Halt::Exit { pc })
+        } else if self.idle_limit > 0 && self.idle >= self.idle_limit {
+            Some(Halt::Idle { frames: self.idle })
+        } else {
+            None
+        };
+        let first = halt.is_some() && !self.halted;
+        self.halted = halt.is_some();
+        halt.filter(|_| first)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+}
+
+fn opcode_at(chip: &Chip8, pc: u16) -> Option<u16> {
+    let high = chip.get_ram(pc).ok()?;
+    let low = chip.get_ram(pc.checked_add(1)?).ok()?;
+    Some(u16::from_be_bytes([high, low]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jump_to_self() {
+        // 6001, then 1202 jumping to itself
+        let mut chip = Chip8::new(&[0x60, 0x01, 0x12, 0x02]).unwrap();
+        let mut detector = HaltDetector::new(0);
+        assert_eq!(detector.frame(&chip), None);
+        chip.step().unwrap();
+        assert_eq!(detector.frame(&chip), Some(Halt::JumpToSelf { pc: 0x202 }));
+        // once, even though it stays there
+        chip.step().unwrap();
+        assert_eq!(detector.frame(&chip), None);
+        assert!(detector.is_halted());
+
+        // 00FD, which stays where it is
+        let mut chip = Chip8::new(&[0x00, 0xFD]).unwrap();
+        chip.set_platform(crate::cpu::Platform::SuperChip);
+        chip.step().unwrap();
+        let mut detector = HaltDetector::new(0);
+        assert_eq!(detector.frame(&chip), Some(Halt::Exit { pc: 0x200 }));
+    }
+
+    #[test]
+    fn test_idle() {
+        // 7001 1200, a loop that never draws
+        let mut chip = Chip8::new(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut detector = HaltDetector::new(3);
+        let frame = |chip: &mut Chip8, detector: &mut HaltDetector| {
+            chip.step().unwrap();
+            chip.step().unwrap();
+            detector.frame(chip)
+        };
+        // the first frame sets what the screen looks like
+        for _ in 0..3 {
+            assert_eq!(frame(&mut chip, &mut detector), None);
+        }
+        assert_eq!(
+            frame(&mut chip, &mut detector),
+            Some(Halt::Idle { frames: 3 })
+        );
+        assert_eq!(frame(&mut chip, &mut detector), None);
+
+        // a key moves it again, then it has to be quiet for 3 more frames
+        chip.keyboard.set_key(5, true).unwrap();
+        assert_eq!(frame(&mut chip, &mut detector), None);
+        assert!(!detector.is_halted());
+        assert_eq!(frame(&mut chip, &mut detector), None);
+        assert_eq!(frame(&mut chip, &mut detector), None);
+        assert_eq!(
+            frame(&mut chip, &mut detector),
+            Some(Halt::Idle { frames: 3 })
+        );
+    }
+
+    #[test]
+    fn test_waiting_for_key() {
+        let chip = Chip8::new(&[0xF0, 0x0A]).unwrap();
+        let mut detector = HaltDetector::new(1);
+        for _ in 0..5 {
+            assert_eq!(detector.frame(&chip), None);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// Small binary edits and comparisons of ROM files, `chip8 hexedit`. Offsets are positions in
+// the file, add 0x200 for the address the byte gets loaded at.
+use crate::memory::Patch;
+use std::fmt::Write as _;
+
+// A byte that isn't the same in both files, None where one file is shorter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Difference {
+    pub offset: usize,
+    pub ours: Option<u8>,
+    pub theirs: Option<u8>,
+}
+
+// Sets every byte, or returns the first offset past the end of the file untouched
+pub fn apply(rom: &mut [u8], edits: &[Patch]) -> Result<(), usize> {
+    if let Some(edit) = edits.iter().find(|e| e.address as usize >= rom.len()) {
+        return Err(edit.address as usize);
+    }
+    for edit in edits {
+        rom[edit.address as usize] = edit.value;
+    }
+    Ok(())
+}
+
+pub fn diff(ours: &[u8], theirs: &[u8]) -> Vec<Difference> {
+    (0..ours.len().max(theirs.len()))
+        .map(|offset| Difference {
+            offset,
+            ours: ours.get(offset).copied(),
+            theirs: theirs.get(offset).copied(),
+        })
+        .filter(|difference| difference.ours != difference.theirs)
+        .collect()
+}
+
+// 16 bytes per line after the offset, like xxd without the text column
+pub fn dump(rom: &[u8]) -> String {
+    let mut text = String::new();
+    for (line, bytes) in rom.chunks(16).enumerate() {
+        let _ = write!(text, "{:04X}:", line * 16);
+        for byte in bytes {
+            let _ = write!(text, " {:02X}", byte);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_and_diff() {
+        let original = [0x00, 0xE0, 0xA2, 0x2A];
+        let mut rom = original;
+        let edit = |address, value| Patch { address, value };
+        assert_eq!(apply(&mut rom, &[edit(2, 0xA3), edit(4, 0)]), Err(4));
+        assert_eq!(rom, original);
+        assert_eq!(apply(&mut rom, &[edit(2, 0xA3), edit(3, 0x00)]), Ok(()));
+        assert_eq!(rom, [0x00, 0xE0, 0xA3, 0x00]);
+
+        let changed = |offset, ours, theirs| Difference {
+            offset,
+            ours,
+            theirs,
+        };
+        assert_eq!(
+            diff(&original, &rom[..3]),
+            [
+                changed(2, Some(0xA2), Some(0xA3)),
+                changed(3, Some(0x2A), None)
+            ]
+        );
+        assert!(diff(&original, &original).is_empty());
+    }
+
+    #[test]
+    fn test_dump() {
+        let rom: Vec<u8> = (0..18).collect();
+        assert_eq!(
+            dump(&rom),
+            "0000: 00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F\n0010: 10 11\n"
+        );
+    }
+}
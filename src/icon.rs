@@ -0,0 +1,169 @@
+// Window title and icon, display.title and display.icon in chip8.toml, and where the window
+// was last time, [window]. Each frontend shows them the way its backend can: minifb only
+// takes an icon on Linux and can't go fullscreen, the terminal only has a title.
+use crate::display::Palette;
+#[cfg(feature = "png")]
+use std::path::Path;
+
+pub const DEFAULT_TITLE: &str = "Chip-8 by Hernani Samuel Diniz";
+
+// The template with {rom} (file name without .ch8) and {version} (of this crate) filled in
+pub fn title(template: &str, rom: &str) -> String {
+    template
+        .replace("{rom}", rom)
+        .replace("{version}", env!("CARGO_PKG_VERSION"))
+}
+
+// 0xAARRGGBB pixels, row by row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Icon {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u32>,
+}
+
+// Where a window was, how big and whether it filled the screen, to open it the same way next
+// time. `monitors` is how the frontend describes the screens it was on, like
+// "1920x1080+0+0,2560x1440+1920+0", None when it can't tell
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct WindowGeometry {
+    pub position: Option<(i32, i32)>,
+    pub size: Option<(u32, u32)>,
+    pub fullscreen: bool,
+    pub monitors: Option<String>,
+}
+
+impl WindowGeometry {
+    // The saved position, unless the monitors changed since and it could be off every screen
+    pub fn position_on(&self, monitors: Option<&str>) -> Option<(i32, i32)> {
+        match (self.monitors.as_deref(), monitors) {
+            (Some(saved), Some(now)) if saved != now => None,
+            _ => self.position,
+        }
+    }
+}
+
+const SIZE: u32 = 32;
+// the 8 of the font, 4 pixels wide
+const EIGHT: [u8; 5] = [0xF0, 0x90, 0xF0, 0x90, 0xF0];
+
+impl Icon {
+    // The font's 8 in the palette colors, what windows get without display.icon
+    pub fn built_in(palette: Palette) -> Icon {
+        // 4x5 glyph pixels of 5x5, centered
+        let (pixel, left, top) = (5, (SIZE - 4 * 5) / 2, (SIZE - 5 * 5) / 2);
+        let mut pixels = vec![0xFF00_0000 | palette.off; (SIZE * SIZE) as usize];
+        for (row, bits) in EIGHT.iter().enumerate() {
+            for column in 0..4 {
+                if bits & (0x80 >> column) == 0 {
+                    continue;
+                }
+                for y in 0..pixel {
+                    let y = top + row as u32 * pixel + y;
+                    let x = left + column * pixel;
+                    let start = (y * SIZE + x) as usize;
+                    pixels[start..start + pixel as usize].fill(0xFF00_0000 | palette.on);
+                }
+            }
+        }
+        Icon {
+            width: SIZE,
+            height: SIZE,
+            pixels,
+        }
+    }
+
+    // An 8 bit RGB or RGBA PNG file
+    #[cfg(feature = "png")]
+    pub fn load(path: &Path) -> Result<Icon, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let mut reader = png::Decoder::new(std::io::BufReader::new(file))
+            .read_info()
+            .map_err(|e| e.to_string())?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+        let channels = match (info.color_type, info.bit_depth) {
+            (png::ColorType::Rgb, png::BitDepth::Eight) => 3,
+            (png::ColorType::Rgba, png::BitDepth::Eight) => 4,
+            _ => return Err("only 8 bit RGB and RGBA icons work".into()),
+        };
+        let pixels = data[..info.buffer_size()]
+            .chunks(channels)
+            .map(|pixel| {
+                let alpha = pixel.get(3).copied().unwrap_or(0xFF);
+                u32::from_be_bytes([alpha, pixel[0], pixel[1], pixel[2]])
+            })
+            .collect();
+        Ok(Icon {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_title() {
+        assert_eq!(title(DEFAULT_TITLE, "pong"), DEFAULT_TITLE);
+        assert_eq!(
+            title("{rom} - chip8 {version}", "pong"),
+            format!("pong - chip8 {}", env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[test]
+    fn test_position_on_other_monitors() {
+        let geometry = WindowGeometry {
+            position: Some((2000, 100)),
+            size: Some((1280, 640)),
+            fullscreen: false,
+            monitors: Some("1920x1080+0+0,2560x1440+1920+0".into()),
+        };
+        let same = "1920x1080+0+0,2560x1440+1920+0";
+        assert_eq!(geometry.position_on(Some(same)), Some((2000, 100)));
+        assert_eq!(geometry.position_on(Some("1920x1080+0+0")), None);
+        // a frontend that can't tell trusts it
+        assert_eq!(geometry.position_on(None), Some((2000, 100)));
+    }
+
+    #[test]
+    fn test_built_in_icon() {
+        let palette = Palette {
+            on: 0x33FF66,
+            off: 0x001A00,
+            ..Palette::default()
+        };
+        let icon = Icon::built_in(palette);
+        assert_eq!(icon.pixels.len(), 32 * 32);
+        // corner, the top left of the 8 and its hole
+        assert_eq!(icon.pixels[0], 0xFF001A00);
+        assert_eq!(icon.pixels[3 * 32 + 6], 0xFF33FF66);
+        assert_eq!(icon.pixels[9 * 32 + 12], 0xFF001A00);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_load_icon() {
+        let path = std::env::temp_dir().join(format!("chip8-icon-{}.png", std::process::id()));
+        let png = crate::frames::encode_png(
+            &crate::display::Display::new(),
+            Palette {
+                on: 0,
+                off: 0x102030,
+                ..Palette::default()
+            },
+            1,
+        )
+        .unwrap();
+        std::fs::write(&path, png).unwrap();
+        let icon = Icon::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((icon.width, icon.height), (64, 32));
+        assert_eq!(icon.pixels[0], 0xFF102030);
+        assert!(Icon::load(Path::new("/nonexistent.png")).is_err());
+    }
+}
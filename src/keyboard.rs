@@ -1,57 +1,261 @@
 // This code is generated by Claude, IO isn't my focus, my focus is on the CPU and the fetch-decode-execute cycle
 use crate::chip8::Chip8Error;
-use minifb::{Key, Window};
-
-const KEY_MAP: [(Key, usize); 16] = [
-    (Key::X, 0x0),
-    (Key::Key1, 0x1),
-    (Key::Key2, 0x2),
-    (Key::Key3, 0x3),
-    (Key::Q, 0x4),
-    (Key::W, 0x5),
-    (Key::E, 0x6),
-    (Key::A, 0x7),
-    (Key::S, 0x8),
-    (Key::D, 0x9),
-    (Key::Z, 0xA),
-    (Key::C, 0xB),
-    (Key::Key4, 0xC),
-    (Key::R, 0xD),
-    (Key::F, 0xE),
-    (Key::V, 0xF),
-];
 
+// The 16 keys of the hex keypad, frontends map their own input onto them (see screen.rs).
+// Apps without a keymap of their own can hand it host keys with set_host_key instead
+#[derive(Clone)]
 pub struct Keyboard {
-    keys: [bool; 16],
+    // bit n is key n
+    keys: u16,
+    // the keys at the end of the last frame, for is_just_pressed and is_just_released
+    previous: u16,
+    // the presses and releases next_event hasn't given yet, oldest first
+    events: [KeyEvent; MAX_EVENTS],
+    first_event: usize,
+    event_count: usize,
+    // the key that went down last, while it's still down
+    last: Option<u8>,
+    // Fx0A started waiting, and the keys already just released then, which don't end it
+    waiting: bool,
+    ignored: u16,
+    // what set_host_key goes through
+    mapping: Keymap,
 }
 
 impl Keyboard {
     pub fn new() -> Self {
-        Keyboard { keys: [false; 16] }
+        Keyboard {
+            keys: 0,
+            previous: 0,
+            events: [KeyEvent {
+                key: 0,
+                pressed: false,
+            }; MAX_EVENTS],
+            first_event: 0,
+            event_count: 0,
+            last: None,
+            waiting: false,
+            ignored: 0,
+            mapping: Keymap::new(),
+        }
+    }
+
+    // Changes which host keys play which keypad keys from now on. Keys down stay down, the
+    // frontend lets go of them like always
+    pub fn set_mapping(&mut self, mapping: Keymap) {
+        self.mapping = mapping;
+    }
+
+    pub fn mapping(&self) -> &Keymap {
+        &self.mapping
+    }
+
+    // A host key going down or up, through the mapping. false for keys that play nothing
+    pub fn set_host_key(&mut self, host_key: char, pressed: bool) -> bool {
+        match self.mapping.key_for(host_key) {
+            Some(key) => self.set_key(key, pressed).is_ok(),
+            None => false,
+        }
     }
 
-    // Called every frame to sync physical keyboard state
-    pub fn update(&mut self, window: &Window) {
-        for (physical_key, chip8_key) in KEY_MAP {
-            self.keys[chip8_key] = window.is_key_down(physical_key);
+    // Frontends, scripted input and tests all go through here
+    pub fn set_key(&mut self, key: usize, pressed: bool) -> Result<bool, Chip8Error> {
+        if key >= 16 {
+            return Err(Chip8Error::InvalidKey);
+        }
+        let bit = 1 << key;
+        if pressed && self.keys & bit == 0 {
+            self.last = Some(key as u8);
+        }
+        if pressed != (self.keys & bit != 0) {
+            self.push_event(KeyEvent {
+                key: key as u8,
+                pressed,
+            });
+        }
+        if pressed {
+            self.keys |= bit;
+        } else {
+            self.keys &= !bit;
         }
+        // with the last one up, any key still down will do
+        if self.last == Some(key as u8) && !pressed {
+            self.last = self.get_pressed_key().map(|key| key as u8);
+        }
+        Ok(true)
     }
 
     pub fn is_pressed(&self, key: usize) -> Result<bool, Chip8Error> {
         if key >= 16 {
             Err(Chip8Error::InvalidKey)
         } else {
-            Ok(self.keys[key])
+            Ok(self.keys & (1 << key) != 0)
+        }
+    }
+
+    // Down now but not at the end of the last frame
+    pub fn is_just_pressed(&self, key: usize) -> Result<bool, Chip8Error> {
+        Ok(self.is_pressed(key)? && self.previous & (1 << key) == 0)
+    }
+
+    // Up now but down at the end of the last frame
+    pub fn is_just_released(&self, key: usize) -> Result<bool, Chip8Error> {
+        Ok(!self.is_pressed(key)? && self.just_released() & (1 << key) != 0)
+    }
+
+    fn just_released(&self) -> u16 {
+        self.previous & !self.keys
+    }
+
+    // What the keys are now becomes the last frame's. The machine calls it on every timer
+    // tick, so a frame is a 60th of a second whatever runs it
+    pub fn end_frame(&mut self) {
+        self.previous = self.keys;
+        self.ignored = 0;
+    }
+
+    // The oldest press or release not given yet. A press and release within one frame, which
+    // is_just_pressed misses, shows up here. Past MAX_EVENTS the oldest are dropped
+    pub fn next_event(&mut self) -> Option<KeyEvent> {
+        if self.event_count == 0 {
+            return None;
+        }
+        let event = self.events[self.first_event];
+        self.first_event = (self.first_event + 1) % MAX_EVENTS;
+        self.event_count -= 1;
+        Some(event)
+    }
+
+    fn push_event(&mut self, event: KeyEvent) {
+        if self.event_count == MAX_EVENTS {
+            self.next_event();
         }
+        self.events[(self.first_event + self.event_count) % MAX_EVENTS] = event;
+        self.event_count += 1;
     }
 
+    // The lowest key down
     pub fn get_pressed_key(&self) -> Option<usize> {
-        for (i, pressed) in self.keys.iter().enumerate() {
-            if *pressed {
-                return Some(i);
-            }
+        (self.keys != 0).then(|| self.keys.trailing_zeros() as usize)
+    }
+
+    // Every key in one read, bit n is key n
+    pub fn bitmask(&self) -> u16 {
+        self.keys
+    }
+
+    // The key pressed most recently of the ones still down
+    pub fn last_pressed(&self) -> Option<u8> {
+        self.last
+    }
+
+    // What Fx0A asks each time it runs, None until it's done: the lowest key just released
+    // (is_just_released) since the wait started. Like on the COSMAC VIP a key has to go down
+    // and back up, a key already held when the wait starts counts once it's let go, and the
+    // release that ended one Fx0A doesn't end the next one in the same frame
+    pub fn wait_for_release(&mut self) -> Option<u8> {
+        if !self.waiting {
+            self.waiting = true;
+            self.ignored = self.just_released();
+            return None;
+        }
+        let released = self.just_released() & !self.ignored;
+        if released == 0 {
+            return None;
+        }
+        self.waiting = false;
+        Some(released.trailing_zeros() as u8)
+    }
+
+    pub fn keys(&self) -> [bool; 16] {
+        core::array::from_fn(|key| self.keys & (1 << key) != 0)
+    }
+
+    // Stops a wait of Fx0A, for a new program
+    pub(crate) fn cancel_wait(&mut self) {
+        self.waiting = false;
+        self.ignored = 0;
+    }
+
+    // What save states keep besides the keys down: the keys at the end of the last frame and
+    // the wait of Fx0A, whether there's one and the releases it ignores
+    pub(crate) fn frame_state(&self) -> [u8; KEYBOARD_STATE_SIZE] {
+        let [previous_low, previous_high] = self.previous.to_le_bytes();
+        let [ignored_low, ignored_high] = self.ignored.to_le_bytes();
+        [
+            previous_low,
+            previous_high,
+            self.waiting as u8,
+            ignored_low,
+            ignored_high,
+        ]
+    }
+
+    // Puts back the keys of a save state as they were, without events or Fx0A seeing keys
+    // go down or up
+    pub(crate) fn restore(
+        &mut self,
+        keys: u16,
+        frame_state: [u8; KEYBOARD_STATE_SIZE],
+    ) -> Result<(), Chip8Error> {
+        let [
+            previous_low,
+            previous_high,
+            waiting,
+            ignored_low,
+            ignored_high,
+        ] = frame_state;
+        if waiting > 1 {
+            return Err(Chip8Error::InvalidState);
+        }
+        self.keys = keys;
+        self.previous = u16::from_le_bytes([previous_low, previous_high]);
+        self.waiting = waiting == 1;
+        self.ignored = u16::from_le_bytes([ignored_low, ignored_high]);
+        if self.last.is_none_or(|key| keys & (1 << key) == 0) {
+            self.last = self.get_pressed_key().map(|key| key as u8);
+        }
+        Ok(())
+    }
+
+    // All 16 at once, like an InputBackend gives them
+    pub fn set_keys(&mut self, keys: [bool; 16]) {
+        for (key, pressed) in keys.into_iter().enumerate() {
+            let _ = self.set_key(key, pressed);
         }
-        None
+    }
+}
+
+// The bytes of Keyboard::frame_state in a save state
+pub(crate) const KEYBOARD_STATE_SIZE: usize = 5;
+
+// How many presses and releases a Keyboard holds for next_event
+pub const MAX_EVENTS: usize = 32;
+
+// A key of the keypad going down or up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub key: u8,
+    pub pressed: bool,
+}
+
+// Where the keypad's state comes from each frame: a window, a GUI toolkit, a gamepad, a
+// test. Emulator reads its one before every frame (set_input_backend). Screen, SdlScreen
+// and Terminal are ones, and so is a Keyboard the app sets keys on, or a fixed [bool; 16]
+pub trait InputBackend {
+    // The 16 keys, true for the ones down
+    fn keys(&mut self) -> [bool; 16];
+}
+
+impl InputBackend for Keyboard {
+    fn keys(&mut self) -> [bool; 16] {
+        Keyboard::keys(self)
+    }
+}
+
+impl InputBackend for [bool; 16] {
+    fn keys(&mut self) -> [bool; 16] {
+        *self
     }
 }
 
@@ -61,6 +265,162 @@ impl Default for Keyboard {
     }
 }
 
+// Which host key plays each keypad key, as the character printed on it. The default is the
+// usual 4x4 block on the left of a QWERTY keyboard:
+//     1 2 3 C        1 2 3 4
+//     4 5 6 D   <-   q w e r
+//     7 8 9 E        a s d f
+//     A 0 B F        z x c v
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keymap {
+    keys: [char; 16],
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self::preset(LayoutPreset::Qwerty)
+    }
+
+    // The keypad on the 4x4 block of a layout, see LayoutPreset
+    pub fn preset(preset: LayoutPreset) -> Self {
+        // the keypad keys in the order the block is written, row by row
+        const KEYPAD: [usize; 16] = [1, 2, 3, 0xC, 4, 5, 6, 0xD, 7, 8, 9, 0xE, 0xA, 0, 0xB, 0xF];
+        let mut keys = ['\0'; 16];
+        for (&key, host_key) in KEYPAD.iter().zip(preset.block().chars()) {
+            keys[key] = host_key;
+        }
+        Keymap { keys }
+    }
+
+    // 16 distinct letters or digits, the host keys of 0x0 to 0xF in order
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut keys = ['\0'; 16];
+        let mut chars = text.chars();
+        for index in 0..16 {
+            let key = chars.next()?.to_ascii_lowercase();
+            if !key.is_ascii_alphanumeric() || keys[..index].contains(&key) {
+                return None;
+            }
+            keys[index] = key;
+        }
+        if chars.next().is_some() {
+            return None;
+        }
+        Some(Keymap { keys })
+    }
+
+    pub fn host_key(&self, key: usize) -> Option<char> {
+        self.keys.get(key).copied()
+    }
+
+    // The keypad key played by a host key, case doesn't matter
+    pub fn key_for(&self, host_key: char) -> Option<usize> {
+        let host_key = host_key.to_ascii_lowercase();
+        self.keys.iter().position(|&key| key == host_key)
+    }
+
+    // (host key, keypad key) pairs, what the frontends turn into their own key codes
+    pub fn iter(&self) -> impl Iterator<Item = (char, usize)> + '_ {
+        self.keys.iter().copied().zip(0..16)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Where the keypad goes on keyboards other than US QWERTY. Keymap::preset turns one into a
+// keymap, --keymap and keymap in chip8.toml take the names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutPreset {
+    // 1234 QWER ASDF ZXCV, the default
+    Qwerty,
+    // 1234 AZER QSDF WXCV
+    Azerty,
+    // 1234 QWFP ARST ZXCD
+    Colemak,
+    // 4321 REWQ FDSA VCXZ, the QWERTY block flipped left to right
+    Mirror,
+}
+
+impl LayoutPreset {
+    pub const ALL: [LayoutPreset; 4] = [
+        LayoutPreset::Qwerty,
+        LayoutPreset::Azerty,
+        LayoutPreset::Colemak,
+        LayoutPreset::Mirror,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LayoutPreset::Qwerty => "qwerty",
+            LayoutPreset::Azerty => "azerty",
+            LayoutPreset::Colemak => "colemak",
+            LayoutPreset::Mirror => "mirror",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<LayoutPreset> {
+        LayoutPreset::ALL
+            .into_iter()
+            .find(|preset| preset.name().eq_ignore_ascii_case(name))
+    }
+
+    // The host keys under the keypad's 1 2 3 C, 4 5 6 D, 7 8 9 E and A 0 B F
+    fn block(self) -> &'static str {
+        match self {
+            LayoutPreset::Qwerty => "1234qwerasdfzxcv",
+            LayoutPreset::Azerty => "1234azerqsdfwxcv",
+            LayoutPreset::Colemak => "1234qwfparstzxcd",
+            LayoutPreset::Mirror => "4321rewqfdsavcxz",
+        }
+    }
+}
+
+// Host keys outside the keypad, for hotkeys. Names are the ones printed on the key:
+// a letter or digit, F1 to F12, Escape, Space, Enter, Backspace or Tab
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HostKey {
+    Char(char),
+    F(u8),
+    Escape,
+    Space,
+    Enter,
+    Backspace,
+    Tab,
+}
+
+const NAMES: [(&str, HostKey); 7] = [
+    ("escape", HostKey::Escape),
+    ("esc", HostKey::Escape),
+    ("space", HostKey::Space),
+    ("enter", HostKey::Enter),
+    ("return", HostKey::Enter),
+    ("backspace", HostKey::Backspace),
+    ("tab", HostKey::Tab),
+];
+
+impl HostKey {
+    pub fn parse(name: &str) -> Option<Self> {
+        let mut chars = name.chars();
+        if let (Some(key), None) = (chars.next(), chars.next()) {
+            return key
+                .is_ascii_alphanumeric()
+                .then(|| HostKey::Char(key.to_ascii_lowercase()));
+        }
+        if let Some((_, key)) = NAMES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        {
+            return Some(*key);
+        }
+        let number = name.strip_prefix(['f', 'F'])?.parse().ok()?;
+        (1..=12).contains(&number).then_some(HostKey::F(number))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,7 +437,177 @@ mod tests {
     fn test_keyboard_initial_state() {
         let keyboard = Keyboard::new();
         for key in 0..16 {
-            assert_eq!(keyboard.is_pressed(key).unwrap(), false);
+            assert!(!keyboard.is_pressed(key).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_keyboard_set_key() {
+        let mut keyboard = Keyboard::new();
+        assert!(keyboard.set_key(0x5, true).is_ok());
+        assert!(keyboard.is_pressed(0x5).unwrap());
+        assert_eq!(keyboard.get_pressed_key(), Some(0x5));
+        assert!(keyboard.set_key(0x5, false).is_ok());
+        assert_eq!(keyboard.get_pressed_key(), None);
+        assert!(keyboard.set_key(0x10, true).is_err());
+    }
+
+    #[test]
+    fn test_set_keys() {
+        let mut keyboard = Keyboard::new();
+        let mut keys = [false; 16];
+        (keys[3], keys[0xA]) = (true, true);
+        keyboard.set_keys(keys);
+        assert_eq!(keyboard.bitmask(), 0x0408);
+        assert_eq!(keyboard.last_pressed(), Some(0xA));
+        assert_eq!(keyboard.keys(), keys);
+        keys[0xA] = false;
+        keyboard.set_keys(InputBackend::keys(&mut keys));
+        assert_eq!(keyboard.last_pressed(), Some(3));
+    }
+
+    #[test]
+    fn test_keymap_and_host_keys() {
+        let keymap = Keymap::new();
+        assert_eq!(keymap.key_for('X'), Some(0x0));
+        assert_eq!(keymap.key_for('v'), Some(0xF));
+        assert_eq!(keymap.host_key(0xC), Some('4'));
+        assert_eq!(keymap.key_for('p'), None);
+
+        let custom = Keymap::parse("0123456789ABCDEF").unwrap();
+        assert_eq!(custom.key_for('b'), Some(0xB));
+        // too short, too long, a key twice, not a letter or digit
+        assert!(Keymap::parse("0123").is_none());
+        assert!(Keymap::parse("0123456789abcdefg").is_none());
+        assert!(Keymap::parse("0123456789abcdee").is_none());
+        assert!(Keymap::parse("0123456789abcde-").is_none());
+
+        assert_eq!(HostKey::parse("P"), Some(HostKey::Char('p')));
+        assert_eq!(HostKey::parse("F5"), Some(HostKey::F(5)));
+        assert_eq!(HostKey::parse("escape"), Some(HostKey::Escape));
+        assert_eq!(HostKey::parse("F13"), None);
+        assert_eq!(HostKey::parse("Shift"), None);
+    }
+
+    #[test]
+    fn test_layout_presets() {
+        assert_eq!(Keymap::preset(LayoutPreset::Qwerty), Keymap::new());
+        assert_eq!(
+            Keymap::preset(LayoutPreset::Qwerty),
+            Keymap::parse("x123qweasdzc4rfv").unwrap()
+        );
+        let azerty = Keymap::preset(LayoutPreset::Azerty);
+        assert_eq!(azerty.key_for('a'), Some(0x4));
+        assert_eq!(azerty.key_for('w'), Some(0xA));
+        let colemak = Keymap::preset(LayoutPreset::Colemak);
+        assert_eq!(colemak.key_for('p'), Some(0xD));
+        let mirror = Keymap::preset(LayoutPreset::Mirror);
+        assert_eq!(
+            (mirror.host_key(1), mirror.host_key(0xF)),
+            (Some('4'), Some('z'))
+        );
+        for preset in LayoutPreset::ALL {
+            // 16 different keys, what parse would take
+            let keys: [char; 16] =
+                core::array::from_fn(|key| Keymap::preset(preset).host_key(key).unwrap());
+            assert!((0..16).all(|key| !keys[..key].contains(&keys[key])));
+            assert_eq!(LayoutPreset::parse(preset.name()), Some(preset));
         }
+        assert_eq!(LayoutPreset::parse("AZERTY"), Some(LayoutPreset::Azerty));
+        assert_eq!(LayoutPreset::parse("dvorak"), None);
+    }
+
+    #[test]
+    fn test_edges_and_events() {
+        let mut keyboard = Keyboard::new();
+        keyboard.set_key(3, true).unwrap();
+        assert_eq!(keyboard.is_just_pressed(3), Ok(true));
+        keyboard.end_frame();
+        // still down, but not just pressed anymore
+        keyboard.set_key(3, true).unwrap();
+        assert_eq!(keyboard.is_just_pressed(3), Ok(false));
+        keyboard.set_key(3, false).unwrap();
+        assert_eq!(keyboard.is_just_released(3), Ok(true));
+        keyboard.end_frame();
+        assert_eq!(keyboard.is_just_released(3), Ok(false));
+        assert!(keyboard.is_just_pressed(16).is_err());
+
+        // a tap within one frame is only an event
+        keyboard.set_key(8, true).unwrap();
+        keyboard.set_key(8, false).unwrap();
+        assert_eq!(keyboard.is_just_pressed(8), Ok(false));
+        for (key, pressed) in [(3, true), (3, false), (8, true), (8, false)] {
+            assert_eq!(keyboard.next_event(), Some(KeyEvent { key, pressed }));
+        }
+        assert_eq!(keyboard.next_event(), None);
+
+        for _ in 0..20 {
+            keyboard.set_key(1, true).unwrap();
+            keyboard.set_key(1, false).unwrap();
+        }
+        assert_eq!(
+            core::iter::from_fn(|| keyboard.next_event()).count(),
+            MAX_EVENTS
+        );
+    }
+
+    #[test]
+    fn test_wait_for_release() {
+        let mut keyboard = Keyboard::new();
+        // let go in this frame before the wait doesn't count
+        keyboard.set_key(2, true).unwrap();
+        keyboard.end_frame();
+        keyboard.set_key(2, false).unwrap();
+        assert_eq!(keyboard.wait_for_release(), None);
+        assert_eq!(keyboard.wait_for_release(), None);
+        keyboard.set_key(5, true).unwrap();
+        keyboard.end_frame();
+        assert_eq!(keyboard.wait_for_release(), None);
+        keyboard.set_key(5, false).unwrap();
+        assert_eq!(keyboard.wait_for_release(), Some(5));
+        // the same release doesn't end the next wait
+        assert_eq!(keyboard.wait_for_release(), None);
+        assert_eq!(keyboard.wait_for_release(), None);
+
+        // held from before, done when it goes up
+        keyboard.set_key(9, true).unwrap();
+        keyboard.end_frame();
+        assert_eq!(keyboard.wait_for_release(), None);
+        keyboard.set_key(9, false).unwrap();
+        assert_eq!(keyboard.wait_for_release(), Some(9));
+    }
+
+    #[test]
+    fn test_set_mapping() {
+        let mut keyboard = Keyboard::new();
+        assert!(keyboard.set_host_key('Q', true));
+        assert!(keyboard.is_pressed(0x4).unwrap());
+        assert!(!keyboard.set_host_key('p', true));
+
+        keyboard.set_mapping(Keymap::parse("0123456789abcdef").unwrap());
+        assert_eq!(keyboard.mapping().host_key(0x4), Some('4'));
+        assert!(!keyboard.set_host_key('q', false));
+        assert!(keyboard.set_host_key('4', false));
+        assert_eq!(keyboard.bitmask(), 0);
+        keyboard.set_host_key('f', true);
+        assert_eq!(keyboard.last_pressed(), Some(0xF));
+    }
+
+    #[test]
+    fn test_bitmask_and_last_pressed() {
+        let mut keyboard = Keyboard::new();
+        assert_eq!(keyboard.last_pressed(), None);
+        keyboard.set_key(0xA, true).unwrap();
+        keyboard.set_key(0x3, true).unwrap();
+        assert_eq!(keyboard.bitmask(), 0b0000_0100_0000_1000);
+        assert_eq!(keyboard.last_pressed(), Some(0x3));
+        assert_eq!(keyboard.get_pressed_key(), Some(0x3));
+        // held down again, still the same press
+        keyboard.set_key(0xA, true).unwrap();
+        assert_eq!(keyboard.last_pressed(), Some(0x3));
+        keyboard.set_key(0x3, false).unwrap();
+        assert_eq!(keyboard.last_pressed(), Some(0xA));
+        keyboard.set_key(0xA, false).unwrap();
+        assert_eq!((keyboard.bitmask(), keyboard.last_pressed()), (0, None));
     }
 }
@@ -0,0 +1,227 @@
+// The screen on a physical LED wall. Every frame where the screen changed gets written to
+// a device file: a serial port to a Pi or microcontroller bridge (set its speed with stty
+// first), or /dev/spidevX.Y for MAX7219 modules wired straight to a Pi. The protocols:
+//     packed:  C8 08, then the 256 bytes of the screen, 8 pixels per byte, row by row,
+//              MSB left. For bridges that drive the panel themselves
+//     rgb:     C8 18, then 64x32 pixels of 3 bytes (red, green, blue) in the palette
+//              colors, row by row. For bridges to a 64x32 HUB75 panel
+//     max7219: a chain of 32 8x8 modules, 4 rows of 8 from the top left, the top left
+//              module first in the chain. One write per module row, so spidev pulses
+//              CS (the load pin) after each
+// The C8 and the second byte let a bridge find where a frame starts.
+//
+// It's a Plugin, on_frame writes the frame, so it works the same in every run loop.
+use crate::chip8::Chip8;
+use crate::display::{Display, Palette};
+use crate::plugin::Plugin;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedProtocol {
+    Packed,
+    Rgb,
+    Max7219,
+}
+
+impl LedProtocol {
+    // "packed", "rgb" or "max7219", any case
+    pub fn parse(name: &str) -> Option<Self> {
+        [
+            ("packed", LedProtocol::Packed),
+            ("rgb", LedProtocol::Rgb),
+            ("max7219", LedProtocol::Max7219),
+        ]
+        .into_iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, protocol)| protocol)
+    }
+}
+
+const MAGIC: u8 = 0xC8;
+const MODULES: usize = 32;
+// MAX7219 registers, rows are 1 to 8
+const DECODE_MODE: u8 = 0x09;
+const INTENSITY: u8 = 0x0A;
+const SCAN_LIMIT: u8 = 0x0B;
+const SHUTDOWN: u8 = 0x0C;
+const DISPLAY_TEST: u8 = 0x0F;
+
+pub struct LedMatrix {
+    out: Box<dyn Write + Send>,
+    protocol: LedProtocol,
+    palette: Palette,
+    // what the wall shows, None until the first frame
+    shown: Option<[u64; 32]>,
+}
+
+impl LedMatrix {
+    pub fn open(path: &Path, protocol: LedProtocol, palette: Palette) -> io::Result<Self> {
+        let device = OpenOptions::new().write(true).open(path)?;
+        Self::new(Box::new(device), protocol, palette)
+    }
+
+    // Any writer, a MAX7219 chain gets set up right away
+    pub fn new(
+        mut out: Box<dyn Write + Send>,
+        protocol: LedProtocol,
+        palette: Palette,
+    ) -> io::Result<Self> {
+        if protocol == LedProtocol::Max7219 {
+            for write in max7219_setup() {
+                out.write_all(&write)?;
+            }
+            out.flush()?;
+        }
+        Ok(LedMatrix {
+            out,
+            protocol,
+            palette,
+            shown: None,
+        })
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.shown = None;
+    }
+
+    // Writes the screen if it isn't what the wall shows already
+    pub fn show(&mut self, display: &Display) -> io::Result<()> {
+        if self.shown == Some(display.rows()) {
+            return Ok(());
+        }
+        for write in encode(self.protocol, display, self.palette) {
+            self.out.write_all(&write)?;
+        }
+        self.out.flush()?;
+        self.shown = Some(display.rows());
+        Ok(())
+    }
+}
+
+impl Plugin for LedMatrix {
+    fn name(&self) -> &str {
+        "led"
+    }
+
+    fn on_frame(&mut self, chip: &mut Chip8) -> Result<(), String> {
+        self.show(&chip.display)
+            .map_err(|error| format!("can't write to the LED matrix: {}", error))
+    }
+}
+
+// The writes of one frame, in order
+pub fn encode(protocol: LedProtocol, display: &Display, palette: Palette) -> Vec<Vec<u8>> {
+    match protocol {
+        LedProtocol::Packed => {
+            let mut frame = vec![MAGIC, 0x08];
+            for row in display.rows() {
+                frame.extend_from_slice(&row.to_be_bytes());
+            }
+            vec![frame]
+        }
+        LedProtocol::Rgb => {
+            let mut frame = vec![MAGIC, 0x18];
+            for (_, _, lit) in display.pixels() {
+                let color = palette.color(lit as u8);
+                frame.extend_from_slice(&color.to_be_bytes()[1..]);
+            }
+            vec![frame]
+        }
+        LedProtocol::Max7219 => (0..8)
+            .map(|line| {
+                // the last module of the chain goes out first, the data shifts through
+                (0..MODULES)
+                    .rev()
+                    .flat_map(|module| {
+                        let y = module / 8 * 8 + line;
+                        let byte = display.rows()[y].to_be_bytes()[module % 8];
+                        [line as u8 + 1, byte]
+                    })
+                    .collect()
+            })
+            .collect(),
+    }
+}
+
+// Raw pixels instead of digits, all 8 rows, a low brightness, on, and out of test mode
+fn max7219_setup() -> Vec<Vec<u8>> {
+    [
+        (DECODE_MODE, 0x00),
+        (SCAN_LIMIT, 0x07),
+        (INTENSITY, 0x03),
+        (SHUTDOWN, 0x01),
+        (DISPLAY_TEST, 0x00),
+    ]
+    .into_iter()
+    .map(|(register, value)| [register, value].repeat(MODULES))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display() -> Display {
+        // a lit pixel at the top left and at (63, 31)
+        let mut display = Display::new();
+        display.set_pixel(0, 1).unwrap();
+        display.set_pixel(64 * 32 - 1, 1).unwrap();
+        display
+    }
+
+    #[test]
+    fn test_packed_and_rgb() {
+        let palette = Palette {
+            on: 0xFF8000,
+            off: 0x000010,
+            ..Palette::default()
+        };
+        let packed = encode(LedProtocol::Packed, &display(), palette);
+        assert_eq!(packed.len(), 1);
+        assert_eq!(packed[0].len(), 2 + 256);
+        assert_eq!(packed[0][..3], [0xC8, 0x08, 0x80]);
+        assert_eq!(packed[0][257], 0x01);
+
+        let rgb = &encode(LedProtocol::Rgb, &display(), palette)[0];
+        assert_eq!(rgb.len(), 2 + 64 * 32 * 3);
+        assert_eq!(rgb[..8], [0xC8, 0x18, 0xFF, 0x80, 0x00, 0x00, 0x00, 0x10]);
+        assert_eq!(rgb[rgb.len() - 3..], [0xFF, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn test_max7219() {
+        let writes = encode(LedProtocol::Max7219, &display(), Palette::default());
+        assert_eq!(writes.len(), 8);
+        assert!(writes.iter().all(|write| write.len() == MODULES * 2));
+        // row 1 of the top left module (last in the write), row 8 of the bottom right (first)
+        assert_eq!(writes[0][MODULES * 2 - 2..], [1, 0x80]);
+        assert_eq!(writes[7][..2], [8, 0x01]);
+        assert_eq!(writes[7][2..4], [8, 0x00]);
+        assert_eq!(LedProtocol::parse("MAX7219"), Some(LedProtocol::Max7219));
+    }
+    struct Unplugged;
+
+    impl Write for Unplugged {
+        fn write(&mut self, _bytes: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("unplugged"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_error_goes_to_the_app() {
+        let mut led =
+            LedMatrix::new(Box::new(Unplugged), LedProtocol::Packed, Palette::default()).unwrap();
+        let mut chip = Chip8::new(&[0x12, 0x00]).unwrap();
+        assert_eq!(
+            led.on_frame(&mut chip),
+            Err("can't write to the LED matrix: unplugged".into())
+        );
+    }
+}
@@ -1,10 +1,121 @@
-pub mod audio;
+// The core (chip8, cpu, display, keyboard, sound) only uses fixed arrays, so it builds with
+// `--no-default-features` for microcontrollers, the Cortex-M0+ of an RP2040 too. Everything
+// that needs an OS is behind `std` and every frontend behind its own feature (gui, audio,
+// term, sdl2, egui).
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+// A puffin scope until the end of the block with the `profiling` feature, nothing without
+macro_rules! profile_scope {
+    ($name:expr) => {
+        #[cfg(feature = "profiling")]
+        puffin::profile_scope!($name);
+    };
+}
+
 pub mod chip8;
+pub mod clock;
 pub mod cpu;
 pub mod display;
+pub mod flash;
+pub mod halt;
 pub mod keyboard;
+pub mod memory;
+pub mod sound;
+
+#[cfg(feature = "alloc")]
+pub mod asm;
+#[cfg(feature = "alloc")]
+pub mod debugger;
+#[cfg(feature = "alloc")]
+pub mod driver;
+#[cfg(feature = "alloc")]
+pub mod emulator;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+#[cfg(feature = "alloc")]
+pub mod lockstep;
+#[cfg(feature = "alloc")]
+pub mod plugin;
+#[cfg(feature = "alloc")]
+pub mod rewind;
+#[cfg(feature = "alloc")]
+pub mod state;
+#[cfg(feature = "alloc")]
+pub mod trace;
+
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "std")]
+pub mod attract;
+#[cfg(feature = "audio")]
+pub mod audio;
+#[cfg(feature = "png")]
+pub mod capture;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+#[cfg(feature = "std")]
+pub mod compat;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "egui")]
+pub mod desktop;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "png")]
+pub mod frames;
+#[cfg(feature = "std")]
+pub mod golden;
+#[cfg(feature = "gpio")]
+pub mod gpio;
+#[cfg(feature = "std")]
+pub mod hexedit;
+#[cfg(feature = "std")]
+pub mod icon;
+#[cfg(feature = "led")]
+pub mod led;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "midi")]
+pub mod midi;
+#[cfg(feature = "std")]
+pub mod netplay;
+#[cfg(feature = "std")]
+pub mod osd;
+#[cfg(feature = "std")]
+pub mod panels;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "gui")]
+pub mod screen;
+#[cfg(feature = "sdl2")]
+pub mod sdl;
+#[cfg(feature = "std")]
+pub mod selftest;
+#[cfg(feature = "spectate")]
+pub mod spectate;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "term")]
+pub mod terminal;
+#[cfg(feature = "std")]
+pub mod testrunner;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+#[cfg(feature = "audio")]
 pub use audio::Audio;
 pub use chip8::Chip8;
-pub use display::Display;
-pub use keyboard::Keyboard;
+pub use display::{Display, DisplayBackend};
+#[cfg(feature = "alloc")]
+pub use driver::Driver;
+#[cfg(feature = "alloc")]
+pub use emulator::Emulator;
+pub use keyboard::{InputBackend, Keyboard};
+#[cfg(feature = "alloc")]
+pub use lockstep::Lockstep;
+#[cfg(feature = "gui")]
+pub use screen::{MinifbBackend, Screen};
+pub use sound::{AudioBackend, NullAudio};
@@ -0,0 +1,535 @@
+// libretro core, so RetroArch (and every device it runs on) can play Chip-8 games.
+// Build it with
+//     cargo rustc --release --lib --features libretro --crate-type cdylib
+// and copy target/release/libchip8.so to RetroArch's cores folder as chip8_libretro.so.
+//
+// The libretro API is a set of global C functions, so the running core lives in a static.
+// Each retro_run is one lockstep frame, sound is a 440 Hz square wave while ST > 0 and
+// save states are Chip8::save_state. The core option chip8_palette picks one of the
+// Palette presets.
+use crate::chip8::Chip8;
+use crate::display::Palette;
+use crate::lockstep::Lockstep;
+use crate::memory::RAM_SIZE;
+use crate::testrunner::IPF;
+use core::ffi::{CStr, c_char, c_uint, c_void};
+use libretro_sys::{
+    DEVICE_ID_JOYPAD_A, DEVICE_ID_JOYPAD_B, DEVICE_ID_JOYPAD_DOWN, DEVICE_ID_JOYPAD_LEFT,
+    DEVICE_ID_JOYPAD_RIGHT, DEVICE_ID_JOYPAD_SELECT, DEVICE_ID_JOYPAD_START, DEVICE_ID_JOYPAD_UP,
+    DEVICE_ID_JOYPAD_X, DEVICE_ID_JOYPAD_Y, DEVICE_JOYPAD, DEVICE_KEYBOARD,
+    ENVIRONMENT_GET_VARIABLE, ENVIRONMENT_GET_VARIABLE_UPDATE, ENVIRONMENT_SET_PIXEL_FORMAT,
+    ENVIRONMENT_SET_VARIABLES, GameInfo, Key, MEMORY_SYSTEM_RAM, PixelFormat, SystemAvInfo,
+    SystemInfo, Variable,
+};
+use std::sync::Mutex;
+
+const SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+const TONE: u32 = 440;
+const VOLUME: i16 = 3000;
+
+// The core option, its first value is the default
+const PALETTE_KEY: &CStr = c"chip8_palette";
+const PALETTE_OPTION: &CStr = c"Palette; amber|green|paper|gameboy";
+
+// Same layout as the desktop frontends when a keyboard is around
+const KEY_MAP: [(Key, usize); 16] = [
+    (Key::X, 0x0),
+    (Key::Number_1, 0x1),
+    (Key::Number_2, 0x2),
+    (Key::Number_3, 0x3),
+    (Key::Q, 0x4),
+    (Key::W, 0x5),
+    (Key::E, 0x6),
+    (Key::A, 0x7),
+    (Key::S, 0x8),
+    (Key::D, 0x9),
+    (Key::Z, 0xA),
+    (Key::C, 0xB),
+    (Key::Number_4, 0xC),
+    (Key::R, 0xD),
+    (Key::F, 0xE),
+    (Key::V, 0xF),
+];
+
+// Gamepads get the 2/4/6/8 directions and 5 to act that most games use
+const JOYPAD_MAP: [(c_uint, usize); 10] = [
+    (DEVICE_ID_JOYPAD_UP, 0x2),
+    (DEVICE_ID_JOYPAD_LEFT, 0x4),
+    (DEVICE_ID_JOYPAD_RIGHT, 0x6),
+    (DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (DEVICE_ID_JOYPAD_A, 0x5),
+    (DEVICE_ID_JOYPAD_B, 0x0),
+    (DEVICE_ID_JOYPAD_X, 0x1),
+    (DEVICE_ID_JOYPAD_Y, 0x3),
+    (DEVICE_ID_JOYPAD_START, 0xF),
+    (DEVICE_ID_JOYPAD_SELECT, 0xE),
+];
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video: Option<VideoRefreshFn>,
+    audio_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Game {
+    chip: Chip8,
+    // the core option, kept across retro_reset
+    palette: Palette,
+    lockstep: Lockstep,
+    rom: Vec<u8>,
+    // position inside the square wave, in samples
+    phase: u32,
+    frame: [u32; 64 * 32],
+    audio: [i16; SAMPLES_PER_FRAME * 2],
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video: None,
+    audio_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+static GAME: Mutex<Option<Box<Game>>> = Mutex::new(None);
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn with_game<T>(default: T, f: impl FnOnce(&mut Game) -> T) -> T {
+    let mut game = GAME.lock().unwrap_or_else(|e| e.into_inner());
+    match game.as_mut() {
+        Some(game) => f(game),
+        None => default,
+    }
+}
+
+impl Game {
+    fn new(rom: &[u8], palette: Palette) -> Option<Box<Game>> {
+        let chip = Chip8::new(rom).ok()?;
+        Some(Box::new(Game {
+            chip,
+            palette,
+            lockstep: Lockstep::new(IPF),
+            rom: rom.to_vec(),
+            phase: 0,
+            frame: [0; 64 * 32],
+            audio: [0; SAMPLES_PER_FRAME * 2],
+        }))
+    }
+
+    fn read_input(&mut self, input_state: InputStateFn) {
+        let mut keys = [false; 16];
+        for (key, chip8_key) in KEY_MAP {
+            keys[chip8_key] |= unsafe { input_state(0, DEVICE_KEYBOARD, 0, key as c_uint) } != 0;
+        }
+        for (button, chip8_key) in JOYPAD_MAP {
+            keys[chip8_key] |= unsafe { input_state(0, DEVICE_JOYPAD, 0, button) } != 0;
+        }
+        for (chip8_key, pressed) in keys.into_iter().enumerate() {
+            let _ = self.chip.keyboard.set_key(chip8_key, pressed);
+        }
+    }
+
+    fn render(&mut self) {
+        profile_scope!("render");
+        for (out, &pixel) in self.frame.iter_mut().zip(&self.chip.display.pixel_bytes()) {
+            *out = self.palette.color(pixel);
+        }
+    }
+
+    fn fill_audio(&mut self) {
+        let half_period = SAMPLE_RATE / TONE / 2;
+        for sample in self.audio.chunks_mut(2) {
+            let value = if !self.chip.is_sound_on() {
+                0
+            } else if (self.phase / half_period).is_multiple_of(2) {
+                VOLUME
+            } else {
+                -VOLUME
+            };
+            sample.fill(value);
+            self.phase = (self.phase + 1) % (half_period * 2);
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_api_version() -> c_uint {
+    libretro_sys::API_VERSION
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    CALLBACKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .environment = Some(callback);
+    let mut variables = [
+        Variable {
+            key: PALETTE_KEY.as_ptr(),
+            value: PALETTE_OPTION.as_ptr(),
+        },
+        Variable {
+            key: core::ptr::null(),
+            value: core::ptr::null(),
+        },
+    ];
+    unsafe {
+        callback(
+            ENVIRONMENT_SET_VARIABLES,
+            variables.as_mut_ptr() as *mut c_void,
+        )
+    };
+}
+
+// The palette the chip8_palette option says, the default without a frontend that has options
+fn palette_option() -> Palette {
+    let Some(environment) = callbacks().environment else {
+        return Palette::default();
+    };
+    let mut variable = Variable {
+        key: PALETTE_KEY.as_ptr(),
+        value: core::ptr::null(),
+    };
+    let found = unsafe {
+        environment(
+            ENVIRONMENT_GET_VARIABLE,
+            &mut variable as *mut Variable as *mut c_void,
+        )
+    };
+    if !found || variable.value.is_null() {
+        return Palette::default();
+    }
+    let name = unsafe { CStr::from_ptr(variable.value) };
+    name.to_str()
+        .ok()
+        .and_then(Palette::preset)
+        .unwrap_or_default()
+}
+
+// Whether the user changed an option since the last look
+fn options_changed() -> bool {
+    let Some(environment) = callbacks().environment else {
+        return false;
+    };
+    let mut updated = false;
+    let ok = unsafe {
+        environment(
+            ENVIRONMENT_GET_VARIABLE_UPDATE,
+            &mut updated as *mut bool as *mut c_void,
+        )
+    };
+    ok && updated
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    CALLBACKS.lock().unwrap_or_else(|e| e.into_inner()).video = Some(callback);
+}
+
+// Only the batch callback is used
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    CALLBACKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .audio_batch = Some(callback);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    CALLBACKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .input_poll = Some(callback);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    CALLBACKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .input_state = Some(callback);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_init() {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_deinit() {
+    *GAME.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// # Safety
+/// `info` must point to a writable retro_system_info.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    let info = unsafe { &mut *info };
+    info.library_name = c"chip8".as_ptr();
+    info.library_version = concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char;
+    info.valid_extensions = c"ch8".as_ptr();
+    info.need_fullpath = false;
+    info.block_extract = false;
+}
+
+/// # Safety
+/// `info` must point to a writable retro_system_av_info.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    let info = unsafe { &mut *info };
+    info.geometry.base_width = 64;
+    info.geometry.base_height = 32;
+    info.geometry.max_width = 64;
+    info.geometry.max_height = 32;
+    info.geometry.aspect_ratio = 2.0;
+    info.timing.fps = 60.0;
+    info.timing.sample_rate = SAMPLE_RATE as f64;
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+// Starts the loaded ROM over
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_reset() {
+    let mut game = GAME.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(reset) = game
+        .as_ref()
+        .and_then(|game| Game::new(&game.rom, game.palette))
+    {
+        *game = Some(reset);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    if let Some(input_poll) = callbacks.input_poll {
+        unsafe { input_poll() };
+    }
+    let palette = options_changed().then(palette_option);
+
+    with_game((), |game| {
+        if let Some(palette) = palette {
+            game.palette = palette;
+        }
+        if let Some(input_state) = callbacks.input_state {
+            game.read_input(input_state);
+        }
+        // a crashed ROM just stops on the bad instruction, the last screen stays up
+        let _ = game.lockstep.run_frame(&mut game.chip);
+        game.render();
+        game.fill_audio();
+
+        if let Some(video) = callbacks.video {
+            let frame = game.frame.as_ptr() as *const c_void;
+            unsafe { video(frame, 64, 32, 64 * 4) };
+        }
+        if let Some(audio_batch) = callbacks.audio_batch {
+            unsafe { audio_batch(game.audio.as_ptr(), SAMPLES_PER_FRAME) };
+        }
+    });
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_game(0, |game| game.chip.state_size())
+}
+
+/// # Safety
+/// `data` must point to `size` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    with_game(false, |game| {
+        let state = game.chip.save_state();
+        if size < state.len() {
+            return false;
+        }
+        let out = unsafe { core::slice::from_raw_parts_mut(data as *mut u8, state.len()) };
+        out.copy_from_slice(&state);
+        true
+    })
+}
+
+/// # Safety
+/// `data` must point to `size` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = unsafe { core::slice::from_raw_parts(data as *const u8, size) };
+    with_game(false, |game| game.chip.load_state(state).is_ok())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must be null or point to a retro_game_info with `size` readable bytes at `data`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    let Some(info) = (unsafe { game.as_ref() }) else {
+        return false;
+    };
+    if info.data.is_null() {
+        return false;
+    }
+    if let Some(environment) = callbacks().environment {
+        let mut format = PixelFormat::ARGB8888 as c_uint;
+        let accepted = unsafe {
+            environment(
+                ENVIRONMENT_SET_PIXEL_FORMAT,
+                &mut format as *mut c_uint as *mut c_void,
+            )
+        };
+        if !accepted {
+            return false;
+        }
+    }
+
+    let rom = unsafe { core::slice::from_raw_parts(info.data as *const u8, info.size) };
+    let loaded = Game::new(rom, palette_option());
+    let ok = loaded.is_some();
+    *GAME.lock().unwrap_or_else(|e| e.into_inner()) = loaded;
+    ok
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_unload_game() {
+    *GAME.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+// NTSC, 60 Hz
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_region() -> c_uint {
+    0
+}
+
+// The 4 KB of ram, for cheats and RetroAchievements
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != MEMORY_SYSTEM_RAM {
+        return core::ptr::null_mut();
+    }
+    with_game(core::ptr::null_mut(), |game| {
+        match game.chip.ram_range_mut(0, RAM_SIZE) {
+            Ok(ram) => ram.as_mut_ptr() as *mut c_void,
+            Err(_) => core::ptr::null_mut(),
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id == MEMORY_SYSTEM_RAM { RAM_SIZE } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static LIT_PIXELS: AtomicUsize = AtomicUsize::new(0);
+    static LOUD_SAMPLES: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn video(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+        assert_eq!((width, height, pitch), (64, 32, 256));
+        let pixels = unsafe { core::slice::from_raw_parts(data as *const u32, 64 * 32) };
+        // in the green of the chip8_palette option
+        let lit = pixels.iter().filter(|&&pixel| pixel == 0x33FF66).count();
+        LIT_PIXELS.store(lit, Ordering::SeqCst);
+    }
+
+    unsafe extern "C" fn audio_batch(data: *const i16, frames: usize) -> usize {
+        let samples = unsafe { core::slice::from_raw_parts(data, frames * 2) };
+        let loud = samples.iter().filter(|&&sample| sample != 0).count();
+        LOUD_SAMPLES.store(loud, Ordering::SeqCst);
+        frames
+    }
+
+    unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        if cmd == ENVIRONMENT_GET_VARIABLE {
+            let variable = unsafe { &mut *(data as *mut Variable) };
+            assert_eq!(unsafe { CStr::from_ptr(variable.key) }, PALETTE_KEY);
+            variable.value = c"green".as_ptr();
+        }
+        true
+    }
+
+    unsafe extern "C" fn input_state(
+        _port: c_uint,
+        _device: c_uint,
+        _index: c_uint,
+        _id: c_uint,
+    ) -> i16 {
+        0
+    }
+
+    #[test]
+    fn test_core_runs_and_serializes() {
+        // draws the font 0, sets ST to 0x20 and spins
+        let rom: [u8; 10] = [0xA0, 0x50, 0xD0, 0x05, 0x60, 0x20, 0xF0, 0x18, 0x12, 0x08];
+        let info = GameInfo {
+            path: core::ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: core::ptr::null(),
+        };
+        retro_set_environment(environment);
+        retro_set_video_refresh(video);
+        retro_set_audio_sample_batch(audio_batch);
+        retro_set_input_state(input_state);
+        assert!(unsafe { retro_load_game(&info) });
+
+        retro_run();
+        assert_eq!(LIT_PIXELS.load(Ordering::SeqCst), 14);
+        assert_eq!(LOUD_SAMPLES.load(Ordering::SeqCst), SAMPLES_PER_FRAME * 2);
+        assert_eq!(retro_get_memory_size(MEMORY_SYSTEM_RAM), 4096);
+        assert!(!retro_get_memory_data(MEMORY_SYSTEM_RAM).is_null());
+
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+        retro_reset();
+        retro_run();
+        // a reset keeps the option
+        assert_eq!(LIT_PIXELS.load(Ordering::SeqCst), 14);
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+        assert!(!unsafe { retro_unserialize(state.as_ptr() as *const c_void, 10) });
+
+        retro_unload_game();
+        assert!(retro_get_memory_data(MEMORY_SYSTEM_RAM).is_null());
+    }
+}
@@ -0,0 +1,297 @@
+use crate::chip8::{Chip8, Chip8Error};
+use alloc::vec::Vec;
+
+// Deterministic lockstep mode: nothing here looks at the wall clock.
+// Every frame runs a fixed number of instructions, ticks the timers once (virtual 60 Hz)
+// and applies the scripted input for that frame, so the same ROM + seed + script
+// always ends in the exact same machine state.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub key: usize,
+    pub pressed: bool,
+}
+
+// Key presses and releases tied to frame numbers instead of real time, and the frames
+// to take a screenshot at for whoever runs the script
+#[derive(Debug, Clone, Default)]
+pub struct InputScript {
+    events: Vec<InputEvent>,
+    screenshots: Vec<u64>,
+}
+
+impl InputScript {
+    pub fn new() -> Self {
+        InputScript {
+            events: Vec::new(),
+            screenshots: Vec::new(),
+        }
+    }
+
+    // The text format of --input-script, statements split by newlines or `;`:
+    //     frame 120: press 5; frame 180: release 5
+    //     frame 300: tap A   # down for one frame
+    //     frame 600: screenshot
+    // Keys are hex digits, `#` starts a comment. Returns the number of the first bad line
+    pub fn parse(text: &str) -> Result<Self, usize> {
+        let mut script = InputScript::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            for statement in line.split(';').map(str::trim) {
+                if !statement.is_empty() {
+                    script.parse_statement(statement).ok_or(number + 1)?;
+                }
+            }
+        }
+        Ok(script)
+    }
+
+    fn parse_statement(&mut self, statement: &str) -> Option<()> {
+        let (frame, action) = statement.strip_prefix("frame")?.split_once(':')?;
+        let frame: u64 = frame.trim().parse().ok()?;
+        let words: Vec<&str> = action.split_whitespace().collect();
+        let key = |digit: &str| {
+            let key = usize::from_str_radix(digit, 16).ok()?;
+            (digit.len() == 1).then_some(key)
+        };
+        match words.as_slice() {
+            ["press", digit] => self.press(frame, key(digit)?).ok(),
+            ["release", digit] => self.release(frame, key(digit)?).ok(),
+            ["tap", digit] => {
+                self.press(frame, key(digit)?).ok()?;
+                self.release(frame + 1, key(digit)?).ok()
+            }
+            ["screenshot"] => {
+                self.screenshot(frame);
+                Some(())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn press(&mut self, frame: u64, key: usize) -> Result<(), Chip8Error> {
+        self.push(InputEvent {
+            frame,
+            key,
+            pressed: true,
+        })
+    }
+
+    pub fn release(&mut self, frame: u64, key: usize) -> Result<(), Chip8Error> {
+        self.push(InputEvent {
+            frame,
+            key,
+            pressed: false,
+        })
+    }
+
+    pub fn push(&mut self, event: InputEvent) -> Result<(), Chip8Error> {
+        if event.key >= 16 {
+            return Err(Chip8Error::InvalidKey);
+        }
+        // keeps events ordered by frame, events of the same frame keep insertion order
+        let index = self.events.partition_point(|e| e.frame <= event.frame);
+        self.events.insert(index, event);
+        Ok(())
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    // A picture of the screen once `frame` frames ran
+    pub fn screenshot(&mut self, frame: u64) {
+        let index = self.screenshots.partition_point(|&f| f <= frame);
+        if index == 0 || self.screenshots[index - 1] != frame {
+            self.screenshots.insert(index, frame);
+        }
+    }
+
+    pub fn screenshots(&self) -> &[u64] {
+        &self.screenshots
+    }
+}
+
+pub struct Lockstep {
+    // instructions per frame, fixed for the whole run
+    ipf: u32,
+    // virtual frame counter, the only notion of time in this mode
+    frame: u64,
+    script: InputScript,
+    // index of the next script event to apply
+    cursor: usize,
+}
+
+impl Lockstep {
+    pub fn new(ipf: u32) -> Self {
+        Self::with_script(ipf, InputScript::new())
+    }
+
+    pub fn with_script(ipf: u32, script: InputScript) -> Self {
+        Lockstep {
+            ipf,
+            frame: 0,
+            script,
+            cursor: 0,
+        }
+    }
+
+    pub fn ipf(&self) -> u32 {
+        self.ipf
+    }
+
+    // Takes effect from the next frame, for speed changes while playing
+    pub fn set_ipf(&mut self, ipf: u32) {
+        self.ipf = ipf;
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Runs one virtual frame: input, ipf instructions, then one timer tick
+    pub fn run_frame(&mut self, chip: &mut Chip8) -> Result<(), Chip8Error> {
+        while let Some(event) = self.script.events.get(self.cursor) {
+            if event.frame > self.frame {
+                break;
+            }
+            chip.keyboard.set_key(event.key, event.pressed)?;
+            self.cursor += 1;
+        }
+
+        chip.run_frame(self.ipf)?;
+        self.frame += 1;
+
+        Ok(())
+    }
+
+    // Whether the script wants a screenshot of the frame that just ran
+    pub fn screenshot_due(&self) -> bool {
+        self.script.screenshots.binary_search(&self.frame).is_ok()
+    }
+
+    pub fn run_frames(&mut self, chip: &mut Chip8, frames: u64) -> Result<(), Chip8Error> {
+        for _ in 0..frames {
+            self.run_frame(chip)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    // Draws random sprites forever and waits on key 5 once, exercising RNG, timers and input
+    const ROM: [u8; 22] = [
+        0xF5, 0x0A, // 200: V5 = wait key
+        0x60, 0x0F, // 202: V0 = 0x0F
+        0xF0, 0x15, // 204: DT = V0
+        0xC1, 0x3F, // 206: V1 = rand & 0x3F
+        0xC2, 0x1F, // 208: V2 = rand & 0x1F
+        0xC3, 0x0F, // 20A: V3 = rand & 0x0F
+        0xF3, 0x29, // 20C: I = font(V3)
+        0xD1, 0x25, // 20E: draw V1, V2, 5
+        0xF4, 0x07, // 210: V4 = DT
+        0x12, 0x06, // 212: jump 206
+        0x00, 0x00,
+    ];
+
+    fn run(seed: u64) -> Chip8 {
+        let mut chip = Chip8::new(&ROM).unwrap();
+        chip.set_seed(seed);
+        let mut script = InputScript::new();
+        script.press(3, 0x5).unwrap();
+        script.release(4, 0x5).unwrap();
+        let mut lockstep = Lockstep::with_script(10, script);
+        lockstep.run_frames(&mut chip, 120).unwrap();
+        chip
+    }
+
+    fn same_state(a: &Chip8, b: &Chip8) -> bool {
+        a.registers() == b.registers()
+            && (0..2048).all(|i| a.display.get_pixel(i).unwrap() == b.display.get_pixel(i).unwrap())
+            && a.get_pc() == b.get_pc()
+            && a.get_i() == b.get_i()
+            && a.get_dt() == b.get_dt()
+    }
+
+    #[test]
+    fn test_same_seed_same_state() {
+        let a = run(7);
+        let b = run(7);
+        assert!(same_state(&a, &b));
+        assert_eq!(a.get_v(5).unwrap(), 0x5);
+    }
+
+    #[test]
+    fn test_different_seed_different_state() {
+        let a = run(7);
+        let b = run(8);
+        assert!(!same_state(&a, &b));
+    }
+
+    #[test]
+    fn test_script_ordering() {
+        let mut script = InputScript::new();
+        script.press(10, 1).unwrap();
+        script.press(2, 3).unwrap();
+        script.release(10, 1).unwrap();
+        let frames: Vec<u64> = script.events().iter().map(|e| e.frame).collect();
+        assert_eq!(frames, vec![2, 10, 10]);
+        assert!(script.events()[2].key == 1 && !script.events()[2].pressed);
+        assert!(script.press(0, 16).is_err());
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let script = InputScript::parse(
+            "frame 120: press 5; frame 180: release 5\n\
+             # a comment\n\
+             frame 30: tap a  # before the others\n\
+             frame 600: screenshot; frame 2: screenshot",
+        )
+        .unwrap();
+        let events: Vec<_> = script
+            .events()
+            .iter()
+            .map(|e| (e.frame, e.key, e.pressed))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (30, 0xA, true),
+                (31, 0xA, false),
+                (120, 5, true),
+                (180, 5, false)
+            ]
+        );
+        assert_eq!(script.screenshots(), [2, 600]);
+
+        assert_eq!(
+            InputScript::parse("frame 1: press 5\nframe 2: press G").err(),
+            Some(2)
+        );
+        assert_eq!(InputScript::parse("frame 1 press 5").err(), Some(1));
+        assert_eq!(InputScript::parse("frame 1: press 10").err(), Some(1));
+
+        let mut chip = Chip8::new(&[0x12, 0x00]).unwrap();
+        let mut lockstep = Lockstep::with_script(1, script);
+        lockstep.run_frames(&mut chip, 2).unwrap();
+        assert!(lockstep.screenshot_due());
+        lockstep.run_frame(&mut chip).unwrap();
+        assert!(!lockstep.screenshot_due());
+    }
+
+    #[test]
+    fn test_timers_tick_once_per_frame() {
+        let mut chip = Chip8::new(&[0x12, 0x00]).unwrap(); // jump to self
+        chip.set_dt(30);
+        let mut lockstep = Lockstep::new(100);
+        lockstep.run_frames(&mut chip, 10).unwrap();
+        assert_eq!(chip.get_dt(), &20);
+        assert_eq!(lockstep.frame(), 10);
+    }
+}
@@ -1,49 +1,2936 @@
+use chip8::archive::{self, Archive};
+use chip8::asm;
+use chip8::attract::{self, Attract, Change};
+#[cfg(feature = "audio")]
+use chip8::audio::Audio;
+use chip8::capture;
 use chip8::chip8::{Chip8, Chip8Error};
-use std::{
-    env,
-    time::{Duration, Instant},
+#[cfg(feature = "clipboard")]
+use chip8::clipboard::Clipboard;
+use chip8::clock::{self, RealClock, Scheduler};
+use chip8::compat;
+use chip8::config::{self, Config, DataKind, Overrides};
+use chip8::cpu::{Platform, Quirks};
+use chip8::debugger::{
+    self, Access, Break, Debugger, Stop, Suspicious, Target, WatchHit, Watchpoint,
 };
+#[cfg(feature = "egui")]
+use chip8::desktop::{self, DesktopApp};
+use chip8::display::{Aspect, Display, Palette};
+use chip8::flash::{self, FlashLimiter};
+use chip8::frames::{self, DumpError};
+use chip8::golden::{self, Verdict};
+#[cfg(feature = "gpio")]
+use chip8::gpio::GpioKeypad;
+use chip8::halt::{Halt, HaltDetector};
+use chip8::hexedit;
+use chip8::icon::{self, Icon, WindowGeometry};
+use chip8::keyboard::{HostKey, Keyboard, Keymap};
+#[cfg(feature = "led")]
+use chip8::led::{LedMatrix, LedProtocol};
+use chip8::lockstep::{InputScript, Lockstep};
+use chip8::memory::{self, Patch};
+#[cfg(feature = "midi")]
+use chip8::midi::{self, MidiKeys};
+use chip8::netplay::{self, Netplay, NetplayError};
+use chip8::osd::{FrameTimes, Osd};
+use chip8::panels::Panels;
+use chip8::plugin::{Event, Plugin, PluginError, Plugins};
+#[cfg(feature = "remote")]
+use chip8::remote::RemoteServer;
+#[cfg(feature = "gui")]
+use chip8::screen::Screen;
+#[cfg(feature = "sdl2")]
+use chip8::sdl::SdlScreen;
+use chip8::selftest;
+#[cfg(feature = "spectate")]
+use chip8::spectate::SpectatorServer;
+use chip8::stats::Stats;
+#[cfg(feature = "term")]
+use chip8::terminal::Terminal;
+use chip8::testrunner::{self, Condition, Outcome};
+use chip8::trace;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-fn main() -> Result<(), Chip8Error> {
+#[derive(Parser)]
+#[command(
+    about = "Chip-8 by Hernani Samuel Diniz",
+    subcommand_negates_reqs = true
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// .ch8 file to run [default: picked in a file dialog]
+    rom: Option<String>,
+
+    /// Deterministic mode: fixed instructions per frame, virtual 60 Hz timers, seeded RNG
+    #[arg(long)]
+    lockstep: bool,
+
+    /// Seed for the random generator (Cxnn), makes runs repeatable
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Instructions per frame (per window update in realtime mode) [default: speed in chip8.toml]
+    #[arg(long)]
+    ipf: Option<u32>,
+
+    /// Instructions per second, the same as --ipf with a 60th of it (rounded), like 600 for --ipf 10
+    #[arg(long, conflicts_with = "ipf")]
+    ips: Option<u32>,
+
+    /// Quirks preset, vip for the original interpreter or modern (schip) for later ones
+    /// [default: quirks in chip8.toml]
+    #[arg(long, value_name = "PRESET", value_parser = parse_quirks)]
+    quirks: Option<Quirks>,
+
+    /// amber, green, paper, gameboy, or colors of lit and unlit pixels like #33FF66,#000000,
+    /// then XO-CHIP's second plane and both planes [default: display.palette in chip8.toml]
+    #[arg(long, value_name = "COLORS", value_parser = parse_palette)]
+    palette: Option<Palette>,
+
+    /// Keys playing the keypad: qwerty, azerty, colemak, mirror or the 16 keys of 0 to F
+    /// like x123qweasdzc4rfv [default: keymap in chip8.toml]
+    #[arg(long, value_name = "LAYOUT", value_parser = parse_keymap)]
+    keymap: Option<Keymap>,
+
+    /// Window pixels per Chip-8 pixel [default: display.scale in chip8.toml]
+    #[arg(long)]
+    scale: Option<usize>,
+
+    /// Shape of a pixel as width:height, 2:1 stretches the picture [default: display.aspect in chip8.toml]
+    #[arg(long, value_parser = parse_aspect)]
+    aspect: Option<Aspect>,
+
+    /// Play without sound
+    #[arg(long)]
+    mute: bool,
+
+    /// Instruction set, chip8, schip for SUPER-CHIP or xochip for XO-CHIP games [default: platform in chip8.toml]
+    #[arg(long, value_parser = parse_platform)]
+    platform: Option<Platform>,
+
+    /// Profiles from chip8.toml to play with, in order, like vip or schip-modern,green
+    #[arg(long, value_name = "NAME", value_delimiter = ',')]
+    profile: Vec<String>,
+
+    /// Bytes to change after loading, like 0x2A4=0x00,0x2A5=0xEE, after the patches in chip8.toml
+    #[arg(long, value_name = "ADDR=BYTE", value_delimiter = ',', value_parser = parse_patch)]
+    patch: Vec<Patch>,
+
+    /// Replace all 4 KiB of RAM after loading with this image, like one from `chip8 dump-ram`
+    #[arg(long, value_name = "FILE")]
+    ram_image: Option<PathBuf>,
+
+    /// With --ram-image, where the program goes on from [default: 0x200]
+    #[arg(long, value_name = "ADDR", value_parser = parse_address, requires = "ram_image")]
+    start_pc: Option<u16>,
+
+    /// With --ram-image, the I register to start with [default: 0]
+    #[arg(long, value_name = "ADDR", value_parser = parse_address, requires = "ram_image")]
+    start_i: Option<u16>,
+
+    /// Play keys from a script like `frame 120: press 5; frame 600: screenshot` instead of
+    /// the keyboard, in lockstep frames. Screenshots go to screenshot_NNNN.png
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["host", "join", "dump_frames"])]
+    input_script: Option<PathBuf>,
+
+    /// Attract mode for booths: the ROMs in DIR play by themselves one after the other until
+    /// a key hands the one on screen to the player. game.txt is the input script of game.ch8
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["rom", "host", "join", "input_script", "dump_frames"])]
+    attract: Option<PathBuf>,
+
+    /// With --attract, the seconds each ROM plays by itself
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 30,
+        requires = "attract"
+    )]
+    attract_seconds: u64,
+
+    /// With --attract, the seconds without a key before the demos go on, 0 never
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 60,
+        requires = "attract"
+    )]
+    attract_idle: u64,
+
+    /// Settings file to use instead of chip8.toml in the config directory
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Where to play, each frontend needs its cargo feature [default: the first one built in]
+    #[arg(long, value_enum)]
+    frontend: Option<FrontendKind>,
+
+    /// Print key presses, buzzer changes and crashes to stderr
+    #[arg(long)]
+    log_events: bool,
+
+    /// Write a CSV line per frame to FILE: frame time, instructions, draws and sound, for
+    /// looking into slowdowns
+    #[arg(long, value_name = "FILE")]
+    stats: Option<PathBuf>,
+
+    /// Let the ROM print to stderr: 00FA prints the string at I and FxFA prints Vx
+    #[arg(long)]
+    debug_port: bool,
+
+    /// Write every instruction to FILE with its address, opcode, mnemonic and the registers
+    /// it changed, for diffing a run against another emulator's log
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// On a crash, save the screen, the state and the last instructions to the captures folder
+    #[arg(long)]
+    capture: bool,
+
+    /// With --capture, how many of the last instructions to keep
+    #[arg(long, value_name = "N", default_value_t = capture::DEFAULT_LINES, requires = "capture")]
+    capture_lines: usize,
+
+    /// What to do when the program halts (jumps to itself): say so, also pause, or nothing
+    #[arg(long, value_enum, value_name = "ACTION", default_value_t = OnHalt::Message)]
+    on_halt: OnHalt,
+
+    /// Also count as halted N frames without drawing, sound or key changes, 0 never does
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    halt_idle: u64,
+
+    /// Host a two player game on this address, like 0.0.0.0:7700, and wait for the other player
+    #[arg(long, value_name = "ADDR", conflicts_with = "join")]
+    host: Option<String>,
+
+    /// Join a two player game hosted on this address, the ROM has to be the same file
+    #[arg(long, value_name = "ADDR")]
+    join: Option<String>,
+
+    /// Frames of input delay when hosting, more hides more network lag
+    #[arg(long, default_value_t = netplay::DEFAULT_DELAY)]
+    input_delay: u8,
+
+    /// Accept JSON commands over HTTP on this address, like 127.0.0.1:8080
+    #[cfg(feature = "remote")]
+    #[arg(long, value_name = "ADDR")]
+    remote: Option<String>,
+
+    /// Web pages allowed to send --remote commands, like http://localhost:3000
+    #[cfg(feature = "remote")]
+    #[arg(
+        long,
+        value_name = "ORIGIN",
+        value_delimiter = ',',
+        requires = "remote"
+    )]
+    remote_origin: Vec<String>,
+
+    /// Press keys with the notes of this MIDI input port, a part of its name or its number
+    #[cfg(feature = "midi")]
+    #[arg(long, value_name = "PORT")]
+    midi: Option<String>,
+
+    /// With --midi, the note that is key 0, the next 15 are keys 1 to F (60 is middle C)
+    #[cfg(feature = "midi")]
+    #[arg(long, value_name = "NOTE", default_value_t = midi::DEFAULT_BASE, requires = "midi")]
+    midi_base: u8,
+
+    /// Read a 4x4 matrix keypad on a Raspberry Pi, the BCM numbers of its row pins, top first
+    #[cfg(feature = "gpio")]
+    #[arg(
+        long,
+        value_name = "PINS",
+        value_delimiter = ',',
+        requires = "gpio_cols"
+    )]
+    gpio_rows: Option<Vec<u8>>,
+
+    /// With --gpio-rows, the BCM numbers of the column pins, left first
+    #[cfg(feature = "gpio")]
+    #[arg(
+        long,
+        value_name = "PINS",
+        value_delimiter = ',',
+        requires = "gpio_rows"
+    )]
+    gpio_cols: Option<Vec<u8>>,
+
+    /// Stream the session live to spectators, open http://ADDR in a browser to watch
+    #[cfg(feature = "spectate")]
+    #[arg(long, value_name = "ADDR")]
+    spectate: Option<String>,
+
+    /// Show the screen on an LED wall behind this serial port or spidev device
+    #[cfg(feature = "led")]
+    #[arg(long, value_name = "DEVICE")]
+    led: Option<PathBuf>,
+
+    /// With --led, what the device gets: packed, rgb (HUB75 bridges) or max7219
+    #[cfg(feature = "led")]
+    #[arg(long, value_name = "PROTOCOL", default_value = "packed", value_parser = parse_led_protocol, requires = "led")]
+    led_protocol: LedProtocol,
+
+    /// Serve profiling scopes on this address for puffin_viewer, like 127.0.0.1:8585
+    #[cfg(feature = "profiling")]
+    #[arg(long, value_name = "ADDR")]
+    profiler: Option<String>,
+
+    /// Run N frames without a window, write them as PNG files and exit (lockstep timing)
+    #[arg(long, value_name = "N")]
+    dump_frames: Option<u64>,
+
+    /// With --dump-frames, only write every K-th frame
+    #[arg(long, value_name = "K", default_value_t = 1, requires = "dump_frames")]
+    every: u64,
+
+    /// With --dump-frames, the directory for the PNG files
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "frames",
+        requires = "dump_frames"
+    )]
+    dump_dir: PathBuf,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OnHalt {
+    // nothing, the game stays on screen
+    Ignore,
+    // PROGRAM HALTED on the OSD
+    Message,
+    // the message, and pause like the pause hotkey
+    Pause,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FrontendKind {
+    Gui,
+    Sdl2,
+    Term,
+    Egui,
+}
+
+impl FrontendKind {
+    fn feature(self) -> &'static str {
+        match self {
+            FrontendKind::Gui => "gui",
+            FrontendKind::Sdl2 => "sdl2",
+            FrontendKind::Term => "term",
+            FrontendKind::Egui => "egui",
+        }
+    }
+
+    fn built_in() -> Self {
+        if cfg!(feature = "egui") {
+            FrontendKind::Egui
+        } else if cfg!(feature = "gui") {
+            FrontendKind::Gui
+        } else if cfg!(feature = "sdl2") {
+            FrontendKind::Sdl2
+        } else {
+            FrontendKind::Term
+        }
+    }
+}
+
+// What the main loop needs from a frontend
+trait Frontend {
+    fn is_open(&self) -> bool;
+    fn read_keys(&mut self, keyboard: &mut Keyboard);
+    fn render(&mut self, display: &Display);
+    fn update(&mut self);
+    fn set_palette(&mut self, palette: Palette);
+    fn set_keymap(&mut self, keymap: &Keymap);
+    fn is_key_down(&self, key: HostKey) -> bool;
+    fn set_message(&mut self, message: Option<&str>);
+    fn set_keypad(&mut self, pressed: Option<[bool; 16]>);
+    fn set_frame_times(&mut self, times: Option<&[f32]>);
+    fn set_title(&mut self, title: &str);
+    fn set_icon(&mut self, icon: &Icon);
+    // None when there's no window of our own to remember
+    fn geometry(&self) -> Option<WindowGeometry>;
+    // false when the frontend can't
+    fn toggle_fullscreen(&mut self) -> bool;
+    // A second window with the debugger panels, Err when the frontend can't have one
+    fn open_debug_window(&mut self) -> Result<(), String>;
+    fn close_debug_window(&mut self);
+    // Every poll while it's open, false once it was closed
+    fn update_debug(&mut self, panels: &Panels) -> bool;
+}
+
+// unused in a build without frontends, only the headless subcommands work there
+#[allow(unused_macros)]
+macro_rules! impl_frontend {
+    ($type:ty) => {
+        impl Frontend for $type {
+            fn is_open(&self) -> bool {
+                <$type>::is_open(self)
+            }
+            fn read_keys(&mut self, keyboard: &mut Keyboard) {
+                <$type>::read_keys(self, keyboard)
+            }
+            fn render(&mut self, display: &Display) {
+                <$type>::render(self, display)
+            }
+            fn update(&mut self) {
+                <$type>::update(self)
+            }
+            fn set_palette(&mut self, palette: Palette) {
+                <$type>::set_palette(self, palette)
+            }
+            fn set_keymap(&mut self, keymap: &Keymap) {
+                <$type>::set_keymap(self, keymap)
+            }
+            fn is_key_down(&self, key: HostKey) -> bool {
+                <$type>::is_key_down(self, key)
+            }
+            fn set_message(&mut self, message: Option<&str>) {
+                <$type>::set_message(self, message)
+            }
+            fn set_keypad(&mut self, pressed: Option<[bool; 16]>) {
+                <$type>::set_keypad(self, pressed)
+            }
+            fn set_frame_times(&mut self, times: Option<&[f32]>) {
+                <$type>::set_frame_times(self, times)
+            }
+            fn set_title(&mut self, title: &str) {
+                <$type>::set_title(self, title)
+            }
+            fn set_icon(&mut self, icon: &Icon) {
+                <$type>::set_icon(self, icon)
+            }
+            fn geometry(&self) -> Option<WindowGeometry> {
+                <$type>::geometry(self)
+            }
+            fn toggle_fullscreen(&mut self) -> bool {
+                <$type>::toggle_fullscreen(self)
+            }
+            fn open_debug_window(&mut self) -> Result<(), String> {
+                <$type>::open_debug_window(self)
+            }
+            fn close_debug_window(&mut self) {
+                <$type>::close_debug_window(self)
+            }
+            fn update_debug(&mut self, panels: &Panels) -> bool {
+                <$type>::update_debug(self, panels)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "gui")]
+impl_frontend!(Screen);
+#[cfg(feature = "sdl2")]
+impl_frontend!(SdlScreen);
+#[cfg(feature = "term")]
+impl_frontend!(Terminal);
+
+#[allow(unused_variables)]
+fn open_frontend(
+    kind: FrontendKind,
+    scale: usize,
+    aspect: Aspect,
+    geometry: &WindowGeometry,
+) -> Result<Box<dyn Frontend>, String> {
+    #[cfg(not(any(feature = "gui", feature = "sdl2")))]
+    let _ = geometry;
+    match kind {
+        #[cfg(feature = "gui")]
+        FrontendKind::Gui => Screen::with_geometry(icon::DEFAULT_TITLE, scale, aspect, geometry)
+            .map(|screen| Box::new(screen) as Box<dyn Frontend>),
+        #[cfg(feature = "sdl2")]
+        FrontendKind::Sdl2 => SdlScreen::with_geometry(scale as u32, aspect, geometry)
+            .map(|sdl| Box::new(sdl) as Box<dyn Frontend>),
+        #[cfg(feature = "term")]
+        FrontendKind::Term => Terminal::new()
+            .map(|term| Box::new(term) as Box<dyn Frontend>)
+            .map_err(|e| e.to_string()),
+        #[allow(unreachable_patterns)]
+        kind => Err(format!(
+            "chip8 was built without the `{0}` feature, rebuild with `--features {0}`",
+            kind.feature()
+        )),
+    }
+}
+
+// Where the window was last time, see [window] in chip8.toml
+fn open_screen(
+    kind: FrontendKind,
+    scale: usize,
+    aspect: Aspect,
+    saved: &Config,
+) -> Result<Box<dyn Frontend>, AppError> {
+    open_frontend(kind, scale, aspect, &saved.window.geometry()).map_err(|error| {
+        AppError::Setup(format!(
+            "can't open the {} frontend: {}. Without a display, `chip8 run --headless` runs \
+             the ROM anyway and `--dump-frames` shows what it draws",
+            kind.feature(),
+            error
+        ))
+    })
+}
+
+// display.icon, or the built-in one when there's none or it can't be read
+fn load_icon(config: &Config) -> Icon {
+    let icon = config.display.icon.as_ref().and_then(|path| {
+        Icon::load(path)
+            .inspect_err(|error| {
+                eprintln!("WARNING: can't read the icon {}: {}", path.display(), error)
+            })
+            .ok()
+    });
+    icon.unwrap_or_else(|| Icon::built_in(config.display.palette))
+}
+
+// Without the clipboard feature the copy_frame hotkey only says so
+#[cfg(not(feature = "clipboard"))]
+struct Clipboard;
+
+#[cfg(not(feature = "clipboard"))]
+impl Clipboard {
+    fn new() -> Result<Self, String> {
+        Err("chip8 was built without the `clipboard` feature".into())
+    }
+
+    fn copy_frame(&mut self, _display: &Display, _palette: Palette) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Without the audio feature games just play silent
+#[cfg(not(feature = "audio"))]
+struct Audio;
+
+#[cfg(not(feature = "audio"))]
+impl Audio {
+    fn new() -> Result<Self, String> {
+        Ok(Audio)
+    }
+
+    fn silent() -> Self {
+        Audio
+    }
+
+    fn set_volume(&mut self, _volume: f32) {}
+
+    fn set_tone(&mut self, _tone: f32) {}
+
+    fn set_pitch(&mut self, _pitch: chip8::chip8::Pitch, _steps_per_octave: f32) {}
+
+    fn set_timbre(&mut self, _timbre: chip8::chip8::Timbre, _ripple: bool) {}
+
+    fn update(&self, _sound_on: bool) {}
+
+    fn update_from(&mut self, _chip: &Chip8) {}
+}
+
+// --remote, polled by the run loops between frames
+#[cfg(feature = "remote")]
+struct Remote(Option<RemoteServer>);
+
+#[cfg(feature = "remote")]
+impl Remote {
+    fn open(args: &Args, ipf: u32) -> Result<Self, AppError> {
+        let Some(addr) = &args.remote else {
+            return Ok(Remote(None));
+        };
+        let server = RemoteServer::bind(addr.as_str(), ipf, args.remote_origin.clone())
+            .map_err(|error| listen(addr, error))?;
+        eprintln!("Remote control on http://{}", server.local_addr());
+        Ok(Remote(Some(server)))
+    }
+
+    fn poll(&mut self, chip: &mut Chip8) {
+        if let Some(server) = &mut self.0 {
+            server.poll(chip);
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.0.as_ref().is_some_and(RemoteServer::is_paused)
+    }
+}
+
+#[cfg(not(feature = "remote"))]
+struct Remote;
+
+#[cfg(not(feature = "remote"))]
+impl Remote {
+    fn open(_args: &Args, _ipf: u32) -> Result<Self, AppError> {
+        Ok(Remote)
+    }
+
+    fn poll(&mut self, _chip: &mut Chip8) {}
+
+    fn is_paused(&self) -> bool {
+        false
+    }
+}
+
+// --midi, polled after the frontend read its keys
+#[cfg(feature = "midi")]
+struct Midi(Option<MidiKeys>);
+
+#[cfg(feature = "midi")]
+impl Midi {
+    fn open(args: &Args) -> Result<Self, AppError> {
+        let Some(port) = &args.midi else {
+            return Ok(Midi::none());
+        };
+        let keys = MidiKeys::open(port, args.midi_base)
+            .map_err(|error| AppError::Setup(format!("can't open MIDI: {}", error)))?;
+        eprintln!("MIDI notes from {}", keys.port());
+        Ok(Midi(Some(keys)))
+    }
+
+    fn none() -> Self {
+        Midi(None)
+    }
+
+    fn poll(&mut self, keyboard: &mut Keyboard) {
+        if let Some(keys) = &mut self.0 {
+            keys.poll(keyboard);
+        }
+    }
+}
+
+#[cfg(not(feature = "midi"))]
+struct Midi;
+
+#[cfg(not(feature = "midi"))]
+impl Midi {
+    fn open(_args: &Args) -> Result<Self, AppError> {
+        Ok(Midi)
+    }
+
+    fn none() -> Self {
+        Midi
+    }
+
+    fn poll(&mut self, _keyboard: &mut Keyboard) {}
+}
+
+// --gpio-rows and --gpio-cols, polled like Midi
+#[cfg(feature = "gpio")]
+struct Gpio(Option<GpioKeypad>);
+
+#[cfg(feature = "gpio")]
+impl Gpio {
+    fn open(args: &Args) -> Result<Self, AppError> {
+        let (Some(rows), Some(columns)) = (&args.gpio_rows, &args.gpio_cols) else {
+            return Ok(Gpio::none());
+        };
+        let four = |pins: &[u8]| {
+            <[u8; 4]>::try_from(pins).map_err(|_| {
+                AppError::Setup(format!(
+                    "a keypad has 4 rows and 4 columns, not {}",
+                    pins.len()
+                ))
+            })
+        };
+        let keypad = GpioKeypad::open(four(rows)?, four(columns)?)
+            .map_err(|error| AppError::Setup(format!("can't open the GPIO keypad: {}", error)))?;
+        Ok(Gpio(Some(keypad)))
+    }
+
+    fn none() -> Self {
+        Gpio(None)
+    }
+
+    fn poll(&mut self, keyboard: &mut Keyboard) {
+        if let Some(keypad) = &mut self.0 {
+            keypad.poll(keyboard);
+        }
+    }
+}
+
+#[cfg(not(feature = "gpio"))]
+struct Gpio;
+
+#[cfg(not(feature = "gpio"))]
+impl Gpio {
+    fn open(_args: &Args) -> Result<Self, AppError> {
+        Ok(Gpio)
+    }
+
+    fn none() -> Self {
+        Gpio
+    }
+
+    fn poll(&mut self, _keyboard: &mut Keyboard) {}
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the known test ROMs found in DIR headlessly and compare their final screens
+    Check { dir: PathBuf },
+
+    /// Run every .ch8 in DIR for a few seconds and write a compatibility table
+    Compat {
+        dir: PathBuf,
+
+        /// Emulated seconds per ROM (60 frames each)
+        #[arg(long, default_value_t = 5)]
+        seconds: u64,
+
+        #[arg(long, value_enum, default_value_t = ReportFormat::Markdown)]
+        format: ReportFormat,
+
+        /// Output file, prints to the terminal when not given
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+
+    /// Check the emulator core without a window or sound device
+    Selftest,
+
+    /// Record or verify golden frame hashes for every ROM in a directory
+    Golden {
+        #[command(subcommand)]
+        action: GoldenAction,
+    },
+
+    /// Print where the settings file and the files chip8 writes are
+    Paths,
+
+    /// List the ROMs in DIR (paths.roms without it) with their title, authors, year and
+    /// description from the programs.json of the chip8Archive, when there's one
+    List { dir: Option<PathBuf> },
+
+    /// Run a ROM for a bounded time and exit, for CI jobs. Exits with 1 if the last frame
+    /// doesn't have the expected hash, 2 if the ROM crashes and 3 if it halts with
+    /// --exit-on-halt
+    Run {
+        rom: PathBuf,
+
+        /// Without a window or audio device, the only way `run` works for now
+        #[arg(long, required = true)]
+        headless: bool,
+
+        /// Frames to run (60 per emulated second)
+        #[arg(long, alias = "timeout-frames", default_value_t = 600)]
+        max_frames: u64,
+
+        /// Stop earlier if this much real time went by
+        #[arg(long, value_name = "SECONDS")]
+        max_seconds: Option<f64>,
+
+        /// Hash of the last frame (as printed by a previous run) the run must end on
+        #[arg(long, value_name = "HASH", value_parser = parse_hash)]
+        exit_code_from_frame_hash: Option<u64>,
+
+        /// Stop as soon as the program halts (see --halt-idle) and exit with 3
+        #[arg(long)]
+        exit_on_halt: bool,
+
+        /// Stop once this holds after a frame, like mem[0x3FF]==0x55 or vF!=0. Exits with 1
+        /// if it never does before --max-frames (or --timeout-frames)
+        #[arg(long, value_name = "CONDITION", value_parser = parse_condition)]
+        exit_when: Option<Condition>,
+    },
+
+    /// Print a ROM as hex, change bytes of it or compare it with another file. Offsets are in
+    /// the file, 0x200 less than the address in RAM
+    Hexedit {
+        rom: PathBuf,
+
+        /// Bytes to change, like 0x10=0xA2,0x11=0x2A
+        #[arg(long, value_name = "OFFSET=BYTE", value_delimiter = ',', value_parser = parse_edit, requires = "out")]
+        set: Vec<Patch>,
+
+        /// Where to write the changed ROM, can be the same file
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+
+        /// List the bytes that differ from this file (after --set), exits with 1 if any
+        #[arg(long, value_name = "FILE")]
+        diff: Option<PathBuf>,
+    },
+
+    /// Assemble SOURCE into a ROM: Cowgod's mnemonics like the debug window's disassembly,
+    /// labels, db and dw
+    Asm {
+        source: PathBuf,
+
+        /// Where to write the ROM, SOURCE with .ch8 by default
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+    },
+
+    /// Go through a ROM one instruction at a time, forwards and backwards. `help` inside
+    /// lists the commands
+    Debug { rom: PathBuf },
+
+    /// Print the registers, stack, timers and used RAM of a ROM after some frames, or of a
+    /// state file like the state.bin of a --capture, for bug reports and text diffs
+    DumpState {
+        /// A .ch8 ROM or a saved state
+        file: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = StateFormat::Json)]
+        format: StateFormat,
+
+        /// Lockstep frames to run a ROM for first
+        #[arg(long, default_value_t = 0)]
+        frames: u64,
+    },
+
+    /// Write the 4 KiB of RAM of a ROM after some frames, or of a state file, as a raw image
+    /// for other tools. --ram-image and `load-ram` in `chip8 debug` read it back
+    DumpRam {
+        /// A .ch8 ROM or a saved state
+        file: PathBuf,
+
+        /// Where to write the image
+        #[arg(long, value_name = "FILE")]
+        out: PathBuf,
+
+        /// Lockstep frames to run a ROM for first
+        #[arg(long, default_value_t = 0)]
+        frames: u64,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StateFormat {
+    Json,
+    Toml,
+}
+
+fn parse_patch(text: &str) -> Result<Patch, String> {
+    Patch::parse(text).ok_or_else(|| format!("`{}` isn't ADDR=BYTE inside the 4 KiB of RAM", text))
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    memory::parse_address(text).ok_or_else(|| format!("`{}` isn't an address in RAM", text))
+}
+
+fn parse_edit(text: &str) -> Result<Patch, String> {
+    Patch::parse(text).ok_or_else(|| format!("`{}` isn't OFFSET=BYTE", text))
+}
+
+fn parse_aspect(text: &str) -> Result<Aspect, String> {
+    Aspect::parse(text).ok_or_else(|| format!("`{}` isn't like 1:1 or 2:1", text))
+}
+
+fn parse_quirks(text: &str) -> Result<Quirks, String> {
+    Quirks::preset(text).ok_or_else(|| format!("`{}` isn't vip or modern", text))
+}
+
+fn parse_palette(text: &str) -> Result<Palette, String> {
+    config::parse_palette(text).ok_or_else(|| {
+        format!(
+            "`{}` isn't amber, green, paper, gameboy or #RRGGBB colors like #33FF66,#000000",
+            text
+        )
+    })
+}
+
+fn parse_keymap(text: &str) -> Result<Keymap, String> {
+    config::parse_keymap(text).ok_or_else(|| {
+        format!(
+            "`{}` isn't qwerty, azerty, colemak, mirror or 16 different keys for 0 to F",
+            text
+        )
+    })
+}
+
+fn parse_platform(text: &str) -> Result<Platform, String> {
+    Platform::parse(text).ok_or_else(|| format!("`{}` isn't chip8, schip or xochip", text))
+}
+
+#[cfg(feature = "led")]
+fn parse_led_protocol(text: &str) -> Result<LedProtocol, String> {
+    LedProtocol::parse(text).ok_or_else(|| format!("`{}` isn't packed, rgb or max7219", text))
+}
+
+fn parse_condition(text: &str) -> Result<Condition, String> {
+    Condition::parse(text).ok_or_else(|| format!("`{}` isn't like mem[0x3FF]==0x55 or vF!=0", text))
+}
+
+fn parse_hash(text: &str) -> Result<u64, String> {
+    u64::from_str_radix(text.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("`{}` isn't a hex hash", text))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(Subcommand)]
+enum GoldenAction {
+    /// Run every .ch8 in DIR and write the screen hashes at the chosen frames
+    Record {
+        dir: PathBuf,
+
+        /// Frames to hash, comma separated
+        #[arg(long, value_delimiter = ',', default_values_t = golden::DEFAULT_FRAMES)]
+        frames: Vec<u64>,
+
+        /// Golden file, defaults to DIR/golden.txt
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Run the ROMs again and compare against the recorded hashes
+    Verify {
+        dir: PathBuf,
+
+        /// Golden file, defaults to DIR/golden.txt
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+}
+
+// Why the binary stopped, main prints it and exits with its code
+enum AppError {
+    // chip8 couldn't start: a file, the settings, the window, the network. The message says
+    // what to do about it. Exit code 1
+    Setup(String),
+    // the ROM did something impossible. Exit code 2
+    Crash { error: Chip8Error, pc: u16 },
+    // `run --exit-on-halt` saw the program stop. Exit code 3
+    Halted { halt: Halt, frame: u64 },
+}
+
+impl AppError {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            AppError::Setup(_) => ExitCode::from(1),
+            AppError::Crash { .. } => ExitCode::from(2),
+            AppError::Halted { .. } => ExitCode::from(3),
+        }
+    }
+
+    fn crash(error: Chip8Error, chip: &Chip8) -> Self {
+        AppError::Crash {
+            error,
+            pc: *chip.get_pc(),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::Setup(message) => write!(f, "{}", message),
+            AppError::Crash { error, pc } => {
+                write!(f, "the ROM crashed at {:03X}: {:?}", pc, error)
+            }
+            AppError::Halted { halt, frame } => {
+                write!(f, "the ROM halted at frame {}: {}", frame, halt)
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
     // Now, it'll run in the model "chip8 file.ch8"
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 || !args[1].ends_with(".ch8") {
-        let message = if args.len() != 2 {
-            "ERROR: Must have only 2 args. \n    example: 'chip8 file.ch8'".to_string()
+    let mut args = Args::parse();
+    // --ips is --ipf for people who think in instructions per second, rounded to the nearest
+    // frame's worth so 1000 runs at 17 per frame and not 16
+    args.ipf = args
+        .ipf
+        .or(args.ips.map(|ips| (ips.saturating_add(30) / 60).max(1)));
+    let result = match args.command {
+        Some(Command::Check { dir }) => {
+            check(&dir);
+            Ok(())
+        }
+        Some(Command::Compat {
+            dir,
+            seconds,
+            format,
+            out,
+        }) => {
+            run_compat(&dir, seconds, format, out);
+            Ok(())
+        }
+        Some(Command::Selftest) => {
+            run_selftest();
+            Ok(())
+        }
+        Some(Command::Golden { action }) => {
+            run_golden(action);
+            Ok(())
+        }
+        Some(Command::Paths) => print_paths(&args),
+        Some(Command::List { ref dir }) => list_roms(&args, dir.as_deref()),
+        Some(Command::Run {
+            ref rom,
+            headless: _,
+            max_frames,
+            max_seconds,
+            exit_code_from_frame_hash,
+            exit_on_halt,
+            exit_when,
+        }) => {
+            let limit = max_seconds.map(Duration::from_secs_f64);
+            let expected = exit_code_from_frame_hash;
+            run_headless(
+                &args,
+                rom,
+                max_frames,
+                limit,
+                expected,
+                exit_on_halt,
+                exit_when,
+            )
+        }
+        Some(Command::Debug { ref rom }) => run_debugger(&args, rom),
+        Some(Command::DumpState {
+            ref file,
+            format,
+            frames,
+        }) => dump_state(&args, file, format, frames),
+        Some(Command::DumpRam {
+            ref file,
+            ref out,
+            frames,
+        }) => machine_of(&args, file, frames)
+            .and_then(|chip| dump_ram(&chip, out).map_err(AppError::Setup)),
+        Some(Command::Hexedit {
+            ref rom,
+            ref set,
+            ref out,
+            ref diff,
+        }) => hexedit(rom, set, out.as_deref(), diff.as_deref()),
+        Some(Command::Asm {
+            ref source,
+            ref out,
+        }) => assemble(source, out.as_deref()),
+        None => play(&args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("ERROR: {}", error);
+            error.exit_code()
+        }
+    }
+}
+
+// `chip8 game.ch8`
+fn play(args: &Args) -> Result<(), AppError> {
+    let config_path = args.config.clone().or_else(config::default_path);
+    let saved = load_config(config_path.as_deref())?;
+    if let Some(dir) = &args.attract {
+        return play_attract(args, dir, saved, config_path);
+    }
+    let rom_path = match &args.rom {
+        Some(rom) => PathBuf::from(rom),
+        None => match pick_rom(&saved)? {
+            Some(rom) => rom,
+            // closing the dialog is how to say no
+            None => return Ok(()),
+        },
+    };
+    match rom_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("ch8") => {}
+        Some("8o" | "gif") => {
+            return Err(AppError::Setup(
+                "Octo programs and cartridges can't run yet, export a .ch8 from Octo".into(),
+            ));
+        }
+        _ => return Err(AppError::Setup("chip8 only accepts .ch8 files".into())),
+    }
+
+    // With the .ch8 file, it's time to read and run it
+    let rom = read_rom(&rom_path)?;
+    let config = rom_config(args, &saved, &rom_path, &rom)?;
+    let ipf = args.ipf.unwrap_or(config.speed);
+    let scale = args.scale.unwrap_or(config.display.scale);
+    let mut chip = load_chip(args, &config, &rom_path, &rom)?;
+    if args.capture {
+        chip.set_history(args.capture_lines);
+    }
+    if let Some(count) = args.dump_frames {
+        return dump_frames(args, &mut chip, ipf, count, config.display.palette, scale);
+    }
+    let kind = args.frontend.unwrap_or_else(FrontendKind::built_in);
+    #[cfg(feature = "egui")]
+    if matches!(kind, FrontendKind::Egui) {
+        return play_desktop(args, chip, &config, &rom_path, saved, config_path);
+    }
+    let netplay = open_netplay(args, ipf, &mut chip, &rom)?;
+    // the other side can't go back with us
+    if netplay.is_none() {
+        chip.set_rewind(REWIND_EVERY, REWIND_SNAPSHOTS);
+    }
+    let midi = Midi::open(args)?;
+    let gpio = Gpio::open(args)?;
+    // before the frontend, a terminal one would hide the warnings
+    let window_icon = load_icon(&config);
+    let aspect = args.aspect.unwrap_or(config.display.aspect);
+    let mut screen = open_screen(kind, scale, aspect, &saved)?;
+    screen.set_palette(config.display.palette);
+    screen.set_keymap(&config.keymap);
+    screen.set_title(&window_title(&config, &rom_path));
+    screen.set_icon(&window_icon);
+    let mut audio = open_audio(&config, args.mute);
+    let mut controls = Controls::new(args, &chip, &rom_path, &rom, &config, saved, config_path);
+    controls.midi = midi;
+    controls.gpio = gpio;
+
+    let mut plugins = load_plugins(args, &config, &mut chip)?;
+    let mut remote = Remote::open(args, ipf)?;
+    let script = load_script(args)?;
+
+    let result = if let Some(netplay) = netplay {
+        run_netplay(
+            &mut chip,
+            screen.as_mut(),
+            &mut audio,
+            netplay,
+            &mut plugins,
+            &mut controls,
+        )
+    } else if args.lockstep || script.is_some() {
+        run_lockstep(
+            &mut chip,
+            screen.as_mut(),
+            &mut audio,
+            &mut plugins,
+            &mut remote,
+            &mut controls,
+            script,
+        )
+    } else {
+        run_realtime(
+            &mut chip,
+            screen.as_mut(),
+            &mut audio,
+            &mut plugins,
+            &mut remote,
+            &mut controls,
+        )
+    };
+    if let Err(error) = &result {
+        plugins.event(&mut chip, &Event::Crash(error.clone()));
+    }
+    warn_plugins(plugins.shutdown(&mut chip));
+    controls.save_window(screen.as_ref());
+    if let Err(error) = &result
+        && args.capture
+    {
+        // the terminal frontend gives the screen back first, or the message would vanish
+        drop(screen);
+        save_capture(&config, &rom_path, &chip, error, controls.palette, scale);
+    }
+    result.map_err(|error| AppError::crash(error, &chip))
+}
+
+// `chip8 --attract DIR`: the frontend, the sound and the plugins stay open from one ROM to
+// the next. A ROM that can't load or crashes is skipped with a warning, so a broken one
+// doesn't take the booth down
+fn play_attract(
+    args: &Args,
+    dir: &Path,
+    saved: Config,
+    config_path: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let demos = attract::demos(dir)
+        .map_err(|error| AppError::Setup(format!("can't read {}: {}", dir.display(), error)))?;
+    if demos.is_empty() {
+        return Err(AppError::Setup(format!(
+            "there are no .ch8 files in {}",
+            dir.display()
+        )));
+    }
+    let kind = args.frontend.unwrap_or_else(FrontendKind::built_in);
+    if matches!(kind, FrontendKind::Egui) {
+        return Err(AppError::Setup(
+            "attract mode needs the gui, sdl2 or term frontend".into(),
+        ));
+    }
+    let mut attract = Attract::new(
+        demos.len(),
+        args.attract_seconds * 60,
+        args.attract_idle * 60,
+    );
+    let mut midi = Midi::open(args)?;
+    let mut gpio = Gpio::open(args)?;
+    // the debug window stays open from one ROM to the next
+    let mut debug_window = false;
+    let window_icon = load_icon(&saved);
+    let scale = args.scale.unwrap_or(saved.display.scale);
+    let aspect = args.aspect.unwrap_or(saved.display.aspect);
+    let mut screen = open_screen(kind, scale, aspect, &saved)?;
+    screen.set_icon(&window_icon);
+    let mut audio = open_audio(&saved, args.mute);
+    let mut plugins: Option<Plugins> = None;
+    // failures in a row, once every ROM failed there's nothing left to show
+    let mut failed = 0;
+
+    loop {
+        let demo = &demos[attract.current()];
+        let loaded = read_rom(&demo.rom).and_then(|rom| {
+            let config = rom_config(args, &saved, &demo.rom, &rom)?;
+            let chip = load_chip(args, &config, &demo.rom, &rom)?;
+            Ok((rom, config, chip))
+        });
+        let (rom, config, mut chip) = match loaded {
+            Ok(loaded) => loaded,
+            Err(error) if failed + 1 < demos.len() => {
+                eprintln!("WARNING: {}, skipping it", error);
+                failed += 1;
+                attract.skip();
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+        let script = match demo.script.as_deref().map(read_script) {
+            Some(Ok(script)) => script,
+            Some(Err(error)) => {
+                eprintln!("WARNING: {}, the demo plays without it", error);
+                InputScript::new()
+            }
+            None => InputScript::new(),
+        };
+        screen.set_title(&window_title(&config, &demo.rom));
+        let mut controls = Controls::new(
+            args,
+            &chip,
+            &demo.rom,
+            &rom,
+            &config,
+            saved.clone(),
+            config_path.clone(),
+        );
+        // the palette, keymap and sound of the ROM's own settings
+        let _ = controls.apply(saved.clone(), screen.as_mut(), &mut chip, &mut audio);
+        controls.midi = midi;
+        controls.gpio = gpio;
+        controls.debug_window = debug_window;
+        controls.osd.show("PRESS ANY KEY");
+        let plugins = match &mut plugins {
+            Some(plugins) => {
+                plugins.load(&mut chip);
+                plugins
+            }
+            None => plugins.insert(load_plugins(args, &config, &mut chip)?),
+        };
+
+        let result = run_attract(
+            &mut chip,
+            screen.as_mut(),
+            &mut audio,
+            plugins,
+            &mut controls,
+            &mut attract,
+            script,
+        );
+        midi = std::mem::replace(&mut controls.midi, Midi::none());
+        gpio = std::mem::replace(&mut controls.gpio, Gpio::none());
+        debug_window = controls.debug_window;
+        match result {
+            Ok(true) => failed = 0,
+            Ok(false) => {
+                warn_plugins(plugins.shutdown(&mut chip));
+                controls.save_window(screen.as_ref());
+                return Ok(());
+            }
+            Err(error) => {
+                plugins.event(&mut chip, &Event::Crash(error.clone()));
+                eprintln!(
+                    "WARNING: {} crashed at {:03X}: {:?}, on to the next ROM",
+                    demo.rom.display(),
+                    chip.get_pc(),
+                    error
+                );
+                attract.skip();
+            }
+        }
+    }
+}
+
+// `chip8` without a ROM: a file dialog, in paths.roms when it's set. None when it's closed
+#[cfg(feature = "dialog")]
+fn pick_rom(config: &Config) -> Result<Option<PathBuf>, AppError> {
+    // over ssh there's nowhere to show it, and rfd would say nothing either
+    #[cfg(target_os = "linux")]
+    if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        return Err(no_rom());
+    }
+    let mut dialog = rfd::FileDialog::new()
+        .set_title("Open a Chip-8 program")
+        .add_filter("Chip-8 programs", &["ch8", "8o", "gif"]);
+    if let Some(dir) = &config.paths.roms {
+        dialog = dialog.set_directory(dir);
+    }
+    Ok(dialog.pick_file())
+}
+
+#[cfg(not(feature = "dialog"))]
+fn pick_rom(_config: &Config) -> Result<Option<PathBuf>, AppError> {
+    Err(no_rom())
+}
+
+fn no_rom() -> AppError {
+    AppError::Setup("no ROM given, run `chip8 game.ch8`".into())
+}
+
+// `chip8 --frontend egui game.ch8`, the app has its own loop and menus for what the
+// hotkeys do in the others
+#[cfg(feature = "egui")]
+fn play_desktop(
+    args: &Args,
+    mut chip: Chip8,
+    config: &Config,
+    rom_path: &Path,
+    mut saved: Config,
+    config_path: Option<PathBuf>,
+) -> Result<(), AppError> {
+    if args.host.is_some() || args.join.is_some() {
+        return Err(AppError::Setup(
+            "netplay needs the gui, sdl2 or term frontend".into(),
+        ));
+    }
+    let settings = desktop::Settings {
+        title: window_title(config, rom_path),
+        scale: args.scale.unwrap_or(config.display.scale),
+        aspect: args.aspect.unwrap_or(config.display.aspect),
+        ipf: args.ipf.unwrap_or(config.speed),
+        palette: config.display.palette,
+        flash_limit: config.display.flash_limit,
+        keymap: config.keymap,
+        volume: config.audio.volume,
+        tone: config.audio.tone,
+        timbre: config.audio.timbre,
+        ripple: config.audio.ripple,
+        muted: args.mute,
+        window: saved.window.geometry(),
+    };
+    let icon = load_icon(config);
+    // muted from the Audio menu still needs the device, to unmute
+    let audio = open_audio(config, false);
+    let plugins = load_plugins(args, config, &mut chip)?;
+    let (geometry, plugin_errors) = DesktopApp::new(chip, settings, audio, plugins)
+        .run(&icon)
+        .map_err(|error| AppError::Setup(format!("can't open the egui frontend: {}", error)))?;
+    warn_plugins(plugin_errors);
+    if let Some(path) = &config_path
+        && saved.window.remember
+    {
+        saved.window.set_geometry(&geometry);
+        if let Err(error) = saved.save_tweaks(path) {
+            eprintln!("WARNING: can't save {}: {}", path.display(), error);
+        }
+    }
+    Ok(())
+}
+
+fn window_title(config: &Config, rom_path: &Path) -> String {
+    let name = rom_path.file_stem().unwrap_or_default();
+    icon::title(&config.display.title, &name.to_string_lossy())
+}
+
+// The sound device, or silence with --mute, audio.enabled = false or no device
+fn open_audio(config: &Config, mute: bool) -> Audio {
+    let mut audio = if mute || !config.audio.enabled {
+        Audio::silent()
+    } else {
+        Audio::new().unwrap_or_else(|error| {
+            eprintln!(
+                "WARNING: no sound ({}), playing muted. --mute hides this",
+                error
+            );
+            Audio::silent()
+        })
+    };
+    audio.set_volume(config.audio.volume);
+    audio.set_tone(config.audio.tone);
+    audio.set_pitch(config.audio.pitch, config.audio.steps_per_octave);
+    audio.set_timbre(config.audio.timbre, config.audio.ripple);
+    audio
+}
+
+// --trace FILE
+fn start_trace(args: &Args, chip: &mut Chip8) -> Result<(), AppError> {
+    if let Some(path) = &args.trace {
+        let file = std::fs::File::create(path).map_err(|error| {
+            AppError::Setup(format!("can't write {}: {}", path.display(), error))
+        })?;
+        chip.set_trace(trace::to_writer(io::BufWriter::new(file)));
+    }
+    Ok(())
+}
+
+// The built-in plugins the flags turn on, loaded into the machine. Only some features read
+// the config
+#[allow(unused_variables)]
+fn load_plugins(args: &Args, config: &Config, chip: &mut Chip8) -> Result<Plugins, AppError> {
+    let mut plugins = Plugins::new();
+    if args.log_events {
+        plugins.register(Box::new(EventLog));
+    }
+    if args.debug_port {
+        plugins.register(Box::new(DebugPort));
+    }
+    if let Some(path) = &args.stats {
+        let stats = std::fs::File::create(path)
+            .and_then(|file| Stats::new(io::BufWriter::new(file)))
+            .map_err(|error| {
+                AppError::Setup(format!("can't write {}: {}", path.display(), error))
+            })?;
+        plugins.register(Box::new(stats));
+    }
+    start_trace(args, chip)?;
+    #[cfg(feature = "spectate")]
+    if let Some(addr) = &args.spectate {
+        let server = SpectatorServer::bind(addr.as_str()).map_err(|error| listen(addr, error))?;
+        eprintln!("Spectators can watch on http://{}", server.local_addr());
+        plugins.register(Box::new(server));
+    }
+    #[cfg(feature = "led")]
+    if let Some(device) = &args.led {
+        let led = LedMatrix::open(device, args.led_protocol, config.display.palette).map_err(
+            |error| AppError::Setup(format!("can't open {}: {}", device.display(), error)),
+        )?;
+        plugins.register(Box::new(led));
+    }
+    #[cfg(feature = "profiling")]
+    if let Some(addr) = &args.profiler {
+        let server = puffin_http::Server::new(addr).map_err(|error| listen(addr, error))?;
+        eprintln!("Profiling, connect with: puffin_viewer --url {}", addr);
+        puffin::set_scopes_on(true);
+        plugins.register(Box::new(Profiler { _server: server }));
+    }
+    plugins.load(chip);
+    Ok(plugins)
+}
+
+// --capture, a warning when it fails since the crash is the error to report
+fn save_capture(
+    config: &Config,
+    rom_path: &Path,
+    chip: &Chip8,
+    error: &Chip8Error,
+    palette: Palette,
+    scale: usize,
+) {
+    let Some(dir) = config.data_dir(DataKind::Captures) else {
+        eprintln!("WARNING: no captures folder, set paths.captures in chip8.toml");
+        return;
+    };
+    let name = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    match capture::capture(&dir, &name, chip, error, palette, scale) {
+        Ok(bundle) => eprintln!("Crash captured in {}", bundle.display()),
+        Err(error) => eprintln!(
+            "WARNING: can't save the capture in {}: {:?}",
+            dir.display(),
+            error
+        ),
+    }
+}
+
+fn read_rom(path: &Path) -> Result<Vec<u8>, AppError> {
+    std::fs::read(path)
+        .map_err(|error| AppError::Setup(format!("can't read {}: {}", path.display(), error)))
+}
+
+fn listen(addr: &str, error: impl fmt::Display) -> AppError {
+    AppError::Setup(format!("can't listen on {}: {}", addr, error))
+}
+
+// Settings from chip8.toml with the ROM's own section and --profile, flags given on the
+// command line win over them
+fn rom_config(
+    args: &Args,
+    saved: &Config,
+    rom_path: &Path,
+    rom: &[u8],
+) -> Result<Config, AppError> {
+    // the recommended options of the archive go under everything the file says
+    let mut base = saved.clone();
+    let program = archive::program_for(rom_path).unwrap_or_else(|error| {
+        eprintln!("WARNING: {}", error);
+        None
+    });
+    if let Some(program) = program {
+        eprintln!("{}", program.summary());
+        if !program.is_supported() {
+            eprintln!(
+                "WARNING: it's made for {}, it may not run right",
+                program.platform
+            );
+        }
+        if program.recommended != Overrides::default() {
+            eprintln!("Using its recommended options from {}", archive::FILE_NAME);
+        }
+        base = saved
+            .with_overrides(&program.recommended)
+            .map_err(|error| AppError::Setup(error.to_string()))?;
+    }
+    if let Some((key, _)) = saved.find_game(rom_path, rom) {
+        eprintln!("Using the settings of [game.\"{}\"]", key);
+    }
+    base.for_rom(rom_path, rom)
+        .with_profiles(&args.profile)
+        .and_then(|config| config.with_overrides(&flag_overrides(args)))
+        .map_err(|error| AppError::Setup(error.to_string()))
+}
+
+// What --platform, --quirks, --keymap, --palette and --patch change over the file and the
+// profiles
+fn flag_overrides(args: &Args) -> Overrides {
+    Overrides {
+        keymap: args.keymap,
+        platform: args.platform,
+        quirks: args.quirks,
+        palette: args.palette,
+        patch: args.patch.clone(),
+        ..Overrides::default()
+    }
+}
+
+// The ROM in a new machine, with what the settings and flags change in it. The platform
+// comes first, XO-CHIP programs can be larger
+fn load_chip(args: &Args, config: &Config, rom_path: &Path, rom: &[u8]) -> Result<Chip8, AppError> {
+    let mut chip = config.machine(rom).map_err(|error| match error {
+        Chip8Error::RomTooLarge => AppError::Setup(format!(
+            "{} is {} bytes, more than the {} a {} program can have",
+            rom_path.display(),
+            rom.len(),
+            config.platform.memory_map().size - memory::PROGRAM_START,
+            config.platform.name()
+        )),
+        error => AppError::Setup(format!("can't patch the ROM: {:?}", error)),
+    })?;
+    set_up(args, &mut chip);
+    if let Some(path) = &args.ram_image {
+        load_ram(
+            &mut chip,
+            path,
+            args.start_pc.unwrap_or(0x200),
+            args.start_i.unwrap_or(0),
+        )
+        .map_err(AppError::Setup)?;
+    }
+    Ok(chip)
+}
+
+// A RAM image from a file, then the program goes on from pc with I at i
+fn load_ram(chip: &mut Chip8, path: &Path, pc: u16, i: u16) -> Result<(), String> {
+    let image = std::fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    chip.load_ram_image(&image).map_err(|_| {
+        format!(
+            "{} is {} bytes, a RAM image has {}",
+            path.display(),
+            image.len(),
+            chip.memory_map().size
+        )
+    })?;
+    chip.set_pc(pc)
+        .and(chip.set_i(i))
+        .expect("addresses in RAM");
+    Ok(())
+}
+
+fn dump_ram(chip: &Chip8, path: &Path) -> Result<(), String> {
+    std::fs::write(path, chip.ram_image()).map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+// What the flags change in a freshly loaded machine besides the settings
+fn set_up(args: &Args, chip: &mut Chip8) {
+    chip.set_debug_port(args.debug_port);
+    if let Some(seed) = args.seed {
+        chip.set_seed(seed);
+    }
+}
+
+// --config or chip8.toml in the config directory. A broken file stops here, playing with
+// settings other than the ones written down would be more confusing
+fn load_config(path: Option<&Path>) -> Result<Config, AppError> {
+    let Some(path) = path else {
+        return Ok(Config::default());
+    };
+    Config::load(path).map_err(|error| AppError::Setup(format!("{}: {}", path.display(), error)))
+}
+
+// --dump-frames, a crash stops the dump but keeps the frames written so far
+fn dump_frames(
+    args: &Args,
+    chip: &mut Chip8,
+    ipf: u32,
+    count: u64,
+    palette: Palette,
+    scale: usize,
+) -> Result<(), AppError> {
+    let dir = &args.dump_dir;
+    match frames::dump_frames(chip, ipf, count, args.every, dir, palette, scale) {
+        Ok(written) => {
+            println!("{} frames written to {}", written.len(), dir.display());
+            Ok(())
+        }
+        Err(DumpError::Io(error)) => Err(AppError::Setup(format!(
+            "can't write frames to {}: {}",
+            dir.display(),
+            error
+        ))),
+        Err(DumpError::Chip8(error)) => Err(AppError::crash(error, chip)),
+    }
+}
+
+// --host or --join, before the frontend opens so the waiting shows in the terminal
+fn open_netplay(
+    args: &Args,
+    ipf: u32,
+    chip: &mut Chip8,
+    rom: &[u8],
+) -> Result<Option<Netplay>, AppError> {
+    let netplay = if let Some(addr) = &args.host {
+        let listener = std::net::TcpListener::bind(addr).map_err(|error| listen(addr, error))?;
+        eprintln!("Waiting for the other player on {}...", addr);
+        Netplay::host(&listener, chip, rom, ipf, args.input_delay)
+    } else if let Some(addr) = &args.join {
+        Netplay::join(addr.as_str(), chip, rom)
+    } else {
+        return Ok(None);
+    };
+    netplay
+        .map(Some)
+        .map_err(|error| AppError::Setup(format!("netplay: {:?}", error)))
+}
+
+// The plugins that stopped, the game goes on without them
+fn warn_plugins(errors: Vec<PluginError>) {
+    for error in errors {
+        eprintln!("WARNING: {}", error);
+    }
+}
+
+// Built-in plugin behind --log-events
+struct EventLog;
+
+impl Plugin for EventLog {
+    fn name(&self) -> &str {
+        "event-log"
+    }
+
+    fn on_event(&mut self, chip: &mut Chip8, event: &Event) {
+        eprintln!("pc {:03X}: {:?}", chip.get_pc(), event);
+    }
+}
+
+// Built-in plugin behind --debug-port
+struct DebugPort;
+
+impl Plugin for DebugPort {
+    fn name(&self) -> &str {
+        "debug-port"
+    }
+
+    fn on_event(&mut self, _chip: &mut Chip8, event: &Event) {
+        if let Event::Debug(output) = event {
+            eprintln!("DEBUG {}", output);
+        }
+    }
+}
+
+// Built-in plugin behind --profiler, every 60 Hz frame is a puffin frame
+#[cfg(feature = "profiling")]
+struct Profiler {
+    _server: puffin_http::Server,
+}
+
+#[cfg(feature = "profiling")]
+impl Plugin for Profiler {
+    fn name(&self) -> &str {
+        "profiler"
+    }
+
+    fn on_frame(&mut self, _chip: &mut Chip8) -> Result<(), String> {
+        puffin::GlobalProfiler::lock().new_frame();
+        Ok(())
+    }
+}
+
+// What the player changes while playing: hotkeys from chip8.toml, each one acting once when
+// its key goes down, and edits of the file itself, applied without a restart
+struct Controls {
+    keys: config::Hotkeys,
+    // the machine as it was loaded, reset goes back to it
+    initial: Chip8,
+    rom: Vec<u8>,
+    rom_path: PathBuf,
+    // --profile, then whatever the profile hotkey picked
+    profiles: Vec<String>,
+    // --quirks and --palette, over whatever profile is picked
+    flags: Overrides,
+    paused: bool,
+    // every hotkey as of the last poll, in the order of Controls::hotkeys
+    down: [bool; 15],
+    // while the rewind hotkey is held the game doesn't run, it goes back
+    rewinding: bool,
+    // the keys the keypad overlay shows as down, None while it's hidden
+    keypad: Option<[bool; 16]>,
+    palette: Palette,
+    // of the screenshots an input script takes
+    scale: usize,
+    volume: f32,
+    ipf: u32,
+    // --ipf, editing the file doesn't change what was asked on the command line
+    ipf_flag: Option<u32>,
+    // the file as it was read, changes made while playing go in it and get saved
+    saved: Config,
+    path: Option<PathBuf>,
+    watcher: Option<config::Watcher>,
+    osd: Osd,
+    clipboard: Option<Clipboard>,
+    on_halt: OnHalt,
+    halt: HaltDetector,
+    // None with display.flash_limit = false
+    flash: Option<FlashLimiter>,
+    // None while the frame time graph is hidden
+    frame_times: Option<FrameTimes>,
+    // --midi, its notes press keys on top of the ones of the frontend
+    midi: Midi,
+    // --gpio-rows, the same for a keypad on the pins of a Pi
+    gpio: Gpio,
+    // the debugger panels are in a window of their own
+    debug_window: bool,
+}
+
+impl Controls {
+    fn new(
+        args: &Args,
+        chip: &Chip8,
+        rom_path: &Path,
+        rom: &[u8],
+        config: &Config,
+        saved: Config,
+        path: Option<PathBuf>,
+    ) -> Self {
+        Controls {
+            keys: config.hotkeys,
+            initial: chip.clone(),
+            rom: rom.to_vec(),
+            rom_path: rom_path.to_path_buf(),
+            profiles: args.profile.clone(),
+            flags: flag_overrides(args),
+            paused: false,
+            down: [false; 15],
+            rewinding: false,
+            keypad: None,
+            palette: config.display.palette,
+            scale: args.scale.unwrap_or(config.display.scale),
+            volume: config.audio.volume,
+            ipf: args.ipf.unwrap_or(config.speed),
+            ipf_flag: args.ipf,
+            saved,
+            watcher: path.as_deref().map(config::Watcher::new),
+            path,
+            osd: Osd::new(),
+            clipboard: None,
+            on_halt: args.on_halt,
+            halt: HaltDetector::new(args.halt_idle),
+            flash: config.display.flash_limit.then(FlashLimiter::new),
+            frame_times: None,
+            midi: Midi::none(),
+            gpio: Gpio::none(),
+            debug_window: false,
+        }
+    }
+
+    fn hotkeys(&self) -> [HostKey; 15] {
+        let keys = &self.keys;
+        [
+            keys.quit,
+            keys.pause,
+            keys.reset,
+            keys.profile,
+            keys.keypad,
+            keys.swap_colors,
+            keys.volume_down,
+            keys.volume_up,
+            keys.copy_frame,
+            keys.frame_times,
+            keys.fullscreen,
+            keys.debug_window,
+            keys.quicksave,
+            keys.quickload,
+            keys.rewind,
+        ]
+    }
+
+    // The keys of the frontend, with the MIDI notes and the GPIO keypad on top
+    fn read_keys(&mut self, screen: &mut dyn Frontend, keyboard: &mut Keyboard) {
+        screen.read_keys(keyboard);
+        self.midi.poll(keyboard);
+        self.gpio.poll(keyboard);
+    }
+
+    // Called after read_keys, false once quit was pressed
+    fn poll(&mut self, screen: &mut dyn Frontend, chip: &mut Chip8, audio: &mut Audio) -> bool {
+        let mut pressed = [false; 15];
+        for (index, key) in self.hotkeys().into_iter().enumerate() {
+            let down = screen.is_key_down(key);
+            pressed[index] = down && !self.down[index];
+            self.down[index] = down;
+        }
+        let [
+            quit,
+            pause,
+            reset,
+            profile,
+            keypad,
+            swap_colors,
+            volume_down,
+            volume_up,
+            copy_frame,
+            frame_times,
+            fullscreen,
+            debug_window,
+            quicksave,
+            quickload,
+            _,
+        ] = pressed;
+
+        if pause {
+            self.paused = !self.paused;
+            // the time paused isn't a slow frame
+            if let Some(times) = &mut self.frame_times {
+                times.restart();
+            }
+        }
+        if reset {
+            // keeping the rewind buffer, back to before the reset is a rewind away
+            chip.restore(self.initial.clone());
+        }
+        if profile {
+            let names = self.saved.profile_names();
+            // a single profile moves to the next one, anything else starts over
+            let next = match self.profiles.as_slice() {
+                [current] => names
+                    .iter()
+                    .position(|name| name == current)
+                    .map(|at| at + 1),
+                _ => Some(0),
+            };
+            self.profiles = next
+                .and_then(|at| names.get(at))
+                .map(|name| vec![name.clone()])
+                .unwrap_or_default();
+            let saved = self.saved.clone();
+            if self.apply(saved, screen, chip, audio).is_ok() {
+                let name = self.profiles.first().map_or("NONE", String::as_str);
+                self.osd.show(format!("PROFILE {}", name));
+            }
+        }
+        if swap_colors {
+            // the file's own colors, the ones of a [game] section, a profile or --palette
+            // still win over them and aren't written into it
+            let palette = self.saved.display.palette;
+            let mut saved = self.saved.clone();
+            saved.display.palette = Palette {
+                on: palette.off,
+                off: palette.on,
+                ..palette
+            };
+            if self.apply(saved, screen, chip, audio).is_ok() {
+                self.save();
+            }
+        }
+        if volume_down || volume_up {
+            let step = if volume_up { 0.1 } else { -0.1 };
+            self.volume = (self.volume + step).clamp(0.0, 1.0);
+            audio.set_volume(self.volume);
+            self.saved.audio.volume = self.volume;
+            self.save();
+            self.osd
+                .show(format!("VOLUME {}%", (self.volume * 100.0).round()));
+        }
+        if copy_frame {
+            self.copy_frame(chip);
+        }
+        if frame_times {
+            self.frame_times = match self.frame_times.take() {
+                Some(_) => {
+                    screen.set_frame_times(None);
+                    None
+                }
+                None => Some(FrameTimes::new()),
+            };
+            chip.draw_flag = true;
+        }
+        // held, not pressed
+        self.rewinding = self.down[14] && chip.rewind_frames() + chip.rewind(REWIND_STEP) > 0;
+        if self.rewinding {
+            chip.draw_flag = true;
+            self.osd.show("REWIND");
+        }
+        if quicksave {
+            self.quicksave(chip);
+        }
+        if quickload {
+            self.quickload(chip);
+        }
+        if fullscreen && !screen.toggle_fullscreen() {
+            self.osd.show("NO FULLSCREEN HERE");
+        }
+        if debug_window {
+            if self.debug_window {
+                screen.close_debug_window();
+                self.debug_window = false;
+            } else {
+                match screen.open_debug_window() {
+                    Ok(()) => self.debug_window = true,
+                    Err(error) => self.osd.show(format!("NO DEBUG WINDOW: {}", error)),
+                }
+            }
+        }
+        // every poll, paused too, so the window keeps up with its events
+        if self.debug_window && !screen.update_debug(&Panels::new(chip)) {
+            self.debug_window = false;
+        }
+
+        if let Some(reloaded) = self.watcher.as_mut().and_then(config::Watcher::poll) {
+            match reloaded {
+                Ok(saved) => match self.apply(saved, screen, chip, audio) {
+                    Ok(()) => self.osd.show("CONFIG RELOADED"),
+                    // a profile we play with was taken out of the file
+                    Err(error) => self.osd.show(format!("CONFIG ERROR: {}", error)),
+                },
+                // the game goes on with the settings it had, the first line says where it is
+                Err(error) => {
+                    let text = error.to_string();
+                    let first_line = text.lines().next().unwrap_or_default();
+                    let place = first_line.trim_start_matches("TOML parse error at ");
+                    self.osd.show(format!("CONFIG ERROR: {}", place));
+                }
+            }
+        }
+        let keys_down = std::array::from_fn(|key| chip.keyboard.is_pressed(key) == Ok(true));
+        let shown = if keypad {
+            self.keypad.is_none().then_some(keys_down)
         } else {
-            "ERROR: chip8 only accepts .ch8 files.".to_string()
+            self.keypad.map(|_| keys_down)
         };
-        eprintln!("{}", message);
-        std::process::exit(1)
+        if shown != self.keypad {
+            self.keypad = shown;
+            screen.set_keypad(shown);
+            chip.draw_flag = true;
+        }
+        if self.osd.tick() {
+            screen.set_message(self.osd.message());
+            chip.draw_flag = true;
+        }
+        !quit
     }
 
-    // With the .ch8 file, it's time to read and run it
-    let file_name: String = args[1].clone();
-    let rom = std::fs::read(&file_name).expect("Failed to read ROM");
-    let mut chip = Chip8::new(&rom)?;
-
-    // Chip-8 main loop
-    let sixty_hz = Duration::from_micros(16_666); // 1/60s ≈ 16.666 ms
-    let mut last_tick = Instant::now();
-    while chip.display.is_open() {
-        chip.update_keyboard();
-        for _ in 0..10 {
+    // What a new version of the file or another profile can change in a running game. Scale
+    // and audio on or off need a restart
+    fn apply(
+        &mut self,
+        saved: Config,
+        screen: &mut dyn Frontend,
+        chip: &mut Chip8,
+        audio: &mut Audio,
+    ) -> Result<(), config::ConfigError> {
+        let config = saved
+            .for_rom(&self.rom_path, &self.rom)
+            .with_profiles(&self.profiles)?
+            .with_overrides(&self.flags)?;
+        self.keys = config.hotkeys;
+        self.palette = config.display.palette;
+        if config.display.flash_limit != self.flash.is_some() {
+            self.flash = config.display.flash_limit.then(FlashLimiter::new);
+        }
+        screen.set_palette(self.shown_palette());
+        screen.set_keymap(&config.keymap);
+        self.volume = config.audio.volume;
+        audio.set_volume(self.volume);
+        audio.set_tone(config.audio.tone);
+        audio.set_pitch(config.audio.pitch, config.audio.steps_per_octave);
+        audio.set_timbre(config.audio.timbre, config.audio.ripple);
+        self.ipf = self.ipf_flag.unwrap_or(config.speed);
+        chip.quirks = config.quirks;
+        self.initial.quirks = config.quirks;
+        chip.draw_flag = true;
+        self.saved = saved;
+        Ok(())
+    }
+
+    // The clipboard opens on the first copy and stays open, or the frame goes away with it
+    fn copy_frame(&mut self, chip: &Chip8) {
+        if self.clipboard.is_none() {
+            self.clipboard = Clipboard::new()
+                .inspect_err(|error| eprintln!("WARNING: can't open the clipboard: {}", error))
+                .ok();
+        }
+        let copied = self.clipboard.as_mut().map(|clipboard| {
+            clipboard
+                .copy_frame(&chip.display, self.palette)
+                .inspect_err(|error| eprintln!("WARNING: can't copy the frame: {}", error))
+        });
+        self.osd.show(match copied {
+            Some(Ok(())) => "FRAME COPIED",
+            _ => "COPY FAILED",
+        });
+    }
+
+    // The ROM's file in the states folder, one slot per game
+    fn state_path(&self) -> Option<PathBuf> {
+        let name = self.rom_path.file_stem().unwrap_or_default();
+        let dir = self.saved.data_dir(DataKind::States);
+        dir.map(|dir| dir.join(name).with_extension("state"))
+    }
+
+    fn quicksave(&mut self, chip: &Chip8) {
+        let saved = self
+            .state_path()
+            .ok_or_else(|| "no states folder".to_string());
+        let saved = saved.and_then(|path| {
+            std::fs::create_dir_all(path.parent().unwrap_or(&path))
+                .and_then(|()| std::fs::write(&path, chip.save_state()))
+                .map_err(|error| format!("{}: {}", path.display(), error))
+        });
+        self.osd.show(match saved {
+            Ok(()) => "STATE SAVED",
+            Err(error) => {
+                eprintln!("WARNING: can't save the state, {}", error);
+                "SAVE FAILED"
+            }
+        });
+    }
+
+    // Settings like quirks and the palette stay as they are, the rest is the saved machine
+    fn quickload(&mut self, chip: &mut Chip8) {
+        let state = self.state_path().and_then(|path| std::fs::read(path).ok());
+        let Some(state) = state else {
+            self.osd.show("NO STATE SAVED");
+            return;
+        };
+        match chip.load_state(&state) {
+            Ok(()) => {
+                chip.draw_flag = true;
+                self.osd.show("STATE LOADED");
+            }
+            Err(_) => self.osd.show("BAD STATE FILE"),
+        }
+    }
+
+    // The palette with the contrast the flash limiter allows right now
+    fn shown_palette(&self) -> Palette {
+        let contrast = self.flash.as_ref().map_or(1.0, FlashLimiter::contrast);
+        flash::dim(self.palette, contrast)
+    }
+
+    // Once per 60 Hz frame, before the screen is drawn
+    fn end_frame(&mut self, screen: &mut dyn Frontend, chip: &mut Chip8) {
+        self.limit_flashes(screen, chip);
+        if let Some(times) = &mut self.frame_times {
+            times.tick();
+            screen.set_frame_times(Some(&times.times()));
+            chip.draw_flag = true;
+        }
+    }
+
+    fn limit_flashes(&mut self, screen: &mut dyn Frontend, chip: &mut Chip8) {
+        let Some(limiter) = &mut self.flash else {
+            return;
+        };
+        let before = limiter.contrast();
+        if limiter.frame(&chip.display) != before {
+            screen.set_palette(self.shown_palette());
+            chip.draw_flag = true;
+        }
+    }
+
+    // After every frame that ran, what --on-halt says once the program stops
+    fn check_halt(&mut self, chip: &Chip8) {
+        if self.on_halt == OnHalt::Ignore || self.halt.frame(chip).is_none() {
+            return;
+        }
+        self.osd.show("PROGRAM HALTED");
+        if self.on_halt == OnHalt::Pause {
+            self.paused = true;
+        }
+    }
+
+    // Netplay can't pause or reset alone, both machines have to stay the same
+    fn quit(&self, screen: &dyn Frontend) -> bool {
+        screen.is_key_down(self.keys.quit)
+    }
+
+    // Where the window is now, for the next run to open it there. On the way out only
+    fn save_window(&mut self, screen: &dyn Frontend) {
+        if let Some(geometry) = screen.geometry()
+            && self.saved.window.remember
+        {
+            self.saved.window.set_geometry(&geometry);
+            self.save();
+        }
+    }
+
+    fn save(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        match self.saved.save_tweaks(path) {
+            // our own write isn't an edit to reload
+            Ok(()) => {
+                if let Some(watcher) = &mut self.watcher {
+                    watcher.mark_seen();
+                }
+            }
+            Err(error) => eprintln!("WARNING: can't save {}: {}", path.display(), error),
+        }
+    }
+}
+
+// Chip-8 main loop
+fn run_realtime(
+    chip: &mut Chip8,
+    screen: &mut dyn Frontend,
+    audio: &mut Audio,
+    plugins: &mut Plugins,
+    remote: &mut Remote,
+    controls: &mut Controls,
+) -> Result<(), Chip8Error> {
+    // the timers tick at 60 Hz whatever the speed, ticks missed in a slow frame are dropped
+    let mut timers = Scheduler::new(RealClock::new(), clock::FRAME, 1);
+    while screen.is_open() {
+        controls.read_keys(screen, &mut chip.keyboard);
+        if !controls.poll(screen, chip, audio) {
+            break;
+        }
+        remote.poll(chip);
+        if remote.is_paused() || controls.paused || controls.rewinding {
+            // still keeps the window alive and shows what the remote steps did
+            screen.render(&chip.display);
+            audio.update(false);
+            timers.wait();
+            continue;
+        }
+        for _ in 0..controls.ipf {
             // more steps
             chip.step()?;
         }
+        controls.check_halt(chip);
 
         if chip.draw_flag {
-            chip.display.render();
+            screen.render(&chip.display);
             chip.draw_flag = false;
         } else {
-            chip.display.window_update();
+            screen.update();
         }
 
-        if last_tick.elapsed() >= sixty_hz {
+        if timers.due() > 0 {
             chip.decrease_timers();
-            last_tick = Instant::now();
+            warn_plugins(plugins.frame(chip));
+            controls.end_frame(screen, chip);
+        }
+        audio.update_from(chip);
+    }
+
+    Ok(())
+}
+
+// Lockstep loop: emulation only advances in whole virtual frames, the wall clock is
+// used just to present them at 60 fps. The keyboard is sampled once per frame.
+fn run_lockstep(
+    chip: &mut Chip8,
+    screen: &mut dyn Frontend,
+    audio: &mut Audio,
+    plugins: &mut Plugins,
+    remote: &mut Remote,
+    controls: &mut Controls,
+    script: Option<InputScript>,
+) -> Result<(), Chip8Error> {
+    let mut frames = Scheduler::new(RealClock::new(), clock::FRAME, 1);
+    // a script has the keypad to itself
+    let scripted = script.is_some();
+    let mut lockstep = Lockstep::with_script(controls.ipf, script.unwrap_or_default());
+    while screen.is_open() {
+        if !scripted {
+            controls.read_keys(screen, &mut chip.keyboard);
+        }
+        if !controls.poll(screen, chip, audio) {
+            break;
+        }
+        remote.poll(chip);
+        if !remote.is_paused() && !controls.paused && !controls.rewinding {
+            lockstep.set_ipf(controls.ipf);
+            lockstep.run_frame(chip)?;
+            if lockstep.screenshot_due() {
+                screenshot(chip, lockstep.frame(), controls.palette, controls.scale);
+            }
+            warn_plugins(plugins.frame(chip));
+            controls.check_halt(chip);
+            controls.end_frame(screen, chip);
+        }
+
+        if chip.draw_flag {
+            screen.render(&chip.display);
+            chip.draw_flag = false;
+        } else {
+            screen.update();
+        }
+        audio.update_from(chip);
+
+        frames.wait();
+    }
+
+    Ok(())
+}
+
+// Attract loop: lockstep frames like run_lockstep, the script plays the demo and the keys of
+// the frontend only count as someone walking up to play. True when the next demo is due,
+// false once quit was pressed or the window closed
+fn run_attract(
+    chip: &mut Chip8,
+    screen: &mut dyn Frontend,
+    audio: &mut Audio,
+    plugins: &mut Plugins,
+    controls: &mut Controls,
+    attract: &mut Attract,
+    script: InputScript,
+) -> Result<bool, Chip8Error> {
+    let mut frames = Scheduler::new(RealClock::new(), clock::FRAME, 1);
+    let mut lockstep = Lockstep::with_script(controls.ipf, script);
+    let mut keys = Keyboard::new();
+    while screen.is_open() {
+        let keyboard = if attract.is_playing() {
+            &mut chip.keyboard
+        } else {
+            &mut keys
+        };
+        controls.read_keys(screen, keyboard);
+        let input = keyboard.bitmask() != 0;
+        if !controls.poll(screen, chip, audio) {
+            return Ok(false);
+        }
+        match attract.frame(input) {
+            Some(Change::Demo(_)) => return Ok(true),
+            // from the start, the keys go to the game from the next frame on
+            Some(Change::Play) => {
+                *chip = controls.initial.clone();
+                lockstep = Lockstep::new(controls.ipf);
+                controls.osd.show("GO");
+            }
+            None => {}
+        }
+        if !controls.paused {
+            lockstep.set_ipf(controls.ipf);
+            lockstep.run_frame(chip)?;
+            warn_plugins(plugins.frame(chip));
+            controls.check_halt(chip);
+            controls.end_frame(screen, chip);
+        }
+
+        if chip.draw_flag {
+            screen.render(&chip.display);
+            chip.draw_flag = false;
+        } else {
+            screen.update();
+        }
+        audio.update_from(chip);
+
+        frames.wait();
+    }
+
+    Ok(false)
+}
+
+// --input-script, read before anything opens
+fn load_script(args: &Args) -> Result<Option<InputScript>, AppError> {
+    args.input_script.as_deref().map(read_script).transpose()
+}
+
+fn read_script(path: &Path) -> Result<InputScript, AppError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| AppError::Setup(format!("can't read {}: {}", path.display(), error)))?;
+    InputScript::parse(&text).map_err(|line| {
+        AppError::Setup(format!(
+            "{}:{}: isn't like `frame 120: press 5`, `release 5`, `tap 5` or `screenshot`",
+            path.display(),
+            line
+        ))
+    })
+}
+
+// `frame 600: screenshot` of an input script, to screenshot_0600.png
+fn screenshot(chip: &Chip8, frame: u64, palette: Palette, scale: usize) {
+    let path = PathBuf::from(format!("screenshot_{:04}.png", frame));
+    match frames::write_png(&path, &chip.display, palette, scale) {
+        Ok(()) => eprintln!("{} written", path.display()),
+        Err(error) => eprintln!("WARNING: can't write {}: {:?}", path.display(), error),
+    }
+}
+
+// Netplay loop: like lockstep, but every frame waits for the other player's keys
+fn run_netplay(
+    chip: &mut Chip8,
+    screen: &mut dyn Frontend,
+    audio: &mut Audio,
+    mut netplay: Netplay,
+    plugins: &mut Plugins,
+    controls: &mut Controls,
+) -> Result<(), Chip8Error> {
+    let mut frames = Scheduler::new(RealClock::new(), clock::FRAME, 1);
+    let mut keys = Keyboard::new();
+    while screen.is_open() {
+        controls.read_keys(screen, &mut keys);
+        if controls.quit(screen) {
+            break;
+        }
+        match netplay.run_frame(chip, &keys) {
+            Ok(()) => {}
+            Err(NetplayError::Chip8(error)) => return Err(error),
+            Err(error) => {
+                eprintln!("Netplay stopped at frame {}: {:?}", netplay.frame(), error);
+                return Ok(());
+            }
+        }
+        warn_plugins(plugins.frame(chip));
+        controls.end_frame(screen, chip);
+
+        if chip.draw_flag {
+            screen.render(&chip.display);
+            chip.draw_flag = false;
+        } else {
+            screen.update();
+        }
+        audio.update_from(chip);
+
+        frames.wait();
+    }
+
+    Ok(())
+}
+
+// `chip8 check DIR`: prints one line per test ROM and exits with 1 if any of them failed
+fn check(dir: &std::path::Path) {
+    let mut failed = false;
+    for (test, outcome) in testrunner::check_dir(dir) {
+        match &outcome {
+            Outcome::Pass => println!("PASS  {}", test.name),
+            Outcome::Mismatch(differences) => {
+                println!("FAIL  {} ({} pixels differ)", test.name, differences);
+                if let Ok(rom) = std::fs::read(dir.join(test.file))
+                    && let Ok(chip) = testrunner::run_headless(&rom, test.pokes, test.frames)
+                {
+                    print!("{}", chip.display_ascii());
+                }
+            }
+            Outcome::NoReference => println!("RAN   {} (no reference image)", test.name),
+            Outcome::Missing => println!("SKIP  {} ({} not found)", test.name, test.file),
+            Outcome::Crashed(error) => println!("FAIL  {} ({:?})", test.name, error),
+        }
+        failed |= outcome.is_failure();
+    }
+
+    if failed {
+        std::process::exit(1)
+    }
+}
+
+// `chip8 run --headless`: lockstep frames with the settings a player would get, no
+// frontend. Prints the frame it stopped at and its hash
+fn run_headless(
+    args: &Args,
+    rom_path: &Path,
+    max_frames: u64,
+    limit: Option<Duration>,
+    expected: Option<u64>,
+    exit_on_halt: bool,
+    exit_when: Option<Condition>,
+) -> Result<(), AppError> {
+    let rom = read_rom(rom_path)?;
+    let config_path = args.config.clone().or_else(config::default_path);
+    let config = rom_config(args, &load_config(config_path.as_deref())?, rom_path, &rom)?;
+    let mut chip = load_chip(args, &config, rom_path, &rom)?;
+    chip.set_decode_cache(true);
+    start_trace(args, &mut chip)?;
+
+    let start = Instant::now();
+    let script = load_script(args)?.unwrap_or_default();
+    let mut lockstep = Lockstep::with_script(args.ipf.unwrap_or(config.speed), script);
+    let mut detector = HaltDetector::new(args.halt_idle);
+    while lockstep.frame() < max_frames && limit.is_none_or(|limit| start.elapsed() < limit) {
+        if let Err(error) = lockstep.run_frame(&mut chip) {
+            println!("CRASH frame {}", lockstep.frame());
+            return Err(AppError::crash(error, &chip));
+        }
+        for output in chip.take_debug_output() {
+            eprintln!("DEBUG {}", output);
+        }
+        if lockstep.screenshot_due() {
+            let scale = args.scale.unwrap_or(config.display.scale);
+            screenshot(&chip, lockstep.frame(), config.display.palette, scale);
+        }
+        if let Some(halt) = detector.frame(&chip)
+            && exit_on_halt
+        {
+            let frame = lockstep.frame();
+            println!("HALT frame {} hash {:016x}", frame, chip.display_hash());
+            return Err(AppError::Halted { halt, frame });
+        }
+        if exit_when.is_some_and(|condition| condition.holds(&chip)) {
+            break;
+        }
+    }
+
+    let hash = chip.display_hash();
+    println!("frame {} hash {:016x}", lockstep.frame(), hash);
+    if let Some(condition) = exit_when
+        && !condition.holds(&chip)
+    {
+        println!("FAIL  {} never held", condition);
+        std::process::exit(1)
+    }
+    if let Some(expected) = expected
+        && hash != expected
+    {
+        println!("FAIL  expected hash {:016x}", expected);
+        std::process::exit(1)
+    }
+    Ok(())
+}
+
+// `chip8 dump-state`
+fn dump_state(args: &Args, path: &Path, format: StateFormat, frames: u64) -> Result<(), AppError> {
+    let chip = machine_of(args, path, frames)?;
+    print!("{}", state_document(&chip, format));
+    Ok(())
+}
+
+// The machine of dump-state and dump-ram: files that load as save states are states,
+// anything else a ROM run for some frames
+fn machine_of(args: &Args, path: &Path, frames: u64) -> Result<Chip8, AppError> {
+    let bytes = read_rom(path)?;
+    let mut chip = Chip8::new(&[]).expect("an empty ROM fits");
+    if chip.load_state(&bytes).is_err() {
+        let config_path = args.config.clone().or_else(config::default_path);
+        let config = rom_config(args, &load_config(config_path.as_deref())?, path, &bytes)?;
+        chip = load_chip(args, &config, path, &bytes)?;
+        let mut lockstep = Lockstep::new(args.ipf.unwrap_or(config.speed));
+        lockstep
+            .run_frames(&mut chip, frames)
+            .map_err(|error| AppError::crash(error, &chip))?;
+    }
+    Ok(chip)
+}
+
+fn state_document(chip: &Chip8, format: StateFormat) -> String {
+    match format {
+        StateFormat::Json => chip.view().to_json(),
+        StateFormat::Toml => chip.view().to_toml(),
+    }
+}
+
+// `chip8 golden record|verify`: exits with 1 on any error or differing frame
+fn run_golden(action: GoldenAction) {
+    match action {
+        GoldenAction::Record { dir, frames, file } => {
+            let file = file.unwrap_or_else(|| dir.join(golden::GOLDEN_FILE));
+            let recording = golden::record(&dir, &frames).unwrap_or_else(|error| {
+                eprintln!("ERROR: can't read {}: {}", dir.display(), error);
+                std::process::exit(1)
+            });
+            for (rom, error) in &recording.crashed {
+                eprintln!("WARNING: {} crashed ({:?}), not recorded", rom, error);
+            }
+            let snapshots = recording.snapshots;
+            if let Err(error) = std::fs::write(&file, golden::to_text(&snapshots)) {
+                eprintln!("ERROR: can't write {}: {}", file.display(), error);
+                std::process::exit(1)
+            }
+            println!("{} frames recorded in {}", snapshots.len(), file.display());
+        }
+        GoldenAction::Verify { dir, file } => {
+            let file = file.unwrap_or_else(|| dir.join(golden::GOLDEN_FILE));
+            let text = std::fs::read_to_string(&file).unwrap_or_else(|error| {
+                eprintln!("ERROR: can't read {}: {}", file.display(), error);
+                std::process::exit(1)
+            });
+            let snapshots = golden::parse(&text).unwrap_or_else(|line| {
+                eprintln!("ERROR: {} line {} is malformed", file.display(), line);
+                std::process::exit(1)
+            });
+
+            let mut failed = 0;
+            for (snapshot, verdict) in golden::verify(&dir, &snapshots) {
+                let label = format!("{} @ frame {}", snapshot.rom, snapshot.frame);
+                match verdict {
+                    Verdict::Match => continue,
+                    Verdict::Differ { expected, actual } => {
+                        println!(
+                            "DIFF  {} (expected {:016x}, got {:016x})",
+                            label, expected, actual
+                        )
+                    }
+                    Verdict::Missing => println!("MISS  {} (ROM not found)", label),
+                    Verdict::Crashed(error) => println!("FAIL  {} ({:?})", label, error),
+                }
+                failed += 1;
+            }
+
+            println!(
+                "{} of {} frames match",
+                snapshots.len() - failed,
+                snapshots.len()
+            );
+            if failed > 0 {
+                std::process::exit(1)
+            }
+        }
+    }
+}
+
+// `chip8 compat DIR`
+fn run_compat(dir: &std::path::Path, seconds: u64, format: ReportFormat, out: Option<PathBuf>) {
+    let reports = compat::run_dir(dir, seconds * 60).unwrap_or_else(|error| {
+        eprintln!("ERROR: can't read {}: {}", dir.display(), error);
+        std::process::exit(1)
+    });
+    let text = match format {
+        ReportFormat::Markdown => compat::to_markdown(&reports),
+        ReportFormat::Html => compat::to_html(&reports),
+    };
+
+    match out {
+        None => print!("{}", text),
+        Some(file) => {
+            if let Err(error) = std::fs::write(&file, text) {
+                eprintln!("ERROR: can't write {}: {}", file.display(), error);
+                std::process::exit(1)
+            }
+            println!("{} ROMs written to {}", reports.len(), file.display());
+        }
+    }
+}
+
+// `chip8 paths`, with the overrides of the environment and the file already applied
+fn print_paths(args: &Args) -> Result<(), AppError> {
+    let config_path = args.config.clone().or_else(config::default_path);
+    let saved = load_config(config_path.as_deref())?;
+    let unknown = "(no home directory)".to_string();
+    let shown = |path: Option<PathBuf>| path.map_or(unknown.clone(), |p| p.display().to_string());
+    println!("{:<12}{}", "config", shown(config_path));
+    for kind in DataKind::ALL {
+        println!("{:<12}{}", kind.name(), shown(saved.data_dir(kind)));
+    }
+    Ok(())
+}
+
+// `chip8 list`: a line per ROM, and the description under the ones the archive knows
+fn list_roms(args: &Args, dir: Option<&Path>) -> Result<(), AppError> {
+    let config_path = args.config.clone().or_else(config::default_path);
+    let saved = load_config(config_path.as_deref())?;
+    let dir = dir
+        .map(Path::to_path_buf)
+        .or(saved.paths.roms)
+        .ok_or_else(|| AppError::Setup("no DIR given and paths.roms isn't set".into()))?;
+    let archive = Archive::find(&dir).unwrap_or_else(|error| {
+        eprintln!("WARNING: {}", error);
+        None
+    });
+    let roms = archive::list(&dir, archive.as_ref())
+        .map_err(|error| AppError::Setup(format!("can't read {}: {}", dir.display(), error)))?;
+    for (rom, program) in roms {
+        let name = rom.file_name().unwrap_or_default().to_string_lossy();
+        match program {
+            Some(program) => {
+                println!("{:<24}{}", name, program.summary());
+                // the first paragraph, some go on for pages
+                if let Some(line) = program.description.lines().next() {
+                    println!("{:<24}{}", "", line);
+                }
+            }
+            None => println!("{}", name),
+        }
+    }
+    Ok(())
+}
+
+// `chip8 hexedit`: the dump without --set or --diff
+// `chip8 asm game.s`
+fn assemble(source_path: &Path, out: Option<&Path>) -> Result<(), AppError> {
+    let source = std::fs::read_to_string(source_path).map_err(|error| {
+        AppError::Setup(format!("can't read {}: {}", source_path.display(), error))
+    })?;
+    let rom = asm::assemble(&source)
+        .map_err(|error| AppError::Setup(format!("{}, {}", source_path.display(), error)))?;
+    let out = out.map_or_else(|| source_path.with_extension("ch8"), Path::to_path_buf);
+    std::fs::write(&out, &rom)
+        .map_err(|error| AppError::Setup(format!("can't write {}: {}", out.display(), error)))?;
+    println!("{} bytes to {}", rom.len(), out.display());
+    Ok(())
+}
+
+fn hexedit(
+    rom_path: &Path,
+    edits: &[Patch],
+    out: Option<&Path>,
+    other: Option<&Path>,
+) -> Result<(), AppError> {
+    let mut rom = read_rom(rom_path)?;
+    if let Err(offset) = hexedit::apply(&mut rom, edits) {
+        return Err(AppError::Setup(format!(
+            "{:#X} is past the end of {} ({} bytes), nothing was written",
+            offset,
+            rom_path.display(),
+            rom.len()
+        )));
+    }
+    if let Some(out) = out {
+        std::fs::write(out, &rom).map_err(|error| {
+            AppError::Setup(format!("can't write {}: {}", out.display(), error))
+        })?;
+    }
+    let Some(other) = other else {
+        if out.is_none() {
+            print!("{}", hexedit::dump(&rom));
         }
+        return Ok(());
+    };
+
+    let differences = hexedit::diff(&rom, &read_rom(other)?);
+    let byte = |value: Option<u8>| value.map_or("--".into(), |v| format!("{:02X}", v));
+    for difference in &differences {
+        println!(
+            "{:04X}: {} {}",
+            difference.offset,
+            byte(difference.ours),
+            byte(difference.theirs)
+        );
+    }
+    if !differences.is_empty() {
+        std::process::exit(1)
     }
+    Ok(())
+}
+
+// Rewind keeps a snapshot every 2 frames for 30 seconds, and holding the hotkey goes back
+// 2 frames per frame shown, twice as fast as the game went
+const REWIND_EVERY: u32 = 2;
+const REWIND_SNAPSHOTS: usize = 30 * 60 / REWIND_EVERY as usize;
+const REWIND_STEP: u32 = 2;
+
+const DEBUG_HELP: &str = "\
+step [N]       s, run N instructions (1)
+step-back [N]  b, undo the last N instructions (1)
+trace [N]      list the last N instructions (10) with their numbers
+goto N         back to instruction number N of the trace, PC on it
+when COND      the last instruction after which COND (like v5==0 or mem[0x3FF]!=0)
+               started to hold, from as far back as step-back goes
+draw           run until the next Dxyn
+clear          run until the next 00E0
+sound          run until the buzzer starts or stops
+continue       c, run until a break-on stops it
+break-on WHAT  stop on keypress (Ex9E, ExA1, Fx0A), key K (the same for key K), sound
+               (Fx18 starting the buzzer), smc (code the program wrote) or data (code
+               where the ROM seems to have data). Without WHAT, lists them
+break-off      forget every break-on
+break [ADDR]   stop with PC on ADDR, before the instruction there runs. Without ADDR,
+               lists the breakpoints
+delete ADDR    remove the breakpoint on ADDR, or every one with `delete all`
+watch ADDR[-END] [read|write]
+               stop after an instruction reads or writes RAM from ADDR to END (Dxyn,
+               Fx33, Fx55, Fx65), both without read or write. Without ADDR, lists them
+unwatch ADDR   remove the watchpoints from ADDR, or every one with `unwatch all`
+screen         print the screen
+diff           turn on or off printing what every command changed
+dump-state [F] print the machine as json (the default) or toml
+dump-ram FILE  write the 4 KiB of RAM to FILE
+load-ram FILE [PC [I]]
+               replace RAM with an image from FILE, PC and I stay unless given
+key K up|down  press or release key K (0 to F)
+quit           q
+An empty line repeats the last command";
+
+// `chip8 debug`: a prompt on stdin, the machine is printed after every command
+fn run_debugger(args: &Args, rom_path: &Path) -> Result<(), AppError> {
+    let rom = read_rom(rom_path)?;
+    let config_path = args.config.clone().or_else(config::default_path);
+    let config = rom_config(args, &load_config(config_path.as_deref())?, rom_path, &rom)?;
+    let mut chip = load_chip(args, &config, rom_path, &rom)?;
+    let mut debugger = Debugger::new(args.ipf.unwrap_or(config.speed), &chip);
 
+    println!("{}", debug_status(&debugger, &chip));
+    let mut last = String::new();
+    let mut show_diff = false;
+    for line in io::stdin().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if !line.trim().is_empty() {
+            last = line;
+        }
+        let before = show_diff.then(|| chip.view());
+        let words: Vec<&str> = last.split_whitespace().collect();
+        let count = || words.get(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+        match words.as_slice() {
+            ["s" | "step", ..] => {
+                for _ in 0..count() {
+                    if let Err(error) = debugger.step(&mut chip) {
+                        println!("{:?}", error);
+                        break;
+                    }
+                    if let Some(hit) = debugger.watch_hit() {
+                        print_watch_hit(hit);
+                        break;
+                    }
+                    if let Some(condition) = debugger.break_at(&chip) {
+                        print_break(condition);
+                        break;
+                    }
+                }
+            }
+            ["b" | "step-back", ..] => {
+                for _ in 0..count() {
+                    if !debugger.step_back(&mut chip) {
+                        println!("can't go further back");
+                        break;
+                    }
+                }
+                // the replay prints what was already printed the first time
+                chip.take_debug_output();
+            }
+            ["trace", ..] => {
+                let count = words.get(1).and_then(|n| n.parse().ok()).unwrap_or(10);
+                for traced in debugger.trace(count) {
+                    println!(
+                        "#{} {:03X}: {:04X}",
+                        traced.number, traced.pc, traced.opcode
+                    );
+                }
+            }
+            ["goto", number] => {
+                let number = number.trim_start_matches('#').parse();
+                if !number.is_ok_and(|number| debugger.go_to(&mut chip, number)) {
+                    println!("not an instruction in the trace");
+                }
+                chip.take_debug_output();
+            }
+            ["when", condition] => match parse_condition(condition) {
+                Ok(condition) => match debugger.when(|chip| condition.holds(chip)) {
+                    Some(number) => println!("{} since #{}", condition, number),
+                    None => println!("{} never held", condition),
+                },
+                Err(error) => println!("{}", error),
+            },
+            [target @ ("draw" | "clear" | "sound")] => {
+                let goal = match *target {
+                    "draw" => Target::Draw,
+                    "clear" => Target::Clear,
+                    _ => Target::Sound,
+                };
+                let stop = debugger.run_to(&mut chip, goal, debugger::RUN_LIMIT);
+                print_stop(stop, target);
+            }
+            ["c" | "continue"] => {
+                let stop = debugger.run(&mut chip, debugger::RUN_LIMIT);
+                print_stop(stop, "break");
+            }
+            ["break-on"] => println!("{:?}", debugger.breaks()),
+            ["break-on", "keypress"] => debugger.break_on(Break::Input),
+            ["break-on", "sound"] => debugger.break_on(Break::Sound),
+            ["break-on", "smc"] => debugger.break_on(Break::Written),
+            ["break-on", "data"] => debugger.break_on(Break::Data),
+            ["break-on", "key", key] => {
+                match u8::from_str_radix(key.trim_start_matches("0x"), 16) {
+                    Ok(key) if key < 16 => debugger.break_on(Break::Key(key)),
+                    _ => println!("keys go from 0 to F"),
+                }
+            }
+            ["break-off"] => debugger.clear_breaks(),
+            ["break"] => {
+                let addresses: Vec<String> = debugger
+                    .breakpoints()
+                    .map(|pc| format!("{:03X}", pc))
+                    .collect();
+                println!("breakpoints: {}", addresses.join(" "));
+            }
+            ["break", address] => match memory::parse_address(address) {
+                Some(pc) => {
+                    debugger.set_breakpoint(pc);
+                }
+                None => println!("not an address in RAM"),
+            },
+            ["watch"] => {
+                for watchpoint in debugger.watchpoints() {
+                    let access = match (watchpoint.read, watchpoint.write) {
+                        (true, false) => "read",
+                        (false, true) => "write",
+                        _ => "read and write",
+                    };
+                    println!("{:03X}-{:03X} {}", watchpoint.start, watchpoint.end, access);
+                }
+            }
+            ["watch", range, access @ ..] if access.len() <= 1 => {
+                let (read, write) = match access.first() {
+                    None => (true, true),
+                    Some(&"read") => (true, false),
+                    Some(&"write") => (false, true),
+                    Some(_) => {
+                        println!("watch read or write, or both without either");
+                        continue;
+                    }
+                };
+                let (start, end) = range.split_once('-').unwrap_or((range, range));
+                match (memory::parse_address(start), memory::parse_address(end)) {
+                    (Some(start), Some(end)) if start <= end => debugger.watch(Watchpoint {
+                        start,
+                        end,
+                        read,
+                        write,
+                    }),
+                    _ => println!("not a range of RAM"),
+                }
+            }
+            ["unwatch", "all"] => debugger.clear_watchpoints(),
+            ["unwatch", address] => {
+                if !memory::parse_address(address).is_some_and(|start| debugger.unwatch(start)) {
+                    println!("no watchpoint there");
+                }
+            }
+            ["delete", "all"] => debugger.clear_breakpoints(),
+            ["delete", address] => {
+                if !memory::parse_address(address).is_some_and(|pc| debugger.clear_breakpoint(pc)) {
+                    println!("no breakpoint there");
+                }
+            }
+            ["screen"] => print!("{}", chip.display_ascii()),
+            ["dump-state"] => print!("{}", state_document(&chip, StateFormat::Json)),
+            ["dump-state", "json"] => print!("{}", state_document(&chip, StateFormat::Json)),
+            ["dump-state", "toml"] => print!("{}", state_document(&chip, StateFormat::Toml)),
+            ["dump-ram", file] => {
+                if let Err(error) = dump_ram(&chip, Path::new(file)) {
+                    println!("{}", error);
+                }
+            }
+            ["load-ram", file, registers @ ..] if registers.len() <= 2 => {
+                let registers: Option<Vec<u16>> = registers
+                    .iter()
+                    .map(|text| memory::parse_address(text))
+                    .collect();
+                let Some(registers) = registers else {
+                    println!("PC and I are addresses in RAM");
+                    continue;
+                };
+                let pc = registers.first().copied().unwrap_or(*chip.get_pc());
+                let i = registers.get(1).copied().unwrap_or(*chip.get_i());
+                if let Err(error) = load_ram(&mut chip, Path::new(file), pc, i) {
+                    println!("{}", error);
+                }
+                debugger.checkpoint(&chip);
+            }
+            ["diff"] => {
+                show_diff = !show_diff;
+                println!("diff {}", if show_diff { "on" } else { "off" });
+            }
+            ["key", key, state @ ("up" | "down")] => {
+                let key = usize::from_str_radix(key, 16).unwrap_or(16);
+                if chip.keyboard.set_key(key, *state == "down").is_err() {
+                    println!("keys go from 0 to F");
+                }
+                debugger.checkpoint(&chip);
+            }
+            ["q" | "quit"] => break,
+            [] => {}
+            _ => println!("{}", DEBUG_HELP),
+        }
+        for (pc, kind) in debugger.take_warnings() {
+            let what = match kind {
+                Suspicious::Written => "bytes the program wrote itself",
+                Suspicious::Data => "what looked like data in the ROM",
+            };
+            println!("warning: PC reached {:03X}, {}", pc, what);
+        }
+        for output in chip.take_debug_output() {
+            println!("debug {}", output);
+        }
+        if let Some(before) = before {
+            print!("{}", before.diff(&chip.view()));
+        }
+        println!("{}", debug_status(&debugger, &chip));
+    }
     Ok(())
 }
+
+fn print_stop(stop: Result<Stop, Chip8Error>, target: &str) {
+    match stop {
+        Ok(Stop::Reached) => {}
+        Ok(Stop::Break(condition)) => print_break(condition),
+        Ok(Stop::Watch(hit)) => print_watch_hit(hit),
+        Ok(Stop::Limit) => println!("no {} in {} instructions", target, debugger::RUN_LIMIT),
+        Err(error) => println!("{:?}", error),
+    }
+}
+
+fn print_watch_hit(hit: WatchHit) {
+    let access = match hit.access {
+        Access::Read => "read",
+        Access::Write => "wrote",
+    };
+    println!(
+        "watchpoint: {:03X} {} {:03X}, {:02X} there",
+        hit.pc, access, hit.address, hit.value
+    );
+}
+
+fn print_break(condition: Break) {
+    match condition {
+        Break::At(pc) => println!("breakpoint at {:03X}", pc),
+        condition => println!("break on {:?}", condition),
+    }
+}
+
+// #instructions (frame) PC: opcode, then the registers
+fn debug_status(debugger: &Debugger, chip: &Chip8) -> String {
+    let pc = *chip.get_pc();
+    let opcode = debugger::next_opcode(chip).map_or("----".into(), |op| format!("{:04X}", op));
+    let registers: Vec<String> = chip
+        .registers()
+        .iter()
+        .enumerate()
+        .map(|(index, value)| format!("V{:X}={:02X}", index, value))
+        .collect();
+    format!(
+        "#{} (frame {}) {:03X}: {}  {} I={:03X} DT={:02X} ST={:02X}",
+        debugger.executed(),
+        debugger.frame(),
+        pc,
+        opcode,
+        registers.join(" "),
+        chip.get_i(),
+        chip.get_dt(),
+        chip.get_st()
+    )
+}
+
+// `chip8 selftest`: exits with 1 if any check fails
+fn run_selftest() {
+    let mut failed = false;
+    for (name, result) in selftest::run_all() {
+        match result {
+            Ok(()) => println!("ok    {}", name),
+            Err(message) => {
+                println!("FAIL  {}: {}", name, message);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        std::process::exit(1)
+    }
+}
@@ -0,0 +1,164 @@
+// Where things live in RAM, so the loader, the CPU and tools agree on the addresses
+// instead of each having its own 0x50 and 0x200:
+//     0x000..0x050  reserved, the interpreter lived here on the COSMAC VIP
+//     0x050..0x0A0  the font, 16 glyphs of 5 bytes
+//     0x0A0..0x140  the large font of SUPER-CHIP, 16 glyphs of 10 bytes
+//     0x140..0x200  work RAM, nothing uses it, free scratch space for tools
+//     0x200..0x1000 the program, where ROMs are loaded and PC starts
+//     0x1000..      extended memory, only XO-CHIP machines have it
+use core::ops::Range;
+
+pub const RAM_SIZE: usize = 4096;
+pub const FONT_START: usize = 0x50;
+pub const FONT_SIZE: usize = 16 * 5;
+pub const LARGE_FONT_START: usize = FONT_START + FONT_SIZE;
+pub const LARGE_FONT_SIZE: usize = 16 * 10;
+pub const PROGRAM_START: usize = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Reserved,
+    Font,
+    LargeFont,
+    WorkRam,
+    Program,
+    Extended,
+}
+
+impl Region {
+    pub const ALL: [Region; 6] = [
+        Region::Reserved,
+        Region::Font,
+        Region::LargeFont,
+        Region::WorkRam,
+        Region::Program,
+        Region::Extended,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::Reserved => "reserved",
+            Region::Font => "font",
+            Region::LargeFont => "large_font",
+            Region::WorkRam => "work_ram",
+            Region::Program => "program",
+            Region::Extended => "extended",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Region> {
+        Region::ALL.into_iter().find(|region| region.name() == name)
+    }
+}
+
+// The regions of a machine with `size` bytes of RAM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub size: usize,
+}
+
+impl MemoryMap {
+    pub const CHIP8: MemoryMap = MemoryMap { size: RAM_SIZE };
+    pub const XO_CHIP: MemoryMap = MemoryMap { size: 0x10000 };
+
+    pub const fn range(&self, region: Region) -> Range<usize> {
+        match region {
+            Region::Reserved => 0..FONT_START,
+            Region::Font => FONT_START..FONT_START + FONT_SIZE,
+            Region::LargeFont => LARGE_FONT_START..LARGE_FONT_START + LARGE_FONT_SIZE,
+            Region::WorkRam => LARGE_FONT_START + LARGE_FONT_SIZE..PROGRAM_START,
+            Region::Program => PROGRAM_START..RAM_SIZE,
+            // empty on a 4 KiB machine
+            Region::Extended => RAM_SIZE..self.size,
+        }
+    }
+
+    pub fn region_of(&self, address: usize) -> Option<Region> {
+        Region::ALL
+            .into_iter()
+            .find(|&region| self.range(region).contains(&address))
+    }
+
+    // `len` bytes at `offset` into the region, None if they don't all fit in it
+    pub fn checked_range(&self, region: Region, offset: usize, len: usize) -> Option<Range<usize>> {
+        let range = self.range(region);
+        let start = range.start.checked_add(offset)?;
+        let end = start.checked_add(len)?;
+        (end <= range.end).then_some(start..end)
+    }
+}
+
+// One byte of RAM overwritten after loading, to fix a ROM or skip its intro without a hex
+// editor. "0x2A4=0xEE" on the command line and in chip8.toml
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Patch {
+    pub address: u16,
+    pub value: u8,
+}
+
+impl Patch {
+    // "address=value", each one hex with 0x or decimal, the address inside RAM
+    pub fn parse(text: &str) -> Option<Patch> {
+        let (address, value) = text.split_once('=')?;
+        let address = parse_address(address.trim())?;
+        let value = u8::try_from(parse_number(value.trim())?).ok()?;
+        Some(Patch { address, value })
+    }
+}
+
+// An address inside RAM, hex with 0x or decimal
+pub fn parse_address(text: &str) -> Option<u16> {
+    let address = u16::try_from(parse_number(text)?).ok()?;
+    ((address as usize) < RAM_SIZE).then_some(address)
+}
+
+pub(crate) fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions_cover_ram() {
+        let map = MemoryMap::CHIP8;
+        let mut next = 0;
+        for region in Region::ALL {
+            let range = map.range(region);
+            assert_eq!(range.start, next, "{:?}", region);
+            next = range.end;
+            assert_eq!(Region::parse(region.name()), Some(region));
+        }
+        assert_eq!(next, RAM_SIZE);
+        assert!(map.range(Region::Extended).is_empty());
+        assert_eq!(MemoryMap::XO_CHIP.range(Region::Extended).len(), 0xF000);
+
+        assert_eq!(map.region_of(0x50), Some(Region::Font));
+        assert_eq!(map.region_of(0x9F), Some(Region::Font));
+        assert_eq!(map.region_of(0xA0), Some(Region::LargeFont));
+        assert_eq!(map.region_of(0x140), Some(Region::WorkRam));
+        assert_eq!(map.region_of(0xFFF), Some(Region::Program));
+        assert_eq!(map.region_of(0x1000), None);
+
+        assert_eq!(map.checked_range(Region::Font, 5, 5), Some(0x55..0x5A));
+        assert_eq!(map.checked_range(Region::Font, 76, 5), None);
+        assert_eq!(map.checked_range(Region::Program, usize::MAX, 1), None);
+    }
+
+    #[test]
+    fn test_parse_patch() {
+        let patch = Patch {
+            address: 0x2A4,
+            value: 0xEE,
+        };
+        assert_eq!(Patch::parse("0x2A4=0xEE"), Some(patch));
+        assert_eq!(Patch::parse(" 676 = 238 "), Some(patch));
+        for bad in ["0x2A4", "0x1000=0", "0x200=0x100", "zz=1", "0x200=-1"] {
+            assert_eq!(Patch::parse(bad), None, "{}", bad);
+        }
+    }
+}
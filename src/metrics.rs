@@ -0,0 +1,184 @@
+// Opt-in performance numbers for apps embedding the emulator: frame time histogram,
+// instructions per second, dropped frames and audio underruns. Nothing here knows about a
+// metrics backend, apps read a snapshot and forward it wherever they want, to_prometheus
+// covers the common case.
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+// Upper bounds of the frame time buckets, in seconds. 1/60 s is a frame on time
+pub const FRAME_TIME_BUCKETS: [f64; 8] = [0.004, 0.008, 0.0167, 0.02, 0.0334, 0.05, 0.1, 0.25];
+
+const FRAME: Duration = Duration::from_micros(16_667);
+// how often the instructions per second are worked out
+const IPS_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsSnapshot {
+    pub frames: u64,
+    // frames per bucket of FRAME_TIME_BUCKETS, the extra last one is everything slower
+    pub frame_time_buckets: [u64; 9],
+    pub frame_time_sum: Duration,
+    pub instructions: u64,
+    // over the last second
+    pub instructions_per_second: f64,
+    pub dropped_frames: u64,
+    pub audio_underruns: u64,
+}
+
+pub struct Metrics {
+    snapshot: MetricsSnapshot,
+    last_frame: Option<Instant>,
+    window_start: Option<Instant>,
+    window_instructions: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            snapshot: MetricsSnapshot {
+                frames: 0,
+                frame_time_buckets: [0; 9],
+                frame_time_sum: Duration::ZERO,
+                instructions: 0,
+                instructions_per_second: 0.0,
+                dropped_frames: 0,
+                audio_underruns: 0,
+            },
+            last_frame: None,
+            window_start: None,
+            window_instructions: 0,
+        }
+    }
+
+    // Called once per frame by the run loop with the instructions it ran. Frame time is the
+    // time since the previous call, so it includes the waiting of the loop
+    pub fn record_frame(&mut self, instructions: u64) {
+        self.record_frame_at(Instant::now(), instructions);
+    }
+
+    fn record_frame_at(&mut self, now: Instant, instructions: u64) {
+        let snapshot = &mut self.snapshot;
+        snapshot.instructions += instructions;
+
+        if let Some(last) = self.last_frame {
+            let frame_time = now - last;
+            snapshot.frames += 1;
+            snapshot.frame_time_sum += frame_time;
+            let seconds = frame_time.as_secs_f64();
+            let bucket = FRAME_TIME_BUCKETS
+                .iter()
+                .position(|&bound| seconds <= bound)
+                .unwrap_or(FRAME_TIME_BUCKETS.len());
+            snapshot.frame_time_buckets[bucket] += 1;
+            // a frame that took 3 frames of time means 2 frames were never shown
+            let late = (frame_time.as_micros() / FRAME.as_micros()) as u64;
+            snapshot.dropped_frames += late.saturating_sub(1);
+        }
+        self.last_frame = Some(now);
+
+        // the instructions of the very first frame ran before anything was measured
+        let Some(start) = self.window_start else {
+            self.window_start = Some(now);
+            return;
+        };
+        self.window_instructions += instructions;
+        let window = now - start;
+        if window >= IPS_WINDOW {
+            snapshot.instructions_per_second =
+                self.window_instructions as f64 / window.as_secs_f64();
+            self.window_start = Some(now);
+            self.window_instructions = 0;
+        }
+    }
+
+    // For the audio side: the device wanted samples and there were none ready
+    pub fn record_audio_underrun(&mut self) {
+        self.snapshot.audio_underruns += 1;
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.snapshot.clone()
+    }
+
+    // Prometheus text format, ready to be served on /metrics
+    pub fn to_prometheus(&self) -> String {
+        let snapshot = &self.snapshot;
+        let mut text = String::new();
+        text.push_str("# TYPE chip8_frame_time_seconds histogram\n");
+        let mut cumulative = 0;
+        for (bound, count) in FRAME_TIME_BUCKETS.iter().zip(snapshot.frame_time_buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                text,
+                "chip8_frame_time_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            text,
+            "chip8_frame_time_seconds_bucket{{le=\"+Inf\"}} {}",
+            snapshot.frames
+        );
+        let _ = writeln!(
+            text,
+            "chip8_frame_time_seconds_sum {}",
+            snapshot.frame_time_sum.as_secs_f64()
+        );
+        let _ = writeln!(text, "chip8_frame_time_seconds_count {}", snapshot.frames);
+        let instructions = snapshot.instructions as f64;
+        write_metric(&mut text, "instructions_total", "counter", instructions);
+        let ips = snapshot.instructions_per_second;
+        write_metric(&mut text, "instructions_per_second", "gauge", ips);
+        let dropped = snapshot.dropped_frames as f64;
+        write_metric(&mut text, "dropped_frames_total", "counter", dropped);
+        let underruns = snapshot.audio_underruns as f64;
+        write_metric(&mut text, "audio_underruns_total", "counter", underruns);
+        text
+    }
+}
+
+fn write_metric(text: &mut String, name: &str, kind: &str, value: f64) {
+    let _ = writeln!(
+        text,
+        "# TYPE chip8_{} {}\nchip8_{} {}",
+        name, kind, name, value
+    );
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_and_rates() {
+        let mut metrics = Metrics::new();
+        let start = Instant::now();
+        // 60 frames on time, then one that took 3 frames
+        for frame in 0..=60 {
+            metrics.record_frame_at(start + FRAME * frame, 10);
+        }
+        metrics.record_frame_at(start + FRAME * 63, 10);
+        metrics.record_audio_underrun();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.frames, 61);
+        assert_eq!(snapshot.frame_time_buckets[2], 60);
+        assert_eq!(snapshot.frame_time_buckets[6], 1);
+        assert_eq!(snapshot.instructions, 620);
+        assert_eq!(snapshot.dropped_frames, 2);
+        assert_eq!(snapshot.audio_underruns, 1);
+        // 600 instructions after the first frame, in a second
+        assert!((snapshot.instructions_per_second - 600.0).abs() < 1.0);
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("chip8_frame_time_seconds_bucket{le=\"0.0167\"} 60\n"));
+        assert!(text.contains("chip8_frame_time_seconds_bucket{le=\"+Inf\"} 61\n"));
+        assert!(text.contains("chip8_dropped_frames_total 2\n"));
+    }
+}
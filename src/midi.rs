@@ -0,0 +1,123 @@
+use crate::keyboard::Keyboard;
+use std::sync::mpsc::{self, Receiver};
+
+// MIDI notes as keypad keys, for driving sound toys and livecoding from a keyboard or a
+// sequencer. Sixteen notes in a row from `base` are keys 0 to F, note-on presses one and
+// note-off (or note-on with velocity 0) lets it go. The channel doesn't matter.
+
+// middle C is key 0, so keys 0 to F are C4 to D#5
+pub const DEFAULT_BASE: u8 = 60;
+
+pub struct MidiKeys {
+    // note changes from the MIDI thread, applied on poll
+    events: Receiver<(usize, bool)>,
+    held: [bool; 16],
+    port: String,
+    _connection: midir::MidiInputConnection<()>,
+}
+
+impl MidiKeys {
+    // Listens on the first input port whose name contains `port` (any case), or the one at
+    // that index in the list of ports
+    pub fn open(port: &str, base: u8) -> Result<Self, String> {
+        let mut input = midir::MidiInput::new("chip8").map_err(|error| error.to_string())?;
+        input.ignore(midir::Ignore::All);
+        let ports = input.ports();
+        let names: Vec<String> = ports
+            .iter()
+            .map(|port| input.port_name(port).unwrap_or_default())
+            .collect();
+        let wanted = port.to_lowercase();
+        let index = port
+            .parse::<usize>()
+            .ok()
+            .filter(|&index| index < ports.len())
+            .or_else(|| {
+                names
+                    .iter()
+                    .position(|name| name.to_lowercase().contains(&wanted))
+            })
+            .ok_or_else(|| {
+                if names.is_empty() {
+                    "there are no MIDI input ports".to_string()
+                } else {
+                    format!("no MIDI port like `{}` in {}", port, names.join(", "))
+                }
+            })?;
+
+        let (sender, events) = mpsc::channel();
+        let connection = input
+            .connect(
+                &ports[index],
+                "chip8 keypad",
+                move |_, message, _| {
+                    if let Some(change) = key_change(message, base) {
+                        let _ = sender.send(change);
+                    }
+                },
+                (),
+            )
+            .map_err(|error| error.to_string())?;
+        Ok(MidiKeys {
+            events,
+            held: [false; 16],
+            port: names[index].clone(),
+            _connection: connection,
+        })
+    }
+
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    // Called after the frontend read its keys: notes held keep their keys down, notes let go
+    // since the last poll let their keys up
+    pub fn poll(&mut self, keyboard: &mut Keyboard) {
+        for (key, pressed) in self.events.try_iter() {
+            self.held[key] = pressed;
+            if !pressed {
+                let _ = keyboard.set_key(key, false);
+            }
+        }
+        for (key, &held) in self.held.iter().enumerate() {
+            if held {
+                let _ = keyboard.set_key(key, true);
+            }
+        }
+    }
+}
+
+// The key a MIDI message presses (true) or lets go (false), None for other messages and
+// notes outside the 16 from base
+pub fn key_change(message: &[u8], base: u8) -> Option<(usize, bool)> {
+    let &[status, note, velocity] = message else {
+        return None;
+    };
+    let pressed = match status & 0xF0 {
+        0x90 => velocity > 0,
+        0x80 => false,
+        _ => return None,
+    };
+    let key = note.checked_sub(base)? as usize;
+    (key < 16).then_some((key, pressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_change() {
+        // note-on of middle C on channel 1 and 10
+        assert_eq!(key_change(&[0x90, 60, 100], 60), Some((0, true)));
+        assert_eq!(key_change(&[0x99, 75, 1], 60), Some((0xF, true)));
+        assert_eq!(key_change(&[0x80, 61, 64], 60), Some((1, false)));
+        // velocity 0 is a note-off
+        assert_eq!(key_change(&[0x90, 61, 0], 60), Some((1, false)));
+        assert_eq!(key_change(&[0x90, 76, 100], 60), None);
+        assert_eq!(key_change(&[0x90, 59, 100], 60), None);
+        // control change, and a message cut short
+        assert_eq!(key_change(&[0xB0, 60, 100], 60), None);
+        assert_eq!(key_change(&[0x90, 60], 60), None);
+    }
+}
@@ -0,0 +1,230 @@
+// Two player netplay on top of lockstep mode. Both peers run the same ROM with the same seed
+// and instructions per frame, and only trade the keypad of every frame over TCP, so the two
+// machines stay identical without ever sending the screen.
+//
+// Local keys are sent `delay` frames ahead: frame N runs with the keys both players had at
+// frame N - delay. A few frames of delay hide the network round trip, if the other peer is
+// late run_frame waits for it. The keys of both players are OR'ed together, 2 player games
+// give each player its own keys anyway (1/4 and C/D in Pong).
+use crate::chip8::{Chip8, Chip8Error, rom_hash};
+use crate::keyboard::Keyboard;
+use crate::lockstep::Lockstep;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAGIC: [u8; 4] = *b"C8NP";
+const VERSION: u8 = 1;
+// 2 frames of 16.6 ms are enough on a LAN, internet games want 4 to 8
+pub const DEFAULT_DELAY: u8 = 3;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    // the other side runs another ROM or another version of this protocol
+    Mismatch(&'static str),
+    // a message arrived for the wrong frame, the peers are no longer in lockstep
+    Desync,
+    Chip8(Chip8Error),
+}
+
+impl From<io::Error> for NetplayError {
+    fn from(error: io::Error) -> Self {
+        NetplayError::Io(error)
+    }
+}
+
+pub struct Netplay {
+    stream: TcpStream,
+    lockstep: Lockstep,
+    delay: u64,
+    // keys sent but not played yet, the first `delay` frames play with no keys
+    local: VecDeque<u16>,
+}
+
+impl Netplay {
+    // Waits for one guest, then sends it the settings of the session. The seed of `chip` is
+    // replaced by one both machines share
+    pub fn host(
+        listener: &TcpListener,
+        chip: &mut Chip8,
+        rom: &[u8],
+        ipf: u32,
+        delay: u8,
+    ) -> Result<Self, NetplayError> {
+        let (mut stream, _) = listener.accept()?;
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+
+        let mut hello = Vec::with_capacity(26);
+        hello.extend_from_slice(&MAGIC);
+        hello.push(VERSION);
+        hello.push(delay);
+        hello.extend_from_slice(&ipf.to_le_bytes());
+        hello.extend_from_slice(&seed.to_le_bytes());
+        hello.extend_from_slice(&rom_hash(rom).to_le_bytes());
+        stream.write_all(&hello)?;
+
+        chip.set_seed(seed);
+        Self::start(stream, ipf, delay)
+    }
+
+    // Connects to a host and takes its settings, the ROM has to be the same file
+    pub fn join(
+        addr: impl ToSocketAddrs,
+        chip: &mut Chip8,
+        rom: &[u8],
+    ) -> Result<Self, NetplayError> {
+        let mut stream = TcpStream::connect(addr)?;
+        let mut hello = [0u8; 26];
+        stream.read_exact(&mut hello)?;
+        if hello[0..4] != MAGIC || hello[4] != VERSION {
+            return Err(NetplayError::Mismatch(
+                "not a chip8 netplay host of this version",
+            ));
+        }
+        let delay = hello[5];
+        let ipf = u32::from_le_bytes(hello[6..10].try_into().unwrap());
+        let seed = u64::from_le_bytes(hello[10..18].try_into().unwrap());
+        if u64::from_le_bytes(hello[18..26].try_into().unwrap()) != rom_hash(rom) {
+            return Err(NetplayError::Mismatch("the host runs a different ROM"));
+        }
+
+        chip.set_seed(seed);
+        Self::start(stream, ipf, delay)
+    }
+
+    fn start(stream: TcpStream, ipf: u32, delay: u8) -> Result<Self, NetplayError> {
+        // every message is tiny and late ones stall both players
+        stream.set_nodelay(true)?;
+        Ok(Netplay {
+            stream,
+            lockstep: Lockstep::new(ipf),
+            delay: delay as u64,
+            local: VecDeque::from(vec![0; delay as usize]),
+        })
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.lockstep.frame()
+    }
+
+    pub fn delay(&self) -> u64 {
+        self.delay
+    }
+
+    // Sends this frame's local keys, waits for the other player's keys of the frame about to
+    // run and runs it with both
+    pub fn run_frame(&mut self, chip: &mut Chip8, keys: &Keyboard) -> Result<(), NetplayError> {
+        let frame = self.lockstep.frame();
+        let local = keys.bitmask();
+        let mut message = [0u8; 10];
+        message[..8].copy_from_slice(&(frame + self.delay).to_le_bytes());
+        message[8..].copy_from_slice(&local.to_le_bytes());
+        self.stream.write_all(&message)?;
+        self.local.push_back(local);
+
+        let remote = if frame < self.delay {
+            0
+        } else {
+            self.stream.read_exact(&mut message)?;
+            if u64::from_le_bytes(message[..8].try_into().unwrap()) != frame {
+                return Err(NetplayError::Desync);
+            }
+            u16::from_le_bytes([message[8], message[9]])
+        };
+
+        let keys = self.local.pop_front().unwrap_or(0) | remote;
+        for key in 0..16 {
+            chip.keyboard
+                .set_key(key, keys & (1 << key) != 0)
+                .map_err(NetplayError::Chip8)?;
+        }
+        self.lockstep.run_frame(chip).map_err(NetplayError::Chip8)
+    }
+}
+
+// Closing a socket with unread keys in it resets the connection, and the reset can throw
+// away keys the other peer still has to play. So say we're done and read until it is too
+impl Drop for Netplay {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Write);
+        let _ = self.stream.set_read_timeout(Some(Duration::from_secs(1)));
+        let _ = io::copy(&mut self.stream, &mut io::sink());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // V0 = random, draws its digit at a random spot, adds key 5 (player 1) and key 9
+    // (player 2) presses into V6/V7, loops
+    const ROM: [u8; 26] = [
+        0xC0, 0x0F, // 200: V0 = rand & 0xF
+        0xF0, 0x29, // 202: I = font(V0)
+        0xC1, 0x3F, // 204: V1 = rand & 0x3F
+        0xD1, 0x15, // 206: draw V1, V1, 5
+        0x62, 0x05, // 208: V2 = 5
+        0xE2, 0xA1, // 20A: skip if key V2 not pressed
+        0x76, 0x01, // 20C: V6 += 1
+        0x62, 0x09, // 20E: V2 = 9
+        0xE2, 0xA1, // 210: skip if key V2 not pressed
+        0x77, 0x01, // 212: V7 += 1
+        0x12, 0x00, // 214: jump 200
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    fn play(mut netplay: Netplay, mut chip: Chip8, key: usize) -> Chip8 {
+        let mut keys = Keyboard::new();
+        for frame in 0..60 {
+            keys.set_key(key, (10..20).contains(&frame)).unwrap();
+            netplay.run_frame(&mut chip, &keys).unwrap();
+        }
+        chip
+    }
+
+    #[test]
+    fn test_peers_stay_in_sync() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let guest = thread::spawn(move || {
+            let mut chip = Chip8::new(&ROM).unwrap();
+            let netplay = Netplay::join(addr, &mut chip, &ROM).unwrap();
+            play(netplay, chip, 9)
+        });
+
+        let mut chip = Chip8::new(&ROM).unwrap();
+        let netplay = Netplay::host(&listener, &mut chip, &ROM, 10, DEFAULT_DELAY).unwrap();
+        assert_eq!(netplay.delay(), 3);
+        let host = play(netplay, chip, 5);
+        let guest = guest.join().unwrap();
+
+        assert_eq!(host.display_hash(), guest.display_hash());
+        for register in 0..16 {
+            assert_eq!(host.get_v(register), guest.get_v(register));
+        }
+        // both players' keys reached both machines
+        assert!(host.get_v(6).unwrap() > 0);
+        assert!(host.get_v(7).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_join_refuses_other_rom() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let guest = thread::spawn(move || {
+            let mut chip = Chip8::default();
+            Netplay::join(addr, &mut chip, &[0x12, 0x00]).err()
+        });
+        let mut chip = Chip8::new(&ROM).unwrap();
+        let _host = Netplay::host(&listener, &mut chip, &ROM, 10, 2).unwrap();
+        assert!(matches!(
+            guest.join().unwrap(),
+            Some(NetplayError::Mismatch(_))
+        ));
+    }
+}
@@ -0,0 +1,460 @@
+// On-screen messages like "CONFIG RELOADED", shown over the game for a few seconds, the
+// keypad overlay and the frame time graph. The frontends paint them: layout and keypad_layout say where the boxes and
+// the dots of the text go, in a tiny 3x5 font that only knows capitals, digits and some
+// punctuation.
+use crate::keyboard::Keymap;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+// glyph plus one pixel of space
+pub const ADVANCE: usize = GLYPH_WIDTH + 1;
+// the same between lines
+pub const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+#[derive(Default)]
+pub struct Osd {
+    message: Option<(String, Instant)>,
+    changed: bool,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn show(&mut self, text: impl Into<String>) {
+        self.message = Some((text.into(), Instant::now() + MESSAGE_TIME));
+        self.changed = true;
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_ref().map(|(text, _)| text.as_str())
+    }
+
+    // Called once per loop, true when the message appeared or went away since the last call
+    // so the frontend knows to draw again
+    pub fn tick(&mut self) -> bool {
+        if self
+            .message
+            .as_ref()
+            .is_some_and(|(_, until)| *until <= Instant::now())
+        {
+            self.message = None;
+            self.changed = true;
+        }
+        std::mem::take(&mut self.changed)
+    }
+}
+
+// Where a message goes on a screen of width x height, with font pixels of `pixel` screen
+// pixels: a box along the bottom edge, and the top left corner of every lit font pixel
+pub struct Layout {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub dots: Vec<(usize, usize)>,
+}
+
+pub fn layout(text: &str, width: usize, height: usize, pixel: usize) -> Layout {
+    let pixel = pixel.max(1);
+    // as many characters as fit, with one font pixel of margin all around
+    let fits = width.saturating_sub(pixel) / (ADVANCE * pixel);
+    let chars = text.chars().count().min(fits);
+    let box_width = (chars * ADVANCE + 1) * pixel;
+    let box_height = (GLYPH_HEIGHT + 2) * pixel;
+    let (x, y) = (0, height.saturating_sub(box_height));
+
+    let mut dots = Vec::new();
+    let text: String = text.chars().take(chars).collect();
+    text_dots(&text, x + pixel, y + pixel, pixel, &mut dots);
+
+    Layout {
+        x,
+        y,
+        width: box_width.min(width),
+        height: box_height.min(height),
+        dots,
+    }
+}
+
+// Lines of text from the top left corner with a font pixel of margin, for windows of their
+// own like the debug window. width and height fit the longest line and every line
+pub struct TextLayout {
+    pub width: usize,
+    pub height: usize,
+    pub dots: Vec<(usize, usize)>,
+}
+
+pub fn text_layout(lines: &[String], pixel: usize) -> TextLayout {
+    let pixel = pixel.max(1);
+    let columns = lines.iter().map(|line| line.chars().count()).max();
+    let mut dots = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let y = (1 + index * LINE_HEIGHT) * pixel;
+        text_dots(line, pixel, y, pixel, &mut dots);
+    }
+    TextLayout {
+        width: (columns.unwrap_or(0) * ADVANCE + 1) * pixel,
+        height: (lines.len() * LINE_HEIGHT + 1) * pixel,
+        dots,
+    }
+}
+
+// The top left corner of every lit font pixel of `text`, its first glyph at (x, y)
+fn text_dots(text: &str, x: usize, y: usize, pixel: usize, dots: &mut Vec<(usize, usize)>) {
+    for (index, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) != 0 {
+                    let dot_x = x + (index * ADVANCE + col) * pixel;
+                    dots.push((dot_x, y + row * pixel));
+                }
+            }
+        }
+    }
+}
+
+// The keys as they sit on the COSMAC VIP keypad
+pub const KEYPAD: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// A cell around one glyph plus the grid line between cells, in font pixels
+const CELL_WIDTH: usize = GLYPH_WIDTH + 2;
+const CELL_HEIGHT: usize = GLYPH_HEIGHT + 2;
+const PAD_WIDTH: usize = 4 * (CELL_WIDTH + 1) + 1;
+const PAD_HEIGHT: usize = 4 * (CELL_HEIGHT + 1) + 1;
+
+// The keypad in the top right corner: a lit box whose gaps make the grid, and a cell per key
+// with the host key bound to it written inside. Pressed keys are lit with unlit letters
+pub struct KeypadLayout {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    // the font pixel size used, smaller than asked when the pad had to shrink
+    pub pixel: usize,
+    pub cells: Vec<KeyCell>,
+}
+
+pub struct KeyCell {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub pressed: bool,
+    pub dots: Vec<(usize, usize)>,
+}
+
+pub fn keypad_layout(
+    pressed: &[bool; 16],
+    keymap: &Keymap,
+    width: usize,
+    height: usize,
+    pixel: usize,
+) -> KeypadLayout {
+    // smaller font pixels when the whole pad wouldn't fit
+    let pixel = pixel.min(height / PAD_HEIGHT).max(1);
+    let (pad_width, pad_height) = (PAD_WIDTH * pixel, PAD_HEIGHT * pixel);
+    let (x, y) = (width.saturating_sub(pad_width), 0);
+
+    let mut cells = Vec::with_capacity(16);
+    for (row, keys) in KEYPAD.iter().enumerate() {
+        for (col, &key) in keys.iter().enumerate() {
+            let cell_x = x + (1 + col * (CELL_WIDTH + 1)) * pixel;
+            let cell_y = y + (1 + row * (CELL_HEIGHT + 1)) * pixel;
+            let label = keymap.host_key(key).unwrap_or('?');
+            let mut dots = Vec::new();
+            for (glyph_row, bits) in glyph(label).iter().enumerate() {
+                for glyph_col in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> glyph_col) != 0 {
+                        let dot_x = cell_x + (1 + glyph_col) * pixel;
+                        let dot_y = cell_y + (1 + glyph_row) * pixel;
+                        dots.push((dot_x, dot_y));
+                    }
+                }
+            }
+            cells.push(KeyCell {
+                x: cell_x,
+                y: cell_y,
+                width: CELL_WIDTH * pixel,
+                height: CELL_HEIGHT * pixel,
+                pressed: pressed[key],
+                dots,
+            });
+        }
+    }
+
+    KeypadLayout {
+        x,
+        y,
+        width: pad_width.min(width),
+        height: pad_height.min(height),
+        pixel,
+        cells,
+    }
+}
+
+// Frames the graph keeps, 4 seconds of them
+pub const GRAPH_FRAMES: usize = 240;
+// 1/60 s, the line across the graph
+pub const TARGET_MS: f32 = 1000.0 / 60.0;
+// a bar as tall as the box, anything slower is cut
+const GRAPH_MAX_MS: f32 = 2.0 * TARGET_MS;
+
+// The wall time between the last frames, in milliseconds, oldest first
+#[derive(Default)]
+pub struct FrameTimes {
+    times: VecDeque<f32>,
+    last: Option<Instant>,
+}
+
+impl FrameTimes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Called once per 60 Hz frame, the first call only starts the clock
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last.replace(now) {
+            self.push((now - last).as_secs_f32() * 1000.0);
+        }
+    }
+
+    pub fn push(&mut self, ms: f32) {
+        if self.times.len() == GRAPH_FRAMES {
+            self.times.pop_front();
+        }
+        self.times.push_back(ms);
+    }
+
+    // After a pause the next frame would look like a long stall
+    pub fn restart(&mut self) {
+        self.last = None;
+    }
+
+    pub fn times(&self) -> Vec<f32> {
+        self.times.iter().copied().collect()
+    }
+}
+
+// The frame time graph in the top left corner: a bar per frame, the newest on the right and
+// as tall as its time with two frames of time filling the box, and a line at 1/60 s. Bars
+// poking out above the line are frames that came late
+pub struct GraphLayout {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub bars: Vec<(usize, usize, usize, usize)>,
+    // the 1/60 s line, `line` pixels thick from target_y down
+    pub target_y: usize,
+    pub line: usize,
+}
+
+pub fn graph_layout(times: &[f32], width: usize, height: usize, pixel: usize) -> GraphLayout {
+    let bar = (pixel / 4).max(1);
+    let graph_width = (GRAPH_FRAMES * bar).min(width);
+    let graph_height = (2 * (GLYPH_HEIGHT + 2) * pixel).min(height / 2).max(2);
+    let shown = &times[times.len().saturating_sub(graph_width / bar)..];
+    let scale = |ms: f32| ((ms / GRAPH_MAX_MS).clamp(0.0, 1.0) * graph_height as f32) as usize;
+    let start = graph_width - shown.len() * bar;
+    let bars = shown
+        .iter()
+        .enumerate()
+        .map(|(index, &ms)| {
+            let tall = scale(ms);
+            (start + index * bar, graph_height - tall, bar, tall)
+        })
+        .collect();
+    GraphLayout {
+        x: 0,
+        y: 0,
+        width: graph_width,
+        height: graph_height,
+        bars,
+        target_y: graph_height - scale(TARGET_MS),
+        line: bar,
+    }
+}
+
+// The graph as one line of block characters for text screens, the last `width` frames
+pub fn sparkline(times: &[f32], width: usize) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let shown = &times[times.len().saturating_sub(width)..];
+    shown
+        .iter()
+        .map(|&ms| {
+            let level = (ms / GRAPH_MAX_MS * BLOCKS.len() as f32) as usize;
+            BLOCKS[level.min(BLOCKS.len() - 1)]
+        })
+        .collect()
+}
+
+// "AVG 16.7 MAX 33.4 MS" of the frames kept
+pub fn frame_summary(times: &[f32]) -> String {
+    if times.is_empty() {
+        return String::new();
+    }
+    let average = times.iter().sum::<f32>() / times.len() as f32;
+    let max = times.iter().copied().fold(0.0, f32::max);
+    format!("AVG {:.1} MAX {:.1} MS", average, max)
+}
+
+// 5 rows of 3 bits, MSB left. Lowercase reads as uppercase, anything unknown is a ?
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        ' ' => [0; 5],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '[' => [0b011, 0b010, 0b010, 0b010, 0b011],
+        ']' => [0b110, 0b010, 0b010, 0b010, 0b110],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        '\'' | '`' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        // "I" is 9 dots, at 2 screen pixels per font pixel on a 64x32 screen
+        let layout = layout("I", 64, 32, 2);
+        assert_eq!((layout.x, layout.y), (0, 32 - 7 * 2));
+        assert_eq!((layout.width, layout.height), (5 * 2, 7 * 2));
+        assert_eq!(layout.dots.len(), 9);
+        assert_eq!(layout.dots[0], (2, 32 - 7 * 2 + 2));
+
+        // 15 characters fit in 64 pixels at 1 pixel per font pixel, the rest is cut
+        let long = super::layout("ABCDEFGHIJKLMNOPQRSTUVWXYZ", 64, 32, 1);
+        assert_eq!(long.width, 15 * ADVANCE + 1);
+        assert!(long.dots.iter().all(|&(x, _)| x < 64));
+    }
+
+    #[test]
+    fn test_keypad_layout() {
+        let mut pressed = [false; 16];
+        pressed[0xC] = true;
+        // 20 screen pixels per chip8 pixel, font pixels of 10
+        let pad = keypad_layout(&pressed, &Keymap::new(), 64 * 20, 32 * 20, 10);
+        assert_eq!((pad.x, pad.y), (64 * 20 - PAD_WIDTH * 10, 0));
+        assert_eq!(pad.cells.len(), 16);
+        // C is the fourth key of the top row, bound to 4 by default
+        let c = &pad.cells[3];
+        assert!(c.pressed);
+        assert_eq!(c.dots.len(), 9);
+        assert_eq!((c.x, c.y), (pad.x + (1 + 3 * 6) * 10, 10));
+        assert!(pad.cells.iter().filter(|cell| cell.pressed).count() == 1);
+
+        // a 64x32 screen gets font pixels of 1 and the pad still fits
+        let small = keypad_layout(&pressed, &Keymap::new(), 64, 32, 1);
+        assert_eq!(small.height, 32);
+        assert!(small.cells.iter().all(|cell| cell.y + cell.height <= 32));
+    }
+
+    #[test]
+    fn test_graph_layout() {
+        let mut frames = FrameTimes::new();
+        for _ in 0..GRAPH_FRAMES + 10 {
+            frames.push(TARGET_MS);
+        }
+        frames.push(100.0);
+        let times = frames.times();
+        assert_eq!(times.len(), GRAPH_FRAMES);
+
+        // font pixels of 10 make bars of 2, the whole graph fits in 1280x640
+        let graph = graph_layout(&times, 1280, 640, 10);
+        assert_eq!((graph.width, graph.height), (GRAPH_FRAMES * 2, 140));
+        assert_eq!(graph.bars.len(), GRAPH_FRAMES);
+        // frames on time reach the line, the slow one fills the box
+        assert_eq!(graph.bars[0].1, graph.target_y);
+        assert_eq!(graph.target_y, 70);
+        assert_eq!(
+            graph.bars[GRAPH_FRAMES - 1],
+            (GRAPH_FRAMES * 2 - 2, 0, 2, 140)
+        );
+
+        // on a 64x32 screen only the last 64 frames fit
+        let small = graph_layout(&times, 64, 32, 1);
+        assert_eq!(small.bars.len(), 64);
+        assert!(small.bars.iter().all(|&(x, _, _, _)| x < 64));
+
+        assert_eq!(sparkline(&[0.0, TARGET_MS, 100.0], 2), "▅█");
+        assert_eq!(frame_summary(&[10.0, 20.0]), "AVG 15.0 MAX 20.0 MS");
+    }
+
+    #[test]
+    fn test_messages_expire() {
+        let mut osd = Osd::new();
+        assert!(!osd.tick());
+        osd.show("CONFIG RELOADED");
+        assert!(osd.tick());
+        assert!(!osd.tick());
+        assert_eq!(osd.message(), Some("CONFIG RELOADED"));
+
+        osd.message.as_mut().unwrap().1 = Instant::now();
+        assert!(osd.tick());
+        assert_eq!(osd.message(), None);
+    }
+}
@@ -0,0 +1,150 @@
+// What the debug window shows next to the game: the registers, the RAM around I and the code
+// around PC, as lines of text the frontends draw with the osd font (see osd::text_layout).
+// Worked out again from the machine every frame, nothing is kept between them.
+use crate::chip8::Chip8;
+use crate::osd;
+
+// bytes per memory line, and lines of it
+const MEMORY_COLUMNS: u16 = 8;
+const MEMORY_ROWS: u16 = 8;
+// instructions shown before PC and from it
+const CODE_BEFORE: u16 = 4;
+const CODE_AFTER: u16 = 12;
+// characters across and lines down the panels take at most
+pub const COLUMNS: usize = 28;
+pub const LINES: usize = 35;
+// screen pixels per font pixel in the debug window
+pub const PIXEL: usize = 3;
+
+// A debug window the panels fit in
+pub fn window_size() -> (usize, usize) {
+    let width = (COLUMNS * osd::ADVANCE + 1) * PIXEL;
+    let height = (LINES * osd::LINE_HEIGHT + 1) * PIXEL;
+    (width, height)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Panels {
+    pub registers: Vec<String>,
+    pub memory: Vec<String>,
+    pub code: Vec<String>,
+}
+
+impl Panels {
+    pub fn new(chip: &Chip8) -> Self {
+        Panels {
+            registers: registers(chip),
+            memory: memory(chip),
+            code: code(chip),
+        }
+    }
+
+    // The three of them one under the other, with a title each
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (title, panel) in [
+            ("REGISTERS", &self.registers),
+            ("MEMORY AT I", &self.memory),
+            ("CODE", &self.code),
+        ] {
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.push(title.to_string());
+            lines.extend(panel.iter().cloned());
+        }
+        lines
+    }
+}
+
+fn registers(chip: &Chip8) -> Vec<String> {
+    let v = chip.registers();
+    let mut lines: Vec<String> = v
+        .chunks(4)
+        .enumerate()
+        .map(|(row, values)| {
+            let cells: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(col, value)| format!("V{:X}={:02X}", row * 4 + col, value))
+                .collect();
+            cells.join(" ")
+        })
+        .collect();
+    lines.push(format!(
+        "I={:03X} PC={:03X} SP={}",
+        chip.get_i(),
+        chip.get_pc(),
+        chip.stack().len()
+    ));
+    lines.push(format!("DT={:02X} ST={:02X}", chip.get_dt(), chip.get_st()));
+    lines
+}
+
+// Lines of 8 bytes from the one I is in
+fn memory(chip: &Chip8) -> Vec<String> {
+    let start = chip.get_i() - chip.get_i() % MEMORY_COLUMNS;
+    (0..MEMORY_ROWS)
+        .map(|row| start + row * MEMORY_COLUMNS)
+        .map(|address| {
+            let bytes: Vec<String> = (address..address + MEMORY_COLUMNS)
+                .map(|at| {
+                    chip.get_ram(at)
+                        .map_or("--".into(), |b| format!("{:02X}", b))
+                })
+                .collect();
+            format!("{:03X}: {}", address, bytes.join(" "))
+        })
+        .collect()
+}
+
+// The instructions before and after PC, PC marked with a >. Bytes that aren't an
+// instruction show as data
+fn code(chip: &Chip8) -> Vec<String> {
+    let pc = *chip.get_pc();
+    let start = pc - 2 * (pc / 2).min(CODE_BEFORE);
+    (0..CODE_BEFORE + CODE_AFTER)
+        .map(|index| start + 2 * index)
+        .map_while(|address| {
+            let high = chip.get_ram(address).ok()?;
+            let low = chip.get_ram(address + 1).ok()?;
+            let opcode = u16::from_be_bytes([high, low]);
+            let text = chip
+                .decode(opcode)
+                .map_or("DATA".into(), |instruction| instruction.to_string());
+            let mark = if address == pc { '>' } else { ' ' };
+            Some(format!("{}{:03X}: {:04X} {}", mark, address, opcode, text))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panels() {
+        // CLS, LD I 22A, LD V0 0C, then a byte that isn't an instruction
+        let mut chip = Chip8::new(&[0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x00, 0x01]).unwrap();
+        chip.step().unwrap();
+        chip.step().unwrap();
+        let panels = Panels::new(&chip);
+        assert_eq!(panels.registers[0], "V0=00 V1=00 V2=00 V3=00");
+        assert_eq!(panels.registers[4], "I=22A PC=204 SP=0");
+        assert_eq!(panels.memory.len(), 8);
+        assert!(panels.memory[0].starts_with("228: "));
+
+        let code = &panels.code;
+        assert_eq!(code[0], " 1FC: 0000 DATA");
+        assert_eq!(code[2], " 200: 00E0 CLS");
+        assert_eq!(code[3], " 202: A22A LD I, 22A");
+        assert_eq!(code[4], ">204: 600C LD V0, 0C");
+        assert_eq!(code[5], " 206: 0001 DATA");
+        assert_eq!(code.len(), 16);
+
+        let lines = panels.lines();
+        assert_eq!(lines[0], "REGISTERS");
+        assert_eq!(lines.len(), LINES);
+        assert!(lines.iter().all(|line| line.len() <= COLUMNS));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::chip8::{Chip8, Chip8Error, DebugOutput};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+// Extension point for tools that ride along with the run loop (achievements, stream
+// overlays, loggers...) without touching it. Every hook gets the machine, so a plugin can
+// read ram or registers, or even change them. All hooks do nothing by default. on_frame and
+// on_shutdown can fail with a message for the user, the plugin is dropped and the app shows
+// it (see PluginError).
+pub trait Plugin {
+    fn name(&self) -> &str;
+
+    // the ROM is in memory and nothing ran yet
+    fn on_load(&mut self, _chip: &mut Chip8) {}
+
+    // after every 60 Hz frame
+    fn on_frame(&mut self, _chip: &mut Chip8) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn on_event(&mut self, _chip: &mut Chip8, _event: &Event) {}
+
+    // the run loop is done, last chance to save things
+    fn on_shutdown(&mut self, _chip: &mut Chip8) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// A plugin that gave up, with its name for the message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginError {
+    pub plugin: String,
+    pub message: String,
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the {} plugin stopped: {}", self.plugin, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    KeyDown(usize),
+    KeyUp(usize),
+    SoundOn,
+    SoundOff,
+    Crash(Chip8Error),
+    // the ROM printed something through the debug port, see Chip8::set_debug_port
+    Debug(DebugOutput),
+}
+
+// The plugins of a run loop, in registration order. It also remembers the keys and the
+// buzzer of the last frame to turn their changes into events
+#[derive(Default)]
+pub struct Plugins {
+    plugins: Vec<Box<dyn Plugin>>,
+    keys: [bool; 16],
+    sound_on: bool,
+}
+
+impl Plugins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.push(plugin);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.plugins.iter().map(|plugin| plugin.name())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub fn load(&mut self, chip: &mut Chip8) {
+        self.keys = [false; 16];
+        self.sound_on = false;
+        for plugin in &mut self.plugins {
+            plugin.on_load(chip);
+        }
+    }
+
+    // Sends the key and sound changes and the debug output since the last frame, then on_frame.
+    // The plugins that failed are dropped, their errors are for the app to show
+    #[must_use]
+    pub fn frame(&mut self, chip: &mut Chip8) -> Vec<PluginError> {
+        for key in 0..16 {
+            let pressed = chip.keyboard.is_pressed(key).unwrap_or(false);
+            if pressed != self.keys[key] {
+                self.keys[key] = pressed;
+                let event = if pressed {
+                    Event::KeyDown(key)
+                } else {
+                    Event::KeyUp(key)
+                };
+                self.event(chip, &event);
+            }
+        }
+        if chip.is_sound_on() != self.sound_on {
+            self.sound_on = chip.is_sound_on();
+            let event = if self.sound_on {
+                Event::SoundOn
+            } else {
+                Event::SoundOff
+            };
+            self.event(chip, &event);
+        }
+        for output in chip.take_debug_output() {
+            self.event(chip, &Event::Debug(output));
+        }
+
+        let mut errors = Vec::new();
+        self.plugins
+            .retain_mut(|plugin| match plugin.on_frame(chip) {
+                Ok(()) => true,
+                Err(message) => {
+                    errors.push(PluginError {
+                        plugin: plugin.name().into(),
+                        message,
+                    });
+                    false
+                }
+            });
+        errors
+    }
+
+    pub fn event(&mut self, chip: &mut Chip8, event: &Event) {
+        for plugin in &mut self.plugins {
+            plugin.on_event(chip, event);
+        }
+    }
+
+    #[must_use]
+    pub fn shutdown(&mut self, chip: &mut Chip8) -> Vec<PluginError> {
+        let mut errors = Vec::new();
+        for plugin in &mut self.plugins {
+            if let Err(message) = plugin.on_shutdown(chip) {
+                errors.push(PluginError {
+                    plugin: plugin.name().into(),
+                    message,
+                });
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::rc::Rc;
+    use alloc::string::ToString;
+    use alloc::vec;
+    use core::cell::RefCell;
+
+    // writes every hook call into a shared log
+    struct Recorder {
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Plugin for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        fn on_load(&mut self, chip: &mut Chip8) {
+            self.log
+                .borrow_mut()
+                .push(format!("load {:x}", chip.get_pc()));
+        }
+
+        fn on_frame(&mut self, _chip: &mut Chip8) -> Result<(), String> {
+            self.log.borrow_mut().push("frame".into());
+            Ok(())
+        }
+
+        fn on_event(&mut self, _chip: &mut Chip8, event: &Event) {
+            self.log.borrow_mut().push(format!("{:?}", event));
+        }
+
+        fn on_shutdown(&mut self, _chip: &mut Chip8) -> Result<(), String> {
+            self.log.borrow_mut().push("shutdown".into());
+            Ok(())
+        }
+    }
+
+    // fails on its second frame and on shutdown
+    struct Flaky {
+        frames: u32,
+    }
+
+    impl Plugin for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn on_frame(&mut self, _chip: &mut Chip8) -> Result<(), String> {
+            self.frames += 1;
+            if self.frames == 2 {
+                return Err("disk full".into());
+            }
+            Ok(())
+        }
+
+        fn on_shutdown(&mut self, _chip: &mut Chip8) -> Result<(), String> {
+            Err("can't flush".into())
+        }
+    }
+
+    #[test]
+    fn test_hooks_and_events() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut plugins = Plugins::new();
+        plugins.register(Box::new(Recorder { log: log.clone() }));
+        assert_eq!(plugins.names().collect::<Vec<_>>(), ["recorder"]);
+
+        let mut chip = Chip8::new(&[0x12, 0x00]).unwrap();
+        plugins.load(&mut chip);
+        chip.keyboard.set_key(5, true).unwrap();
+        chip.set_st(2);
+        assert!(plugins.frame(&mut chip).is_empty());
+        assert!(plugins.frame(&mut chip).is_empty());
+        chip.keyboard.set_key(5, false).unwrap();
+        chip.set_st(0);
+        assert!(plugins.frame(&mut chip).is_empty());
+        plugins.event(&mut chip, &Event::Crash(Chip8Error::StackUnderflow));
+        assert!(plugins.shutdown(&mut chip).is_empty());
+
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                "load 200",
+                "KeyDown(5)",
+                "SoundOn",
+                "frame",
+                "frame",
+                "KeyUp(5)",
+                "SoundOff",
+                "frame",
+                "Crash(StackUnderflow)",
+                "shutdown",
+            ]
+        );
+    }
+    #[test]
+    fn test_failing_plugin_is_dropped() {
+        let mut plugins = Plugins::new();
+        plugins.register(Box::new(Flaky { frames: 0 }));
+        let mut chip = Chip8::new(&[0x12, 0x00]).unwrap();
+        assert!(plugins.frame(&mut chip).is_empty());
+        let errors = plugins.frame(&mut chip);
+        assert_eq!(
+            errors,
+            [PluginError {
+                plugin: "flaky".into(),
+                message: "disk full".into(),
+            }]
+        );
+        assert_eq!(errors[0].to_string(), "the flaky plugin stopped: disk full");
+        assert!(plugins.is_empty());
+        assert!(plugins.frame(&mut chip).is_empty());
+
+        // on shutdown every plugin gets its turn, failing or not
+        plugins.register(Box::new(Flaky { frames: 0 }));
+        plugins.register(Box::new(Flaky { frames: 0 }));
+        assert_eq!(plugins.shutdown(&mut chip).len(), 2);
+    }
+}
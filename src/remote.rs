@@ -0,0 +1,469 @@
+// Remote control over HTTP, so test orchestrators, scripts and web dashboards can drive a
+// running emulator. Every command is a POST with a JSON body and gets a JSON answer:
+//     curl -H 'Content-Type: application/json' -d '{"cmd": "press_key", "key": 5}' localhost:8080
+//     {"ok":true}
+// Commands: load_rom (data), pause, resume, step (frames), screenshot,
+// read_memory (address, length), read_region (region, offset, length), press_key / release_key (key) and status.
+// Errors come back as {"ok":false,"error":"..."}.
+//
+// The socket lives on its own thread, commands reach the machine when the run loop calls
+// poll, so they always see the machine between two frames.
+//
+// Any web page the user opens can send requests to localhost, so a POST has to say it's
+// application/json, which browsers only send cross-origin after a preflight, and requests
+// from pages (with an Origin header) are refused unless their origin was allowed. ROMs come
+// as data, never as a path, so nothing reads the user's files for a page.
+use crate::chip8::{Chip8, Chip8Error};
+use crate::memory::Region;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+// requests bigger than this are refused, a ROM as a JSON array fits easily
+const MAX_BODY: usize = 64 * 1024;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Command {
+    LoadRom {
+        data: Vec<u8>,
+    },
+    Pause,
+    Resume,
+    Step {
+        #[serde(default = "one")]
+        frames: u32,
+    },
+    Screenshot,
+    ReadMemory {
+        address: u16,
+        length: usize,
+    },
+    // a region of memory.rs by name, all of it unless offset/length say otherwise
+    ReadRegion {
+        region: String,
+        #[serde(default)]
+        offset: usize,
+        length: Option<usize>,
+    },
+    PressKey {
+        key: usize,
+    },
+    ReleaseKey {
+        key: usize,
+    },
+    Status,
+}
+
+fn one() -> u32 {
+    1
+}
+
+struct Request {
+    command: Command,
+    reply: Sender<Value>,
+}
+
+pub struct RemoteServer {
+    requests: Receiver<Request>,
+    addr: SocketAddr,
+    ipf: u32,
+    paused: bool,
+    // keys pressed over the wire stay down until release_key, whatever the frontend reads
+    held: [bool; 16],
+    // the machine as the app built it (platform, quirks, seed...), load_rom starts from it
+    template: Option<Chip8>,
+}
+
+impl RemoteServer {
+    // ipf is what a frame means for the step command. origins are the web pages allowed to
+    // send commands, like http://localhost:3000, scripts and curl don't need one
+    pub fn bind(addr: impl ToSocketAddrs, ipf: u32, origins: Vec<String>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // one client at a time, a bad client only loses its own request
+                let _ = serve(stream, &sender, &origins);
+            }
+        });
+
+        Ok(RemoteServer {
+            requests,
+            addr,
+            ipf,
+            paused: false,
+            held: [false; 16],
+            template: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // The run loop doesn't run frames while paused, only the step command does
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Runs the commands that arrived since the last call, never blocks. Call it after the
+    // frontend read the keyboard so the held keys win
+    pub fn poll(&mut self, chip: &mut Chip8) {
+        if self.template.is_none() {
+            self.template = Some(chip.clone());
+        }
+        self.press_held(chip);
+        while let Ok(request) = self.requests.try_recv() {
+            let answer = match self.execute(chip, request.command) {
+                Ok(Value::Null) => json!({"ok": true}),
+                Ok(mut value) => {
+                    value["ok"] = json!(true);
+                    value
+                }
+                Err(error) => json!({"ok": false, "error": error}),
+            };
+            let _ = request.reply.send(answer);
+        }
+    }
+
+    fn press_held(&self, chip: &mut Chip8) {
+        for (key, &held) in self.held.iter().enumerate() {
+            if held {
+                let _ = chip.keyboard.set_key(key, true);
+            }
+        }
+    }
+
+    fn execute(&mut self, chip: &mut Chip8, command: Command) -> Result<Value, String> {
+        match command {
+            Command::LoadRom { data } => {
+                let mut fresh = self.template.clone().unwrap_or_else(|| chip.clone());
+                fresh.load_rom(&data).map_err(name)?;
+                // keeps the app's trace, timer callback and rewind buffer
+                chip.restore(fresh);
+                Ok(Value::Null)
+            }
+            Command::Pause => {
+                self.paused = true;
+                Ok(Value::Null)
+            }
+            Command::Resume => {
+                self.paused = false;
+                Ok(Value::Null)
+            }
+            Command::Step { frames } => {
+                for _ in 0..frames {
+                    self.press_held(chip);
+                    chip.run_frame(self.ipf).map_err(name)?;
+                }
+                Ok(json!({"pc": chip.get_pc()}))
+            }
+            Command::Screenshot => Ok(json!({
+                "width": 64,
+                "height": 32,
+                "screen": chip.display_ascii(),
+                "hash": format!("{:016x}", chip.display_hash()),
+            })),
+            Command::ReadMemory { address, length } => {
+                let bytes = chip.ram_range(address, length).map_err(name)?;
+                Ok(json!({"bytes": bytes}))
+            }
+            Command::ReadRegion {
+                region,
+                offset,
+                length,
+            } => {
+                let region =
+                    Region::parse(&region).ok_or_else(|| format!("no region `{}`", region))?;
+                let length = length.unwrap_or(chip.region(region).len().saturating_sub(offset));
+                let bytes = chip.region_range(region, offset, length).map_err(name)?;
+                // offset is inside the region now
+                let start = chip.memory_map().range(region).start + offset;
+                Ok(json!({"address": start, "bytes": bytes}))
+            }
+            Command::PressKey { key } => {
+                chip.keyboard.set_key(key, true).map_err(name)?;
+                self.held[key] = true;
+                Ok(Value::Null)
+            }
+            Command::ReleaseKey { key } => {
+                chip.keyboard.set_key(key, false).map_err(name)?;
+                self.held[key] = false;
+                Ok(Value::Null)
+            }
+            Command::Status => Ok(json!({
+                "paused": self.paused,
+                "pc": chip.get_pc(),
+                "i": chip.get_i(),
+                "v": chip.registers(),
+                "stack": chip.stack(),
+                "dt": chip.get_dt(),
+                "st": chip.get_st(),
+            })),
+        }
+    }
+}
+
+fn name(error: Chip8Error) -> String {
+    format!("{:?}", error)
+}
+
+// Just enough HTTP/1.1 for curl, fetch and test scripts: one request per connection
+fn serve(stream: TcpStream, requests: &Sender<Request>, origins: &[String]) -> io::Result<()> {
+    // a client that connects and says nothing can't hold the server forever
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let method = request_line.split_whitespace().next().unwrap_or("");
+
+    let mut length = 0;
+    let mut json_body = false;
+    let mut origin = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let Some((key, value)) = header.split_once(':') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if key.eq_ignore_ascii_case("content-length") {
+            length = value.parse().unwrap_or(0);
+        } else if key.eq_ignore_ascii_case("content-type") {
+            json_body = value
+                .split(';')
+                .next()
+                .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("application/json"));
+        } else if key.eq_ignore_ascii_case("origin") {
+            origin = Some(value.to_string());
+        }
+    }
+
+    // a page's origin when it's one of the allowed, for the CORS headers
+    let allowed = match origin {
+        None => None,
+        Some(origin) if origins.contains(&origin) => Some(origin),
+        Some(_) => return respond(stream, "403 Forbidden", None, ""),
+    };
+    let answer = match method {
+        // CORS preflight of browsers, for dashboards on another origin
+        "OPTIONS" => return respond(stream, "204 No Content", allowed.as_deref(), ""),
+        "POST" if !json_body => {
+            let error = json!({"ok": false, "error": "Content-Type must be application/json"});
+            return respond(
+                stream,
+                "415 Unsupported Media Type",
+                allowed.as_deref(),
+                &error.to_string(),
+            );
+        }
+        "POST" if length > MAX_BODY => json!({"ok": false, "error": "request too large"}),
+        "POST" => {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+            match serde_json::from_slice(&body) {
+                Ok(command) => {
+                    let (reply, answer) = mpsc::channel();
+                    if requests.send(Request { command, reply }).is_err() {
+                        return Ok(());
+                    }
+                    // the emulator went away without answering
+                    match answer.recv() {
+                        Ok(answer) => answer,
+                        Err(_) => return Ok(()),
+                    }
+                }
+                Err(error) => json!({"ok": false, "error": error.to_string()}),
+            }
+        }
+        _ => return respond(stream, "405 Method Not Allowed", allowed.as_deref(), ""),
+    };
+    respond(stream, "200 OK", allowed.as_deref(), &answer.to_string())
+}
+
+fn respond(
+    mut stream: TcpStream,
+    status: &str,
+    origin: Option<&str>,
+    body: &str,
+) -> io::Result<()> {
+    let cors = match origin {
+        Some(origin) => format!(
+            "Access-Control-Allow-Origin: {}\r\n\
+             Access-Control-Allow-Headers: Content-Type\r\n\
+             Vary: Origin\r\n",
+            origin
+        ),
+        None => String::new(),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         {}\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        cors,
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Platform;
+
+    fn run(server: &mut RemoteServer, chip: &mut Chip8, command: &str) -> Value {
+        server
+            .execute(chip, serde_json::from_str(command).unwrap())
+            .unwrap_or_else(|error| json!({"error": error}))
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut server = RemoteServer::bind("127.0.0.1:0", 10, vec![]).unwrap();
+        let mut chip = Chip8::default();
+        server.poll(&mut chip);
+        // draws the font 0, then waits for a key into V1
+        let load = r#"{"cmd": "load_rom", "data": [160, 80, 208, 5, 241, 10, 18, 6]}"#;
+        assert_eq!(run(&mut server, &mut chip, load), Value::Null);
+
+        run(&mut server, &mut chip, r#"{"cmd": "pause"}"#);
+        assert!(server.is_paused());
+        assert_eq!(
+            run(&mut server, &mut chip, r#"{"cmd": "step"}"#),
+            json!({"pc": 0x204})
+        );
+        let screen = run(&mut server, &mut chip, r#"{"cmd": "screenshot"}"#);
+        assert!(screen["screen"].as_str().unwrap().starts_with("####."));
+
+        run(&mut server, &mut chip, r#"{"cmd": "press_key", "key": 9}"#);
+        // a frontend reading its own keyboard doesn't release it
+        chip.keyboard.set_key(9, false).unwrap();
+        run(&mut server, &mut chip, r#"{"cmd": "step", "frames": 2}"#);
+        let status = run(&mut server, &mut chip, r#"{"cmd": "status"}"#);
+        assert_eq!(status["pc"], 0x204);
+        // Fx0A goes on once it's let go
+        run(
+            &mut server,
+            &mut chip,
+            r#"{"cmd": "release_key", "key": 9}"#,
+        );
+        run(&mut server, &mut chip, r#"{"cmd": "step"}"#);
+        let status = run(&mut server, &mut chip, r#"{"cmd": "status"}"#);
+        assert_eq!(status["v"][1], 9);
+        assert_eq!(status["paused"], true);
+
+        let memory = r#"{"cmd": "read_memory", "address": 512, "length": 2}"#;
+        assert_eq!(
+            run(&mut server, &mut chip, memory),
+            json!({"bytes": [160, 80]})
+        );
+        let outside = r#"{"cmd": "read_memory", "address": 4095, "length": 2}"#;
+        assert_eq!(
+            run(&mut server, &mut chip, outside),
+            json!({"error": "InvalidMemoryAccess"})
+        );
+        let font = r#"{"cmd": "read_region", "region": "font", "offset": 5, "length": 2}"#;
+        assert_eq!(
+            run(&mut server, &mut chip, font),
+            json!({"address": 0x55, "bytes": [0x20, 0x60]})
+        );
+        let past = r#"{"cmd": "read_region", "region": "font", "offset": 79, "length": 2}"#;
+        assert_eq!(
+            run(&mut server, &mut chip, past),
+            json!({"error": "InvalidMemoryAccess"})
+        );
+        let huge = r#"{"cmd": "read_region", "region": "font", "offset": 18446744073709551615}"#;
+        assert_eq!(
+            run(&mut server, &mut chip, huge),
+            json!({"error": "InvalidMemoryAccess"})
+        );
+        assert_eq!(
+            run(&mut server, &mut chip, r#"{"cmd": "press_key", "key": 16}"#),
+            json!({"error": "InvalidKey"})
+        );
+        // files never, only the bytes
+        let path = r#"{"cmd": "load_rom", "path": "/etc/passwd"}"#;
+        assert!(serde_json::from_str::<Command>(path).is_err());
+    }
+
+    // The response to a request with these headers, while poll runs the machine
+    fn send(server: &mut RemoteServer, headers: &str, body: &str) -> String {
+        let addr = server.local_addr();
+        let (headers, body) = (headers.to_string(), body.to_string());
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "POST / HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}",
+                headers,
+                body.len(),
+                body
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let mut chip = Chip8::default();
+        while !client.is_finished() {
+            server.poll(&mut chip);
+            thread::yield_now();
+        }
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn test_load_rom_keeps_the_machine() {
+        let mut server = RemoteServer::bind("127.0.0.1:0", 10, vec![]).unwrap();
+        let mut chip = Chip8::new(&[0x60, 0x2A]).unwrap();
+        chip.set_platform(Platform::XoChip);
+        server.poll(&mut chip);
+        chip.step().unwrap();
+
+        // more than the 3.5 KiB a CHIP-8 has for a program
+        let rom = vec![0x12; 0x1000];
+        let load = json!({"cmd": "load_rom", "data": rom}).to_string();
+        assert_eq!(run(&mut server, &mut chip, &load), Value::Null);
+        assert_eq!(chip.platform(), Platform::XoChip);
+        assert_eq!(chip.get_pc(), &0x200);
+        assert_eq!(chip.get_v(0), Ok(0));
+        assert_eq!(chip.get_ram(0x11FF), Ok(0x12));
+    }
+
+    #[test]
+    fn test_over_http() {
+        let dashboard = "http://localhost:3000";
+        let mut server = RemoteServer::bind("127.0.0.1:0", 10, vec![dashboard.into()]).unwrap();
+        let status = r#"{"cmd": "status"}"#;
+        let json = "Content-Type: application/json\r\n";
+        let response = send(&mut server, json, status);
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(!response.contains("Access-Control-Allow-Origin"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let answer: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(answer["ok"], true);
+        assert_eq!(answer["pc"], 0x200);
+
+        // what a page can send without a preflight
+        let plain = send(&mut server, "Content-Type: text/plain\r\n", status);
+        assert!(plain.starts_with("HTTP/1.1 415"));
+        let page = format!("{}Origin: https://example.com\r\n", json);
+        assert!(send(&mut server, &page, status).starts_with("HTTP/1.1 403"));
+        let allowed = format!("{}Origin: {}\r\n", json, dashboard);
+        let response = send(&mut server, &allowed, status);
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Access-Control-Allow-Origin: http://localhost:3000\r\n"));
+    }
+}
@@ -0,0 +1,162 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+// Save states every few frames, for going back in time while playing. Only the newest is
+// kept whole, every older one is the difference to the one after it: the XOR of the two,
+// where runs of zeros (what didn't change, most of the machine) are just their length.
+// Going back one snapshot undoes a difference, and dropping the oldest one when the buffer
+// is full needs nothing else to change.
+#[derive(Default)]
+pub struct Rewind {
+    // a snapshot every this many frames
+    every: u32,
+    // how many of them are kept, the newest one counted
+    capacity: usize,
+    // frames since the newest one
+    since: u32,
+    newest: Option<Vec<u8>>,
+    // oldest first
+    older: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(every: u32, capacity: usize) -> Self {
+        Rewind {
+            every: every.max(1),
+            capacity,
+            ..Rewind::default()
+        }
+    }
+
+    // Once per frame, with the machine's state if a snapshot is due
+    pub fn frame(&mut self, state: impl FnOnce() -> Vec<u8>) {
+        self.since += 1;
+        if self.newest.is_some() && self.since < self.every {
+            return;
+        }
+        self.since = 0;
+        let state = state();
+        match self.newest.replace(state) {
+            // a state of another size is another platform, what came before is gone
+            Some(newest) if Some(newest.len()) == self.newest.as_ref().map(Vec::len) => {
+                let newer = self.newest.as_deref().unwrap_or_default();
+                self.older.push_back(encode(&newest, newer));
+            }
+            _ => self.older.clear(),
+        }
+        while self.older.len() + 1 > self.capacity.max(1) {
+            self.older.pop_front();
+        }
+    }
+
+    // Frames back to the oldest snapshot
+    pub fn frames(&self) -> u32 {
+        match self.newest {
+            Some(_) => self.since + self.older.len() as u32 * self.every,
+            None => 0,
+        }
+    }
+
+    // The newest snapshot at least this many frames old, or the oldest there is, with how
+    // many frames back it is. The ones after it are dropped, it becomes the newest
+    pub fn back(&mut self, frames: u32) -> Option<(u32, &[u8])> {
+        let newest = self.newest.as_mut()?;
+        let mut back = self.since;
+        while back < frames {
+            let Some(delta) = self.older.pop_back() else {
+                break;
+            };
+            decode(&delta, newest);
+            back += self.every;
+        }
+        self.since = 0;
+        Some((back, newest))
+    }
+}
+
+// The XOR of two states of the same size, as pairs of a run of unchanged bytes and a run
+// of changed ones: two little endian u16 lengths, then the changed run's XORed bytes
+fn encode(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut at = 0;
+    while at < old.len() {
+        let same = run(old, new, at, true);
+        let changed = run(old, new, at + same, false);
+        delta.extend_from_slice(&(same as u16).to_le_bytes());
+        delta.extend_from_slice(&(changed as u16).to_le_bytes());
+        let from = at + same;
+        delta.extend((from..from + changed).map(|i| old[i] ^ new[i]));
+        at = from + changed;
+    }
+    delta
+}
+
+// How many bytes from `at` are (or aren't) the same in both, a u16 at most
+fn run(old: &[u8], new: &[u8], at: usize, same: bool) -> usize {
+    (at..old.len())
+        .take(u16::MAX as usize)
+        .take_while(|&i| (old[i] == new[i]) == same)
+        .count()
+}
+
+// Turns the state encode's `new` was into its `old`
+fn decode(delta: &[u8], state: &mut [u8]) {
+    let (mut at, mut read) = (0, 0);
+    while read + 4 <= delta.len() {
+        let same = u16::from_le_bytes([delta[read], delta[read + 1]]) as usize;
+        let changed = u16::from_le_bytes([delta[read + 2], delta[read + 3]]) as usize;
+        read += 4;
+        at += same;
+        for (byte, xor) in state[at..at + changed].iter_mut().zip(&delta[read..]) {
+            *byte ^= xor;
+        }
+        (at, read) = (at + changed, read + changed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let old: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let mut new = old.clone();
+        new[3] = 0xFF;
+        new[70_000..70_010].fill(0);
+        new[199_999] ^= 1;
+        let delta = encode(&old, &new);
+        // a few changes of a large state are a few bytes
+        assert!(delta.len() < 64, "{}", delta.len());
+        decode(&delta, &mut new);
+        assert_eq!(new, old);
+        assert_eq!(encode(&old, &old).len(), 4 * 4);
+    }
+
+    #[test]
+    fn test_rewind_buffer() {
+        // a snapshot every 2 frames, 3 of them kept
+        let mut rewind = Rewind::new(2, 3);
+        assert!(rewind.back(1).is_none());
+        for frame in 0..9u8 {
+            rewind.frame(|| vec![frame; 8]);
+        }
+        // snapshots of frames 4, 6 and 8, the last frame was 8
+        assert_eq!(rewind.frames(), 4);
+        assert_eq!(rewind.back(0), Some((0, &[8; 8][..])));
+        assert_eq!(rewind.back(3), Some((4, &[4; 8][..])));
+        assert_eq!(rewind.frames(), 0);
+        assert_eq!(rewind.back(10), Some((0, &[4; 8][..])));
+
+        // going on from there
+        rewind.frame(|| vec![9; 8]);
+        rewind.frame(|| vec![10; 8]);
+        assert_eq!(rewind.back(1), Some((2, &[4; 8][..])));
+
+        // another size drops what came before
+        rewind.frame(|| vec![1; 8]);
+        rewind.frame(|| vec![1; 4]);
+        assert_eq!(rewind.frames(), 0);
+    }
+}
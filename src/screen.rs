@@ -0,0 +1,387 @@
+// The minifb window: draws the chip8 display scaled up and reads the physical keyboard.
+// Kept out of the core so Chip8 doesn't depend on an OS
+use crate::display::{Aspect, Display, DisplayBackend, Palette};
+use crate::icon::{DEFAULT_TITLE, Icon, WindowGeometry};
+use crate::keyboard::{HostKey, InputBackend, Keyboard, Keymap};
+use crate::osd;
+use crate::panels::{self, Panels};
+use minifb::{Key, ScaleMode, Window, WindowOptions};
+
+const SCALE: usize = 20;
+
+// Keys by what's printed on them, for keymaps and hotkeys
+const LETTERS: [Key; 26] = [
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+];
+const DIGITS: [Key; 10] = [
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+];
+const FUNCTION_KEYS: [Key; 12] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+];
+
+pub struct Screen {
+    buffer: Vec<u32>,
+    window: Window,
+    // width and height of a chip8 pixel in the window
+    pixel: (usize, usize),
+    palette: Palette,
+    keymap: Vec<(Key, usize)>,
+    // the keymap again, for the labels of the keypad overlay
+    labels: Keymap,
+    message: Option<String>,
+    keypad: Option<[bool; 16]>,
+    frame_times: Option<Vec<f32>>,
+    debug: Option<DebugWindow>,
+}
+
+// The minifb DisplayBackend by the name of its library, for apps picking one of several
+pub type MinifbBackend = Screen;
+
+// The second window, with what it shows now so it's only drawn again when that changes
+struct DebugWindow {
+    window: Window,
+    buffer: Vec<u32>,
+    shown: Option<(Panels, Palette)>,
+}
+
+impl Screen {
+    pub fn new() -> Result<Self, String> {
+        Self::with_options(DEFAULT_TITLE, SCALE)
+    }
+
+    // Several screens can be open at once, each one with its own title and size.
+    // Keys only reach the window that has the focus
+    // Fails without a display, like on a server or over ssh
+    pub fn with_options(title: &str, scale: usize) -> Result<Self, String> {
+        Self::with_aspect(title, scale, Aspect::SQUARE)
+    }
+
+    // Pixels `scale` tall and as wide as the aspect says
+    pub fn with_aspect(title: &str, scale: usize, aspect: Aspect) -> Result<Self, String> {
+        Self::with_geometry(title, scale, aspect, &WindowGeometry::default())
+    }
+
+    // Where and as big as it was last time. The window can be resized and the picture
+    // stretches keeping its shape. minifb can't go fullscreen or tell the monitors apart
+    pub fn with_geometry(
+        title: &str,
+        scale: usize,
+        aspect: Aspect,
+        geometry: &WindowGeometry,
+    ) -> Result<Self, String> {
+        let pixel = aspect.pixel_size(scale);
+        let (width, height) = (64 * pixel.0, 32 * pixel.1);
+        let (window_width, window_height) = geometry
+            .size
+            .map_or((width, height), |(w, h)| (w as usize, h as usize));
+        let options = WindowOptions {
+            resize: true,
+            scale_mode: ScaleMode::AspectRatioStretch,
+            ..WindowOptions::default()
+        };
+        let mut window =
+            Window::new(title, window_width, window_height, options).map_err(|e| e.to_string())?;
+        if let Some((x, y)) = geometry.position_on(None) {
+            window.set_position(x as isize, y as isize);
+        }
+
+        Ok(Screen {
+            buffer: vec![0u32; width * height],
+            window,
+            pixel,
+            palette: Palette::default(),
+            keymap: key_map(&Keymap::new()),
+            labels: Keymap::new(),
+            message: None,
+            keypad: None,
+            frame_times: None,
+            debug: None,
+        })
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_keymap(&mut self, keymap: &Keymap) {
+        self.keymap = key_map(keymap);
+        self.labels = *keymap;
+    }
+
+    // Drawn over the game from the next render on, see osd.rs
+    pub fn set_message(&mut self, message: Option<&str>) {
+        self.message = message.map(str::to_string);
+    }
+
+    // The keypad overlay with these keys down, None hides it
+    pub fn set_keypad(&mut self, pressed: Option<[bool; 16]>) {
+        self.keypad = pressed;
+    }
+
+    // The frame time graph with these times in ms, None hides it
+    pub fn set_frame_times(&mut self, times: Option<&[f32]>) {
+        self.frame_times = times.map(<[f32]>::to_vec);
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    // Only X11 takes an icon from pixels, Windows wants an .ico file and macOS ignores it
+    pub fn set_icon(&mut self, icon: &Icon) {
+        #[cfg(target_os = "linux")]
+        {
+            // _NET_WM_ICON: width, height, then the ARGB pixels
+            let mut data = vec![icon.width as u64, icon.height as u64];
+            data.extend(icon.pixels.iter().map(|&pixel| pixel as u64));
+            if let Ok(icon) = minifb::Icon::try_from(&data[..]) {
+                self.window.set_icon(icon);
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = icon;
+    }
+
+    pub fn set_position(&mut self, x: isize, y: isize) {
+        self.window.set_position(x, y);
+    }
+
+    pub fn geometry(&self) -> Option<WindowGeometry> {
+        let (x, y) = self.window.get_position();
+        let (width, height) = self.window.get_size();
+        Some(WindowGeometry {
+            position: Some((x as i32, y as i32)),
+            size: Some((width as u32, height as u32)),
+            fullscreen: false,
+            monitors: None,
+        })
+    }
+
+    pub fn toggle_fullscreen(&mut self) -> bool {
+        false
+    }
+
+    // A window of its own for the debugger panels, next to the game one. Keys only reach
+    // the game while its window has the focus
+    pub fn open_debug_window(&mut self) -> Result<(), String> {
+        let (width, height) = panels::window_size();
+        let mut window = Window::new("chip8 debugger", width, height, WindowOptions::default())
+            .map_err(|e| e.to_string())?;
+        // the game window sets the pace, this one waiting too would halve it
+        window.set_target_fps(0);
+        let (x, y) = self.window.get_position();
+        let (game_width, _) = self.window.get_size();
+        window.set_position(x + game_width as isize + 16, y);
+        self.debug = Some(DebugWindow {
+            window,
+            buffer: vec![0; width * height],
+            shown: None,
+        });
+        Ok(())
+    }
+
+    pub fn close_debug_window(&mut self) {
+        self.debug = None;
+    }
+
+    // Draws the panels if they changed, false once the debug window was closed
+    pub fn update_debug(&mut self, panels: &Panels) -> bool {
+        let Some(debug) = &mut self.debug else {
+            return false;
+        };
+        if !debug.window.is_open() {
+            self.debug = None;
+            return false;
+        }
+        let shown = (panels.clone(), self.palette);
+        if debug.shown.as_ref() == Some(&shown) {
+            debug.window.update();
+            return true;
+        }
+        let (width, height) = panels::window_size();
+        let text = osd::text_layout(&panels.lines(), panels::PIXEL);
+        debug.buffer.fill(self.palette.off);
+        for &(x, y) in &text.dots {
+            for row in y..(y + panels::PIXEL).min(height) {
+                let start = row * width + x;
+                debug.buffer[start..(start + panels::PIXEL).min((row + 1) * width)]
+                    .fill(self.palette.on);
+            }
+        }
+        let _ = debug
+            .window
+            .update_with_buffer(&debug.buffer, width, height);
+        debug.shown = Some(shown);
+        true
+    }
+
+    // Shows the window in use for other modules in a safe way
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    // Render converts display to scaled version buffer and updates screen
+    pub fn render(&mut self, display: &Display) {
+        profile_scope!("render");
+        let (pixel_width, pixel_height) = self.pixel;
+        let (width, height) = (64 * pixel_width, 32 * pixel_height);
+        // hi-res pixels split the same window in twice as many, uneven when the scale is odd
+        let (columns, lines) = (display.width(), display.height());
+        for row in 0..height {
+            let y = row * lines / height;
+            let line = &mut self.buffer[row * width..(row + 1) * width];
+            for x in 0..columns {
+                let color = self.palette.color(display.pixel_value(x, y));
+                line[x * width / columns..(x + 1) * width / columns].fill(color);
+            }
+        }
+        // font pixels half the size of chip8 pixels, so longer messages fit
+        let pixel = (pixel_height / 2).max(1);
+        let (on, off) = (self.palette.on, self.palette.off);
+        let buffer = &mut self.buffer;
+        let mut fill = |x: usize, y: usize, w: usize, h: usize, color: u32| {
+            for row in y..y + h {
+                buffer[row * width + x..row * width + x + w].fill(color);
+            }
+        };
+        if let Some(message) = &self.message {
+            let layout = osd::layout(message, width, height, pixel);
+            fill(layout.x, layout.y, layout.width, layout.height, on);
+            for &(x, y) in &layout.dots {
+                fill(x, y, pixel, pixel, off);
+            }
+        }
+        if let Some(pressed) = &self.keypad {
+            let pad = osd::keypad_layout(pressed, &self.labels, width, height, pixel);
+            fill(pad.x, pad.y, pad.width, pad.height, on);
+            for cell in &pad.cells {
+                let (back, front) = if cell.pressed { (on, off) } else { (off, on) };
+                fill(cell.x, cell.y, cell.width, cell.height, back);
+                for &(x, y) in &cell.dots {
+                    fill(x, y, pad.pixel, pad.pixel, front);
+                }
+            }
+        }
+        if let Some(times) = &self.frame_times {
+            let graph = osd::graph_layout(times, width, height, pixel);
+            fill(graph.x, graph.y, graph.width, graph.height, off);
+            for &(x, y, w, h) in &graph.bars {
+                fill(x, y, w, h, on);
+            }
+            fill(graph.x, graph.target_y, graph.width, graph.line, on);
+        }
+        // only fails once the window is gone, and is_open says so on the next loop
+        let _ = self.window.update_with_buffer(&self.buffer, width, height);
+    }
+
+    // Function to update screen
+    pub fn update(&mut self) {
+        self.window.update();
+    }
+
+    // Function to say the screen state (open or not)
+    pub fn is_open(&self) -> bool {
+        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    }
+
+    // Called every frame to sync physical keyboard state
+    pub fn read_keys(&self, keyboard: &mut Keyboard) {
+        for &(physical_key, chip8_key) in &self.keymap {
+            let _ = keyboard.set_key(chip8_key, self.window.is_key_down(physical_key));
+        }
+    }
+
+    // For hotkeys, keys of the keymap work too
+    pub fn is_key_down(&self, key: HostKey) -> bool {
+        key_code(key).is_some_and(|key| self.window.is_key_down(key))
+    }
+}
+
+impl DisplayBackend for Screen {
+    fn render(&mut self, display: &Display) {
+        Screen::render(self, display);
+    }
+
+    fn update(&mut self) {
+        Screen::update(self);
+    }
+
+    fn is_open(&self) -> bool {
+        Screen::is_open(self)
+    }
+}
+
+impl InputBackend for Screen {
+    fn keys(&mut self) -> [bool; 16] {
+        let mut keyboard = Keyboard::new();
+        Screen::read_keys(self, &mut keyboard);
+        keyboard.keys()
+    }
+}
+
+fn key_map(keymap: &Keymap) -> Vec<(Key, usize)> {
+    keymap
+        .iter()
+        .filter_map(|(host_key, chip8_key)| Some((key_code(HostKey::Char(host_key))?, chip8_key)))
+        .collect()
+}
+
+fn key_code(key: HostKey) -> Option<Key> {
+    match key {
+        HostKey::Char(c @ 'a'..='z') => Some(LETTERS[(c as u8 - b'a') as usize]),
+        HostKey::Char(c @ '0'..='9') => Some(DIGITS[(c as u8 - b'0') as usize]),
+        HostKey::Char(_) => None,
+        HostKey::F(number) => FUNCTION_KEYS.get(number as usize - 1).copied(),
+        HostKey::Escape => Some(Key::Escape),
+        HostKey::Space => Some(Key::Space),
+        HostKey::Enter => Some(Key::Enter),
+        HostKey::Backspace => Some(Key::Backspace),
+        HostKey::Tab => Some(Key::Tab),
+    }
+}
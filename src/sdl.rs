@@ -0,0 +1,449 @@
+// SDL2 frontend, same window and keys as screen.rs for machines where SDL is the better fit
+// (consoles, handhelds, kiosks). Needs the SDL2 library installed.
+use crate::display::{Aspect, Display, DisplayBackend, Palette};
+use crate::icon::{DEFAULT_TITLE, Icon, WindowGeometry};
+use crate::keyboard::{HostKey, InputBackend, Keyboard, Keymap};
+use crate::osd;
+use crate::panels::{self, Panels};
+use sdl2::EventPump;
+use sdl2::VideoSubsystem;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::surface::Surface;
+use sdl2::video::{FullscreenType, Window};
+use std::time::{Duration, Instant};
+
+const SCALE: u32 = 20;
+
+// Same update rate limit as minifb's default, so games run at the same speed in every frontend
+const UPDATE_RATE: Duration = Duration::from_millis(4);
+
+pub struct SdlScreen {
+    canvas: Canvas<Window>,
+    events: EventPump,
+    // width and height of a chip8 pixel in the window
+    pixel: (u32, u32),
+    palette: Palette,
+    // scancodes are physical positions, so the keypad stays in place on any keyboard layout
+    keymap: Vec<(Scancode, usize)>,
+    // the keymap again, for the labels of the keypad overlay
+    labels: Keymap,
+    message: Option<String>,
+    keypad: Option<[bool; 16]>,
+    frame_times: Option<Vec<f32>>,
+    // where the window was before going fullscreen, which is what gets saved
+    windowed: WindowGeometry,
+    debug: Option<DebugWindow>,
+    open: bool,
+    next_update: Instant,
+}
+
+// The second window, with what it shows now so it's only drawn again when that changes
+struct DebugWindow {
+    canvas: Canvas<Window>,
+    shown: Option<(Panels, Palette)>,
+}
+
+impl SdlScreen {
+    pub fn new() -> Result<Self, String> {
+        Self::with_scale(SCALE)
+    }
+
+    pub fn with_scale(scale: u32) -> Result<Self, String> {
+        Self::with_aspect(scale, Aspect::SQUARE)
+    }
+
+    // Pixels `scale` tall and as wide as the aspect says
+    pub fn with_aspect(scale: u32, aspect: Aspect) -> Result<Self, String> {
+        Self::with_geometry(scale, aspect, &WindowGeometry::default())
+    }
+
+    // Where and as big as it was last time, if the monitors are still the same. The window
+    // can be resized and the picture scales keeping its shape
+    pub fn with_geometry(
+        scale: u32,
+        aspect: Aspect,
+        geometry: &WindowGeometry,
+    ) -> Result<Self, String> {
+        let (width, height) = aspect.pixel_size(scale as usize);
+        let pixel = (width as u32, height as u32);
+        let context = sdl2::init()?;
+        let video = context.video()?;
+        let (width, height) = geometry.size.unwrap_or((64 * pixel.0, 32 * pixel.1));
+        let mut builder = video.window(DEFAULT_TITLE, width, height);
+        builder.resizable();
+        let position = geometry.position_on(monitors(&video).as_deref());
+        match position {
+            Some((x, y)) => builder.position(x, y),
+            None => builder.position_centered(),
+        };
+        if geometry.fullscreen {
+            builder.fullscreen_desktop();
+        }
+        let window = builder.build().map_err(|e| e.to_string())?;
+        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        // drawing stays in chip8 pixels whatever the window size
+        canvas
+            .set_logical_size(64 * pixel.0, 32 * pixel.1)
+            .map_err(|e| e.to_string())?;
+        let events = context.event_pump()?;
+
+        Ok(SdlScreen {
+            canvas,
+            events,
+            pixel,
+            palette: Palette::default(),
+            keymap: key_map(&Keymap::new()),
+            labels: Keymap::new(),
+            message: None,
+            keypad: None,
+            frame_times: None,
+            windowed: WindowGeometry {
+                position,
+                ..geometry.clone()
+            },
+            debug: None,
+            open: true,
+            next_update: Instant::now(),
+        })
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_keymap(&mut self, keymap: &Keymap) {
+        self.keymap = key_map(keymap);
+        self.labels = *keymap;
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        // only fails on a title with a nul byte
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+
+    pub fn set_icon(&mut self, icon: &Icon) {
+        let mut data: Vec<u8> = icon
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_ne_bytes())
+            .collect();
+        let format = PixelFormatEnum::ARGB8888;
+        if let Ok(surface) =
+            Surface::from_data(&mut data, icon.width, icon.height, icon.width * 4, format)
+        {
+            self.canvas.window_mut().set_icon(surface);
+        }
+    }
+
+    // Drawn over the game from the next render on, see osd.rs
+    pub fn set_message(&mut self, message: Option<&str>) {
+        self.message = message.map(str::to_string);
+    }
+
+    // The keypad overlay with these keys down, None hides it
+    pub fn set_keypad(&mut self, pressed: Option<[bool; 16]>) {
+        self.keypad = pressed;
+    }
+
+    // The frame time graph with these times in ms, None hides it
+    pub fn set_frame_times(&mut self, times: Option<&[f32]>) {
+        self.frame_times = times.map(<[f32]>::to_vec);
+    }
+
+    pub fn render(&mut self, display: &Display) {
+        profile_scope!("render");
+        // hi-res pixels split the same screen in twice as many, uneven when the scale is odd
+        let (screen_width, screen_height) = (64 * self.pixel.0, 32 * self.pixel.1);
+        let (columns, lines) = (display.width() as u32, display.height() as u32);
+        // the lit pixels of each color, 1 to 3 (see Display::pixel_value)
+        let mut lit: [Vec<Rect>; 3] = Default::default();
+        for (x, y) in (0..lines).flat_map(|y| (0..columns).map(move |x| (x, y))) {
+            let value = display.pixel_value(x as usize, y as usize);
+            if value == 0 {
+                continue;
+            }
+            let left = x * screen_width / columns;
+            let top = y * screen_height / lines;
+            let right = (x + 1) * screen_width / columns;
+            let bottom = (y + 1) * screen_height / lines;
+            lit[value as usize - 1].push(Rect::new(
+                left as i32,
+                top as i32,
+                right - left,
+                bottom - top,
+            ));
+        }
+
+        self.canvas.set_draw_color(color(self.palette.off));
+        self.canvas.clear();
+        for (value, rects) in (1..=3).zip(&lit) {
+            self.canvas.set_draw_color(color(self.palette.color(value)));
+            let _ = self.canvas.fill_rects(rects);
+        }
+        let (width, height) = (64 * self.pixel.0 as usize, 32 * self.pixel.1 as usize);
+        let pixel = (self.pixel.1 as usize / 2).max(1);
+        let rect = |x: usize, y: usize, w: usize, h: usize| {
+            Rect::new(x as i32, y as i32, w as u32, h as u32)
+        };
+        if let Some(message) = &self.message {
+            let layout = osd::layout(message, width, height, pixel);
+            self.canvas.set_draw_color(color(self.palette.on));
+            let _ = self
+                .canvas
+                .fill_rect(rect(layout.x, layout.y, layout.width, layout.height));
+            let dots: Vec<Rect> = layout
+                .dots
+                .iter()
+                .map(|&(x, y)| rect(x, y, pixel, pixel))
+                .collect();
+            self.canvas.set_draw_color(color(self.palette.off));
+            let _ = self.canvas.fill_rects(&dots);
+        }
+        if let Some(pressed) = &self.keypad {
+            let pad = osd::keypad_layout(pressed, &self.labels, width, height, pixel);
+            let (on, off) = (color(self.palette.on), color(self.palette.off));
+            self.canvas.set_draw_color(on);
+            let _ = self
+                .canvas
+                .fill_rect(rect(pad.x, pad.y, pad.width, pad.height));
+            for cell in &pad.cells {
+                let (back, front) = if cell.pressed { (on, off) } else { (off, on) };
+                self.canvas.set_draw_color(back);
+                let _ = self
+                    .canvas
+                    .fill_rect(rect(cell.x, cell.y, cell.width, cell.height));
+                let dots: Vec<Rect> = cell
+                    .dots
+                    .iter()
+                    .map(|&(x, y)| rect(x, y, pad.pixel, pad.pixel))
+                    .collect();
+                self.canvas.set_draw_color(front);
+                let _ = self.canvas.fill_rects(&dots);
+            }
+        }
+        if let Some(times) = &self.frame_times {
+            let graph = osd::graph_layout(times, width, height, pixel);
+            self.canvas.set_draw_color(color(self.palette.off));
+            let _ = self
+                .canvas
+                .fill_rect(rect(graph.x, graph.y, graph.width, graph.height));
+            let mut bars: Vec<Rect> = graph
+                .bars
+                .iter()
+                .map(|&(x, y, w, h)| rect(x, y, w, h))
+                .collect();
+            bars.push(rect(graph.x, graph.target_y, graph.width, graph.line));
+            self.canvas.set_draw_color(color(self.palette.on));
+            let _ = self.canvas.fill_rects(&bars);
+        }
+        self.canvas.present();
+        self.update();
+    }
+
+    pub fn geometry(&self) -> Option<WindowGeometry> {
+        let window = self.canvas.window();
+        let fullscreen = window.fullscreen_state() != FullscreenType::Off;
+        let (position, size) = if fullscreen {
+            (self.windowed.position, self.windowed.size)
+        } else {
+            (Some(window.position()), Some(window.size()))
+        };
+        Some(WindowGeometry {
+            position,
+            size,
+            fullscreen,
+            monitors: monitors(window.subsystem()),
+        })
+    }
+
+    // Fullscreen at the desktop resolution, or back to the window it was
+    pub fn toggle_fullscreen(&mut self) -> bool {
+        let window = self.canvas.window_mut();
+        let state = if window.fullscreen_state() == FullscreenType::Off {
+            self.windowed.position = Some(window.position());
+            self.windowed.size = Some(window.size());
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
+        };
+        window.set_fullscreen(state).is_ok()
+    }
+
+    // A window of its own for the debugger panels, next to the game one. The keys are read
+    // whichever of the two has the focus
+    pub fn open_debug_window(&mut self) -> Result<(), String> {
+        let (width, height) = panels::window_size();
+        let game = self.canvas.window();
+        let (x, y) = game.position();
+        let window = game
+            .subsystem()
+            .window("chip8 debugger", width as u32, height as u32)
+            .position(x + game.size().0 as i32 + 16, y)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        self.debug = Some(DebugWindow {
+            canvas,
+            shown: None,
+        });
+        Ok(())
+    }
+
+    pub fn close_debug_window(&mut self) {
+        self.debug = None;
+    }
+
+    // Draws the panels if they changed, false once the debug window was closed
+    pub fn update_debug(&mut self, panels: &Panels) -> bool {
+        let Some(debug) = &mut self.debug else {
+            return false;
+        };
+        let shown = (panels.clone(), self.palette);
+        if debug.shown.as_ref() == Some(&shown) {
+            return true;
+        }
+        let text = osd::text_layout(&panels.lines(), panels::PIXEL);
+        let pixel = panels::PIXEL as u32;
+        let dots: Vec<Rect> = text
+            .dots
+            .iter()
+            .map(|&(x, y)| Rect::new(x as i32, y as i32, pixel, pixel))
+            .collect();
+        debug.canvas.set_draw_color(color(self.palette.off));
+        debug.canvas.clear();
+        debug.canvas.set_draw_color(color(self.palette.on));
+        let _ = debug.canvas.fill_rects(&dots);
+        debug.canvas.present();
+        debug.shown = Some(shown);
+        true
+    }
+
+    // Nothing to redraw, just keeps the loop at the update rate
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        if self.next_update > now {
+            std::thread::sleep(self.next_update - now);
+        } else {
+            self.next_update = now;
+        }
+        self.next_update += UPDATE_RATE;
+    }
+
+    // Closing the window or Esc closes it
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // Called every frame to sync physical keyboard state
+    pub fn read_keys(&mut self, keyboard: &mut Keyboard) {
+        let debug_id = self.debug.as_ref().map(|debug| debug.canvas.window().id());
+        for event in self.events.poll_iter() {
+            match event {
+                // with two windows closing one doesn't quit, only the game window closes both
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } if Some(window_id) == debug_id => self.debug = None,
+                Event::Quit { .. }
+                | Event::Window {
+                    win_event: WindowEvent::Close,
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => self.open = false,
+                // uncovered, the panels need painting again
+                Event::Window {
+                    win_event: WindowEvent::Exposed,
+                    ..
+                } => {
+                    if let Some(debug) = &mut self.debug {
+                        debug.shown = None;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let state = self.events.keyboard_state();
+        for &(scancode, chip8_key) in &self.keymap {
+            let _ = keyboard.set_key(chip8_key, state.is_scancode_pressed(scancode));
+        }
+    }
+
+    // For hotkeys, keys of the keymap work too
+    pub fn is_key_down(&self, key: HostKey) -> bool {
+        scancode(key)
+            .is_some_and(|scancode| self.events.keyboard_state().is_scancode_pressed(scancode))
+    }
+}
+
+impl DisplayBackend for SdlScreen {
+    fn render(&mut self, display: &Display) {
+        SdlScreen::render(self, display);
+    }
+
+    fn update(&mut self) {
+        SdlScreen::update(self);
+    }
+
+    fn is_open(&self) -> bool {
+        SdlScreen::is_open(self)
+    }
+}
+
+impl InputBackend for SdlScreen {
+    fn keys(&mut self) -> [bool; 16] {
+        let mut keyboard = Keyboard::new();
+        SdlScreen::read_keys(self, &mut keyboard);
+        keyboard.keys()
+    }
+}
+
+// The bounds of every display, "1920x1080+0+0,2560x1440+1920+0"
+fn monitors(video: &VideoSubsystem) -> Option<String> {
+    let count = video.num_video_displays().ok()?;
+    let bounds = (0..count)
+        .map(|index| {
+            let bounds = video.display_bounds(index)?;
+            Ok(format!(
+                "{}x{}+{}+{}",
+                bounds.width(),
+                bounds.height(),
+                bounds.x(),
+                bounds.y()
+            ))
+        })
+        .collect::<Result<Vec<_>, String>>();
+    bounds.ok().map(|bounds| bounds.join(","))
+}
+
+fn color(rgb: u32) -> Color {
+    Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+}
+
+fn key_map(keymap: &Keymap) -> Vec<(Scancode, usize)> {
+    keymap
+        .iter()
+        .filter_map(|(host_key, chip8_key)| Some((scancode(HostKey::Char(host_key))?, chip8_key)))
+        .collect()
+}
+
+// SDL names its scancodes after the US layout, which is what keymaps are written in
+fn scancode(key: HostKey) -> Option<Scancode> {
+    match key {
+        HostKey::Char(c) => Scancode::from_name(&c.to_ascii_uppercase().to_string()),
+        HostKey::F(number) => Scancode::from_name(&format!("F{}", number)),
+        HostKey::Escape => Some(Scancode::Escape),
+        HostKey::Space => Some(Scancode::Space),
+        HostKey::Enter => Some(Scancode::Return),
+        HostKey::Backspace => Some(Scancode::Backspace),
+        HostKey::Tab => Some(Scancode::Tab),
+    }
+}
@@ -0,0 +1,289 @@
+use crate::chip8::Chip8;
+
+// Built-in self test, `chip8 selftest`. Everything runs on a headless machine so it works
+// on servers and build machines without a screen or sound card.
+// The checks are written against the CHIP-8 spec, not against this implementation,
+// so they catch a broken build or a broken change in the CPU.
+
+pub struct Check {
+    pub name: &'static str,
+    run: fn() -> Result<(), String>,
+}
+
+impl Check {
+    pub fn run(&self) -> Result<(), String> {
+        (self.run)()
+    }
+}
+
+pub const CHECKS: [Check; 5] = [
+    Check {
+        name: "opcode vectors",
+        run: opcode_vectors,
+    },
+    Check {
+        name: "subroutines and stack",
+        run: subroutines,
+    },
+    Check {
+        name: "timer math",
+        run: timers,
+    },
+    Check {
+        name: "draw and collisions",
+        run: draw_collisions,
+    },
+    Check {
+        name: "memory opcodes",
+        run: memory_opcodes,
+    },
+];
+
+pub fn run_all() -> Vec<(&'static str, Result<(), String>)> {
+    CHECKS
+        .iter()
+        .map(|check| (check.name, check.run()))
+        .collect()
+}
+
+// One instruction executed from 0x200: registers before, registers and PC after
+struct Vector {
+    opcode: u16,
+    before: &'static [(usize, u8)],
+    after: &'static [(usize, u8)],
+    pc: u16,
+}
+
+const fn v(
+    opcode: u16,
+    before: &'static [(usize, u8)],
+    after: &'static [(usize, u8)],
+    pc: u16,
+) -> Vector {
+    Vector {
+        opcode,
+        before,
+        after,
+        pc,
+    }
+}
+
+#[rustfmt::skip]
+const VECTORS: [Vector; 24] = [
+    v(0x6A42, &[],                         &[(0xA, 0x42)],             0x202), // 6xnn
+    v(0x7AFF, &[(0xA, 0x02)],              &[(0xA, 0x01), (0xF, 0)],   0x202), // 7xnn wraps, VF untouched
+    v(0x3A10, &[(0xA, 0x10)],              &[],                        0x204), // 3xnn skip
+    v(0x3A11, &[(0xA, 0x10)],              &[],                        0x202), // 3xnn no skip
+    v(0x4A11, &[(0xA, 0x10)],              &[],                        0x204), // 4xnn skip
+    v(0x5AB0, &[(0xA, 7), (0xB, 7)],       &[],                        0x204), // 5xy0 skip
+    v(0x9AB0, &[(0xA, 7), (0xB, 7)],       &[],                        0x202), // 9xy0 no skip
+    v(0x8AB0, &[(0xB, 9)],                 &[(0xA, 9)],                0x202), // 8xy0
+    v(0x8AB1, &[(0xA, 0xF0), (0xB, 0x0F)], &[(0xA, 0xFF)],             0x202), // 8xy1
+    v(0x8AB2, &[(0xA, 0xF0), (0xB, 0x3C)], &[(0xA, 0x30)],             0x202), // 8xy2
+    v(0x8AB3, &[(0xA, 0xF0), (0xB, 0x3C)], &[(0xA, 0xCC)],             0x202), // 8xy3
+    v(0x8AB4, &[(0xA, 0xF0), (0xB, 0x20)], &[(0xA, 0x10), (0xF, 1)],   0x202), // 8xy4 carry
+    v(0x8AB4, &[(0xA, 0x10), (0xB, 0x20)], &[(0xA, 0x30), (0xF, 0)],   0x202), // 8xy4 no carry
+    v(0x8AB5, &[(0xA, 0x30), (0xB, 0x10)], &[(0xA, 0x20), (0xF, 1)],   0x202), // 8xy5 no borrow
+    v(0x8AB5, &[(0xA, 0x10), (0xB, 0x30)], &[(0xA, 0xE0), (0xF, 0)],   0x202), // 8xy5 borrow
+    v(0x8AB7, &[(0xA, 0x10), (0xB, 0x30)], &[(0xA, 0x20), (0xF, 1)],   0x202), // 8xy7
+    v(0x8AA6, &[(0xA, 0x05)],              &[(0xA, 0x02), (0xF, 1)],   0x202), // 8xy6, y = x shifts the same with either quirk
+    v(0x8AAE, &[(0xA, 0x81)],              &[(0xA, 0x02), (0xF, 1)],   0x202), // 8xyE
+    v(0x8FA4, &[(0xA, 0xFF), (0xF, 0x01)], &[(0xF, 1)],                0x202), // 8xy4 into VF, flag wins
+    v(0x1345, &[],                         &[],                        0x345), // 1nnn
+    v(0xB300, &[(0x0, 0x10)],              &[],                        0x310), // Bnnn
+    v(0xEA9E, &[(0xA, 0x5)],               &[],                        0x204), // Ex9E, key 5 is down
+    v(0xEAA1, &[(0xA, 0x5)],               &[],                        0x202), // ExA1, key 5 is down
+    v(0xEBA1, &[(0xB, 0x6)],               &[],                        0x204), // ExA1, key 6 is up
+];
+
+fn opcode_vectors() -> Result<(), String> {
+    for vector in VECTORS.iter() {
+        let rom = vector.opcode.to_be_bytes();
+        let mut chip = Chip8::new(&rom).map_err(|e| format!("{:?}", e))?;
+        chip.keyboard
+            .set_key(0x5, true)
+            .map_err(|e| format!("{:?}", e))?;
+        for &(register, value) in vector.before {
+            chip.set_v(register, value)
+                .map_err(|e| format!("{:?}", e))?;
+        }
+
+        chip.step()
+            .map_err(|e| format!("{:04X}: {:?}", vector.opcode, e))?;
+
+        for &(register, value) in vector.after {
+            let actual = chip.get_v(register).map_err(|e| format!("{:?}", e))?;
+            if actual != value {
+                return Err(format!(
+                    "{:04X}: V{:X} is {:#04X}, expected {:#04X}",
+                    vector.opcode, register, actual, value
+                ));
+            }
+        }
+        if *chip.get_pc() != vector.pc {
+            return Err(format!(
+                "{:04X}: PC is {:#05X}, expected {:#05X}",
+                vector.opcode,
+                chip.get_pc(),
+                vector.pc
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Runs a small program and returns the machine
+fn run(rom: &[u8], steps: usize) -> Result<Chip8, String> {
+    let mut chip = Chip8::new(rom).map_err(|e| format!("{:?}", e))?;
+    chip.set_seed(0);
+    for _ in 0..steps {
+        chip.step()
+            .map_err(|e| format!("PC {:#05X}: {:?}", chip.get_pc(), e))?;
+    }
+    Ok(chip)
+}
+
+fn expect<T: PartialEq + std::fmt::Debug>(
+    what: &str,
+    actual: T,
+    expected: T,
+) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("{} is {:?}, expected {:?}", what, actual, expected))
+    }
+}
+
+fn subroutines() -> Result<(), String> {
+    let rom = [
+        0x22, 0x06, // 200: call 206
+        0x61, 0x02, // 202: V1 = 2
+        0x12, 0x04, // 204: spin
+        0x60, 0x01, // 206: V0 = 1
+        0x00, 0xEE, // 208: return
+    ];
+    let chip = run(&rom, 4)?;
+    expect("V0 after call", chip.get_v(0).unwrap_or(0), 1)?;
+    expect("V1 after return", chip.get_v(1).unwrap_or(0), 2)?;
+    expect("PC", *chip.get_pc(), 0x204)?;
+
+    // 16 nested calls fit, the 17th overflows
+    let mut chip = Chip8::new(&[0x22, 0x00]).map_err(|e| format!("{:?}", e))?;
+    for _ in 0..16 {
+        chip.step().map_err(|e| format!("{:?}", e))?;
+    }
+    if chip.step().is_ok() {
+        return Err("17th nested call didn't overflow the stack".to_string());
+    }
+    if run(&[0x00, 0xEE], 1).is_ok() {
+        return Err("return without call didn't underflow the stack".to_string());
+    }
+    Ok(())
+}
+
+fn timers() -> Result<(), String> {
+    let rom = [
+        0x60, 0x03, // V0 = 3
+        0xF0, 0x15, // DT = V0
+        0xF0, 0x18, // ST = V0
+        0xF1, 0x07, // V1 = DT
+    ];
+    let mut chip = run(&rom, 4)?;
+    expect("V1 = DT", chip.get_v(1).unwrap_or(0), 3)?;
+    for _ in 0..2 {
+        chip.decrease_timers();
+    }
+    expect("DT after 2 ticks", *chip.get_dt(), 1)?;
+    expect("ST after 2 ticks", *chip.get_st(), 1)?;
+    for _ in 0..5 {
+        chip.decrease_timers();
+    }
+    expect("DT saturates at", *chip.get_dt(), 0)?;
+    expect("ST saturates at", *chip.get_st(), 0)
+}
+
+fn draw_collisions() -> Result<(), String> {
+    let rom = [
+        0x60, 0x3E, // V0 = 62, so the sprite wraps right
+        0x61, 0x1F, // V1 = 31, and down
+        0xA0, 0x50, // I = font 0
+        0xD0, 0x15, // draw
+        0xF2, 0x07, // V2 = DT (just to read something between draws)
+        0x83, 0xF0, // V3 = VF, first draw
+        0xD0, 0x15, // draw again, erases everything
+    ];
+    let mut chip = run(&rom, 6)?;
+    expect("VF after first draw", chip.get_v(3).unwrap_or(9), 0)?;
+    expect(
+        "pixel (62, 31)",
+        chip.display.get_pixel(31 * 64 + 62).ok(),
+        Some(1),
+    )?;
+    expect(
+        "wrapped pixel (1, 0)",
+        chip.display.get_pixel(1).ok(),
+        Some(1),
+    )?;
+    chip.step().map_err(|e| format!("{:?}", e))?;
+    expect("VF after second draw", chip.get_v(0xF).unwrap_or(9), 1)?;
+    expect(
+        "screen after erasing",
+        chip.display.count_differences(""),
+        0,
+    )
+}
+
+fn memory_opcodes() -> Result<(), String> {
+    let rom = [
+        0x60, 0xFE, // V0 = 254
+        0xA3, 0x00, // I = 0x300
+        0xF0, 0x33, // BCD V0
+        0xF2, 0x65, // V0..V2 = mem[I..]
+        0xA3, 0x10, // I = 0x310
+        0xF2, 0x55, // mem[I..] = V0..V2
+        0x64, 0x0A, // V4 = 10
+        0xF4, 0x1E, // I += V4
+        0x65, 0x0B, // V5 = 0xB
+        0xF5, 0x29, // I = font(B)
+        0xC6, 0x0F, // V6 = rand & 0x0F
+    ];
+    let chip = run(&rom, 8)?;
+    expect(
+        "BCD digits",
+        [chip.get_v(0), chip.get_v(1), chip.get_v(2)].map(|r| r.unwrap_or(0)),
+        [2, 5, 4],
+    )?;
+    expect(
+        "stored bytes",
+        [
+            chip.get_ram(0x310),
+            chip.get_ram(0x311),
+            chip.get_ram(0x312),
+        ]
+        .map(|r| r.unwrap_or(0)),
+        [2, 5, 4],
+    )?;
+    // Fx55 left I past V2, like the COSMAC VIP
+    expect("I after Fx1E", *chip.get_i(), 0x31D)?;
+
+    let chip = run(&rom, 11)?;
+    expect("I after Fx29", *chip.get_i(), 0x50 + 0xB * 5)?;
+    let random = chip.get_v(6).unwrap_or(0xFF);
+    if random > 0x0F {
+        return Err(format!("Cxnn ignored its mask: {:#04X}", random));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selftest_passes() {
+        for (name, result) in run_all() {
+            assert!(result.is_ok(), "{}: {:?}", name, result);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::chip8::Chip8;
+
+// Where the beep goes. The core only has the sound timer, Emulator follows it on one of
+// these each frame (Emulator::with_audio). Audio (rodio, the `audio` feature) plays it on
+// the sound device, NullAudio keeps quiet for CI, servers and machines without one
+pub trait AudioBackend {
+    fn start_beep(&mut self);
+    fn stop_beep(&mut self);
+
+    // Once per frame. Backends that play the game's pitch or XO-CHIP's pattern read them here
+    fn update_from(&mut self, chip: &Chip8) {
+        if chip.is_sound_on() {
+            self.start_beep();
+        } else {
+            self.stop_beep();
+        }
+    }
+}
+
+// No sound at all, only whether it would be beeping
+#[derive(Debug, Default)]
+pub struct NullAudio {
+    beeping: bool,
+}
+
+impl NullAudio {
+    pub fn new() -> Self {
+        NullAudio::default()
+    }
+
+    pub fn is_beeping(&self) -> bool {
+        self.beeping
+    }
+}
+
+impl AudioBackend for NullAudio {
+    fn start_beep(&mut self) {
+        self.beeping = true;
+    }
+
+    fn stop_beep(&mut self) {
+        self.beeping = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_null_audio_follows_the_sound_timer() {
+        let mut audio = NullAudio::new();
+        let mut chip = Chip8::new(&[]).unwrap();
+        audio.update_from(&chip);
+        assert!(!audio.is_beeping());
+        chip.set_st(2);
+        audio.update_from(&chip);
+        assert!(audio.is_beeping());
+        chip.set_st(0);
+        audio.update_from(&chip);
+        assert!(!audio.is_beeping());
+    }
+}
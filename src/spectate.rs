@@ -0,0 +1,236 @@
+// Live view of a running session for spectators, over WebSocket. The same port serves
+// web/spectate.html to plain browser requests, so watching is just opening
+// http://host:port. Every frame where something changed each spectator gets one binary
+// message:
+//     byte 0: bit 0 = buzzer on, bit 1 = full frame
+//     full frame: the 256 bytes of the screen, 8 pixels per byte, row by row, MSB left
+//     otherwise: (index, byte) pairs for the bytes of the screen that changed
+// A spectator always gets a full frame first.
+//
+// It's a Plugin, on_frame sends the frame, so it works the same in every run loop.
+use crate::chip8::Chip8;
+use crate::display::Display;
+use crate::plugin::Plugin;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tungstenite::Message;
+
+const PAGE: &str = include_str!("../web/spectate.html");
+
+const SOUND: u8 = 1;
+const FULL: u8 = 2;
+
+// What spectator threads share with the run loop
+#[derive(Default)]
+struct Shared {
+    spectators: Vec<Sender<Arc<Vec<u8>>>>,
+    // the last full frame, for spectators joining late
+    full: Vec<u8>,
+}
+
+pub struct SpectatorServer {
+    shared: Arc<Mutex<Shared>>,
+    addr: SocketAddr,
+    screen: [u8; 256],
+    sound_on: bool,
+    started: bool,
+}
+
+impl SpectatorServer {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let accepting = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = accepting.clone();
+                thread::spawn(move || serve(stream, &shared));
+            }
+        });
+
+        Ok(SpectatorServer {
+            shared,
+            addr,
+            screen: [0; 256],
+            sound_on: false,
+            started: false,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn spectators(&self) -> usize {
+        lock(&self.shared).spectators.len()
+    }
+
+    // Sends what changed since the last call, nothing if the screen and buzzer didn't change
+    pub fn broadcast(&mut self, chip: &Chip8) {
+        let screen = pack(&chip.display);
+        let sound_on = chip.is_sound_on();
+        if self.started && screen == self.screen && sound_on == self.sound_on {
+            return;
+        }
+
+        let flags = if sound_on { SOUND } else { 0 };
+        let mut full = Vec::with_capacity(257);
+        full.push(flags | FULL);
+        full.extend_from_slice(&screen);
+
+        let mut diff = vec![flags];
+        for (index, (&new, &old)) in screen.iter().zip(&self.screen).enumerate() {
+            if new != old {
+                diff.extend_from_slice(&[index as u8, new]);
+            }
+        }
+        let message = if !self.started || diff.len() >= full.len() {
+            full.clone()
+        } else {
+            diff
+        };
+
+        self.screen = screen;
+        self.sound_on = sound_on;
+        self.started = true;
+        let mut shared = lock(&self.shared);
+        shared.full = full;
+        let message = Arc::new(message);
+        // a spectator that left has dropped its receiver
+        shared
+            .spectators
+            .retain(|spectator| spectator.send(message.clone()).is_ok());
+    }
+}
+
+impl Plugin for SpectatorServer {
+    fn name(&self) -> &str {
+        "spectate"
+    }
+
+    fn on_frame(&mut self, chip: &mut Chip8) -> Result<(), String> {
+        self.broadcast(chip);
+        Ok(())
+    }
+}
+
+fn lock(shared: &Mutex<Shared>) -> std::sync::MutexGuard<'_, Shared> {
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// 8 pixels per byte, the wire format above, which is just the rows of the display
+fn pack(display: &Display) -> [u8; 256] {
+    let mut packed = [0u8; 256];
+    for (bytes, row) in packed.chunks_mut(8).zip(display.rows()) {
+        bytes.copy_from_slice(&row.to_be_bytes());
+    }
+    packed
+}
+
+// One thread per connection: the page for browsers, frames for WebSockets
+fn serve(stream: TcpStream, shared: &Mutex<Shared>) {
+    let mut head = [0u8; 2048];
+    let Ok(read) = stream.peek(&mut head) else {
+        return;
+    };
+    let head = String::from_utf8_lossy(&head[..read]).to_ascii_lowercase();
+    if !head.contains("upgrade: websocket") {
+        let _ = send_page(stream);
+        return;
+    }
+
+    let Ok(mut socket) = tungstenite::accept(stream) else {
+        return;
+    };
+    let (sender, frames): (_, Receiver<Arc<Vec<u8>>>) = mpsc::channel();
+    {
+        let mut shared = lock(shared);
+        if !shared.full.is_empty() {
+            let _ = sender.send(Arc::new(shared.full.clone()));
+        }
+        shared.spectators.push(sender);
+    }
+    for frame in frames {
+        if socket.send(Message::binary(frame.to_vec())).is_err() {
+            break;
+        }
+    }
+}
+
+fn send_page(mut stream: TcpStream) -> io::Result<()> {
+    // read the request out first, closing with it unread would reset the connection
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && !line.trim().is_empty() {
+        line.clear();
+    }
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/html; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        PAGE.len(),
+        PAGE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_spectator_gets_full_frame_then_diffs() {
+        let mut server = SpectatorServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        // draws the font 0 at (0, 0), then the font 1 at (8, 0), then beeps
+        let rom = [
+            0xA0, 0x50, 0xD0, 0x05, 0x61, 0x08, 0xA0, 0x55, 0xD1, 0x05, 0x62, 0x10, 0xF2, 0x18,
+            0x12, 0x0E,
+        ];
+        let mut chip = Chip8::new(&rom).unwrap();
+        for _ in 0..2 {
+            chip.step().unwrap();
+        }
+        server.broadcast(&chip);
+
+        let (mut socket, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+        while server.spectators() == 0 {
+            thread::yield_now();
+        }
+        for _ in 0..5 {
+            chip.step().unwrap();
+        }
+        server.broadcast(&chip);
+        // nothing changed, nothing sent
+        server.broadcast(&chip);
+
+        let full = socket.read().unwrap().into_data();
+        assert_eq!(full.len(), 257);
+        assert_eq!(full[0], FULL);
+        assert_eq!(&full[1..3], &[0xF0, 0x00]);
+        assert_eq!(full[1 + 8], 0x90);
+
+        let diff = socket.read().unwrap().into_data();
+        // the 1 has 5 rows, one byte each, and the buzzer is on
+        assert_eq!(diff[0], SOUND);
+        assert_eq!(&diff[1..5], &[1, 0x20, 9, 0x60]);
+        assert_eq!(diff.len(), 1 + 5 * 2);
+    }
+
+    #[test]
+    fn test_serves_page() {
+        let server = SpectatorServer::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("<canvas"));
+    }
+}
@@ -0,0 +1,369 @@
+use crate::chip8::Chip8;
+use crate::memory::RAM_SIZE;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write as _};
+
+// What two moments of a machine differ in, for tests and the debugger to show what one
+// instruction or one frame changed. StateView is a plain copy of everything a ROM can
+// change, StateDiff lists what isn't the same between two of them.
+
+#[derive(Clone, PartialEq)]
+pub struct StateView {
+    pub pc: u16,
+    pub i: u16,
+    pub v: [u8; 16],
+    pub dt: u8,
+    pub st: u8,
+    // the return addresses in use, bottom first
+    pub stack: Vec<u16>,
+    pub ram: Box<[u8; RAM_SIZE]>,
+    pub screen: [u64; 32],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    Pc,
+    I,
+    V(usize),
+    Dt,
+    St,
+}
+
+// old and new value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub register: Register,
+    pub before: u16,
+    pub after: u16,
+}
+
+// a run of changed bytes, from `start`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryChange {
+    pub start: u16,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+// None where the stack was shorter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackChange {
+    pub depth: usize,
+    pub before: Option<u16>,
+    pub after: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub registers: Vec<RegisterChange>,
+    pub memory: Vec<MemoryChange>,
+    pub stack: Vec<StackChange>,
+    // pixels that turned on or off
+    pub pixels: u32,
+}
+
+impl StateView {
+    pub fn of(chip: &Chip8) -> Self {
+        let mut ram = Box::new([0; RAM_SIZE]);
+        for (address, byte) in ram.iter_mut().enumerate() {
+            *byte = chip.get_ram(address as u16).unwrap_or(0);
+        }
+        StateView {
+            pc: *chip.get_pc(),
+            i: *chip.get_i(),
+            v: chip.registers(),
+            dt: *chip.get_dt(),
+            st: *chip.get_st(),
+            stack: chip.stack().to_vec(),
+            ram,
+            screen: chip.display.rows(),
+        }
+    }
+
+    // What changed from this view to `other`
+    pub fn diff(&self, other: &StateView) -> StateDiff {
+        let mut registers = Vec::new();
+        let mut compare = |register, before: u16, after: u16| {
+            if before != after {
+                registers.push(RegisterChange {
+                    register,
+                    before,
+                    after,
+                });
+            }
+        };
+        compare(Register::Pc, self.pc, other.pc);
+        compare(Register::I, self.i, other.i);
+        for (index, (&before, &after)) in self.v.iter().zip(&other.v).enumerate() {
+            compare(Register::V(index), before as u16, after as u16);
+        }
+        compare(Register::Dt, self.dt as u16, other.dt as u16);
+        compare(Register::St, self.st as u16, other.st as u16);
+
+        let mut memory: Vec<MemoryChange> = Vec::new();
+        for (address, (&before, &after)) in self.ram.iter().zip(other.ram.iter()).enumerate() {
+            if before == after {
+                continue;
+            }
+            match memory.last_mut() {
+                Some(run) if run.start as usize + run.before.len() == address => {
+                    run.before.push(before);
+                    run.after.push(after);
+                }
+                _ => memory.push(MemoryChange {
+                    start: address as u16,
+                    before: alloc::vec![before],
+                    after: alloc::vec![after],
+                }),
+            }
+        }
+
+        let stack = (0..self.stack.len().max(other.stack.len()))
+            .map(|depth| StackChange {
+                depth,
+                before: self.stack.get(depth).copied(),
+                after: other.stack.get(depth).copied(),
+            })
+            .filter(|change| change.before != change.after)
+            .collect();
+
+        let pixels = self
+            .screen
+            .iter()
+            .zip(&other.screen)
+            .map(|(before, after)| (before ^ after).count_ones())
+            .sum();
+
+        StateDiff {
+            registers,
+            memory,
+            stack,
+            pixels,
+        }
+    }
+}
+
+// Readable documents of a view for bug reports and text diffs: the registers, the stack,
+// the timers and every 16 byte line of RAM that isn't all zeros, numbers in hex
+impl StateView {
+    pub fn to_json(&self) -> String {
+        let mut text = String::from("{\n");
+        let _ = writeln!(text, "  \"pc\": \"{:#05X}\",", self.pc);
+        let _ = writeln!(text, "  \"i\": \"{:#05X}\",", self.i);
+        let _ = writeln!(text, "  \"v\": [{}],", self.registers().join(", "));
+        let _ = writeln!(text, "  \"dt\": {},", self.dt);
+        let _ = writeln!(text, "  \"st\": {},", self.st);
+        let _ = writeln!(
+            text,
+            "  \"stack\": [{}],",
+            self.return_addresses().join(", ")
+        );
+        text.push_str("  \"memory\": {");
+        for (index, (address, bytes)) in self.memory_lines().enumerate() {
+            let comma = if index == 0 { "" } else { "," };
+            let _ = write!(text, "{}\n    \"{:#05X}\": \"{}\"", comma, address, bytes);
+        }
+        text.push_str("\n  }\n}\n");
+        text
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "pc = \"{:#05X}\"", self.pc);
+        let _ = writeln!(text, "i = \"{:#05X}\"", self.i);
+        let _ = writeln!(text, "v = [{}]", self.registers().join(", "));
+        let _ = writeln!(text, "dt = {}", self.dt);
+        let _ = writeln!(text, "st = {}", self.st);
+        let _ = writeln!(text, "stack = [{}]", self.return_addresses().join(", "));
+        text.push_str("\n[memory]\n");
+        for (address, bytes) in self.memory_lines() {
+            let _ = writeln!(text, "\"{:#05X}\" = \"{}\"", address, bytes);
+        }
+        text
+    }
+
+    fn registers(&self) -> Vec<String> {
+        self.v
+            .iter()
+            .map(|value| alloc::format!("\"{:#04X}\"", value))
+            .collect()
+    }
+
+    fn return_addresses(&self) -> Vec<String> {
+        self.stack
+            .iter()
+            .map(|address| alloc::format!("\"{:#05X}\"", address))
+            .collect()
+    }
+
+    // (address, "A0 50 D0 05 ...") for the lines with something in them
+    fn memory_lines(&self) -> impl Iterator<Item = (usize, String)> + '_ {
+        self.ram
+            .chunks(16)
+            .enumerate()
+            .filter(|(_, line)| line.iter().any(|&byte| byte != 0))
+            .map(|(index, line)| {
+                let bytes: Vec<String> = line
+                    .iter()
+                    .map(|byte| alloc::format!("{:02X}", byte))
+                    .collect();
+                (index * 16, bytes.join(" "))
+            })
+    }
+}
+
+impl Chip8 {
+    pub fn view(&self) -> StateView {
+        StateView::of(self)
+    }
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == StateDiff::default()
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::Pc => write!(f, "PC"),
+            Register::I => write!(f, "I"),
+            Register::V(index) => write!(f, "V{:X}", index),
+            Register::Dt => write!(f, "DT"),
+            Register::St => write!(f, "ST"),
+        }
+    }
+}
+
+// One line per change:
+//     PC 200 -> 202
+//     V3 00 -> 21
+//     RAM 3FE: 00 00 -> 12 34
+//     stack[0] 204 -> ---
+//     3 pixels
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in &self.registers {
+            // the 12 bit registers with 3 digits, the 8 bit ones with 2
+            let width = match change.register {
+                Register::Pc | Register::I => 3,
+                _ => 2,
+            };
+            writeln!(
+                f,
+                "{} {:03$X} -> {:03$X}",
+                change.register, change.before, change.after, width
+            )?;
+        }
+        for change in &self.memory {
+            write!(f, "RAM {:03X}:", change.start)?;
+            for byte in &change.before {
+                write!(f, " {:02X}", byte)?;
+            }
+            write!(f, " ->")?;
+            for byte in &change.after {
+                write!(f, " {:02X}", byte)?;
+            }
+            writeln!(f)?;
+        }
+        let address = |address: Option<u16>| match address {
+            Some(address) => alloc::format!("{:03X}", address),
+            None => "---".into(),
+        };
+        for change in &self.stack {
+            writeln!(
+                f,
+                "stack[{}] {} -> {}",
+                change.depth,
+                address(change.before),
+                address(change.after)
+            )?;
+        }
+        if self.pixels > 0 {
+            writeln!(f, "{} pixels", self.pixels)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_diff_one_instruction() {
+        // 2206: call, then at 206 6321 and A3FE F155 (V0, V1 to 3FE)
+        let rom = [
+            0x22, 0x06, 0x00, 0x00, 0x00, 0x00, 0x63, 0x21, 0x61, 0x34, 0xA3, 0xFE, 0xF1, 0x55,
+        ];
+        let mut chip = Chip8::new(&rom).unwrap();
+        let before = chip.view();
+        assert!(before.diff(&chip.view()).is_empty());
+
+        chip.step().unwrap();
+        let diff = before.diff(&chip.view());
+        assert_eq!(diff.to_string(), "PC 200 -> 206\nstack[0] --- -> 202\n");
+
+        let before = chip.view();
+        for _ in 0..4 {
+            chip.step().unwrap();
+        }
+        let diff = before.diff(&chip.view());
+        assert_eq!(
+            diff.memory,
+            [MemoryChange {
+                start: 0x3FF,
+                before: alloc::vec![0],
+                after: alloc::vec![0x34],
+            }]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "PC 206 -> 20E\nI 000 -> 400\nV1 00 -> 34\nV3 00 -> 21\nRAM 3FF: 00 -> 34\n"
+        );
+    }
+
+    #[test]
+    fn test_documents() {
+        let mut chip = Chip8::new(&[0x22, 0x04]).unwrap();
+        chip.step().unwrap();
+        chip.set_v(0xA, 0x1F).unwrap();
+        let view = chip.view();
+
+        let json = view.to_json();
+        assert!(json.contains("\"pc\": \"0x204\",\n"));
+        assert!(json.contains("\"0x00\", \"0x1F\", \"0x00\""));
+        assert!(json.contains("\"stack\": [\"0x202\"],\n"));
+        // the font, then the program
+        assert!(json.contains("\"0x050\": \"F0 90 90 90"));
+        assert!(json.ends_with(
+            ",\n    \"0x200\": \"22 04 00 00 00 00 00 00 00 00 00 00 00 00 00 00\"\n  }\n}\n"
+        ));
+
+        let toml = view.to_toml();
+        assert!(toml.starts_with("pc = \"0x204\"\ni = \"0x000\"\n"));
+        assert!(toml.contains("dt = 0\nst = 0\nstack = [\"0x202\"]\n\n[memory]\n"));
+        assert!(
+            toml.ends_with("\"0x200\" = \"22 04 00 00 00 00 00 00 00 00 00 00 00 00 00 00\"\n")
+        );
+    }
+
+    #[test]
+    fn test_memory_runs_and_pixels() {
+        let mut chip = Chip8::new(&[]).unwrap();
+        let before = chip.view();
+        for (address, value) in [(0x300, 1), (0x301, 2), (0x303, 3)] {
+            chip.set_ram(address, value).unwrap();
+        }
+        chip.xor_sprite_row(0, 0, 0xF0);
+        let diff = before.diff(&chip.view());
+        assert_eq!(diff.memory.len(), 2);
+        assert_eq!(diff.memory[0].after, [1, 2]);
+        assert_eq!(diff.pixels, 4);
+        assert!(diff.to_string().ends_with("RAM 303: 00 -> 03\n4 pixels\n"));
+    }
+}
@@ -0,0 +1,156 @@
+// A CSV line per 60 Hz frame of a session, for looking into slowdowns users report
+// without being at their machine:
+//     frame,frame_ms,instructions,draws,sound
+//     1,16.702,10,1,0
+// frame_ms is the wall time since the frame before, instructions and draws (Dxyn) what the
+// CPU ran in the frame and sound whether the buzzer was on. The first frame counts from
+// the load. Lines go out every second and when the session ends.
+//
+// It's a Plugin, so it sees the frames of every run loop.
+use crate::chip8::Chip8;
+use crate::plugin::Plugin;
+use std::io::{self, Write};
+use std::time::Instant;
+
+const HEADER: &str = "frame,frame_ms,instructions,draws,sound";
+// frames between flushes
+const FLUSH_EVERY: u64 = 60;
+
+pub struct Stats<W: Write> {
+    out: W,
+    frame: u64,
+    last: Instant,
+    // the counters of the machine at the frame before
+    instructions: u64,
+    draws: u64,
+}
+
+impl<W: Write> Stats<W> {
+    // Writes the header right away
+    pub fn new(mut out: W) -> io::Result<Self> {
+        writeln!(out, "{}", HEADER)?;
+        Ok(Stats {
+            out,
+            frame: 0,
+            last: Instant::now(),
+            instructions: 0,
+            draws: 0,
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // The line of a frame that just ran
+    pub fn record(&mut self, chip: &Chip8) -> io::Result<()> {
+        let now = Instant::now();
+        let frame_ms = (now - self.last).as_secs_f64() * 1000.0;
+        self.last = now;
+        self.frame += 1;
+        // a reset or a rewind takes the counters back, the frame ran from there
+        let instructions = chip.instructions().saturating_sub(self.instructions);
+        let draws = chip.draws().saturating_sub(self.draws);
+        (self.instructions, self.draws) = (chip.instructions(), chip.draws());
+        writeln!(
+            self.out,
+            "{},{:.3},{},{},{}",
+            self.frame,
+            frame_ms,
+            instructions,
+            draws,
+            chip.is_sound_on() as u8
+        )?;
+        if self.frame.is_multiple_of(FLUSH_EVERY) {
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Plugin for Stats<W> {
+    fn name(&self) -> &str {
+        "stats"
+    }
+
+    fn on_load(&mut self, chip: &mut Chip8) {
+        self.last = Instant::now();
+        (self.instructions, self.draws) = (chip.instructions(), chip.draws());
+    }
+
+    fn on_frame(&mut self, chip: &mut Chip8) -> Result<(), String> {
+        self.record(chip)
+            .map_err(|error| format!("can't write the stats: {}", error))
+    }
+
+    fn on_shutdown(&mut self, _chip: &mut Chip8) -> Result<(), String> {
+        self.out
+            .flush()
+            .map_err(|error| format!("can't write the stats: {}", error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        // D005 and a jump back to it, so a frame of 4 instructions draws twice
+        let mut chip = Chip8::new(&[0xD0, 0x05, 0x12, 0x00]).unwrap();
+        let mut stats = Stats::new(Vec::new()).unwrap();
+        stats.on_load(&mut chip);
+        for _ in 0..4 {
+            chip.step().unwrap();
+        }
+        stats.on_frame(&mut chip).unwrap();
+        chip.set_st(5);
+        chip.step().unwrap();
+        stats.on_frame(&mut chip).unwrap();
+
+        let csv = String::from_utf8(stats.into_inner()).unwrap();
+        let lines: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(lines[0].join(","), HEADER);
+        assert_eq!(
+            [lines[1][0], lines[1][2], lines[1][3], lines[1][4]],
+            ["1", "4", "2", "0"]
+        );
+        assert_eq!(
+            [lines[2][0], lines[2][2], lines[2][3], lines[2][4]],
+            ["2", "1", "1", "1"]
+        );
+        assert!(lines[1][1].parse::<f64>().is_ok());
+    }
+    // takes `room` bytes, then fails
+    struct Full {
+        room: usize,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            if self.room == 0 {
+                return Err(io::Error::other("disk full"));
+            }
+            let written = bytes.len().min(self.room);
+            self.room -= written;
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_error_goes_to_the_app() {
+        let mut chip = Chip8::new(&[0x12, 0x00]).unwrap();
+        let mut stats = Stats::new(Full {
+            room: HEADER.len() + 1,
+        })
+        .unwrap();
+        assert_eq!(
+            stats.on_frame(&mut chip),
+            Err("can't write the stats: disk full".into())
+        );
+    }
+}
@@ -0,0 +1,279 @@
+// Terminal frontend (crossterm): two pixel rows per text line with half blocks, so the
+// whole screen fits in 64x16 characters. Works over ssh and on machines without a desktop.
+use crate::display::Palette;
+use crate::display::{Display, DisplayBackend};
+use crate::icon::{Icon, WindowGeometry};
+use crate::keyboard::{HostKey, InputBackend, Keyboard, Keymap};
+use crate::osd;
+use crate::panels::Panels;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{
+    Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor,
+};
+use crossterm::{cursor, execute, queue, terminal};
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+// Same update rate limit as minifb's default, so games run at the same speed in every frontend
+const UPDATE_RATE: Duration = Duration::from_millis(4);
+
+// Terminals send key presses and autorepeat but never releases, so a key stays down
+// for a while after its last press. Long enough to cover the autorepeat delay
+const HOLD: Duration = Duration::from_millis(300);
+
+pub struct Terminal {
+    out: Stdout,
+    // when each key is released, None if it's up
+    held: [Option<Instant>; 16],
+    // keys that arrived in the last read_keys, hotkeys look at these
+    pressed: Vec<KeyCode>,
+    palette: Palette,
+    keymap: Keymap,
+    message: Option<String>,
+    keypad: Option<[bool; 16]>,
+    frame_times: Option<Vec<f32>>,
+    open: bool,
+    next_update: Instant,
+}
+
+impl Terminal {
+    // Switches the terminal to raw mode on an alternate screen, restored on drop
+    pub fn new() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(
+            out,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+
+        Ok(Terminal {
+            out,
+            held: [None; 16],
+            pressed: Vec::new(),
+            palette: Palette::default(),
+            keymap: Keymap::new(),
+            message: None,
+            keypad: None,
+            frame_times: None,
+            open: true,
+            next_update: Instant::now(),
+        })
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_keymap(&mut self, keymap: &Keymap) {
+        self.keymap = *keymap;
+    }
+
+    // The title of the terminal window or tab
+    pub fn set_title(&mut self, title: &str) {
+        let _ = execute!(self.out, terminal::SetTitle(title));
+    }
+
+    // A terminal has no icon of its own to change
+    pub fn set_icon(&mut self, _icon: &Icon) {}
+
+    // The terminal window belongs to the terminal
+    pub fn geometry(&self) -> Option<WindowGeometry> {
+        None
+    }
+
+    pub fn toggle_fullscreen(&mut self) -> bool {
+        false
+    }
+
+    pub fn open_debug_window(&mut self) -> Result<(), String> {
+        Err("the terminal has one screen, `chip8 debug` debugs in it".into())
+    }
+
+    pub fn close_debug_window(&mut self) {}
+
+    pub fn update_debug(&mut self, _panels: &Panels) -> bool {
+        false
+    }
+
+    // Printed under the screen, a terminal has text to spare
+    pub fn set_message(&mut self, message: Option<&str>) {
+        self.message = message.map(str::to_string);
+    }
+
+    // Also under the screen, as 4 lines of keys with the pressed ones in reverse video.
+    // None hides it
+    pub fn set_keypad(&mut self, pressed: Option<[bool; 16]>) {
+        self.keypad = pressed;
+    }
+
+    // Under the keypad, the last 64 frame times as a line of blocks (a full one is two
+    // frames of time) and their average and slowest. None hides it
+    pub fn set_frame_times(&mut self, times: Option<&[f32]>) {
+        self.frame_times = times.map(<[f32]>::to_vec);
+    }
+
+    pub fn render(&mut self, display: &Display) {
+        profile_scope!("render");
+        let pixels = display.pixel_bytes();
+        let mut text = String::with_capacity(16 * (64 * 3 + 2));
+        for y in (0..32).step_by(2) {
+            for x in 0..64 {
+                let top = pixels[y * 64 + x] == 1;
+                let bottom = pixels[(y + 1) * 64 + x] == 1;
+                text.push(match (top, bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            text.push_str("\r\n");
+        }
+
+        let _ = queue!(
+            self.out,
+            cursor::MoveTo(0, 0),
+            SetForegroundColor(color(self.palette.on)),
+            SetBackgroundColor(color(self.palette.off)),
+            Print(text),
+            ResetColor,
+            Print(format!("{:<64.64}", self.message.as_deref().unwrap_or("")))
+        );
+        for (row, keys) in osd::KEYPAD.iter().enumerate() {
+            let _ = queue!(self.out, cursor::MoveTo(0, 17 + row as u16));
+            let Some(pressed) = &self.keypad else {
+                // what a hidden keypad left behind
+                let _ = queue!(self.out, Print(" ".repeat(4 * 3)));
+                continue;
+            };
+            for &key in keys {
+                let label = self.keymap.host_key(key).unwrap_or('?');
+                let attribute = if pressed[key] {
+                    Attribute::Reverse
+                } else {
+                    Attribute::NoReverse
+                };
+                let _ = queue!(
+                    self.out,
+                    SetAttribute(attribute),
+                    Print(format!(" {} ", label.to_ascii_uppercase()))
+                );
+            }
+            let _ = queue!(self.out, SetAttribute(Attribute::Reset));
+        }
+        let (graph, summary) = match &self.frame_times {
+            Some(times) => (osd::sparkline(times, 64), osd::frame_summary(times)),
+            None => (String::new(), String::new()),
+        };
+        let _ = queue!(
+            self.out,
+            cursor::MoveTo(0, 21),
+            Print(format!("{:<64}\r\n{:<64}", graph, summary))
+        );
+        let _ = self.out.flush();
+        self.update();
+    }
+
+    // Nothing to redraw, just keeps the loop at the update rate
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        if self.next_update > now {
+            std::thread::sleep(self.next_update - now);
+        } else {
+            self.next_update = now;
+        }
+        self.next_update += UPDATE_RATE;
+    }
+
+    // Esc or Ctrl+C closes it
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // Called every frame to sync physical keyboard state
+    pub fn read_keys(&mut self, keyboard: &mut Keyboard) {
+        self.pressed.clear();
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            let Ok(Event::Key(key)) = event::read() else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            self.pressed.push(key.code);
+            match key.code {
+                KeyCode::Esc => self.open = false,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.open = false
+                }
+                KeyCode::Char(c) => {
+                    if let Some(chip8_key) = self.keymap.key_for(c) {
+                        self.held[chip8_key] = Some(Instant::now() + HOLD);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        for (chip8_key, held) in self.held.iter_mut().enumerate() {
+            if held.is_some_and(|until| until <= now) {
+                *held = None;
+            }
+            let _ = keyboard.set_key(chip8_key, held.is_some());
+        }
+    }
+
+    // For hotkeys. Down only in the read_keys call that got the press, there's no holding
+    pub fn is_key_down(&self, key: HostKey) -> bool {
+        self.pressed.iter().any(|&code| match (key, code) {
+            (HostKey::Char(key), KeyCode::Char(c)) => key == c.to_ascii_lowercase(),
+            (HostKey::F(key), KeyCode::F(f)) => key == f,
+            (HostKey::Escape, KeyCode::Esc) => true,
+            (HostKey::Space, KeyCode::Char(' ')) => true,
+            (HostKey::Enter, KeyCode::Enter) => true,
+            (HostKey::Backspace, KeyCode::Backspace) => true,
+            (HostKey::Tab, KeyCode::Tab) => true,
+            _ => false,
+        })
+    }
+}
+
+impl DisplayBackend for Terminal {
+    fn render(&mut self, display: &Display) {
+        Terminal::render(self, display);
+    }
+
+    fn update(&mut self) {
+        Terminal::update(self);
+    }
+
+    fn is_open(&self) -> bool {
+        Terminal::is_open(self)
+    }
+}
+
+impl InputBackend for Terminal {
+    fn keys(&mut self) -> [bool; 16] {
+        let mut keyboard = Keyboard::new();
+        Terminal::read_keys(self, &mut keyboard);
+        keyboard.keys()
+    }
+}
+
+fn color(rgb: u32) -> Color {
+    Color::Rgb {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
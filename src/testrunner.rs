@@ -0,0 +1,274 @@
+use crate::chip8::{Chip8, Chip8Error};
+use crate::lockstep::Lockstep;
+use crate::memory::{RAM_SIZE, parse_number};
+use std::fmt;
+use std::path::Path;
+
+// Headless runner for the well-known test ROMs (https://github.com/Timendus/chip8-test-suite).
+// The ROMs aren't shipped with the emulator, they're looked up by file name in a directory,
+// run in lockstep mode for a fixed number of frames and the final screen is compared
+// against the expected image embedded below.
+
+// instructions per frame used for every test ROM
+pub const IPF: u32 = 10;
+
+pub struct TestRom {
+    pub name: &'static str,
+    pub file: &'static str,
+    pub frames: u64,
+    // memory pokes applied after loading, the quirks test reads its platform from 0x1FF
+    pub pokes: &'static [(u16, u8)],
+    // expected final screen, 32 lines of 64 chars where '#' is a lit pixel
+    pub expected: Option<&'static str>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Pass,
+    // number of pixels that differ from the expected image
+    Mismatch(usize),
+    // the ROM ran without errors but there is no image to compare with
+    NoReference,
+    // the ROM file isn't in the directory
+    Missing,
+    Crashed(Chip8Error),
+}
+
+impl Outcome {
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Outcome::Mismatch(_) | Outcome::Crashed(_))
+    }
+}
+
+pub const TEST_ROMS: [TestRom; 4] = [
+    TestRom {
+        name: "IBM logo",
+        file: "2-ibm-logo.ch8",
+        frames: 60,
+        pokes: &[],
+        expected: Some(IBM_LOGO_SCREEN),
+    },
+    TestRom {
+        name: "corax+ opcode test",
+        file: "3-corax+.ch8",
+        frames: 120,
+        pokes: &[],
+        expected: None,
+    },
+    TestRom {
+        name: "flags test",
+        file: "4-flags.ch8",
+        frames: 120,
+        pokes: &[],
+        expected: None,
+    },
+    TestRom {
+        name: "quirks test (CHIP-8)",
+        file: "5-quirks.ch8",
+        frames: 600,
+        pokes: &[(0x1FF, 1)],
+        expected: None,
+    },
+];
+
+const IBM_LOGO_SCREEN: &str = "\
+................................................................
+................................................................
+................................................................
+................................................................
+................................................................
+................................................................
+................................................................
+................................................................
+............########.#########...#####.........#####............
+................................................................
+............########.###########.######.......######............
+................................................................
+..............####.....###...###...#####.....#####..............
+................................................................
+..............####.....#######.....#######.#######..............
+................................................................
+..............####.....#######.....###.#######.###..............
+................................................................
+..............####.....###...###...###..#####..###..............
+................................................................
+............########.###########.#####...###...#####............
+................................................................
+............########.#########...#####....#....#####............
+................................................................
+................................................................
+................................................................
+................................................................
+................................................................
+................................................................
+................................................................
+................................................................
+................................................................
+";
+
+// Loads the ROM on a headless machine with a fixed seed and runs it for the given frames
+pub fn run_headless(rom: &[u8], pokes: &[(u16, u8)], frames: u64) -> Result<Chip8, Chip8Error> {
+    let mut chip = Chip8::new(rom)?;
+    chip.set_seed(0);
+    for &(address, value) in pokes {
+        chip.set_ram(address, value)?;
+    }
+    Lockstep::new(IPF).run_frames(&mut chip, frames)?;
+    Ok(chip)
+}
+
+pub fn check(test: &TestRom, rom: &[u8]) -> Outcome {
+    match run_headless(rom, test.pokes, test.frames) {
+        Err(error) => Outcome::Crashed(error),
+        Ok(chip) => match test.expected {
+            None => Outcome::NoReference,
+            Some(expected) => match chip.display.count_differences(expected) {
+                0 => Outcome::Pass,
+                differences => Outcome::Mismatch(differences),
+            },
+        },
+    }
+}
+
+// Runs every known test ROM found in dir
+pub fn check_dir(dir: &Path) -> Vec<(&'static TestRom, Outcome)> {
+    TEST_ROMS
+        .iter()
+        .map(|test| match std::fs::read(dir.join(test.file)) {
+            Ok(rom) => (test, check(test, &rom)),
+            Err(_) => (test, Outcome::Missing),
+        })
+        .collect()
+}
+
+// What `chip8 run --exit-when` waits for, like mem[0x3FF]==0x55 or vF!=0. Test suites
+// written in Chip-8 itself write their result to memory and the host stops when it shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub place: Place,
+    // == when true, != when false
+    pub equal: bool,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Place {
+    Ram(u16),
+    V(usize),
+}
+
+impl Condition {
+    // numbers are hex with 0x or decimal, registers v0 to vF
+    pub fn parse(text: &str) -> Option<Self> {
+        let (place, value, equal) = match text.split_once("!=") {
+            Some((place, value)) => (place, value, false),
+            None => text
+                .split_once("==")
+                .map(|(place, value)| (place, value, true))?,
+        };
+        let place = place.trim().to_ascii_lowercase();
+        let place = if let Some(address) = place
+            .strip_prefix("mem[")
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            let address = parse_number(address.trim())? as usize;
+            (address < RAM_SIZE).then_some(Place::Ram(address as u16))?
+        } else {
+            let register = place.strip_prefix('v')?;
+            (register.len() == 1)
+                .then(|| usize::from_str_radix(register, 16).ok())
+                .flatten()
+                .map(Place::V)?
+        };
+        let value = u8::try_from(parse_number(value.trim())?).ok()?;
+        Some(Condition {
+            place,
+            equal,
+            value,
+        })
+    }
+
+    pub fn holds(&self, chip: &Chip8) -> bool {
+        let current = match self.place {
+            Place::Ram(address) => chip.get_ram(address),
+            Place::V(register) => chip.get_v(register),
+        };
+        current.is_ok_and(|current| (current == self.value) == self.equal)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.place {
+            Place::Ram(address) => write!(f, "mem[{:#05X}]", address)?,
+            Place::V(register) => write!(f, "V{:X}", register)?,
+        }
+        let operator = if self.equal { "==" } else { "!=" };
+        write!(f, "{}{:#04X}", operator, self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The classic IBM logo program, small enough to keep here as a fixture
+    const IBM_LOGO: [u8; 132] = [
+        0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0x70, 0x09, 0xA2, 0x39, 0xD0,
+        0x1F, 0xA2, 0x48, 0x70, 0x08, 0xD0, 0x1F, 0x70, 0x04, 0xA2, 0x57, 0xD0, 0x1F, 0x70, 0x08,
+        0xA2, 0x66, 0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x75, 0xD0, 0x1F, 0x12, 0x28, 0xFF, 0x00, 0xFF,
+        0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0xFF, 0x00, 0xFF, 0xFF, 0x00, 0xFF,
+        0x00, 0x38, 0x00, 0x3F, 0x00, 0x3F, 0x00, 0x38, 0x00, 0xFF, 0x00, 0xFF, 0x80, 0x00, 0xE0,
+        0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0xF8, 0x00, 0xFC,
+        0x00, 0x3E, 0x00, 0x3F, 0x00, 0x3B, 0x00, 0x39, 0x00, 0xF8, 0x00, 0xF8, 0x03, 0x00, 0x07,
+        0x00, 0x0F, 0x00, 0xBF, 0x00, 0xFB, 0x00, 0xF3, 0x00, 0xE3, 0x00, 0x43, 0xE0, 0x00, 0xE0,
+        0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0xE0, 0x00, 0xE0,
+    ];
+
+    #[test]
+    fn test_ibm_logo_matches() {
+        let outcome = check(&TEST_ROMS[0], &IBM_LOGO);
+        assert!(matches!(outcome, Outcome::Pass), "{:?}", outcome);
+    }
+
+    #[test]
+    fn test_blank_screen_mismatch() {
+        let outcome = check(&TEST_ROMS[0], &[0x12, 0x00]);
+        assert!(matches!(outcome, Outcome::Mismatch(d) if d > 0));
+    }
+
+    #[test]
+    fn test_ibm_logo_ascii_art() {
+        let chip = run_headless(&IBM_LOGO, &[], 60).unwrap();
+        assert_eq!(chip.display_ascii(), IBM_LOGO_SCREEN);
+        crate::assert_display_eq!(chip, IBM_LOGO_SCREEN);
+    }
+
+    // Runs the real suite when CHIP8_TEST_ROMS points at its bin directory
+    #[test]
+    fn test_suite_from_env() {
+        let Ok(dir) = std::env::var("CHIP8_TEST_ROMS") else {
+            return;
+        };
+        for (test, outcome) in check_dir(Path::new(&dir)) {
+            assert!(!outcome.is_failure(), "{}: {:?}", test.name, outcome);
+        }
+    }
+
+    #[test]
+    fn test_condition() {
+        let condition = Condition::parse("mem[0x3FF]==0x55").unwrap();
+        assert_eq!(condition.place, Place::Ram(0x3FF));
+        assert_eq!(condition.to_string(), "mem[0x3FF]==0x55");
+        assert_eq!(Condition::parse("vF != 1").unwrap().to_string(), "VF!=0x01");
+        for wrong in ["mem[0x1000]==1", "v10==1", "mem[3]=1", "v1==256", "i==2"] {
+            assert_eq!(Condition::parse(wrong), None, "{}", wrong);
+        }
+
+        let mut chip = Chip8::new(&[]).unwrap();
+        assert!(!condition.holds(&chip));
+        chip.set_ram(0x3FF, 0x55).unwrap();
+        assert!(condition.holds(&chip));
+        assert!(!Condition::parse("mem[1023]!=85").unwrap().holds(&chip));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::chip8::Chip8;
+use crate::cpu::Instruction;
+use crate::state::{Register, RegisterChange};
+use alloc::vec::Vec;
+use core::fmt;
+
+// Every instruction step runs, for diffing a ROM's run against another emulator's log. Turn
+// it on with Chip8::set_trace and a callback, to_writer makes one that writes a line per
+// instruction:
+//
+//     200: 6A0C  LD VA, 0C           VA 00 -> 0C
+//     202: A2EA  LD I, 2EA           I 000 -> 2EA
+//     204: 1204  JP 204
+//
+// PC isn't in the changes, the next line's address says where it went.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    // I, V0 to VF, DT and ST, the ones that changed
+    pub changes: Vec<RegisterChange>,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // padded by hand, the Display of Instruction writes straight to the formatter
+        write!(f, "{:03X}: {:04X}  ", self.pc, self.opcode)?;
+        if self.changes.is_empty() {
+            return write!(f, "{}", self.instruction);
+        }
+        let mnemonic = alloc::format!("{}", self.instruction);
+        write!(f, "{:<20}", mnemonic)?;
+        for (index, change) in self.changes.iter().enumerate() {
+            let width = if change.register == Register::I { 3 } else { 2 };
+            let separator = if index == 0 { "" } else { ", " };
+            write!(
+                f,
+                "{}{} {:04$X} -> {:04$X}",
+                separator, change.register, change.before, change.after, width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// What an instruction can change besides PC, RAM and the screen
+#[derive(Clone, Copy)]
+pub(crate) struct Registers {
+    i: u16,
+    v: [u8; 16],
+    dt: u8,
+    st: u8,
+}
+
+impl Registers {
+    pub(crate) fn of(chip: &Chip8) -> Self {
+        Registers {
+            i: *chip.get_i(),
+            v: chip.registers(),
+            dt: *chip.get_dt(),
+            st: *chip.get_st(),
+        }
+    }
+
+    pub(crate) fn changes(&self, after: &Registers) -> Vec<RegisterChange> {
+        let mut changes = Vec::new();
+        let mut compare = |register, before: u16, after: u16| {
+            if before != after {
+                changes.push(RegisterChange {
+                    register,
+                    before,
+                    after,
+                });
+            }
+        };
+        compare(Register::I, self.i, after.i);
+        for (index, (&before, &after)) in self.v.iter().zip(&after.v).enumerate() {
+            compare(Register::V(index), before as u16, after as u16);
+        }
+        compare(Register::Dt, self.dt as u16, after.dt as u16);
+        compare(Register::St, self.st as u16, after.st as u16);
+        changes
+    }
+}
+
+// A set_trace callback writing a line per instruction. Write errors are dropped, a full
+// disk shouldn't stop the game
+#[cfg(feature = "std")]
+pub fn to_writer(
+    mut writer: impl std::io::Write + Send + 'static,
+) -> impl FnMut(&TraceEntry) + Send + 'static {
+    move |entry| {
+        let _ = writeln!(writer, "{}", entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::{String, ToString};
+    use alloc::sync::Arc;
+    // the harness links std even for no_std builds, its Mutex shares the sink with the callback
+    extern crate std;
+
+    #[test]
+    fn test_trace() {
+        use std::sync::Mutex;
+
+        // VA = 0C, I = 2EA, VA += VA, then a jump to itself
+        let rom = [0x6A, 0x0C, 0xA2, 0xEA, 0x8A, 0xA4, 0x12, 0x06];
+        let mut chip = Chip8::new(&rom).unwrap();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let sink = entries.clone();
+        chip.step().unwrap();
+        chip.set_trace(move |entry| sink.lock().unwrap().push(entry.clone()));
+        assert!(chip.is_tracing());
+        for _ in 0..3 {
+            chip.step().unwrap();
+        }
+        chip.clear_trace();
+        chip.step().unwrap();
+
+        let entries = entries.lock().unwrap();
+        let lines: Vec<String> = entries.iter().map(|entry| entry.to_string()).collect();
+        assert_eq!(
+            lines,
+            [
+                "202: A2EA  LD I, 2EA           I 000 -> 2EA",
+                "204: 8AA4  ADD VA, VA          VA 0C -> 18",
+                "206: 1206  JP 206",
+            ]
+        );
+        assert_eq!(entries[1].instruction, Instruction::JimCarrey(10, 10));
+        // a copy of the machine doesn't report to the same place
+        assert!(!chip.clone().is_tracing());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_to_writer() {
+        use std::sync::Mutex;
+
+        #[derive(Clone)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for Shared {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(bytes)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let out = Shared(Arc::new(Mutex::new(Vec::new())));
+        let mut chip = Chip8::new(&[0x60, 0x01]).unwrap();
+        chip.set_trace(to_writer(out.clone()));
+        chip.step().unwrap();
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(text, "200: 6001  LD V0, 01           V0 00 -> 01\n");
+    }
+}
@@ -0,0 +1,141 @@
+// JavaScript API for browsers, built with wasm-bindgen:
+//     cargo rustc --release --lib --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//     wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/chip8.wasm
+// JS sees a `Chip8` class, the page does the drawing, sound and key events.
+// Methods use /// comments because wasm-bindgen copies them into the TypeScript types.
+use crate::chip8::{Chip8, Chip8Error};
+use crate::lockstep::Lockstep;
+use crate::testrunner::IPF;
+use wasm_bindgen::prelude::*;
+
+fn js_error(error: Chip8Error) -> JsError {
+    JsError::new(&format!("{:?}", error))
+}
+
+#[wasm_bindgen(js_name = Chip8)]
+pub struct WasmChip8 {
+    chip: Chip8,
+    lockstep: Lockstep,
+    // the screen where framebufferPtr points, so JS reads it without a copy
+    pixels: [u8; 64 * 32],
+}
+
+#[wasm_bindgen(js_class = Chip8)]
+impl WasmChip8 {
+    /// Empty machine, 10 instructions per frame
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmChip8 {
+        WasmChip8 {
+            chip: Chip8::default(),
+            lockstep: Lockstep::new(IPF),
+            pixels: [0; 64 * 32],
+        }
+    }
+
+    /// Resets the machine with the ROM from a Uint8Array, throws if it doesn't fit in ram
+    #[wasm_bindgen(js_name = loadRom)]
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), JsError> {
+        self.chip = Chip8::new(rom).map_err(js_error)?;
+        self.lockstep = Lockstep::new(self.lockstep.ipf());
+        Ok(())
+    }
+
+    /// One frame at 60 Hz: the instructions of a frame and one timer tick.
+    /// Throws with the error name when the ROM crashes
+    #[wasm_bindgen(js_name = stepFrame)]
+    pub fn step_frame(&mut self) -> Result<(), JsError> {
+        self.lockstep.run_frame(&mut self.chip).map_err(js_error)
+    }
+
+    /// A single instruction, for debuggers and pages that keep their own pace.
+    /// Timers only tick in stepFrame
+    pub fn step(&mut self) -> Result<(), JsError> {
+        self.chip.step().map_err(js_error)
+    }
+
+    /// Copy of the screen as a Uint8Array of 64 * 32 bytes, 0 or 1, row by row
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.chip.display.pixel_bytes().to_vec()
+    }
+
+    /// Where the screen is in the wasm memory, 64 * 32 bytes like framebuffer(), brought up
+    /// to date by each call: `new Uint8Array(wasm.memory.buffer, chip.framebufferPtr(), 2048)`
+    #[wasm_bindgen(js_name = framebufferPtr)]
+    pub fn framebuffer_ptr(&mut self) -> *const u8 {
+        self.pixels = self.chip.display.pixel_bytes();
+        self.pixels.as_ptr()
+    }
+
+    /// Only true after a Dxyn or 00E0 since the last call, so the page can skip redraws
+    #[wasm_bindgen(js_name = takeDrawFlag)]
+    pub fn take_draw_flag(&mut self) -> bool {
+        core::mem::take(&mut self.chip.draw_flag)
+    }
+
+    /// Presses or releases one of the 16 keys (0x0 to 0xF), throws for anything else
+    #[wasm_bindgen(js_name = setKey)]
+    pub fn set_key(&mut self, key: u8, pressed: bool) -> Result<(), JsError> {
+        self.chip
+            .keyboard
+            .set_key(key as usize, pressed)
+            .map(|_| ())
+            .map_err(js_error)
+    }
+
+    /// True while the buzzer should sound
+    #[wasm_bindgen(js_name = soundOn)]
+    pub fn sound_on(&self) -> bool {
+        self.chip.is_sound_on()
+    }
+
+    /// The sound timer, counting down at 60 Hz while the buzzer sounds
+    #[wasm_bindgen(js_name = soundTimer)]
+    pub fn sound_timer(&self) -> u8 {
+        *self.chip.get_st()
+    }
+
+    /// Seeds the random generator of Cxnn, for repeatable runs
+    #[wasm_bindgen(js_name = setSeed)]
+    pub fn set_seed(&mut self, seed: u32) {
+        self.chip.set_seed(seed as u64);
+    }
+}
+
+impl Default for WasmChip8 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // JsError only exists inside a JS engine, so natively just the happy path
+    #[test]
+    fn test_frames_and_framebuffer() {
+        let mut chip = WasmChip8::new();
+        chip.load_rom(&[0xA0, 0x50, 0xD0, 0x05, 0x12, 0x04]).unwrap();
+        chip.set_seed(1);
+        chip.step_frame().unwrap();
+        let pixels = chip.framebuffer();
+        assert_eq!(pixels.len(), 64 * 32);
+        assert_eq!(&pixels[..4], &[1, 1, 1, 1]);
+        assert!(chip.take_draw_flag());
+        assert!(!chip.take_draw_flag());
+        chip.set_key(0xF, true).unwrap();
+        assert!(!chip.sound_on());
+        assert_eq!(chip.sound_timer(), 0);
+
+        // V0 = 3, ST = V0, one instruction at a time
+        chip.load_rom(&[0x60, 0x03, 0xF0, 0x18]).unwrap();
+        chip.step().unwrap();
+        assert_eq!(chip.sound_timer(), 0);
+        chip.step().unwrap();
+        assert_eq!(chip.sound_timer(), 3);
+        assert!(chip.sound_on());
+        let pixels = chip.framebuffer_ptr();
+        assert_eq!(pixels, chip.pixels.as_ptr());
+        assert_eq!(chip.pixels, [0; 64 * 32]);
+    }
+}
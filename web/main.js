@@ -0,0 +1,123 @@
+// Browser frontend over the wasm API (src/wasm.rs). pkg/ is made by wasm-bindgen, see README.md
+import init, { Chip8 } from "./pkg/chip8.js";
+
+const SCALE = 10;
+const FRAME_MS = 1000 / 60;
+
+// Same layout as the desktop frontends, by physical key so it works on any keyboard layout
+const KEY_MAP = {
+  KeyX: 0x0, Digit1: 0x1, Digit2: 0x2, Digit3: 0x3,
+  KeyQ: 0x4, KeyW: 0x5, KeyE: 0x6, KeyA: 0x7,
+  KeyS: 0x8, KeyD: 0x9, KeyZ: 0xA, KeyC: 0xB,
+  Digit4: 0xC, KeyR: 0xD, KeyF: 0xE, KeyV: 0xF,
+};
+
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+const status = document.getElementById("status");
+
+// Square wave that is always running, the buzzer just opens and closes its gain
+class Buzzer {
+  constructor() {
+    this.audio = null;
+    this.gain = null;
+  }
+
+  // Browsers only allow sound after a user gesture, so this runs on the file pick
+  start() {
+    if (this.audio) {
+      return;
+    }
+    this.audio = new AudioContext();
+    const oscillator = this.audio.createOscillator();
+    oscillator.type = "square";
+    oscillator.frequency.value = 440;
+    this.gain = this.audio.createGain();
+    this.gain.gain.value = 0;
+    oscillator.connect(this.gain).connect(this.audio.destination);
+    oscillator.start();
+  }
+
+  set(on) {
+    if (this.gain) {
+      this.gain.gain.setTargetAtTime(on ? 0.1 : 0, this.audio.currentTime, 0.005);
+    }
+  }
+}
+
+function draw(pixels) {
+  context.fillStyle = "#000000";
+  context.fillRect(0, 0, canvas.width, canvas.height);
+  context.fillStyle = "#FFB000";
+  for (let i = 0; i < pixels.length; i++) {
+    if (pixels[i]) {
+      context.fillRect((i % 64) * SCALE, Math.floor(i / 64) * SCALE, SCALE, SCALE);
+    }
+  }
+}
+
+const wasm = await init();
+const chip = new Chip8();
+// the screen read in place from the wasm memory, no copy per frame
+const screen = () => new Uint8Array(wasm.memory.buffer, chip.framebufferPtr(), 64 * 32);
+const buzzer = new Buzzer();
+let running = false;
+let last = 0;
+let pending = 0;
+
+function frame(now) {
+  if (!running) {
+    return;
+  }
+  // fixed 60 Hz steps whatever the refresh rate of the screen
+  pending = Math.min(pending + now - last, FRAME_MS * 5);
+  last = now;
+  try {
+    while (pending >= FRAME_MS) {
+      chip.stepFrame();
+      pending -= FRAME_MS;
+    }
+  } catch (error) {
+    running = false;
+    buzzer.set(false);
+    status.textContent = `Crashed: ${error.message}`;
+    return;
+  }
+  if (chip.takeDrawFlag()) {
+    draw(screen());
+  }
+  buzzer.set(chip.soundOn());
+  requestAnimationFrame(frame);
+}
+
+document.getElementById("rom").addEventListener("change", async (event) => {
+  const file = event.target.files[0];
+  if (!file) {
+    return;
+  }
+  try {
+    chip.loadRom(new Uint8Array(await file.arrayBuffer()));
+  } catch (error) {
+    status.textContent = `Can't load ${file.name}: ${error.message}`;
+    return;
+  }
+  buzzer.start();
+  draw(screen());
+  status.textContent = file.name;
+  if (!running) {
+    running = true;
+    last = performance.now();
+    pending = 0;
+    requestAnimationFrame(frame);
+  }
+});
+
+for (const [type, pressed] of [["keydown", true], ["keyup", false]]) {
+  window.addEventListener(type, (event) => {
+    const key = KEY_MAP[event.code];
+    if (key !== undefined) {
+      chip.setKey(key, pressed);
+      event.preventDefault();
+    }
+  });
+}